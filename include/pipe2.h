@@ -0,0 +1,67 @@
+/*
+ * pipe2.h: the C ABI over pipe2's capture engine, built with the `ffi` feature:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * Kept in step with src/ffi.rs by hand; `cbindgen --config cbindgen.toml --output include/pipe2.h` regenerates it.
+ */
+
+#ifndef PIPE2_H
+#define PIPE2_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* Nothing happened since the last call. */
+#define PIPE2_EVENT_NONE 0
+/* The child wrote `data` to `stdout`. */
+#define PIPE2_EVENT_STDOUT 1
+/* The child wrote `data` to `stderr`. */
+#define PIPE2_EVENT_STDERR 2
+/* The child is done, with `exit_code`, or killed by `signal`; nothing comes after this. */
+#define PIPE2_EVENT_EXITED 3
+
+/* A child, and the events that were read from it but not handed over yet. */
+typedef struct Pipe2Child Pipe2Child;
+
+/* One thing that happened to the child, filled in by `pipe2_poll_event`. */
+typedef struct Pipe2Event {
+  /* One of the `PIPE2_EVENT_*` constants. */
+  int kind;
+  /* What was written, valid until the next call with the same child, or until it's freed. */
+  const uint8_t *data;
+  size_t len;
+  /* `-1` if the child didn't exit by itself. */
+  int exit_code;
+  /* The signal that killed the child, or `0` (Unix). */
+  int signal;
+} Pipe2Event;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Why the last call on this thread that failed did, valid until the next call that fails on this thread. */
+const char *pipe2_last_error(void);
+
+/* Spawns `program` with the `argc` arguments in `argv`, and returns the child, or NULL if it couldn't be spawned. */
+Pipe2Child *pipe2_spawn(const char *program, const char *const *argv, size_t argc);
+
+/*
+ * Fills in `event` with the next thing that happened to the child, without waiting for it: returns 1 if there was
+ * something, 0 if there wasn't, and -1 if the child couldn't be polled. Call it every few milliseconds until it hands
+ * over PIPE2_EVENT_EXITED.
+ */
+int pipe2_poll_event(Pipe2Child *child, Pipe2Event *event);
+
+/* Stops the child, and returns 0, or -1 if that failed. Its last output and its exit still come out of polling. */
+int pipe2_kill(Pipe2Child *child);
+
+/* Frees the child, killing it first and waiting for it if it's still running. NULL is ignored. */
+void pipe2_free(Pipe2Child *child);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PIPE2_H */
@@ -0,0 +1,331 @@
+//! `pipe2 agent --socket PATH`: a local job agent, that other programs start runs through, follow the output of, and
+//! stop, over a Unix socket at PATH (a named pipe by that name on Windows).
+//!
+//! The protocol is newline-delimited JSON both ways: every request is an object on a line of its own, with an `op`,
+//! and is answered with an object on a line of its own, with `ok` and, if it's `false`, an `error`:
+//!
+//! - `{"op": "start", "args": ["--timeout", "10m", "--", "make", "-j4"]}` starts a run, with the same arguments as
+//!   `pipe2` itself, and answers with its `run` ID and `pid`. The options that shape the child all apply; the ones
+//!   about what pipe2 does once it's over, like `--report`, don't.
+//! - `{"op": "subscribe", "run": ID}` answers, then turns the connection over to the run's output from then on, as
+//!   `{"run": ID, "stream": "stdout", "data": "..."}` lines, and a last `{"run": ID, "exited": {...}}` line once it's
+//!   done, after which the connection is closed.
+//! - `{"op": "stop", "run": ID}` stops a run the way `--timeout` would, and `{"op": "kill", "run": ID}` kills it on
+//!   the spot.
+//! - `{"op": "list"}` answers with every run so far, running or not, under `runs`.
+//!
+//! The children themselves are polled by the agent's main loop, and every connection is served on a thread of its
+//! own, that hands its requests over to the main loop; so a client that's slow to read only holds up itself.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use pipe2::{Child, Pipe2, Subscriber};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::cli::{self, Action};
+
+/// How much of a run's output a subscriber can fall behind by before its oldest chunks are dropped.
+const CAPACITY: usize = 16 * 1024 * 1024;
+
+/// How often the children are polled.
+const POLL_EVERY: Duration = Duration::from_millis(10);
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Request {
+    Start { args: Vec<String> },
+    Subscribe { run: String },
+    Stop { run: String },
+    Kill { run: String },
+    List,
+}
+
+/// What the main loop hands back to a connection for its request.
+enum Answer {
+    Reply(Value),
+    /// The run's output to follow; `None` if it's over already, with the `exited` line to send instead.
+    Subscribed(Option<Subscriber>, Value),
+}
+
+/// A request from a connection, and where its answer goes.
+type Message = (Request, Sender<Answer>);
+
+struct Run {
+    id: String,
+    command: Vec<String>,
+    pid: u32,
+    /// `None` once it's over.
+    child: Option<Child>,
+    exited: Option<Value>,
+}
+
+/// Serves clients at `path` until pipe2 is stopped.
+pub fn run(path: &Path) -> io::Result<i32> {
+    let (sender, requests) = mpsc::channel();
+    listen(path, sender)?;
+    eprintln!("pipe2: agent listening on {}", path.display());
+
+    let mut runs = Vec::new();
+    loop {
+        while let Ok((request, answer)) = requests.try_recv() {
+            let _ = answer.send(handle(&mut runs, request));
+        }
+        for run in &mut runs {
+            poll(run);
+        }
+        thread::sleep(POLL_EVERY);
+    }
+}
+
+fn handle(runs: &mut Vec<Run>, request: Request) -> Answer {
+    let find = |runs: &mut Vec<Run>, id: &str| {
+        let index = runs.iter().position(|run| run.id == id);
+        index.ok_or_else(|| format!("no run {id:?}"))
+    };
+    let result = match request {
+        Request::Start { args } => start(&args).map(|run| {
+            let reply = json!({ "ok": true, "run": run.id, "pid": run.pid });
+            runs.push(run);
+            reply
+        }),
+        Request::Subscribe { run } => match find(runs, &run) {
+            Ok(index) => {
+                let run = &mut runs[index];
+                let subscriber = run.child.as_mut().map(|child| child.subscribe(CAPACITY));
+                let exited = json!({ "run": run.id, "exited": run.exited });
+                return Answer::Subscribed(subscriber, exited);
+            }
+            Err(e) => Err(e),
+        },
+        Request::Stop { run } => find(runs, &run).and_then(|index| {
+            let Some(child) = &mut runs[index].child else {
+                return Ok(json!({ "ok": true }));
+            };
+            child.kill().map_err(|e| e.to_string())?;
+            Ok(json!({ "ok": true }))
+        }),
+        Request::Kill { run } => find(runs, &run).and_then(|index| {
+            let Some(child) = &mut runs[index].child else {
+                return Ok(json!({ "ok": true }));
+            };
+            #[cfg(unix)]
+            child
+                .send_signal(pipe2::Signal::SIGKILL)
+                .map_err(|e| e.to_string())?;
+            // NOTE: `TerminateProcess` is as abrupt as it gets already.
+            #[cfg(windows)]
+            child.kill().map_err(|e| e.to_string())?;
+            Ok(json!({ "ok": true }))
+        }),
+        Request::List => Ok(json!({
+            "ok": true,
+            "runs": runs
+                .iter()
+                .map(|run| {
+                    json!({
+                        "run": run.id,
+                        "command": run.command,
+                        "pid": run.pid,
+                        "running": run.child.is_some(),
+                        "exited": run.exited,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })),
+    };
+    Answer::Reply(result.unwrap_or_else(|e| json!({ "ok": false, "error": e })))
+}
+
+fn start(args: &[String]) -> Result<Run, String> {
+    let cli = match cli::parse(args.iter().map(Into::into))? {
+        Some(Action::Run(cli)) => cli,
+        Some(_) => return Err("only runs can be started, not the other subcommands".to_owned()),
+        None => return Err("start expects a PROGRAM".to_owned()),
+    };
+    let mut pipe2 = Pipe2::new(&cli.program);
+    pipe2.args(&cli.args);
+    cli.configure(&mut pipe2);
+    // NOTE: the output goes to the subscribers, and nowhere else.
+    pipe2.echo(false);
+    let child = pipe2.spawn().map_err(|e| e.to_string())?;
+    Ok(Run {
+        id: child.run_id().to_string(),
+        command: std::iter::once(&cli.program)
+            .chain(&cli.args)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        pid: child.id(),
+        child: Some(child),
+        exited: None,
+    })
+}
+
+/// Lets the run make progress, and reaps it once it's over.
+fn poll(run: &mut Run) {
+    let Some(child) = &mut run.child else {
+        return;
+    };
+    let done = !matches!(child.poll(), Ok(None));
+    // NOTE: the subscribers got it already; keeping it all for a long-running agent would only use up memory.
+    let (stdout, stderr) = (child.take_stdout(), child.take_stderr());
+    child.recycle(stdout);
+    child.recycle(stderr);
+    if !done {
+        return;
+    }
+    let child = run.child.take().expect("the run is still going");
+    run.exited = Some(match child.wait() {
+        Ok(output) => describe(output.status, output.success),
+        Err(e) => json!({ "error": e.to_string() }),
+    });
+}
+
+fn describe(status: ExitStatus, success: bool) -> Value {
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+    #[cfg(not(unix))]
+    let signal: Option<i32> = None;
+    json!({ "code": status.code(), "signal": signal, "success": success })
+}
+
+/// Answers the requests of one client, until it goes away or subscribes.
+fn serve(
+    reader: impl io::Read,
+    mut writer: impl Write,
+    requests: Sender<Message>,
+) -> io::Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                writeln!(writer, "{}", json!({ "ok": false, "error": e.to_string() }))?;
+                continue;
+            }
+        };
+        let (sender, answer) = mpsc::channel();
+        if requests.send((request, sender)).is_err() {
+            return Ok(());
+        }
+        match answer.recv() {
+            Ok(Answer::Reply(reply)) => writeln!(writer, "{reply}")?,
+            Ok(Answer::Subscribed(subscriber, exited)) => {
+                writeln!(writer, "{}", json!({ "ok": true }))?;
+                if let Some(subscriber) = subscriber {
+                    return follow(&subscriber, exited["run"].clone(), &mut writer);
+                }
+                return writeln!(writer, "{exited}");
+            }
+            Err(_) => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Writes out everything the subscriber receives, until the run is over.
+fn follow(subscriber: &Subscriber, run: Value, writer: &mut impl Write) -> io::Result<()> {
+    while let Some(event) = subscriber.recv() {
+        let line = match event {
+            pipe2::Event::Stdout(chunk) => {
+                json!({ "run": run, "stream": "stdout", "data": String::from_utf8_lossy(&chunk) })
+            }
+            pipe2::Event::Stderr(chunk) => {
+                json!({ "run": run, "stream": "stderr", "data": String::from_utf8_lossy(&chunk) })
+            }
+            pipe2::Event::Exited(status) => {
+                json!({ "run": run, "exited": describe(status, status.success()), "missed": subscriber.missed() })
+            }
+        };
+        writeln!(writer, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Accepts clients on a thread of its own, and serves each on a thread of its own.
+#[cfg(unix)]
+fn listen(path: &Path, requests: Sender<Message>) -> io::Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    // NOTE: a socket left behind by an agent that's gone is replaced; one that's still being listened on is not, and
+    // neither is anything else.
+    pipe2::remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                let reader = stream.try_clone()?;
+                serve(reader, stream, requests)
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn listen(path: &Path, requests: Sender<Message>) -> io::Result<()> {
+    use std::fs::File;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+
+    use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW};
+    use winapi::um::winbase::{
+        PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+        PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    // NOTE: every client gets an instance of the pipe of its own, and the next one is created once it's connected.
+    let instance = move || -> io::Result<OwnedHandle> {
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_DUPLEX,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                64 * 1024,
+                64 * 1024,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { OwnedHandle::from_raw_handle(handle as _) })
+    };
+    let mut pipe = instance()?;
+    thread::spawn(move || {
+        loop {
+            let connected =
+                unsafe { ConnectNamedPipe(pipe.as_raw_handle() as _, std::ptr::null_mut()) } != 0
+                    || unsafe { GetLastError() } == ERROR_PIPE_CONNECTED;
+            let Ok(next) = instance() else {
+                return;
+            };
+            let client = std::mem::replace(&mut pipe, next);
+            if !connected {
+                continue;
+            }
+            let requests = requests.clone();
+            thread::spawn(move || {
+                let stream = File::from(client);
+                let reader = stream.try_clone()?;
+                serve(reader, stream, requests)
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,5 @@
+//! Acts out a [`pipe2::FakeChild`] script given as its arguments, for testing.
+
+fn main() {
+    pipe2::FakeChild::main()
+}
@@ -0,0 +1,170 @@
+//! Handing the child's output to any number of subscribers at once, on threads of their own: a file tee, a webhook, a
+//! TUI, alongside the echo and the capture.
+//!
+//! Every subscriber has a queue of its own, so one that falls behind only holds up itself. The capture loop never
+//! waits for any of them: once a subscriber's queue is full, its oldest chunks make room for the new ones, and it's
+//! told how much it missed.
+
+use std::collections::VecDeque;
+use std::process::ExitStatus;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::iter::Event;
+use crate::run_id::RunId;
+use crate::stream::Stream;
+
+/// Receives everything the child writes from the time it subscribed, and how it exits; see [`Child::subscribe`].
+///
+/// It's `Send`, to be taken to a thread of its own. Iterating blocks until the next event, and ends once the child is
+/// done and everything was received.
+///
+/// [`Child::subscribe`]: crate::Child::subscribe
+pub struct Subscriber {
+    shared: Arc<Shared>,
+    run_id: RunId,
+}
+
+struct Shared {
+    inbox: Mutex<Inbox>,
+    changed: Condvar,
+}
+
+struct Inbox {
+    events: VecDeque<Event>,
+    /// How many bytes `events` holds at most.
+    capacity: usize,
+    bytes: usize,
+    /// How many bytes were dropped for falling behind.
+    missed: u64,
+    closed: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inbox> {
+        self.inbox.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Subscriber {
+    /// Waits for the next event; `None` once there's none left to come.
+    pub fn recv(&self) -> Option<Event> {
+        let mut inbox = self.shared.lock();
+        loop {
+            if let Some(event) = inbox.pop() {
+                return Some(event);
+            }
+            if inbox.closed {
+                return None;
+            }
+            inbox = self
+                .shared
+                .changed
+                .wait(inbox)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// The next event if there's one already, without waiting.
+    pub fn try_recv(&self) -> Option<Event> {
+        self.shared.lock().pop()
+    }
+
+    /// The run it's receiving the output of, see [`Child::run_id`](crate::Child::run_id).
+    pub fn run_id(&self) -> RunId {
+        self.run_id
+    }
+
+    /// How many bytes of output were dropped so far because this subscriber fell behind.
+    pub fn missed(&self) -> u64 {
+        self.shared.lock().missed
+    }
+}
+
+impl Iterator for Subscriber {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.recv()
+    }
+}
+
+impl Inbox {
+    fn pop(&mut self) -> Option<Event> {
+        let event = self.events.pop_front()?;
+        self.bytes -= size(&event);
+        Some(event)
+    }
+}
+
+/// How much of an inbox's capacity `event` takes up.
+fn size(event: &Event) -> usize {
+    match event {
+        Event::Stdout(chunk) | Event::Stderr(chunk) => chunk.len(),
+        Event::Exited(_) => 0,
+    }
+}
+
+/// The child's end: every subscriber's queue.
+#[derive(Default)]
+pub(crate) struct Broadcast {
+    subscribers: Vec<Arc<Shared>>,
+    /// Set once the child is done, when whoever subscribes late only gets an end.
+    closed: bool,
+}
+
+impl Broadcast {
+    pub(crate) fn subscribe(&mut self, capacity: usize, run_id: RunId) -> Subscriber {
+        let shared = Arc::new(Shared {
+            inbox: Mutex::new(Inbox {
+                events: VecDeque::new(),
+                capacity,
+                bytes: 0,
+                missed: 0,
+                closed: self.closed,
+            }),
+            changed: Condvar::new(),
+        });
+        self.subscribers.push(shared.clone());
+        Subscriber { shared, run_id }
+    }
+
+    /// Hands a chunk of `stream` to every subscriber, making room in the queues of those that fell behind.
+    pub(crate) fn send(&mut self, stream: Stream, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        // NOTE: whoever dropped their subscriber doesn't need anything more.
+        self.subscribers
+            .retain(|shared| Arc::strong_count(shared) > 1);
+        for shared in &self.subscribers {
+            let mut inbox = shared.lock();
+            // NOTE: a chunk bigger than the whole queue still goes through once the queue is empty.
+            while inbox.bytes > 0 && inbox.bytes + chunk.len() > inbox.capacity {
+                let Some(event) = inbox.pop() else {
+                    break;
+                };
+                inbox.missed += size(&event) as u64;
+            }
+            let event = match stream {
+                Stream::Stdout => Event::Stdout(chunk.to_vec()),
+                Stream::Stderr => Event::Stderr(chunk.to_vec()),
+            };
+            inbox.bytes += chunk.len();
+            inbox.events.push_back(event);
+            shared.changed.notify_all();
+        }
+    }
+
+    /// Tells every subscriber how the child exited, if it did, and that nothing more is coming.
+    pub(crate) fn close(&mut self, status: Option<ExitStatus>) {
+        self.closed = true;
+        for shared in self.subscribers.drain(..) {
+            let mut inbox = shared.lock();
+            if let Some(status) = status {
+                inbox.events.push_back(Event::Exited(status));
+            }
+            inbox.closed = true;
+            shared.changed.notify_all();
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Stops a run from another thread, like a GUI's stop button would; see [`Pipe2::cancellation_handle`].
+///
+/// Cancelling stops the child the way [`Child::kill`](crate::Child::kill) does, grace period included, and the run
+/// still drains the pipes and returns normally, with [`Output::cancelled`](crate::Output::cancelled) set.
+///
+/// [`Pipe2::cancellation_handle`]: crate::Pipe2::cancellation_handle
+#[derive(Clone, Debug, Default)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    /// Asks the run to stop; it's noticed on the next poll. Cancelling again, or after the child is done, does
+    /// nothing.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,284 @@
+//! Where a stream's capture is kept: in memory, or for very large captures, in a memory-mapped file.
+//!
+//! A mapped file grows by whole chunks, remapped each time, rather than being copied over into a bigger allocation
+//! like a `Vec` is; and since the kernel writes it back on its own, what was captured is still on disk if pipe2 dies
+//! halfway through the run.
+//!
+//! On Linux, the file can be a memfd instead: an anonymous one, sealed read-only once the run is over, which a collector
+//! process can be handed to map the capture from without copying it.
+
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::{
+    ffi::CStr,
+    os::fd::{AsFd, BorrowedFd, FromRawFd, RawFd},
+    sync::Arc,
+};
+
+/// How big a mapped file starts out, and how much it grows by at least.
+#[cfg(unix)]
+const MAPPED_CHUNK: usize = 1024 * 1024;
+
+/// Where [`Pipe2::capture_to_file`](crate::Pipe2::capture_to_file) and
+/// [`Pipe2::capture_to_memfd`](crate::Pipe2::capture_to_memfd) capture a stream to.
+#[cfg(unix)]
+#[derive(Clone)]
+pub(crate) enum Target {
+    File(PathBuf),
+    /// A memfd by that name.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Memfd(&'static CStr),
+}
+
+#[cfg(unix)]
+impl Target {
+    pub(crate) fn open(&self) -> io::Result<MappedFile> {
+        match self {
+            Self::File(path) => MappedFile::create(path),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Self::Memfd(name) => MappedFile::memfd(name),
+        }
+    }
+}
+
+pub(crate) enum Capture {
+    Memory(Vec<u8>),
+    #[cfg(unix)]
+    Mapped(MappedFile),
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Self::Memory(Vec::new())
+    }
+}
+
+impl Capture {
+    pub(crate) fn extend(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Memory(captured) => {
+                captured.extend_from_slice(data);
+                Ok(())
+            }
+            #[cfg(unix)]
+            Self::Mapped(file) => file.extend(data),
+        }
+    }
+
+    /// What was captured in memory since the last take, for the [`Output`](crate::Output). A mapped capture is left in
+    /// its file rather than copied out, since it's there for being too big to keep in memory.
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        match self {
+            Self::Memory(captured) => std::mem::take(captured),
+            #[cfg(unix)]
+            Self::Mapped(_) => Vec::new(),
+        }
+    }
+
+    /// What was captured since the last take, with what's captured next going into `spare` rather than a new
+    /// allocation. A mapped file keeps all of it regardless.
+    pub(crate) fn take_into(&mut self, mut spare: Vec<u8>) -> Vec<u8> {
+        match self {
+            Self::Memory(captured) => {
+                spare.clear();
+                std::mem::replace(captured, spare)
+            }
+            #[cfg(unix)]
+            Self::Mapped(file) => file.take(),
+        }
+    }
+
+    /// The file the stream is captured into, if it's one with a path.
+    #[cfg(unix)]
+    pub(crate) fn path(&self) -> Option<PathBuf> {
+        match self {
+            Self::Memory(_) => None,
+            Self::Mapped(file) => file.path.clone(),
+        }
+    }
+
+    /// Seals the memfd the stream was captured into, if it was, and hands it out; nothing more can be captured then.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn seal(&mut self) -> io::Result<Option<Memfd>> {
+        match self {
+            Self::Mapped(file) if file.path.is_none() => file.seal().map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A stream captured into a memfd by [`Pipe2::capture_to_memfd`](crate::Pipe2::capture_to_memfd), sealed so that
+/// nobody can write to it, shrink it or grow it anymore. Whoever it's handed to (over a Unix socket, or by inheriting
+/// it) can map it and read the capture in place, trusting it to stay as it is.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[derive(Clone, Debug)]
+pub struct Memfd(Arc<File>);
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Memfd {
+    /// The memfd as a file, to read from or to map read-only.
+    pub fn file(&self) -> &File {
+        &self.0
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl AsFd for Memfd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl AsRawFd for Memfd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// A file that's written through a shared memory mapping, cut down to what was written to it once dropped.
+#[cfg(unix)]
+pub(crate) struct MappedFile {
+    file: File,
+    /// Where it is; a memfd, to be sealed rather than left behind, has none.
+    path: Option<PathBuf>,
+    map: *mut u8,
+    capacity: usize,
+    len: usize,
+    /// How much of it [`MappedFile::take`] handed out already.
+    taken: usize,
+}
+
+// NOTE: the mapping is only ever reached through `&mut self`, like a `Vec`'s buffer.
+#[cfg(unix)]
+unsafe impl Send for MappedFile {}
+
+#[cfg(unix)]
+impl MappedFile {
+    /// Creates the file at `path`, replacing whatever was there.
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Self::new(file, Some(path.to_owned()))
+    }
+
+    /// Creates an anonymous file that can be sealed, named `name` for `/proc/*/fd` to show.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn memfd(name: &CStr) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Self::new(unsafe { File::from_raw_fd(fd) }, None)
+    }
+
+    fn new(file: File, path: Option<PathBuf>) -> io::Result<Self> {
+        let mut mapped = Self {
+            file,
+            path,
+            map: std::ptr::null_mut(),
+            capacity: 0,
+            len: 0,
+            taken: 0,
+        };
+        mapped.grow(MAPPED_CHUNK)?;
+        Ok(mapped)
+    }
+
+    fn extend(&mut self, data: &[u8]) -> io::Result<()> {
+        let needed = self.len + data.len();
+        if needed > self.capacity {
+            let capacity = needed.div_ceil(MAPPED_CHUNK) * MAPPED_CHUNK;
+            self.grow(capacity.max(self.capacity * 2))?;
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.map.add(self.len), data.len());
+        }
+        self.len = needed;
+        Ok(())
+    }
+
+    /// Makes the file `capacity` bytes long and maps all of it, in place of the old mapping.
+    fn grow(&mut self, capacity: usize) -> io::Result<()> {
+        // NOTE: reserving the blocks up front means a full disk fails here, rather than as a `SIGBUS` on writing.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        match unsafe { libc::posix_fallocate(self.file.as_raw_fd(), 0, capacity as libc::off_t) } {
+            0 => {}
+            errno => return Err(io::Error::from_raw_os_error(errno)),
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        self.file.set_len(capacity as u64)?;
+
+        let map = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                capacity,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        self.unmap();
+        self.map = map.cast();
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    fn contents(&self) -> &[u8] {
+        if self.map.is_null() {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.map, self.len) }
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        let taken = self.contents()[self.taken..].to_vec();
+        self.taken = self.len;
+        taken
+    }
+
+    /// Cuts the file down to what was written to it and seals it, which the kernel only allows once it's unmapped.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn seal(&mut self) -> io::Result<Memfd> {
+        self.unmap();
+        self.file.set_len(self.len as u64)?;
+        let seals =
+            libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_ADD_SEALS, seals) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.capacity = 0;
+        Ok(Memfd(Arc::new(self.file.try_clone()?)))
+    }
+
+    fn unmap(&mut self) {
+        if !self.map.is_null() {
+            unsafe { libc::munmap(self.map.cast(), self.capacity) };
+            self.map = std::ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        self.unmap();
+        let _ = self.file.set_len(self.len as u64);
+    }
+}
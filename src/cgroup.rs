@@ -0,0 +1,129 @@
+//! cgroup v2 placement and limits for the child, on Linux.
+//!
+//! The child joins its cgroup between `fork` and `exec`, by writing to a `cgroup.procs` we opened beforehand, so
+//! everything it does from its first instruction on is accounted for there.
+
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[derive(Clone)]
+enum Placement {
+    Existing(PathBuf),
+    /// Created under the given parent for this spawn, and removed after.
+    Transient(PathBuf),
+}
+
+/// The builder's cgroup configuration.
+#[derive(Clone, Default)]
+pub(crate) struct CgroupConfig {
+    placement: Option<Placement>,
+    pub(crate) memory_max: Option<u64>,
+    pub(crate) cpu_max: Option<(Duration, Duration)>,
+}
+
+impl CgroupConfig {
+    pub(crate) fn existing(&mut self, path: PathBuf) {
+        self.placement = Some(Placement::Existing(path));
+    }
+
+    pub(crate) fn transient(&mut self, parent: PathBuf) {
+        self.placement = Some(Placement::Transient(parent));
+    }
+
+    /// Creates the cgroup if it's transient, applies the limits, and opens it for the child to join. `None` if the
+    /// child isn't going in a cgroup at all.
+    pub(crate) fn prepare(&self) -> io::Result<Option<Cgroup>> {
+        let has_limits = self.memory_max.is_some() || self.cpu_max.is_some();
+        let (path, transient) = match &self.placement {
+            None if has_limits => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "memory and CPU limits need a cgroup to apply to",
+                ));
+            }
+            None => return Ok(None),
+            Some(Placement::Existing(path)) => (path.clone(), false),
+            Some(Placement::Transient(parent)) => {
+                static COUNTER: AtomicUsize = AtomicUsize::new(0);
+                let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+                let path = parent.join(format!("pipe2-{}-{n}", std::process::id()));
+                if has_limits {
+                    enable_controllers(parent, self)?;
+                }
+                fs::create_dir(&path)?;
+                (path, true)
+            }
+        };
+
+        // NOTE: built first, so that a transient cgroup is removed again if anything below fails.
+        let mut cgroup = Cgroup {
+            path,
+            procs: None,
+            transient,
+        };
+        if let Some(bytes) = self.memory_max {
+            fs::write(cgroup.path.join("memory.max"), bytes.to_string())?;
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            let value = format!("{} {}", quota.as_micros(), period.as_micros());
+            fs::write(cgroup.path.join("cpu.max"), value)?;
+        }
+        cgroup.procs = Some(
+            OpenOptions::new()
+                .write(true)
+                .open(cgroup.path.join("cgroup.procs"))?,
+        );
+        Ok(Some(cgroup))
+    }
+}
+
+/// Limits in a fresh cgroup only exist for the controllers its parent hands down.
+fn enable_controllers(parent: &Path, config: &CgroupConfig) -> io::Result<()> {
+    let enabled = fs::read_to_string(parent.join("cgroup.subtree_control"))?;
+    let enabled: Vec<&str> = enabled.split_whitespace().collect();
+    let mut wanted = Vec::new();
+    if config.memory_max.is_some() && !enabled.contains(&"memory") {
+        wanted.push("+memory");
+    }
+    if config.cpu_max.is_some() && !enabled.contains(&"cpu") {
+        wanted.push("+cpu");
+    }
+    if wanted.is_empty() {
+        return Ok(());
+    }
+    fs::write(parent.join("cgroup.subtree_control"), wanted.join(" "))
+}
+
+/// A cgroup prepared for one child.
+pub(crate) struct Cgroup {
+    path: PathBuf,
+    procs: Option<File>,
+    transient: bool,
+}
+
+impl Cgroup {
+    /// `cgroup.procs`, for the child to write itself into.
+    pub(crate) fn procs_fd(&self) -> Option<RawFd> {
+        self.procs.as_ref().map(|procs| procs.as_raw_fd())
+    }
+
+    /// The most memory the cgroup has used at once, in bytes; needs Linux 5.19 or later.
+    pub(crate) fn peak_memory(&self) -> Option<u64> {
+        let peak = fs::read_to_string(self.path.join("memory.peak")).ok()?;
+        peak.trim().parse().ok()
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // NOTE: this fails while anything the child left behind is still in there, which leaves the cgroup around
+        // rather than yanking it from under them.
+        if self.transient {
+            let _ = fs::remove_dir(&self.path);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! A bidirectional byte channel between us and the child, for children that speak a request/response protocol.
+//!
+//! Unix uses a `socketpair`, Windows a duplex named pipe. The child finds its end through the [`CHANNEL_ENV`]
+//! environment variable, which holds the descriptor number (Unix) or the handle value (Windows).
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::stream::ChildStream;
+
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[cfg(windows)]
+use std::fs::File;
+#[cfg(windows)]
+use std::os::windows::io::OwnedHandle;
+
+/// Name of the environment variable telling the child where its end of the channel is.
+pub const CHANNEL_ENV: &str = "PIPE2_CHANNEL";
+
+/// Our end of the channel.
+///
+/// Neither side of it ever blocks: whatever the child sends is pulled in by [`Child::poll`](crate::Child::poll) and
+/// buffered until it's read from here, and writes are queued and pushed out as the child makes room for them.
+/// `read` returns [`io::ErrorKind::WouldBlock`] while nothing has arrived yet, and `Ok(0)` once the child closed its
+/// end.
+pub struct Channel {
+    #[cfg(unix)]
+    stream: UnixStream,
+    #[cfg(windows)]
+    stream: File,
+    inbound: VecDeque<u8>,
+    outbound: VecDeque<u8>,
+    eof: bool,
+}
+
+impl Channel {
+    /// Creates the channel, returning our end and the one to hand to the child.
+    #[cfg(unix)]
+    pub(crate) fn pair() -> io::Result<(Self, OwnedFd)> {
+        let (ours, theirs) = UnixStream::pair()?;
+        ours.set_nonblocking(true)?;
+        Ok((Self::new(ours), theirs.into()))
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn pair() -> io::Result<(Self, OwnedHandle)> {
+        let (ours, theirs) = crate::windows_pipe_utils::duplex_pipe()?;
+        Ok((Self::new(File::from(ours)), theirs))
+    }
+
+    fn new(#[cfg(unix)] stream: UnixStream, #[cfg(windows)] stream: File) -> Self {
+        Self {
+            stream,
+            inbound: VecDeque::new(),
+            outbound: VecDeque::new(),
+            eof: false,
+        }
+    }
+
+    /// Bytes received from the child that haven't been read yet.
+    pub fn available(&self) -> usize {
+        self.inbound.len()
+    }
+
+    /// Bytes written to the channel that the child hasn't been able to take yet.
+    pub fn pending(&self) -> usize {
+        self.outbound.len()
+    }
+
+    /// Pushes out as much of the queued input as the child has room for, then pulls in whatever it sent.
+    pub(crate) fn pump(&mut self, scratchpad: &mut [u8]) -> io::Result<()> {
+        self.push()?;
+        self.pull(scratchpad)
+    }
+
+    fn push(&mut self) -> io::Result<()> {
+        while !self.outbound.is_empty() {
+            let (front, _) = self.outbound.as_slices();
+            #[cfg(unix)]
+            let written = match self.stream.write(front) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e),
+            };
+            #[cfg(windows)]
+            let written = crate::windows_pipe_utils::write_pipe(&mut self.stream, front)?;
+            if written == 0 {
+                break;
+            }
+            self.outbound.drain(..written);
+        }
+        Ok(())
+    }
+
+    fn pull(&mut self, scratchpad: &mut [u8]) -> io::Result<()> {
+        while !self.eof {
+            match self.stream.read_available(scratchpad)? {
+                None => break,
+                Some(0) => self.eof = true,
+                Some(n) => self.inbound.extend(&scratchpad[..n]),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inbound.is_empty() {
+            return if self.eof {
+                Ok(0)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            };
+        }
+        self.inbound.read(buf)
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outbound.extend(buf);
+        Ok(buf.len())
+    }
+
+    /// Only tries once: anything the child can't take right now stays queued for the next poll.
+    fn flush(&mut self) -> io::Result<()> {
+        self.push()
+    }
+}
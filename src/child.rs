@@ -0,0 +1,1543 @@
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+#[cfg(unix)]
+use nix::sys::signal::{Signal, kill};
+#[cfg(unix)]
+use nix::unistd::Pid;
+
+use crate::broadcast::{Broadcast, Subscriber};
+use crate::cancel::CancellationHandle;
+use crate::capture::Capture;
+#[cfg(unix)]
+use crate::capture::MappedFile;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::capture::Memfd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::cgroup::Cgroup;
+use crate::channel::Channel;
+use crate::clock::{Clock, SystemClock};
+use crate::control::{Command, ControlSocket};
+use crate::echo::{BrokenPipe, Echo};
+use crate::events::{EventLog, EventSink};
+#[cfg(all(feature = "file-access", target_os = "linux"))]
+use crate::file_access::{self, FileAccess};
+use crate::iter::Events;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::network::{self, Connection, NetworkMonitor};
+#[cfg(unix)]
+use crate::notify::NotifySocket;
+use crate::on_line::Abort;
+use crate::outlet::{Backpressure, DEFAULT_CHUNK_POOL, Flush, Outlet};
+use crate::probe::{Probe, Prober};
+use crate::process::{Process, ResourceUsage};
+#[cfg(unix)]
+use crate::pty::Pty;
+use crate::read_sizes::{DEFAULT_MAX_READ_BUFFER, INITIAL_READ_BUFFER, ReadSizes, Tuner};
+use crate::reason::ExitReason;
+use crate::recording::Recording;
+use crate::run_id::RunId;
+use crate::sanitize::Sanitized;
+use crate::severity::{Classifier, Counter, Severities};
+use crate::stdin::Feeder;
+use crate::stream::{ChildStream, OutputClosed, Stream, StreamState};
+use crate::trace::Trace;
+use crate::transform::{self, Pipeline};
+
+/// Everything the child wrote while it ran, along with how it exited.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Whether the child was killed for running past [`Pipe2::timeout`](crate::Pipe2::timeout).
+    pub timed_out: bool,
+    /// Whether the child was killed for using more CPU time than [`Pipe2::cpu_limit`](crate::Pipe2::cpu_limit)
+    /// allows.
+    pub cpu_limit_exceeded: bool,
+    /// Whether the child was killed for writing nothing within
+    /// [`Pipe2::first_output_within`](crate::Pipe2::first_output_within).
+    pub first_output_timed_out: bool,
+    /// Whether the child was killed for still running too long after closing its output, see
+    /// [`OutputClosed::Timeout`].
+    pub output_closed_timed_out: bool,
+    /// Whether the child was left running after closing its output, see [`OutputClosed::Detach`]; `status` is a
+    /// success then.
+    pub detached: bool,
+    /// Whether the run was stopped through a [`CancellationHandle`].
+    pub cancelled: bool,
+    /// Whether the run was stopped by a [`LineAction::Abort`](crate::LineAction::Abort).
+    pub aborted: bool,
+    /// The most memory the child's cgroup used at once, in bytes, if it was put in one with
+    /// [`Pipe2::cgroup`](crate::Pipe2::cgroup) and the kernel keeps track (Linux 5.19 and later).
+    pub peak_memory: Option<u64>,
+    /// What the child used, counted by the kernel when it was reaped; `None` on Windows, or if it was left running.
+    pub resource_usage: Option<ResourceUsage>,
+    /// How many lines each [`Pipe2::classify`](crate::Pipe2::classify) severity was found in.
+    pub severities: Severities,
+    /// How big the reads from the child's pipes were.
+    pub read_sizes: ReadSizes,
+    /// The run's ID, see [`Child::run_id`].
+    pub run_id: RunId,
+    /// The file `stdout` was captured into, with [`Pipe2::capture_to_file`](crate::Pipe2::capture_to_file); `stdout`
+    /// is left empty then, rather than have the capture read back into memory.
+    #[cfg(unix)]
+    pub stdout_file: Option<PathBuf>,
+    /// The file `stderr` was captured into, with [`Pipe2::capture_to_file`](crate::Pipe2::capture_to_file); `stderr`
+    /// is left empty then.
+    #[cfg(unix)]
+    pub stderr_file: Option<PathBuf>,
+    /// The memfd `stdout` was captured into, with [`Pipe2::capture_to_memfd`](crate::Pipe2::capture_to_memfd).
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub stdout_memfd: Option<Memfd>,
+    /// The memfd `stderr` was captured into, with [`Pipe2::capture_to_memfd`](crate::Pipe2::capture_to_memfd).
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub stderr_memfd: Option<Memfd>,
+    /// What was kept from the child with [`Pipe2::paranoid`](crate::Pipe2::paranoid).
+    pub sanitized: Option<Sanitized>,
+    /// The TCP and UDP sockets the child and its descendants were seen with, if
+    /// [`Pipe2::monitor_network`](crate::Pipe2::monitor_network) was on.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub connections: Option<Vec<Connection>>,
+    /// The files the child and its descendants were seen writing to, if
+    /// [`Pipe2::track_file_writes`](crate::Pipe2::track_file_writes) was on.
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    pub files_written: Option<Vec<PathBuf>>,
+    /// Why the child was stopped, if it was for what its `stdin` was fed from failing partway.
+    pub stdin_error: Option<String>,
+    /// Why the run ended, from all of the above.
+    pub reason: ExitReason,
+    /// Whether the run counts as a success: the child exited by itself, with one of
+    /// [`Pipe2::success_codes`](crate::Pipe2::success_codes). `status` is still the code it really exited with.
+    pub success: bool,
+}
+
+/// The parts of the builder's configuration that still matter once the child is running.
+#[derive(Clone)]
+pub(crate) struct Settings {
+    pub(crate) echo: bool,
+    /// Whether `stdout` goes on to another child's `stdin`, see [`Pipeline`](crate::Pipeline), rather than being
+    /// echoed.
+    pub(crate) piped_stdout: bool,
+    pub(crate) broken_pipe: BrokenPipe,
+    pub(crate) backpressure: Backpressure,
+    pub(crate) flush: Flush,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) cpu_limit: Option<Duration>,
+    pub(crate) first_output_within: Option<Duration>,
+    pub(crate) output_closed: OutputClosed,
+    pub(crate) until_eof: bool,
+    pub(crate) eof_cutoff: Option<Duration>,
+    pub(crate) grace: Duration,
+    pub(crate) heartbeat: Option<Duration>,
+    pub(crate) hexdump: bool,
+    pub(crate) max_line: Option<usize>,
+    pub(crate) transforms: Vec<transform::Factory>,
+    pub(crate) squash_repeats: bool,
+    pub(crate) pretty_json: bool,
+    pub(crate) classifiers: Vec<Classifier>,
+    pub(crate) label: Option<String>,
+    pub(crate) success_codes: Vec<i32>,
+    pub(crate) ready_probe: Option<Probe>,
+    pub(crate) max_read_buffer: usize,
+    pub(crate) chunk_pool: usize,
+    pub(crate) clock: Arc<dyn Clock>,
+    /// See [`Pipe2::trace_io`](crate::Pipe2::trace_io).
+    pub(crate) trace: Option<EventSink>,
+    pub(crate) correlation_id: Option<String>,
+    #[cfg(unix)]
+    pub(crate) redact_passwords: bool,
+    /// Shared by every run of the same builder, so cancelling stops restarts too.
+    pub(crate) cancellation: CancellationHandle,
+    #[cfg(unix)]
+    pub(crate) kill_signal: Signal,
+    #[cfg(windows)]
+    pub(crate) ctrl_break: bool,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) monitor_network: bool,
+    /// Whether the child runs under a [`Pipe2::syscall_policy`](crate::Pipe2::syscall_policy), which has it killed
+    /// with `SIGSYS` for breaking it.
+    #[cfg(all(feature = "seccomp", target_os = "linux"))]
+    pub(crate) syscall_policy: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            echo: true,
+            piped_stdout: false,
+            broken_pipe: BrokenPipe::default(),
+            backpressure: Backpressure::default(),
+            flush: Flush::default(),
+            timeout: None,
+            cpu_limit: None,
+            first_output_within: None,
+            output_closed: OutputClosed::default(),
+            until_eof: false,
+            eof_cutoff: None,
+            grace: Duration::from_secs(5),
+            heartbeat: None,
+            hexdump: false,
+            max_line: None,
+            transforms: Vec::new(),
+            squash_repeats: false,
+            pretty_json: false,
+            classifiers: Vec::new(),
+            label: None,
+            success_codes: vec![0],
+            ready_probe: None,
+            max_read_buffer: DEFAULT_MAX_READ_BUFFER,
+            chunk_pool: DEFAULT_CHUNK_POOL,
+            clock: Arc::new(SystemClock),
+            trace: None,
+            correlation_id: None,
+            #[cfg(unix)]
+            redact_passwords: false,
+            cancellation: CancellationHandle::default(),
+            #[cfg(unix)]
+            kill_signal: Signal::SIGTERM,
+            #[cfg(windows)]
+            ctrl_break: false,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            monitor_network: false,
+            #[cfg(all(feature = "seccomp", target_os = "linux"))]
+            syscall_policy: false,
+        }
+    }
+}
+
+/// One of the child's output streams, along with everything read from it so far.
+struct Pipe {
+    stream: Box<dyn ChildStream + Send>,
+    which: Stream,
+    state: StreamState,
+    captured: Capture,
+    /// Everything read so far, including what's been taken out of `captured`.
+    total: u64,
+    /// Rewrites what's captured and echoed, with [`Settings::transforms`] and [`Settings::max_line`].
+    transforms: Pipeline,
+    /// The last chunk, once `transforms` are through with it.
+    transformed: Vec<u8>,
+}
+
+impl Pipe {
+    fn new(
+        stream: Box<dyn ChildStream + Send>,
+        which: Stream,
+        settings: &Settings,
+        abort: &Abort,
+    ) -> Self {
+        Self {
+            stream,
+            which,
+            state: StreamState::Open,
+            captured: Capture::default(),
+            total: 0,
+            transforms: Pipeline::new(&settings.transforms, which, settings.max_line, abort),
+            transformed: Vec::new(),
+        }
+    }
+
+    /// Reads one chunk, if there's any, and captures it. Returns how many bytes were read, and what's to be echoed
+    /// of them. A stream that's at EOF, or failed, isn't read anymore.
+    fn drain<'a>(
+        &'a mut self,
+        scratchpad: &'a mut [u8],
+        trace: Option<&Trace>,
+    ) -> io::Result<(usize, &'a [u8])> {
+        if self.state != StreamState::Open {
+            return Ok((0, &[]));
+        }
+        let result = self.stream.read_available(scratchpad);
+        if let Some(trace) = trace {
+            let stream = match self.which {
+                Stream::Stdout => "stdout",
+                Stream::Stderr => "stderr",
+            };
+            match &result {
+                Ok(Some(0)) => trace.record(format_args!("{stream} eof, open -> eof")),
+                Ok(Some(n)) => {
+                    trace.record(format_args!("{stream} read {n} of {}", scratchpad.len()))
+                }
+                Ok(None) => trace.record(format_args!("{stream} would block")),
+                Err(e) => trace.record(format_args!("{stream} error {e}, open -> error")),
+            }
+        }
+        let n = match result {
+            Ok(Some(0)) => {
+                self.state = StreamState::Eof;
+                0
+            }
+            Ok(n) => n.unwrap_or(0),
+            Err(e) => {
+                self.state = StreamState::Error;
+                return Err(e);
+            }
+        };
+        self.total += n as u64;
+        let chunk = if self.transforms.is_empty() {
+            &scratchpad[..n]
+        } else {
+            self.transformed.clear();
+            self.transforms
+                .transform(&scratchpad[..n], &mut self.transformed);
+            &self.transformed
+        };
+        self.captured.extend(chunk)?;
+        Ok((n, chunk))
+    }
+
+    /// Lets go of what the transforms held back, like the end of a line that was cut short, once the stream is done.
+    /// Returns what's to be echoed.
+    fn finish(&mut self) -> io::Result<&[u8]> {
+        self.transformed.clear();
+        self.transforms.finish(&mut self.transformed);
+        self.captured.extend(&self.transformed)?;
+        Ok(&self.transformed)
+    }
+}
+
+/// How much of what's left in the pipes once the child exits is read at most; whatever the child started may still
+/// have them open, and keep writing.
+const LEFTOVER_LIMIT: usize = 16 * 1024 * 1024;
+
+/// How long a child has to still be running after closing its output for [`OutputClosed::Detach`] to leave it; a
+/// child that's exiting closes its output too, a moment before it can be seen to have exited.
+const DETACH_DELAY: Duration = Duration::from_millis(100);
+
+/// Where [`Child::kill`] is at.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stopping {
+    No,
+    /// Asked nicely, and will be forcefully killed at the deadline if it's still around.
+    Graceful(Instant),
+    Forced,
+}
+
+/// A running child whose pipes are drained as it executes.
+pub struct Child {
+    child: Process,
+    stdout: Option<Pipe>,
+    stderr: Pipe,
+    /// How `stdout` and `stderr` are relayed, with [`Settings::echo`] on.
+    echo: [Echo; 2],
+    /// Our own `stdout` and `stderr`, for the echo to go to.
+    outlet: Outlet,
+    /// Whether our own `stdout` and `stderr` were closed, see [`Settings::broken_pipe`].
+    echo_closed: [bool; 2],
+    counters: [Counter; 2],
+    severities: Severities,
+    settings: Settings,
+    channel: Option<Channel>,
+    /// See [`Pipe2::control_socket`](crate::Pipe2::control_socket).
+    control: Option<ControlSocket>,
+    /// What's left to write to the child's `stdin`, if it's fed by us; dropped to close it.
+    stdin: Option<Feeder>,
+    /// See [`Pipe2::notify_ready`](crate::Pipe2::notify_ready); dropped once the child is ready.
+    #[cfg(unix)]
+    notify: Option<NotifySocket>,
+    /// See [`Pipe2::pty`](crate::Pipe2::pty).
+    #[cfg(unix)]
+    pty: Option<Pty>,
+    /// See [`Pipe2::ready_on_port`](crate::Pipe2::ready_on_port); dropped once the child is ready.
+    probe: Option<Prober>,
+    /// Whether the child said it's ready, see [`Child::wait_ready`].
+    ready: bool,
+    paused: bool,
+    started: Instant,
+    /// When the child last wrote anything, or the last heartbeat went out.
+    last_output: Instant,
+    last_total: u64,
+    timed_out: bool,
+    /// When the child's CPU time was last looked at, for the CPU limit.
+    last_cpu_check: Instant,
+    /// See [`Pipe2::monitor_network`](crate::Pipe2::monitor_network).
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    network: Option<NetworkMonitor>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    last_network_sample: Instant,
+    /// See [`Pipe2::track_file_writes`](crate::Pipe2::track_file_writes).
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    files: Option<FileAccess>,
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    last_file_sample: Instant,
+    cpu_limit_exceeded: bool,
+    /// See [`Output::stdin_error`].
+    stdin_error: Option<String>,
+    first_output_timed_out: bool,
+    /// When both streams were found closed, if they were while the child was running.
+    output_closed: Option<Instant>,
+    output_closed_timed_out: bool,
+    detached: bool,
+    cancelled: bool,
+    /// Set by the [`Settings::transforms`] of this run alone.
+    abort: Abort,
+    aborted: bool,
+    stopping: Stopping,
+    /// What the pipes are read into, grown by `tuner` for children that keep filling it.
+    scratchpad: Vec<u8>,
+    tuner: Tuner,
+    read_sizes: ReadSizes,
+    /// Buffers handed back through [`Child::recycle`], for the next takes to go into.
+    pool: Vec<Vec<u8>>,
+    broadcast: Broadcast,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    cgroup: Option<Cgroup>,
+    events: Option<EventLog>,
+    trace: Option<Trace>,
+    run_id: RunId,
+    /// See [`Pipe2::paranoid`](crate::Pipe2::paranoid).
+    sanitized: Option<Sanitized>,
+    /// See [`Pipe2::record_session`](crate::Pipe2::record_session).
+    recording: Option<Recording>,
+    /// Whether anything came on `stdout` and `stderr` yet, for the `first_output` event.
+    seen_output: [bool; 2],
+    exited: bool,
+    /// When the child exited, for [`Settings::eof_cutoff`].
+    exited_at: Option<Instant>,
+    /// Whether the wait for EOF after the exit was cut off.
+    eof_cut_off: bool,
+}
+
+impl Child {
+    pub(crate) fn new(
+        child: Process,
+        stdout: Option<Box<dyn ChildStream + Send>>,
+        stderr: Box<dyn ChildStream + Send>,
+        settings: Settings,
+        channel: Option<Channel>,
+    ) -> Self {
+        let probe = settings.ready_probe.clone().map(Prober::start);
+        let tuner = Tuner::new(settings.max_read_buffer);
+        let now = settings.clock.now();
+        let run_id = RunId::generate();
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let network = settings
+            .monitor_network
+            .then(|| NetworkMonitor::new(child.id()));
+        let trace = settings
+            .trace
+            .clone()
+            .map(|sink| Trace::new(sink, settings.clock.clone(), child.id(), run_id));
+        let abort = Abort::default();
+        Self {
+            child,
+            stdout: stdout.map(|stdout| Pipe::new(stdout, Stream::Stdout, &settings, &abort)),
+            stderr: Pipe::new(stderr, Stream::Stderr, &settings, &abort),
+            echo: [Echo::new(&settings, true), Echo::new(&settings, false)],
+            outlet: Outlet::new(&settings),
+            echo_closed: [false; 2],
+            counters: Default::default(),
+            severities: Severities::default(),
+            settings,
+            channel,
+            control: None,
+            stdin: None,
+            #[cfg(unix)]
+            notify: None,
+            #[cfg(unix)]
+            pty: None,
+            probe,
+            ready: false,
+            paused: false,
+            started: now,
+            last_output: now,
+            last_total: 0,
+            timed_out: false,
+            last_cpu_check: now,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            network,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            last_network_sample: now,
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            files: None,
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            last_file_sample: now,
+            cpu_limit_exceeded: false,
+            stdin_error: None,
+            first_output_timed_out: false,
+            output_closed: None,
+            output_closed_timed_out: false,
+            detached: false,
+            cancelled: false,
+            abort,
+            aborted: false,
+            stopping: Stopping::No,
+            scratchpad: vec![0u8; INITIAL_READ_BUFFER],
+            tuner,
+            read_sizes: ReadSizes::default(),
+            pool: Vec::new(),
+            broadcast: Broadcast::default(),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            cgroup: None,
+            events: None,
+            trace,
+            run_id,
+            sanitized: None,
+            recording: None,
+            seen_output: [false; 2],
+            exited: false,
+            exited_at: None,
+            eof_cut_off: false,
+        }
+    }
+
+    /// Starts logging events, beginning with the spawn itself.
+    pub(crate) fn with_events(mut self, sink: Option<EventSink>) -> Self {
+        self.events = sink.map(|sink| {
+            EventLog::new(
+                sink,
+                self.settings.clock.clone(),
+                self.child.id(),
+                self.settings.label.clone(),
+                self.run_id,
+                self.settings.correlation_id.clone(),
+            )
+        });
+        self
+    }
+
+    /// Keeps writing the child's `stdin` for it, if it's fed from a [`StdinSource`](crate::stdin::StdinSource).
+    pub(crate) fn with_stdin(mut self, stdin: Option<Feeder>) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Waits for `READY=1` on `notify`, if there's one.
+    #[cfg(unix)]
+    pub(crate) fn with_notify(mut self, notify: Option<NotifySocket>) -> Self {
+        self.notify = notify;
+        self
+    }
+
+    /// Keeps our end of the child's pseudo-terminal, if it has one, for [`Child::resize_pty`].
+    #[cfg(unix)]
+    pub(crate) fn with_pty(mut self, pty: Option<Pty>) -> Self {
+        self.pty = pty;
+        self
+    }
+
+    /// Starts recording the session to `sink`, if there's one, once [`Child::with_pty`] said how big the terminal is.
+    pub(crate) fn with_recording(mut self, sink: Option<EventSink>) -> Self {
+        #[cfg(unix)]
+        let size = match &self.pty {
+            Some(pty) => Some(pty.size()),
+            None => crate::pty::terminal_size(),
+        };
+        #[cfg(not(unix))]
+        let size = None;
+        self.recording = sink.map(|sink| Recording::start(sink, size));
+        self
+    }
+
+    /// Captures into `files` (for `stdout` and `stderr`) instead of memory, where there's one.
+    #[cfg(unix)]
+    pub(crate) fn with_capture_files(mut self, files: [Option<MappedFile>; 2]) -> Self {
+        let [stdout, stderr] = files;
+        if let (Some(pipe), Some(file)) = (&mut self.stdout, stdout) {
+            pipe.captured = Capture::Mapped(file);
+        }
+        if let Some(file) = stderr {
+            self.stderr.captured = Capture::Mapped(file);
+        }
+        self
+    }
+
+    /// Serves commands for the child on `control`, if there's one.
+    pub(crate) fn with_control(mut self, control: Option<ControlSocket>) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// A handle that stops this child from another thread; the same one [`Pipe2::cancellation_handle`] hands out.
+    ///
+    /// [`Pipe2::cancellation_handle`]: crate::Pipe2::cancellation_handle
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.settings.cancellation.clone()
+    }
+
+    /// The child's process ID, or 0 for a WASI module (see [`Pipe2::wasi`](crate::Pipe2::wasi)), which has none.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn emit(&self, event: &str, fields: serde_json::Value) {
+        if let Some(trace) = &self.trace {
+            trace.record(format_args!("event {event} {fields}"));
+        }
+        if let Some(events) = &self.events {
+            events.emit(event, fields);
+        }
+    }
+
+    /// Logs a chunk read from stream `index` (0 for `stdout`, 1 for `stderr`).
+    fn emit_chunk(&mut self, index: usize, n: usize) {
+        if n == 0 || self.events.is_none() {
+            return;
+        }
+        let stream = ["stdout", "stderr"][index];
+        if !self.seen_output[index] {
+            self.seen_output[index] = true;
+            self.emit("first_output", json!({ "stream": stream }));
+        }
+        self.emit("chunk", json!({ "stream": stream, "bytes": n }));
+    }
+
+    /// Keeps the child's cgroup around for as long as the child, to report on it and remove it after.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn with_cgroup(mut self, cgroup: Option<Cgroup>) -> Self {
+        self.cgroup = cgroup;
+        self
+    }
+
+    /// Has `files` follow the child, for [`Output::files_written`].
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    pub(crate) fn with_file_access(mut self, files: Option<FileAccess>) -> Self {
+        self.files = files.map(|mut files| {
+            files.follow(self.child.id());
+            files
+        });
+        self
+    }
+
+    /// Notes what was kept from the child, for [`Output::sanitized`].
+    pub(crate) fn with_sanitized(mut self, sanitized: Sanitized) -> Self {
+        if !sanitized.is_empty() {
+            self.emit(
+                "sanitized",
+                json!({
+                    "env": sanitized.env,
+                    "path_entries": sanitized.path_entries,
+                    "fds": sanitized.fds,
+                }),
+            );
+        }
+        self.sanitized = Some(sanitized);
+        self
+    }
+
+    /// Our end of the channel requested with [`Pipe2::channel`](crate::Pipe2::channel).
+    pub fn channel(&mut self) -> Option<&mut Channel> {
+        self.channel.as_mut()
+    }
+
+    /// Suspends the child (`SIGSTOP` on Unix, `NtSuspendProcess` on Windows).
+    ///
+    /// Polling carries on as usual while it's paused, so whatever it wrote right before stopping still gets drained.
+    pub fn pause(&mut self) -> io::Result<()> {
+        self.os_process()?;
+        #[cfg(unix)]
+        kill(self.pid(), Signal::SIGSTOP)?;
+
+        #[cfg(windows)]
+        crate::windows_process_utils::suspend_process(&self.child)?;
+
+        self.paused = true;
+        self.emit("paused", json!({}));
+        Ok(())
+    }
+
+    /// Lets a child stopped by [`Child::pause`] carry on (`SIGCONT` on Unix, `NtResumeProcess` on Windows).
+    pub fn resume(&mut self) -> io::Result<()> {
+        self.os_process()?;
+        #[cfg(unix)]
+        kill(self.pid(), Signal::SIGCONT)?;
+
+        #[cfg(windows)]
+        crate::windows_process_utils::resume_process(&self.child)?;
+
+        self.paused = false;
+        self.emit("resumed", json!({}));
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Sends `signal` to the child and nothing more, for passing on a signal that was meant for it; unlike
+    /// [`Child::kill`], there's no `SIGKILL` to follow, and the child may well carry on.
+    #[cfg(unix)]
+    pub fn send_signal(&mut self, signal: Signal) -> io::Result<()> {
+        if self.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        self.os_process()?;
+        kill(self.pid(), signal)?;
+        self.emit("signal", json!({ "signal": signal.as_str() }));
+        Ok(())
+    }
+
+    /// Changes the size of the child's pseudo-terminal (see [`Pipe2::pty`](crate::Pipe2::pty)) to `rows` and `cols`,
+    /// which has the kernel send the child `SIGWINCH` so that a full-screen program redraws itself; for following the
+    /// size of our own terminal, see [`terminal_size`](crate::terminal_size).
+    #[cfg(unix)]
+    pub fn resize_pty(&mut self, rows: u16, cols: u16) -> io::Result<()> {
+        let pty = self.pty.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the child has no pseudo-terminal",
+            )
+        })?;
+        pty.resize(rows, cols)?;
+        if let Some(recording) = &mut self.recording {
+            recording.resize(rows, cols);
+        }
+        self.emit("resized", json!({ "rows": rows, "cols": cols }));
+        Ok(())
+    }
+
+    /// Asks the child to stop.
+    ///
+    /// On Unix, this sends the [`Pipe2::kill_signal`](crate::Pipe2::kill_signal) (`SIGTERM` unless configured
+    /// otherwise), and follows up with `SIGKILL` if the child is still running once the
+    /// [`Pipe2::grace_period`](crate::Pipe2::grace_period) is over. On Windows, the child is terminated right away,
+    /// unless [`Pipe2::ctrl_break`](crate::Pipe2::ctrl_break) was asked for, in which case it gets a `CTRL_BREAK_EVENT`
+    /// and the same grace period first.
+    ///
+    /// Doesn't wait for the child to go; keep polling, or [`Child::wait`], to drain whatever it writes on the way out.
+    pub fn kill(&mut self) -> io::Result<()> {
+        if self.child.try_wait()?.is_some() || self.stopping != Stopping::No {
+            return Ok(());
+        }
+
+        // NOTE: a WASI module has nothing to be asked with, and is interrupted right away.
+        if !self.child.is_os_process() {
+            self.child.kill()?;
+            self.emit("signal", json!({ "signal": "interrupt" }));
+            self.stopping = Stopping::Forced;
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        self.signal(self.settings.kill_signal)?;
+
+        #[cfg(windows)]
+        {
+            use crate::windows_process_utils::send_ctrl_break;
+            // NOTE: if the event can't be delivered (no console shared with the child, typically), there's nothing
+            // to wait for.
+            if self.settings.ctrl_break && send_ctrl_break(self.child.id()).is_ok() {
+                self.emit("signal", json!({ "signal": "CTRL_BREAK_EVENT" }));
+                if self.paused {
+                    self.resume()?;
+                }
+                self.stopping = Stopping::Graceful(self.settings.clock.now() + self.settings.grace);
+            } else {
+                self.child.kill()?;
+                self.emit("signal", json!({ "signal": "TerminateProcess" }));
+                self.stopping = Stopping::Forced;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends `signal` to the child, following up with `SIGKILL` once the grace period is over.
+    #[cfg(unix)]
+    fn signal(&mut self, signal: Signal) -> io::Result<()> {
+        self.os_process()?;
+        kill(self.pid(), signal)?;
+        self.emit("signal", json!({ "signal": signal.as_str() }));
+        // NOTE: a stopped child would only see the signal once continued.
+        if self.paused {
+            self.resume()?;
+        }
+        self.stopping = Stopping::Graceful(self.settings.clock.now() + self.settings.grace);
+        Ok(())
+    }
+
+    /// Takes what's been captured from `stdout` so far, so that a long-running child's output doesn't pile up until
+    /// [`Child::wait`]; that only returns what was captured after the last take.
+    pub fn take_stdout(&mut self) -> Vec<u8> {
+        let spare = self.pool.pop().unwrap_or_default();
+        match &mut self.stdout {
+            Some(stdout) => stdout.captured.take_into(spare),
+            None => Vec::new(),
+        }
+    }
+
+    /// Like [`Child::take_stdout`], for `stderr`.
+    pub fn take_stderr(&mut self) -> Vec<u8> {
+        let spare = self.pool.pop().unwrap_or_default();
+        self.stderr.captured.take_into(spare)
+    }
+
+    /// Hands back a buffer from [`Child::take_stdout`] or [`Child::take_stderr`] once it's been dealt with, for what's
+    /// captured next to go into instead of a fresh allocation. Up to [`Pipe2::chunk_pool`](crate::Pipe2::chunk_pool)
+    /// of them are kept.
+    pub fn recycle(&mut self, mut buffer: Vec<u8>) {
+        if self.pool.len() < self.settings.chunk_pool {
+            buffer.clear();
+            self.pool.push(buffer);
+        }
+    }
+
+    /// Starts handing everything the child writes from now on, and how it exits, to a [`Subscriber`] as well, to be
+    /// dealt with on a thread of its own. There can be any number of them, each holding up to `capacity` bytes that
+    /// weren't received yet; past that, a subscriber that fell behind loses its oldest chunks rather than holding up
+    /// the child, see [`Subscriber::missed`]. The echo and the capture go on as before.
+    pub fn subscribe(&mut self, capacity: usize) -> Subscriber {
+        self.broadcast.subscribe(capacity, self.run_id)
+    }
+
+    /// The ID this run was given at the spawn, random and unique, which the event log, the trace, the control socket's
+    /// `status`, the subscribers and the [`Output`] all carry, to tie the run to whatever else it's logged by.
+    pub fn run_id(&self) -> RunId {
+        self.run_id
+    }
+
+    /// How many bytes have been read from `stdout` and `stderr` so far, counting what's been taken since.
+    pub fn bytes_read(&self) -> (u64, u64) {
+        let stdout = self.stdout.as_ref().map_or(0, |stdout| stdout.total);
+        (stdout, self.stderr.total)
+    }
+
+    /// Prints a status line to `stderr` if the child has been quiet for the
+    /// [`Pipe2::heartbeat`](crate::Pipe2::heartbeat) interval.
+    fn heartbeat(&mut self) -> io::Result<()> {
+        let (stdout, stderr) = self.bytes_read();
+        if stdout + stderr != self.last_total {
+            self.last_total = stdout + stderr;
+            self.last_output = self.settings.clock.now();
+            return Ok(());
+        }
+        let Some(interval) = self.settings.heartbeat else {
+            return Ok(());
+        };
+        if self.since(self.last_output) >= interval && !self.exited {
+            self.last_output = self.settings.clock.now();
+            let label = match &self.settings.label {
+                Some(label) => format!(" [{label}]"),
+                None => String::new(),
+            };
+            writeln!(
+                io::stderr(),
+                "pipe2:{label} still running after {}, {} bytes of output so far",
+                format_elapsed(self.since(self.started)),
+                self.last_total
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads one chunk from stream `index` (0 for `stdout`, 1 for `stderr`), if there's any, and relays it. Returns
+    /// how many bytes were read.
+    fn drain(&mut self, index: usize) -> io::Result<usize> {
+        let echoing = self.echoing(index);
+        let pipe = match (index, &mut self.stdout) {
+            (0, Some(stdout)) => stdout,
+            (0, None) => return Ok(0),
+            _ => &mut self.stderr,
+        };
+        let (n, chunk) = pipe.drain(&mut self.scratchpad[..], self.trace.as_ref())?;
+        let echoed = if echoing && !chunk.is_empty() {
+            let mut echo = Vec::new();
+            self.echo[index]
+                .write(&mut echo, chunk)
+                .and_then(|()| self.outlet.send(index, &echo))
+        } else {
+            Ok(())
+        };
+        if let Some(stdin) = &mut self.stdin {
+            stdin.observe(index, chunk);
+        }
+        self.broadcast
+            .send([Stream::Stdout, Stream::Stderr][index], chunk);
+        if let Some(recording) = &mut self.recording {
+            recording.output(chunk);
+        }
+        if !self.settings.classifiers.is_empty() {
+            self.counters[index].feed(&self.settings.classifiers, chunk, &mut self.severities);
+        }
+        self.echoed(index, echoed)?;
+        self.emit_chunk(index, n);
+        if n > 0 {
+            self.read_sizes.record(n);
+            if let Some(size) = self.tuner.read(n, self.scratchpad.len()) {
+                self.scratchpad.resize(size, 0);
+                self.emit("read_buffer_grown", json!({ "bytes": size }));
+            }
+        }
+        Ok(n)
+    }
+
+    /// Whether every stream we read is at EOF (or failed).
+    fn streams_closed(&self) -> bool {
+        self.stdout
+            .as_ref()
+            .is_none_or(|stdout| stdout.state != StreamState::Open)
+            && self.stderr.state != StreamState::Open
+    }
+
+    /// Reads whatever the child left in stream `index` when it exited, up to [`LEFTOVER_LIMIT`]; stops early at EOF,
+    /// or once the pipe is empty, since something the child started may still have it open.
+    fn drain_leftover(&mut self, index: usize) -> io::Result<()> {
+        let mut leftover = 0;
+        while leftover < LEFTOVER_LIMIT {
+            match self.drain(index)? {
+                0 => break,
+                n => leftover += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether stream `index` is still relayed to ours.
+    fn echoing(&self, index: usize) -> bool {
+        self.settings.echo
+            && !self.echo_closed[index]
+            && !(index == 0 && self.settings.piped_stdout)
+    }
+
+    /// Sees to how echoing on stream `index` went: if ours was closed, that stream isn't echoed anymore, and the
+    /// [`Pipe2::on_broken_pipe`](crate::Pipe2::on_broken_pipe) policy decides what happens to the run.
+    fn echoed(&mut self, index: usize, result: io::Result<()>) -> io::Result<()> {
+        let Err(e) = result else {
+            return Ok(());
+        };
+        if e.kind() != io::ErrorKind::BrokenPipe || self.settings.broken_pipe == BrokenPipe::Error {
+            return Err(e);
+        }
+        self.echo_closed[index] = true;
+        let stream = ["stdout", "stderr"][index];
+        self.emit("echo_closed", json!({ "stream": stream }));
+        if self.settings.broken_pipe == BrokenPipe::Kill {
+            self.broken_pipe()?;
+        }
+        Ok(())
+    }
+
+    /// Stops the child because where its output was going is gone, as if it had been writing there itself.
+    fn broken_pipe(&mut self) -> io::Result<()> {
+        #[cfg(unix)]
+        if self.child.try_wait()?.is_none() && self.stopping == Stopping::No {
+            self.signal(Signal::SIGPIPE)?;
+        }
+
+        #[cfg(windows)]
+        self.kill()?;
+
+        Ok(())
+    }
+
+    /// Relays what's left of stream `index` once the child is gone, like a last line that was never ended.
+    fn finish(&mut self, index: usize) -> io::Result<()> {
+        let echoing = self.echoing(index);
+        let pipe = match (index, &mut self.stdout) {
+            (0, Some(stdout)) => stdout,
+            (0, None) => return Ok(()),
+            _ => &mut self.stderr,
+        };
+        let rest = pipe.finish()?;
+        if let Some(recording) = &mut self.recording {
+            recording.output(rest);
+        }
+        self.broadcast
+            .send([Stream::Stdout, Stream::Stderr][index], rest);
+        let echoed = if echoing {
+            let mut echo = Vec::new();
+            self.echo[index]
+                .write(&mut echo, rest)
+                .and_then(|()| self.echo[index].finish(&mut echo))
+                .and_then(|()| self.outlet.send(index, &echo))
+        } else {
+            Ok(())
+        };
+        let classifiers = &self.settings.classifiers;
+        self.counters[index].feed(classifiers, rest, &mut self.severities);
+        self.counters[index].finish(classifiers, &mut self.severities);
+        self.echoed(index, echoed)
+    }
+
+    /// Wraps up once the child has exited: finishes both streams, and waits for the echo to be written out.
+    pub(crate) fn close(&mut self) -> io::Result<()> {
+        self.finish(0)?;
+        self.finish(1)?;
+        let status = self.child.try_wait()?;
+        self.broadcast.close(status);
+        self.close_echo()
+    }
+
+    /// Waits for the echo to be written out, once the streams are finished, and says how much of it was dropped.
+    fn close_echo(&mut self) -> io::Result<()> {
+        self.outlet.close();
+        for index in 0..2 {
+            if self.echoing(index) {
+                let checked = self.outlet.check(index);
+                self.echoed(index, checked)?;
+            }
+            let dropped = self.outlet.dropped(index);
+            if dropped > 0 {
+                let stream = ["stdout", "stderr"][index];
+                self.emit(
+                    "echo_dropped",
+                    json!({ "stream": stream, "bytes": dropped }),
+                );
+                writeln!(
+                    io::stderr(),
+                    "pipe2: dropped {dropped} bytes of {stream} from the echo, it couldn't keep up"
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// How long it's been since `instant`, going by [`Pipe2::clock`](crate::Pipe2::clock).
+    fn since(&self, instant: Instant) -> Duration {
+        self.settings.clock.now().saturating_duration_since(instant)
+    }
+
+    /// Where the time comes from, see [`Pipe2::clock`](crate::Pipe2::clock).
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        &*self.settings.clock
+    }
+
+    /// What stops this run when a [`LineAction::Abort`](crate::LineAction::Abort) comes up, see [`Output::aborted`].
+    pub(crate) fn abort(&self) -> &Abort {
+        &self.abort
+    }
+
+    /// How big the reads from the child's pipes have been so far.
+    pub fn read_sizes(&self) -> &ReadSizes {
+        &self.read_sizes
+    }
+
+    /// How many lines of each severity have been seen so far, see [`Pipe2::classify`](crate::Pipe2::classify).
+    pub fn severities(&self) -> Severities {
+        self.severities
+    }
+
+    /// Fails for a WASI module, which has no process of its own to signal or suspend.
+    fn os_process(&self) -> io::Result<()> {
+        if self.child.is_os_process() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "a WASI module can't be signalled or suspended",
+        ))
+    }
+
+    #[cfg(unix)]
+    fn pid(&self) -> Pid {
+        Pid::from_raw(self.child.id() as i32)
+    }
+
+    /// Reads whatever is currently available on the pipes, then checks whether the child has exited.
+    ///
+    /// Never blocks, so it can be interleaved with other work; [`Child::wait`] calls it in a loop.
+    pub fn poll(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.detached {
+            return Ok(Some(success()));
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.poll();
+        }
+
+        self.drain(0)?;
+        self.drain(1)?;
+        for index in 0..2 {
+            if self.echoing(index) {
+                let checked = self.outlet.check(index);
+                self.echoed(index, checked)?;
+            }
+        }
+
+        self.heartbeat()?;
+
+        #[cfg(unix)]
+        if let Some(pty) = &mut self.pty {
+            // NOTE: what was typed goes with how the terminal was when it was typed, not once the child has read it.
+            let hidden =
+                self.settings.redact_passwords && self.recording.is_some() && !pty.echoing();
+            let typed = pty.pump(&mut self.scratchpad)?;
+            if let Some(recording) = &mut self.recording
+                && !typed.is_empty()
+            {
+                recording.input(typed, hidden);
+            }
+        }
+
+        if let Some(channel) = &mut self.channel {
+            channel.pump(&mut self.scratchpad[..])?;
+        }
+
+        #[cfg(unix)]
+        if let Some(notify) = &mut self.notify
+            && notify.ready()?
+        {
+            self.notify = None;
+            self.became_ready("notify");
+        }
+        if let Some(probe) = &self.probe
+            && probe.ready()
+        {
+            let via = probe.probe().name();
+            self.probe = None;
+            self.became_ready(via);
+        }
+
+        if let Some(mut control) = self.control.take() {
+            let served = control.serve(|command| self.control(command));
+            self.control = Some(control);
+            served?;
+        }
+
+        if let Some(stdin) = &mut self.stdin
+            && stdin.pump()?
+        {
+            let bytes = stdin.written();
+            let failure = stdin.take_failure();
+            self.stdin = None;
+            match failure {
+                Some(e) if !self.exited => {
+                    writeln!(io::stderr(), "pipe2: {e}, stopping the child")?;
+                    self.emit(
+                        "stdin_failed",
+                        json!({ "bytes": bytes, "error": e.to_string() }),
+                    );
+                    self.stdin_error = Some(e.to_string());
+                    self.kill()?;
+                }
+                _ => self.emit("stdin_closed", json!({ "bytes": bytes })),
+            }
+        }
+
+        if !self.cancelled && !self.exited && self.settings.cancellation.is_cancelled() {
+            self.cancelled = true;
+            self.emit("cancelled", json!({}));
+            self.kill()?;
+        }
+        if !self.aborted && !self.exited && self.abort.is_aborted() {
+            self.aborted = true;
+            self.emit("aborted", json!({}));
+            self.kill()?;
+        }
+
+        if self.output_closed.is_none() && !self.exited && self.streams_closed() {
+            self.output_closed = Some(self.settings.clock.now());
+            self.emit("output_closed", json!({}));
+        }
+        if let Some(closed) = self.output_closed
+            && !self.exited
+        {
+            match self.settings.output_closed {
+                OutputClosed::Detach
+                    if self.since(closed) >= DETACH_DELAY && self.child.try_wait()?.is_none() =>
+                {
+                    self.detached = true;
+                    self.exited = true;
+                    self.emit("detached", json!({}));
+                    return Ok(Some(success()));
+                }
+                OutputClosed::Timeout(timeout)
+                    if !self.output_closed_timed_out && self.since(closed) >= timeout =>
+                {
+                    self.output_closed_timed_out = true;
+                    self.emit(
+                        "output_closed_timeout",
+                        json!({ "after": timeout.as_secs_f64() }),
+                    );
+                    self.kill()?;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(timeout) = self.settings.timeout
+            && !self.timed_out
+            && self.since(self.started) >= timeout
+        {
+            self.timed_out = true;
+            self.emit("timeout", json!({ "after": timeout.as_secs_f64() }));
+            self.kill()?;
+        }
+
+        if let Some(within) = self.settings.first_output_within
+            && !self.first_output_timed_out
+            && !self.exited
+            && self.bytes_read() == (0, 0)
+            && self.since(self.started) >= within
+        {
+            self.first_output_timed_out = true;
+            self.emit(
+                "first_output_timeout",
+                json!({ "after": within.as_secs_f64() }),
+            );
+            self.kill()?;
+        }
+
+        // NOTE: reading the CPU time means a syscall or two (and a file read on Linux), so not on every poll.
+        if let Some(limit) = self.settings.cpu_limit
+            && !self.cpu_limit_exceeded
+            && !self.exited
+            && self.since(self.last_cpu_check) >= Duration::from_millis(100)
+        {
+            self.last_cpu_check = self.settings.clock.now();
+            if let Some(used) = self.child.cpu_time()
+                && used >= limit
+            {
+                self.cpu_limit_exceeded = true;
+                self.emit(
+                    "cpu_limit",
+                    json!({ "limit": limit.as_secs_f64(), "used": used.as_secs_f64() }),
+                );
+                self.kill()?;
+            }
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if !self.exited && self.since(self.last_network_sample) >= network::SAMPLE_EVERY {
+            self.last_network_sample = self.settings.clock.now();
+            let new = match &mut self.network {
+                Some(network) => network.sample(),
+                None => Vec::new(),
+            };
+            for connection in new {
+                self.emit(
+                    "connection",
+                    json!({
+                        "protocol": connection.protocol.as_str(),
+                        "local": connection.local.to_string(),
+                        "remote": connection.remote.map(|remote| remote.to_string()),
+                    }),
+                );
+            }
+        }
+
+        #[cfg(all(feature = "file-access", target_os = "linux"))]
+        if !self.exited && self.since(self.last_file_sample) >= file_access::SAMPLE_EVERY {
+            self.last_file_sample = self.settings.clock.now();
+            self.sample_files();
+        }
+
+        if let Stopping::Graceful(deadline) = self.stopping
+            && self.settings.clock.now() >= deadline
+            && self.child.try_wait()?.is_none()
+        {
+            self.child.kill()?;
+            #[cfg(unix)]
+            self.emit("signal", json!({ "signal": "SIGKILL" }));
+            #[cfg(windows)]
+            self.emit("signal", json!({ "signal": "TerminateProcess" }));
+            self.stopping = Stopping::Forced;
+        }
+
+        let status = self.child.try_wait()?;
+        if let Some(status) = status
+            && !self.exited
+        {
+            // NOTE: the child may well have written more since the pipes were drained above, right before exiting.
+            self.drain_leftover(0)?;
+            self.drain_leftover(1)?;
+            // NOTE: fanotify still has whatever was closed as the child exited.
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            self.sample_files();
+            #[cfg(unix)]
+            if let Some(pty) = &mut self.pty {
+                pty.stop_passthrough();
+            }
+            self.exited = true;
+            self.exited_at = Some(self.settings.clock.now());
+            self.emit_exit(status);
+        }
+
+        // NOTE: whatever the child started may still have the pipes open, and keep writing to them.
+        if let Some(exited_at) = self.exited_at
+            && self.settings.until_eof
+            && !self.eof_cut_off
+            && !self.streams_closed()
+        {
+            match self.settings.eof_cutoff {
+                Some(cutoff) if self.since(exited_at) >= cutoff => {
+                    self.eof_cut_off = true;
+                    self.emit("eof_cutoff", json!({ "after": cutoff.as_secs_f64() }));
+                }
+                _ => return Ok(None),
+            }
+        }
+        Ok(status)
+    }
+
+    /// Carries out a command that came in on the control socket, and answers it.
+    fn control(&mut self, command: Command) -> io::Result<String> {
+        match command {
+            Command::Status => {
+                let (stdout, stderr) = self.bytes_read();
+                Ok(json!({
+                    "pid": self.child.id(),
+                    "run_id": self.run_id.to_string(),
+                    "correlation_id": self.settings.correlation_id,
+                    "running": !self.exited,
+                    "paused": self.paused,
+                    "elapsed": self.since(self.started).as_secs_f64(),
+                    "stdout_bytes": stdout,
+                    "stderr_bytes": stderr,
+                })
+                .to_string())
+            }
+            Command::Stop => {
+                self.emit("control", json!({ "command": "stop" }));
+                self.kill()?;
+                Ok("ok".to_owned())
+            }
+            Command::Kill => {
+                self.emit("control", json!({ "command": "kill" }));
+                if self.child.try_wait()?.is_none() {
+                    self.child.kill()?;
+                    #[cfg(unix)]
+                    self.emit("signal", json!({ "signal": "SIGKILL" }));
+                    #[cfg(windows)]
+                    self.emit("signal", json!({ "signal": "TerminateProcess" }));
+                    self.stopping = Stopping::Forced;
+                }
+                Ok("ok".to_owned())
+            }
+        }
+    }
+
+    /// When more than one applies, a cancellation comes first, then the timeouts, then the limits.
+    fn reason(&self, status: ExitStatus) -> ExitReason {
+        if self.cancelled {
+            ExitReason::Cancelled
+        } else if self.aborted {
+            ExitReason::Aborted
+        } else if self.timed_out || self.output_closed_timed_out {
+            ExitReason::TimedOut
+        } else if self.first_output_timed_out {
+            ExitReason::IdleTimeout
+        } else if self.cpu_limit_exceeded {
+            ExitReason::ResourceLimit
+        } else if self.stdin_error.is_some() {
+            ExitReason::InputFailed
+        } else if self.policy_violated(status) {
+            ExitReason::PolicyViolation
+        } else if status.code().is_none() && !self.detached {
+            ExitReason::Signaled
+        } else {
+            ExitReason::Exited
+        }
+    }
+
+    /// Whether the child was killed for breaking its [`Pipe2::syscall_policy`](crate::Pipe2::syscall_policy).
+    #[cfg(all(feature = "seccomp", target_os = "linux"))]
+    fn policy_violated(&self, status: ExitStatus) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+        self.settings.syscall_policy && status.signal() == Some(libc::SIGSYS)
+    }
+
+    #[cfg(not(all(feature = "seccomp", target_os = "linux")))]
+    fn policy_violated(&self, _status: ExitStatus) -> bool {
+        false
+    }
+
+    /// Logs the files the child was seen writing to for the first time as `file_written` events.
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    fn sample_files(&mut self) {
+        let Some(files) = &mut self.files else {
+            return;
+        };
+        let method = files.method();
+        for path in files.sample() {
+            self.emit(
+                "file_written",
+                json!({ "path": path.to_string_lossy(), "method": method }),
+            );
+        }
+    }
+
+    fn emit_exit(&self, status: ExitStatus) {
+        #[cfg(unix)]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal()
+        };
+        #[cfg(not(unix))]
+        let signal: Option<i32> = None;
+        self.emit(
+            "exited",
+            json!({
+                "code": status.code(),
+                "signal": signal,
+                "timed_out": self.timed_out,
+                "cpu_limit_exceeded": self.cpu_limit_exceeded,
+                "first_output_timed_out": self.first_output_timed_out,
+                "output_closed_timed_out": self.output_closed_timed_out,
+                "cancelled": self.cancelled,
+                "aborted": self.aborted,
+                "reason": self.reason(status),
+            }),
+        );
+    }
+
+    /// Goes through what the child writes as it comes, and how it exits, for a plain `for` loop instead of polling;
+    /// see [`Events`].
+    pub fn events(self) -> Events {
+        Events::new(self)
+    }
+
+    /// Whether the child said it's ready, see [`Child::wait_ready`].
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Drains the pipes until the child says it's ready, like [`Child::wait`] would, and returns `true`; or `false` if
+    /// it hasn't within `timeout`. The child keeps running either way, to be waited for as usual. It's an error if
+    /// the child exits before it's ready, or there's no way of telling, like [`Pipe2::notify_ready`] or
+    /// [`Pipe2::ready_on_port`].
+    ///
+    /// [`Pipe2::notify_ready`]: crate::Pipe2::notify_ready
+    /// [`Pipe2::ready_on_port`]: crate::Pipe2::ready_on_port
+    pub fn wait_ready(&mut self, timeout: Duration) -> io::Result<bool> {
+        #[cfg(unix)]
+        let waiting = self.notify.is_some() || self.probe.is_some();
+        #[cfg(not(unix))]
+        let waiting = self.probe.is_some();
+        if !self.ready && !waiting {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the child has no way of saying it's ready",
+            ));
+        }
+        let started = self.settings.clock.now();
+        while !self.ready {
+            if self.poll()?.is_some() {
+                return Err(io::Error::other("the child exited before it was ready"));
+            }
+            if self.since(started) >= timeout {
+                return Ok(false);
+            }
+            self.settings.clock.sleep(Duration::from_millis(10));
+        }
+        Ok(true)
+    }
+
+    fn became_ready(&mut self, via: &str) {
+        self.ready = true;
+        self.emit("ready", json!({ "via": via }));
+    }
+
+    /// Drains the pipes until the child exits.
+    pub fn wait(mut self) -> io::Result<Output> {
+        // NOTE(gabriela): pipes are read during program execution, ensuring that no issues such as the pipe buffer
+        // becoming full and leading to blocking on process I/O operations happens.
+        //
+        // On Windows: https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-createnamedpipea
+        //
+        // "Whenever a pipe write operation occurs, the system first tries to charge the memory against the pipe write quota.
+        // If the remaining pipe write quota is enough to fulfill the request, the write operation completes immediately.
+        // If the remaining pipe write quota is too small to fulfill the request, the system will try to expand the buffers
+        // to accommodate the data using nonpaged pool reserved for the process. The write operation will block until the data
+        // is read from the pipe so that the additional buffer quota can be released."
+        //
+        // TL;DR: the `stdout`/`stderr` pipe buffers could get filled up if we don't read them *as* the process is executing,
+        // causing blocks on I/O.
+        let status = loop {
+            if let Some(status) = self.poll()? {
+                break status;
+            }
+
+            self.settings.clock.sleep(Duration::from_millis(10));
+        };
+        self.close()?;
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let peak_memory = self.cgroup.as_ref().and_then(Cgroup::peak_memory);
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let peak_memory = None;
+
+        let reason = self.reason(status);
+        let success = reason == ExitReason::Exited
+            && status
+                .code()
+                .is_some_and(|code| self.settings.success_codes.contains(&code));
+        let stdout = self
+            .stdout
+            .as_mut()
+            .map(|stdout| stdout.captured.take())
+            .unwrap_or_default();
+        let stderr = self.stderr.captured.take();
+        #[cfg(unix)]
+        let (stdout_file, stderr_file) = (
+            self.stdout
+                .as_ref()
+                .and_then(|stdout| stdout.captured.path()),
+            self.stderr.captured.path(),
+        );
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let (stdout_memfd, stderr_memfd) = (
+            self.stdout
+                .as_mut()
+                .map(|stdout| stdout.captured.seal())
+                .transpose()?
+                .flatten(),
+            self.stderr.captured.seal()?,
+        );
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+            timed_out: self.timed_out,
+            cpu_limit_exceeded: self.cpu_limit_exceeded,
+            first_output_timed_out: self.first_output_timed_out,
+            output_closed_timed_out: self.output_closed_timed_out,
+            detached: self.detached,
+            cancelled: self.cancelled,
+            aborted: self.aborted,
+            reason,
+            success,
+            peak_memory,
+            resource_usage: self.child.resource_usage(),
+            severities: self.severities,
+            read_sizes: self.read_sizes,
+            run_id: self.run_id,
+            #[cfg(unix)]
+            stdout_file,
+            #[cfg(unix)]
+            stderr_file,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            stdout_memfd,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            stderr_memfd,
+            sanitized: self.sanitized.take(),
+            stdin_error: self.stdin_error.take(),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            connections: self.network.as_ref().map(NetworkMonitor::connections),
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            files_written: self.files.as_ref().map(FileAccess::written),
+        })
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for Child {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.child.as_raw_handle()
+    }
+}
+
+/// What a detached child counts as having exited with.
+fn success() -> ExitStatus {
+    #[cfg(unix)]
+    use std::os::unix::process::ExitStatusExt;
+    #[cfg(windows)]
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(0)
+}
+
+/// `1h02m03s`, `2m05s` or `42s`.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, s) => format!("{h}h{m:02}m{s:02}s"),
+    }
+}
@@ -0,0 +1,132 @@
+//! `--ci-group` and `--ci-error`: folding the program's output into a collapsible group in CI logs, and pointing out
+//! the lines that look like errors.
+
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::problems::Problem;
+
+/// The CI systems whose log markers we know.
+#[derive(Clone, Copy)]
+pub enum Ci {
+    GitHubActions,
+    GitLab,
+}
+
+impl Ci {
+    /// Which CI we're running under, going by the variables each of them sets.
+    pub fn detect() -> Option<Self> {
+        let set = |name: &str| std::env::var_os(name).is_some_and(|value| value == "true");
+        if set("GITHUB_ACTIONS") {
+            Some(Self::GitHubActions)
+        } else if set("GITLAB_CI") {
+            Some(Self::GitLab)
+        } else {
+            None
+        }
+    }
+
+    pub fn start_group(self, title: &str) {
+        match self {
+            Self::GitHubActions => println!("::group::{}", escape(title)),
+            Self::GitLab => println!(
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{title}",
+                now(),
+                section_name()
+            ),
+        }
+        let _ = io::stdout().flush();
+    }
+
+    pub fn end_group(self) {
+        match self {
+            Self::GitHubActions => println!("::endgroup::"),
+            Self::GitLab => println!("\x1b[0Ksection_end:{}:{}\r\x1b[0K", now(), section_name()),
+        }
+        let _ = io::stdout().flush();
+    }
+
+    /// Emits an annotation for each line of `output` containing one of `patterns`.
+    ///
+    /// NOTE: this happens once the program is done, after the group is closed, so the errors stay in sight when it's
+    /// folded away. GitLab has no annotations, so there the lines are repeated in red instead.
+    pub fn annotate(self, output: &[u8], patterns: &[String]) {
+        if patterns.is_empty() {
+            return;
+        }
+        let text = String::from_utf8_lossy(output);
+        for line in text.lines() {
+            if !patterns
+                .iter()
+                .any(|pattern| line.contains(pattern.as_str()))
+            {
+                continue;
+            }
+            match self {
+                Self::GitHubActions => println!("::error::{}", escape(line)),
+                Self::GitLab => println!("\x1b[31m{line}\x1b[0m"),
+            }
+        }
+        let _ = io::stdout().flush();
+    }
+
+    /// Emits a GitHub annotation for each problem, at its place in the file where it says. Other CIs have nothing to
+    /// show them in.
+    pub fn annotate_problems(self, problems: &[Problem]) {
+        let Self::GitHubActions = self else {
+            return;
+        };
+        for problem in problems {
+            let level = match problem
+                .severity
+                .as_deref()
+                .map(str::to_ascii_lowercase)
+                .as_deref()
+            {
+                Some("warning" | "warn") => "warning",
+                Some("note" | "info" | "notice" | "help") => "notice",
+                _ => "error",
+            };
+            let mut properties = Vec::new();
+            if let Some(file) = &problem.file {
+                properties.push(format!("file={}", escape_property(file)));
+            }
+            if let Some(line) = problem.line {
+                properties.push(format!("line={line}"));
+            }
+            if let Some(column) = problem.column {
+                properties.push(format!("col={column}"));
+            }
+            println!(
+                "::{level} {}::{}",
+                properties.join(","),
+                escape(&problem.message)
+            );
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+/// GitHub's workflow commands end at a newline, and treat `%` as the start of an escape.
+fn escape(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Property values also can't hold the `:` and `,` that separate them.
+fn escape_property(text: &str) -> String {
+    escape(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Unique to this pipe2, so the sections of several in one job don't end each other.
+fn section_name() -> String {
+    format!("pipe2_{}", std::process::id())
+}
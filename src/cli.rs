@@ -0,0 +1,1976 @@
+//! Command line parsing for the `pipe2` binary.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+use pipe2::SyscallPolicy;
+#[cfg(feature = "upload")]
+use pipe2::Upload;
+use pipe2::{
+    Backpressure, BrokenPipe, Clock, Disposition, Flush, Latin1, OutputClosed, Pipe2, Redact,
+    Severity, StdinClose, StdinPart, StripAnsi, Utf16Le,
+};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use pipe2::{IoPriority, Namespace};
+#[cfg(windows)]
+use pipe2::{MitigationPolicy, PriorityClass};
+#[cfg(unix)]
+use pipe2::{Signal, Stream};
+
+use crate::daemon::{Logs, SUPERVISE_FLAG};
+use crate::each::Batch;
+use crate::junit::{CasePatterns, case_pattern};
+use crate::pipe::{Pipe, Stage};
+use crate::problems::Matcher;
+use crate::schedule::{Cron, Overlap, Schedule, When};
+use crate::service::{self, Ready, Service};
+#[cfg(unix)]
+use crate::signals::{self, Handling};
+use crate::template::Variables;
+
+pub const USAGE: &str = "\
+Usage: pipe2 [OPTIONS] [--] PROGRAM [ARGS...]
+       pipe2 run [--set NAME=VALUE | --values FILE | --template]... TASK [ARGS...]
+       pipe2 each [-P|--max-procs N] [-0|--null] [--live] [OPTIONS] [--] PROGRAM [ARGS...]
+       pipe2 schedule (--every DUR | --cron EXPR) [--overlap POLICY] [--report-dir DIR [--keep N]] [OPTIONS] [--]
+                      PROGRAM [ARGS...]
+       pipe2 with-service --service CMD (--ready-port [HOST:]PORT | --ready-http URL [--ready-status N] |
+                          --ready-notify) [--ready-timeout DUR] [OPTIONS] [--] PROGRAM [ARGS...]
+       pipe2 pipe --into CMD [--through STAGE]... [OPTIONS] [--] PROGRAM [ARGS...]
+       pipe2 tee --to CMD [--to CMD]... [OPTIONS] [--] PROGRAM [ARGS...]
+       pipe2 show FILE
+       pipe2 rerun [--diff] FILE
+       pipe2 agent --socket PATH
+
+Runs PROGRAM, relaying its stdout/stderr live while capturing them separately. `run` runs a task from pipe2.toml
+(or $PIPE2_CONFIG), with ARGS added to its own. `each` runs PROGRAM for every line (or NUL-separated item) of stdin,
+N at a time, with `{}` in ARGS standing for the item, and each run's output shown once it's done (or line by line as
+it comes, with --live). `schedule` runs PROGRAM every DUR or on a cron schedule (in
+UTC), with POLICY (skip, queue or kill-previous) [default: skip] saying what to do if the last run is still going,
+and keeps the last N [default: 10] runs' reports in DIR. `with-service` starts CMD, waits up to DUR [default: 30s]
+for it to accept connections on PORT, answer URL with status N [default: 200] or send READY=1 to its NOTIFY_SOCKET,
+then runs PROGRAM and stops CMD once it's done. `pipe` feeds PROGRAM's stdout to CMD's stdin like `PROGRAM | CMD`,
+through each STAGE in order (strip-ansi, redact=TEXT, grep=TEXT or grep-v=TEXT to keep or drop lines containing
+TEXT), with both stderrs captured and the exit code of whichever failed, CMD's first. `tee` feeds a copy of PROGRAM's
+stdout to every CMD's stdin like `PROGRAM | tee >(CMD) >(CMD)`, relaying it too, and exits with the first failure's
+exit code. `show` pretty-prints a report saved with --report. `rerun` runs the command in a report again, as
+--report-env recorded it, with --diff comparing its output to the recorded output. `agent` serves clients that start
+runs, follow their output and stop them over a Unix socket (or named pipe) at PATH, with newline-delimited JSON
+(`agent` feature). Use `pipe2 --` to run a program called `run`, `each`, `schedule`, `with-service`, `pipe`, `tee`,
+`show`, `rerun` or `agent`.
+
+Options:
+  --env KEY=VALUE      Set an environment variable for the child; can be repeated
+  --paranoid           Remove LD_PRELOAD, LD_AUDIT, LD_LIBRARY_PATH, DYLD_* and relative PATH entries from the
+                       child's environment, and close every descriptor or handle but its stdio; what was removed is
+                       recorded in the report
+  --monitor-network    Watch for TCP and UDP sockets the child or anything it starts opens, sampled every 100ms,
+                       and list them in the report and the summary (Linux)
+  --track-file-writes  List the files the child or anything it starts writes to in the report and the summary,
+                       through fanotify when permitted and by sampling otherwise (Linux, with the `file-access`
+                       feature)
+  --cwd DIR            Run the child in DIR
+  --wasi               Run PROGRAM as a WASI module (a .wasm or .wat file) inside pipe2 with wasmtime, seeing only
+                       --cwd as `.` (`wasi` feature)
+  --stdin-file PATH    Feed the file at PATH to the child's stdin, with progress lines for large files
+  --stdin-text STRING  Feed STRING to the child's stdin
+  --stdin-cmd CMD      Feed what CMD writes on stdout to the child's stdin; --stdin-file, --stdin-text and
+                       --stdin-cmd can be repeated and mixed, and are fed one after the other, the child being stopped
+                       if one fails partway, like CMD exiting with an error
+  --stdin-null         Give the child an empty stdin instead of pipe2's
+  --stdin-rate SIZE    Feed the child's stdin at most SIZE bytes a second, with an optional K/M/G suffix
+  --stdin-line-delay D Wait D after each line fed to the child's stdin, e.g. 100ms for 10 lines a second
+  --stdin-close WHEN   Close the child's stdin immediately, after-input or never [default: after-input]
+  --stdin-close-on PAT Close the child's stdin once PAT shows up in its output
+  --stdout WHERE       Capture the child's stdout, inherit ours, or send it to null [default: capture]
+  --stderr WHERE       Likewise for stderr; e.g. `--stdout inherit` leaves the terminal to a TUI child
+  --merge-output       Give the child one pipe for stdout and stderr, keeping their exact order; it's all captured
+                       as stdout
+  --pty                Run the child on a pseudo-terminal that follows the size of ours, for full-screen programs
+                       and colors; everything it writes is captured as stdout (Unix)
+  --pty-passthrough    With --pty, pass our stdin on to the child keystroke by keystroke, with our terminal in raw
+                       mode meanwhile, to wrap interactive programs like `script` does (Unix)
+  --timeout DUR        Kill the child if it's still running after DUR
+  --first-output-within DUR
+                       Kill the child if it hasn't written anything after DUR
+  --on-output-closed P When the child closes stdout and stderr but keeps running, wait for it, detach and leave it
+                       running, or timeout:DUR to kill it if it's still running DUR later [default: wait]
+  --until-eof          Keep reading after the child exits, until whatever it left running in the background closes
+                       stdout and stderr too
+  --eof-cutoff DUR     Stop waiting for that DUR after the child exits; implies --until-eof
+  --cpu-limit DUR      Kill the child once it has used DUR of CPU time (Linux, Windows)
+  --grace DUR          Time between the kill signal and SIGKILL [default: 5s]
+  --restart POLICY     Run the child again when it exits: never, on-failure or always [default: never]
+  --max-restarts N     Give up restarting after N times
+  --success-codes LIST Count the comma-separated exit codes in LIST as a success, e.g. 0,1 for robocopy: pipe2 exits
+                       with 0 then, and doesn't restart on-failure; the reports keep the real code [default: 0]
+  --watch PATH         Run the child again, stopping it first, whenever something under PATH changes; can be repeated
+                       (`notify` feature)
+  --wait-ready DUR     Give the child a NOTIFY_SOCKET like systemd does, and stop it if it hasn't sent READY=1 to it
+                       within DUR (Unix)
+  --wait-for-port A[:DUR]
+                       Consider the child ready once something accepts TCP connections at A, like 127.0.0.1:5432,
+                       and stop it if nothing has within DUR [default: 30s]
+  --wait-for-http URL  Consider the child ready once a GET of the http:// URL is answered with --expect-status
+  --expect-status N    The status --wait-for-http waits for [default: 200]
+  --ready-timeout DUR  Stop the child if --wait-for-http hasn't passed within DUR [default: 30s]
+  --heartbeat DUR      Print a status line to stderr whenever the child has been silent for DUR
+  --label NAME         Start every echoed line with [NAME], and add NAME to the events, report, metrics and JUnit
+                       report, to tell runs apart
+  --correlation-id ID  Tag the run with ID, like the ID of the request that set it off, in the events, report and
+                       --json, next to the random ID pipe2 gives every run
+  --on-broken-pipe P   When our stdout or stderr is closed (say, piped into `head`), kill the child with SIGPIPE,
+                       ignore it and keep capturing, or error out [default: kill]
+  --backpressure P     When our stdout or stderr is slower than the child, block, drop what it can't take, or
+                       buffer:SIZE and drop what doesn't fit; the capture is always complete [default: block]
+  --flush WHEN         Flush the echo after every chunk, every line, every:DUR or only on exit [default: chunk]
+  --decode ENC         Convert the output from latin1 or utf-16le to UTF-8 as it's read
+  --strip-ansi         Drop color codes and other escape sequences from the output, echo and capture alike
+  --redact TEXT        Replace TEXT with *** in the output, echo and capture alike; can be repeated
+  --hexdump            Echo output that isn't printable text as a hex dump, still capturing the exact bytes
+  --squash-repeats     Collapse repeated lines in the echo into `last message repeated N times`
+  --pretty-json        Pretty-print the stdout lines that are JSON objects in the echo, colored on a terminal
+  --classify S=TEXT    Count lines containing TEXT as severity S (error, warning or info) and color them on a
+                       terminal; can be repeated
+  --fail-on-errors     Exit with 1 if any line was classified as an error, even if the child exited with 0
+  --max-read-buffer S  Let the buffer the child's output is read into grow up to S bytes when reads keep filling it
+                       [default: 1M]
+  --chunk-pool N       Keep N buffers around for the echo to reuse, rather than allocating one for every read
+                       [default: 16]
+  --capture-file FILE  Capture stdout into FILE through a memory mapping rather than in memory, for very large
+                       output; what was captured is still in FILE if pipe2 dies (Unix)
+  --capture-stderr-file FILE
+                       The same for stderr (Unix)
+  --max-line SIZE      Cut lines longer than SIZE bytes short in the echo and the capture, noting how much was cut
+  --kill-signal SIG    Signal sent first when killing the child, by name or number [default: SIGTERM] (Unix)
+  --signal SIG=WHAT    What to do when pipe2 receives SIG while the child runs: forward it to the child, stop the
+                       child, or ignore it; can be repeated (Unix)
+  --ctrl-break         Send CTRL_BREAK_EVENT before terminating the child, giving it --grace to exit (Windows)
+  --pdeathsig SIG      Signal the child receives if pipe2 itself dies (Linux)
+  --kill-on-parent-death
+                       Kill the child and everything it started if pipe2 itself dies, with a Job Object (Windows)
+  --mitigation LIST    Comma-separated mitigation policies to create the child with: no-child-processes,
+                       force-aslr, high-entropy-aslr, strict-handles, no-dynamic-code, no-win32k,
+                       no-extension-points, microsoft-signed-only, no-remote-images (Windows)
+  --unshare LIST       Comma-separated namespaces (net, mount, pid, ipc, uts, user) to isolate the child in (Linux)
+  --syscall-policy LIST
+                       Comma-separated seccomp policies (no-network, no-exec) that kill the child when broken
+                       (Linux, with the `seccomp` feature)
+  --cgroup PATH        Place the child in the existing cgroup v2 at PATH (Linux)
+  --transient-cgroup P Place the child in a cgroup of its own under P, removed afterwards (Linux)
+  --memory-max SIZE    Limit the child's cgroup to SIZE bytes of memory, with an optional K/M/G suffix (Linux)
+  --cpu-max CPUS       Limit the child's cgroup to CPUS CPUs' worth of time, e.g. `0.5` (Linux)
+  --chroot DIR         Run the child with DIR as its root directory (Unix)
+  --umask MODE         Set the child's umask, in octal (Unix)
+  --setsid             Start the child in a session of its own, without a controlling terminal (Unix)
+  --nice N             Run the child at niceness N (Unix)
+  --ionice CLASS[:N]   I/O scheduling class: realtime, best-effort or idle, with level N (Linux)
+  --priority-class C   idle, below-normal, normal, above-normal, high or realtime (Windows)
+  --cpus LIST          Restrict the child to these CPUs, e.g. `0,2-3` (Linux, Windows)
+  --uid USER           Run the child as USER, by name or number (Unix)
+  --gid GROUP          Run the child with GROUP as its group, by name or number (Unix)
+  --groups LIST        Comma-separated supplementary groups for the child (Unix)
+  --run-as [DOM\\]USER  Run the child as USER, logging on with the password in $PIPE2_PASSWORD (Windows)
+  --restricted-token   Run the child without administrator groups and privileges (Windows)
+  --no-window          Create console children without a console window (Windows)
+  --detached-console   Create console children without any console (Windows)
+  --hide-window        Start the child's first window hidden (Windows)
+  --detach             Run PROGRAM in the background under a supervising pipe2, print its PID and exit
+  --log-dir DIR        Where --detach writes stdout.log and stderr.log [default: .]
+  --log-size SIZE      Rotate the logs once they'd grow past SIZE [default: 10M]
+  --log-keep N         Rotated logs to keep around [default: 5]
+  --metrics-file FILE  Keep Prometheus metrics about the program (bytes read, uptime, exit code) in FILE
+  --metrics-addr ADDR  Serve the same metrics over HTTP on ADDR, like `127.0.0.1:9100`
+  --control PATH       Accept stop, kill and status commands on a Unix socket at PATH (a named pipe on Windows)
+  --events FILE        Write an NDJSON log of the run's events (spawn, output, signals, exit) to FILE, `-` for stderr
+  --trace-io FILE      Trace every poll, read and would-block of the capture loop to FILE, `-` for stderr, to attach
+                       to a report of output getting stuck
+  --tee FILE           Also write everything the child writes to stdout and stderr to FILE, in the order it came, on
+                       a thread of its own; can be repeated
+  --upload URL         Also stream everything the child writes to stdout and stderr to the object at URL, like
+                       `http://minio:9000/ci-logs/build.log`, as an S3 multipart upload, signed with
+                       $AWS_ACCESS_KEY_ID and $AWS_SECRET_ACCESS_KEY if they're set (`upload` feature)
+  --upload-part-size SIZE
+                       Send the upload in parts of SIZE, with an optional K/M/G suffix [default: 8M]
+  --record FILE        Record the session to FILE as an asciinema recording: the output, and with --pty-passthrough
+                       what was typed, each with the time it came
+  --redact-passwords   Record what's typed while the child's terminal doesn't echo it, at password prompts, as `*`s
+                       (Unix)
+  --report FILE        Save a JSON report of the run (command, timing, exit, output, resource usage) to FILE
+  --report-env         Also record what it takes to run it again in the report: the program's full path, the
+                       working directory, stdin and the environment, with secrets redacted
+  --run-dir DIR        Keep the report, event log and output of each run in a new directory under DIR, with
+                       DIR/latest pointing at the last one
+  --ci-group           Fold the program's output into a collapsible group under GitHub Actions or GitLab CI
+  --ci-error PATTERN   Annotate output lines containing PATTERN as errors in CI; can be repeated
+  --problem-matcher P  Collect diagnostics from lines like P, e.g. `{file}:{line}:{column}: {severity}: {message}`,
+                       into the report; can be repeated
+  --problem-annotations
+                       Also turn them into GitHub Actions annotations
+  --junit FILE         Write a JUnit XML report of the run to FILE, with the program as the one test case
+  --junit-pass P       Count each output line like P, e.g. `test {name} ... ok`, as a passing test case instead
+  --junit-fail P       Likewise for failing test cases
+  --tap                Run every command in `PROGRAM [ARGS...] ::: PROGRAM [ARGS...] ...` as a test, reporting in TAP
+  --set NAME=VALUE     Fill in {NAME} placeholders in PROGRAM and ARGS (or a task's settings) with VALUE
+  --values FILE        Likewise with the NAME=VALUE lines of FILE; the environment fills in the rest
+  --template           Fill in placeholders from the environment alone
+  --json               Print how the run ended to stderr once it's over, as a JSON object: its `reason` (exited,
+                       signaled, timed_out, idle_timeout, cancelled, spawn_error, resource_limit...), whether it
+                       counted as a `success`, exit code, signal, duration and captured byte counts
+  --summary            Print the exit status and captured byte counts to stderr once the child exits; for `each`
+                       and --tap, a table with a line for every run. Off by default: older versions always printed
+                       them, to stdout
+  -h, --help           Print this help
+
+DUR is a number followed by `ms`, `s`, `m` or `h` (seconds if left out).";
+
+pub struct Cli {
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    pub env: Vec<(OsString, OsString)>,
+    pub paranoid: bool,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub monitor_network: bool,
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    pub track_file_writes: bool,
+    pub cwd: Option<PathBuf>,
+    #[cfg(feature = "wasi")]
+    pub wasi: bool,
+    pub stdin: Option<Stdin>,
+    pub stdin_rate: Option<u64>,
+    pub stdin_line_delay: Option<Duration>,
+    pub stdin_close: Option<StdinClose>,
+    pub timeout: Option<Duration>,
+    pub first_output_within: Option<Duration>,
+    pub output_closed: OutputClosed,
+    pub until_eof: bool,
+    pub eof_cutoff: Option<Duration>,
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    pub cpu_limit: Option<Duration>,
+    pub grace: Option<Duration>,
+    pub heartbeat: Option<Duration>,
+    #[cfg(unix)]
+    pub wait_ready: Option<Duration>,
+    pub wait_for_port: Option<(String, Duration)>,
+    pub wait_for_http: Option<String>,
+    pub expect_status: u16,
+    pub ready_timeout: Option<Duration>,
+    pub label: Option<String>,
+    pub correlation_id: Option<String>,
+    pub success_codes: Option<Vec<i32>>,
+    pub broken_pipe: BrokenPipe,
+    pub backpressure: Backpressure,
+    pub flush: Flush,
+    pub stdout: Disposition,
+    pub stderr: Disposition,
+    pub merge_output: bool,
+    #[cfg(unix)]
+    pub pty: bool,
+    #[cfg(unix)]
+    pub pty_passthrough: bool,
+    pub decode: Option<Decode>,
+    pub strip_ansi: bool,
+    pub redact: Vec<String>,
+    pub hexdump: bool,
+    pub squash_repeats: bool,
+    pub pretty_json: bool,
+    pub classifiers: Vec<(Severity, String)>,
+    pub fail_on_errors: bool,
+    pub max_line: Option<usize>,
+    pub max_read_buffer: Option<usize>,
+    pub chunk_pool: Option<usize>,
+    #[cfg(unix)]
+    pub capture_files: [Option<PathBuf>; 2],
+    pub restart: Restart,
+    #[cfg(feature = "notify")]
+    pub watch: Vec<PathBuf>,
+    #[cfg(unix)]
+    pub kill_signal: Option<Signal>,
+    #[cfg(unix)]
+    pub signals: Vec<(Signal, Handling)>,
+    #[cfg(windows)]
+    pub ctrl_break: bool,
+    #[cfg(windows)]
+    pub kill_on_parent_death: bool,
+    #[cfg(windows)]
+    pub mitigations: Vec<MitigationPolicy>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub pdeathsig: Option<Signal>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub unshare: Vec<Namespace>,
+    #[cfg(all(feature = "seccomp", target_os = "linux"))]
+    pub syscall_policies: Vec<SyscallPolicy>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub cgroup: Option<OsString>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub transient_cgroup: Option<OsString>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub memory_max: Option<u64>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub cpu_max: Option<f64>,
+    #[cfg(unix)]
+    pub chroot: Option<OsString>,
+    #[cfg(unix)]
+    pub umask: Option<u32>,
+    #[cfg(unix)]
+    pub setsid: bool,
+    #[cfg(unix)]
+    pub nice: Option<i32>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub ionice: Option<IoPriority>,
+    #[cfg(windows)]
+    pub priority_class: Option<PriorityClass>,
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    pub cpus: Option<Vec<usize>>,
+    #[cfg(unix)]
+    pub uid: Option<u32>,
+    #[cfg(unix)]
+    pub gid: Option<u32>,
+    #[cfg(unix)]
+    pub groups: Option<Vec<u32>>,
+    #[cfg(windows)]
+    pub run_as: Option<RunAs>,
+    #[cfg(windows)]
+    pub restricted_token: bool,
+    #[cfg(windows)]
+    pub creation_flags: u32,
+    #[cfg(windows)]
+    pub show_window: Option<u16>,
+    pub detach: bool,
+    /// Set for the background copy started by `--detach`.
+    pub supervise: bool,
+    pub logs: Logs,
+    pub metrics_file: Option<PathBuf>,
+    pub metrics_addr: Option<String>,
+    pub control: Option<PathBuf>,
+    pub events: Option<PathBuf>,
+    pub trace_io: Option<PathBuf>,
+    pub tee: Vec<PathBuf>,
+    #[cfg(feature = "upload")]
+    pub upload: Option<Upload>,
+    pub record: Option<PathBuf>,
+    #[cfg(unix)]
+    pub redact_passwords: bool,
+    pub report: Option<PathBuf>,
+    pub report_env: bool,
+    pub run_dir: Option<PathBuf>,
+    pub ci_group: bool,
+    pub ci_errors: Vec<String>,
+    pub problem_matchers: Vec<Matcher>,
+    pub problem_annotations: bool,
+    pub junit: Option<PathBuf>,
+    pub junit_cases: CasePatterns,
+    pub tap: bool,
+    pub summary: bool,
+    pub json: bool,
+}
+
+/// How long `--wait-for-port` and `--wait-for-http` wait if they aren't told.
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait before restarting the child, so one that fails right away doesn't spin.
+pub const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Whether, and how many times, the child is started again after it exits.
+#[derive(Clone, Copy, Default)]
+pub struct Restart {
+    pub policy: RestartPolicy,
+    pub max: Option<u32>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl Restart {
+    /// Once the child has been restarted `restarts` times so far, whether to start it again after it exited, with
+    /// `succeeded` saying whether it counted as a success.
+    pub fn again(&self, succeeded: bool, restarts: u32) -> bool {
+        let wanted = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !succeeded,
+            RestartPolicy::Always => true,
+        };
+        wanted && self.max.is_none_or(|max| restarts < max)
+    }
+
+    /// Waits out the [`RESTART_DELAY`] on `clock`, checking `stopped` every 10ms; `false` if it said to stop.
+    pub fn delay(&self, clock: &dyn Clock, stopped: impl Fn() -> bool) -> bool {
+        let deadline = clock.now() + RESTART_DELAY;
+        while clock.now() < deadline {
+            if stopped() {
+                return false;
+            }
+            clock.sleep(Duration::from_millis(10));
+        }
+        !stopped()
+    }
+}
+
+/// Where the child's `stdin` comes from, when it isn't ours.
+pub enum Stdin {
+    File(PathBuf),
+    Text(String),
+    /// What a command writes, from `--stdin-cmd`.
+    Command(Vec<String>),
+    Null,
+    /// More than one of `--stdin-file`, `--stdin-text` and `--stdin-cmd`, in the order they were given.
+    Concat(Vec<Stdin>),
+}
+
+impl Stdin {
+    /// What [`Pipe2::stdin_sources`] feeds the child from, for a `Command` or a `Concat`.
+    fn parts(&self) -> Vec<StdinPart> {
+        match self {
+            Self::File(path) => vec![StdinPart::File(path.clone())],
+            Self::Text(text) => vec![StdinPart::Bytes(text.clone().into_bytes())],
+            Self::Command(command) => vec![StdinPart::Command(
+                command[0].clone().into(),
+                command[1..].iter().map(OsString::from).collect(),
+            )],
+            Self::Null => Vec::new(),
+            Self::Concat(parts) => parts.iter().flat_map(Self::parts).collect(),
+        }
+    }
+}
+
+/// The encoding `--decode` converts the output from.
+#[derive(Clone, Copy)]
+pub enum Decode {
+    Latin1,
+    Utf16Le,
+}
+
+/// What the command line asks for.
+pub enum Action {
+    Run(Box<Cli>),
+    Each(Box<Cli>, Batch),
+    Schedule(Box<Cli>, Schedule),
+    WithService(Box<Cli>, Service),
+    Pipe(Box<Cli>, Pipe),
+    Tee(Box<Cli>, Vec<Vec<String>>),
+    Show(PathBuf),
+    Rerun(PathBuf, bool),
+    /// The socket to listen on.
+    #[cfg(feature = "agent")]
+    Agent(PathBuf),
+}
+
+#[cfg(windows)]
+pub struct RunAs {
+    pub user: String,
+    pub domain: Option<String>,
+    pub password: OsString,
+}
+
+impl Cli {
+    /// How long to wait for the child to be ready, if there's a way of telling: the longest of the timeouts given.
+    pub fn wait_for_ready(&self) -> Option<Duration> {
+        #[cfg(unix)]
+        let notify = self.wait_ready;
+        #[cfg(not(unix))]
+        let notify = None;
+        let port = self.wait_for_port.as_ref().map(|(_, timeout)| *timeout);
+        let http = self
+            .wait_for_http
+            .as_ref()
+            .map(|_| self.ready_timeout.unwrap_or(DEFAULT_READY_TIMEOUT));
+        notify.into_iter().chain(port).chain(http).max()
+    }
+
+    /// The program and its arguments, space-separated, for showing to people.
+    pub fn command_line(&self) -> String {
+        std::iter::once(&self.program)
+            .chain(&self.args)
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Applies the options, though not the program or its arguments, to the builder.
+    pub fn configure(&self, pipe2: &mut Pipe2) {
+        for (key, value) in &self.env {
+            pipe2.env(key, value);
+        }
+        pipe2.paranoid(self.paranoid);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        pipe2.monitor_network(self.monitor_network);
+        #[cfg(all(feature = "file-access", target_os = "linux"))]
+        pipe2.track_file_writes(self.track_file_writes);
+        if let Some(cwd) = &self.cwd {
+            pipe2.current_dir(cwd);
+        }
+        #[cfg(feature = "wasi")]
+        pipe2.wasi(self.wasi);
+        match &self.stdin {
+            Some(Stdin::File(path)) => pipe2.stdin_file(path).stdin_progress(true),
+            Some(Stdin::Text(text)) => pipe2.stdin_bytes(text.as_bytes()),
+            Some(Stdin::Null) => pipe2.stdin_null(),
+            Some(stdin) => pipe2.stdin_sources(stdin.parts()).stdin_progress(true),
+            None => pipe2,
+        };
+        if let Some(rate) = self.stdin_rate {
+            pipe2.stdin_rate(rate);
+        }
+        if let Some(delay) = self.stdin_line_delay {
+            pipe2.stdin_line_delay(delay);
+        }
+        if let Some(close) = &self.stdin_close {
+            pipe2.stdin_close(close.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            pipe2.timeout(timeout);
+        }
+        if let Some(within) = self.first_output_within {
+            pipe2.first_output_within(within);
+        }
+        pipe2.on_output_closed(self.output_closed);
+        pipe2.drain_until_eof(self.until_eof);
+        if let Some(cutoff) = self.eof_cutoff {
+            pipe2.eof_cutoff(cutoff);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android", windows))]
+        if let Some(limit) = self.cpu_limit {
+            pipe2.cpu_limit(limit);
+        }
+        if let Some(grace) = self.grace {
+            pipe2.grace_period(grace);
+        }
+        if let Some(heartbeat) = self.heartbeat {
+            pipe2.heartbeat(heartbeat);
+        }
+        #[cfg(unix)]
+        pipe2.notify_ready(self.wait_ready.is_some());
+        if let Some((address, _)) = &self.wait_for_port {
+            pipe2.ready_on_port(address);
+        }
+        if let Some(url) = &self.wait_for_http {
+            pipe2.ready_on_http(url, self.expect_status);
+        }
+        if let Some(label) = &self.label {
+            pipe2.label(label);
+        }
+        if let Some(id) = &self.correlation_id {
+            pipe2.correlation_id(id);
+        }
+        if let Some(codes) = &self.success_codes {
+            pipe2.success_codes(codes);
+        }
+        if let Some(path) = &self.control {
+            pipe2.control_socket(path);
+        }
+        pipe2.on_broken_pipe(self.broken_pipe);
+        pipe2.backpressure(self.backpressure);
+        pipe2.flush(self.flush);
+        pipe2.stdout(self.stdout);
+        pipe2.stderr(self.stderr);
+        pipe2.merge_output(self.merge_output);
+        #[cfg(unix)]
+        pipe2
+            .pty(self.pty)
+            .pty_passthrough(self.pty_passthrough)
+            .redact_passwords(self.redact_passwords);
+        // NOTE: decoded first so the others see text, and escape sequences gone so they can't split a secret.
+        match self.decode {
+            Some(Decode::Latin1) => {
+                pipe2.transform(|| Latin1);
+            }
+            Some(Decode::Utf16Le) => {
+                pipe2.transform(Utf16Le::new);
+            }
+            None => {}
+        }
+        if self.strip_ansi {
+            pipe2.transform(StripAnsi::new);
+        }
+        if !self.redact.is_empty() {
+            let secrets = self.redact.clone();
+            pipe2.transform(move || Redact::new(&secrets));
+        }
+        pipe2.hexdump(self.hexdump);
+        pipe2.squash_repeats(self.squash_repeats);
+        pipe2.pretty_json(self.pretty_json);
+        for (severity, text) in &self.classifiers {
+            pipe2.classify(*severity, text);
+        }
+        if let Some(max) = self.max_line {
+            pipe2.max_line_length(max);
+        }
+        if let Some(max) = self.max_read_buffer {
+            pipe2.max_read_buffer(max);
+        }
+        if let Some(buffers) = self.chunk_pool {
+            pipe2.chunk_pool(buffers);
+        }
+        #[cfg(unix)]
+        for (stream, path) in [Stream::Stdout, Stream::Stderr]
+            .into_iter()
+            .zip(&self.capture_files)
+        {
+            if let Some(path) = path {
+                pipe2.capture_to_file(stream, path);
+            }
+        }
+        #[cfg(unix)]
+        if let Some(signal) = self.kill_signal {
+            pipe2.kill_signal(signal);
+        }
+        #[cfg(windows)]
+        pipe2.ctrl_break(self.ctrl_break);
+        #[cfg(windows)]
+        pipe2.kill_on_parent_death(self.kill_on_parent_death);
+        #[cfg(windows)]
+        for &policy in &self.mitigations {
+            pipe2.mitigation_policy(policy);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(signal) = self.pdeathsig {
+            pipe2.parent_death_signal(signal);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        for &namespace in &self.unshare {
+            pipe2.unshare(namespace);
+        }
+        #[cfg(all(feature = "seccomp", target_os = "linux"))]
+        for &policy in &self.syscall_policies {
+            pipe2.syscall_policy(policy);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(path) = &self.cgroup {
+            pipe2.cgroup(path);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(parent) = &self.transient_cgroup {
+            pipe2.transient_cgroup(parent);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(bytes) = self.memory_max {
+            pipe2.memory_max(bytes);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(cpus) = self.cpu_max {
+            const PERIOD: Duration = Duration::from_millis(100);
+            pipe2.cpu_max(PERIOD.mul_f64(cpus), PERIOD);
+        }
+        #[cfg(unix)]
+        if let Some(root) = &self.chroot {
+            pipe2.chroot(root);
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.umask {
+            pipe2.umask(mode);
+        }
+        #[cfg(unix)]
+        pipe2.new_session(self.setsid);
+        #[cfg(unix)]
+        if let Some(nice) = self.nice {
+            pipe2.nice(nice);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(priority) = self.ionice {
+            pipe2.io_priority(priority);
+        }
+        #[cfg(windows)]
+        if let Some(class) = self.priority_class {
+            pipe2.priority_class(class);
+        }
+        #[cfg(any(target_os = "linux", target_os = "android", windows))]
+        if let Some(cpus) = &self.cpus {
+            pipe2.cpu_affinity(cpus.iter().copied());
+        }
+        #[cfg(unix)]
+        if let Some(uid) = self.uid {
+            pipe2.uid(uid);
+        }
+        #[cfg(unix)]
+        if let Some(gid) = self.gid {
+            pipe2.gid(gid);
+        }
+        #[cfg(unix)]
+        if let Some(groups) = &self.groups {
+            pipe2.groups(groups);
+        }
+        #[cfg(windows)]
+        if let Some(run_as) = &self.run_as {
+            let domain = run_as.domain.as_deref().map(std::ffi::OsStr::new);
+            pipe2.run_as_user(&run_as.user, domain, &run_as.password);
+            // NOTE: the child gets our environment (rather than the other account's) either way, but not the password.
+            pipe2.env_remove("PIPE2_PASSWORD");
+        }
+        #[cfg(windows)]
+        if self.restricted_token {
+            pipe2.restricted_token(true);
+        }
+        #[cfg(windows)]
+        pipe2.creation_flags(self.creation_flags);
+        #[cfg(windows)]
+        if let Some(show) = self.show_window {
+            pipe2.show_window(show);
+        }
+    }
+}
+
+/// `Ok(None)` means help was asked for.
+pub fn parse<I: IntoIterator<Item = OsString>>(args: I) -> Result<Option<Action>, String> {
+    let mut args = args.into_iter().peekable();
+    // NOTE: the background copy started by `--detach` gets the original arguments after this, subcommand and all.
+    let supervise = args.next_if(|arg| arg == SUPERVISE_FLAG);
+    if args.peek().is_some_and(|arg| arg == "run") {
+        args.next();
+        let mut variables = Variables::default();
+        let task = loop {
+            let arg = args.next().ok_or("run expects a TASK")?;
+            let arg = arg.to_str().ok_or("invalid TASK")?.to_owned();
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next().and_then(|arg| arg.into_string().ok()))
+                    .ok_or_else(|| format!("{flag} expects a value"))
+            };
+            match flag.as_str() {
+                "--set" => variables.set(&value()?)?,
+                "--values" => variables.load(&value()?)?,
+                "--template" => variables.enabled = true,
+                _ => break arg,
+            }
+        };
+        let args = crate::config::task_args(&task, args, &variables)?;
+        return Ok(
+            parse_run(supervise.into_iter().chain(args))?.map(|cli| Action::Run(Box::new(cli)))
+        );
+    }
+    if args.peek().is_some_and(|arg| arg == "each") {
+        args.next();
+        let mut batch = Batch::default();
+        loop {
+            match args.peek().and_then(|arg| arg.to_str()) {
+                Some("-P" | "--max-procs") => {
+                    args.next();
+                    let value = args.next().ok_or("--max-procs expects a value")?;
+                    let value = value.to_string_lossy();
+                    batch.max_procs = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of processes {value:?}"))?;
+                }
+                Some("-0" | "--null") => {
+                    args.next();
+                    batch.null = true;
+                }
+                Some("--live") => {
+                    args.next();
+                    batch.live = true;
+                }
+                _ => break,
+            }
+        }
+        return Ok(parse_run(supervise.into_iter().chain(args))?
+            .map(|cli| Action::Each(Box::new(cli), batch)));
+    }
+    if args.peek().is_some_and(|arg| arg == "schedule") {
+        args.next();
+        let (mut when, mut overlap, mut report_dir, mut keep) = (None, Overlap::Skip, None, 10);
+        while let Some(flag) = args.peek().and_then(|arg| arg.to_str()).map(str::to_owned) {
+            let mut value = || {
+                args.next();
+                args.next()
+                    .and_then(|value| value.into_string().ok())
+                    .ok_or_else(|| format!("{flag} expects a value"))
+            };
+            match flag.as_str() {
+                "--every" => when = Some(When::Every(parse_duration(&value()?)?)),
+                "--cron" => when = Some(When::Cron(Cron::parse(&value()?)?)),
+                "--overlap" => overlap = Overlap::parse(&value()?)?,
+                "--report-dir" => report_dir = Some(value()?.into()),
+                "--keep" => {
+                    let value = value()?;
+                    keep = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of reports {value:?}"))?;
+                }
+                _ => break,
+            }
+        }
+        let when = when.ok_or("schedule expects --every or --cron")?;
+        let schedule = Schedule {
+            when,
+            overlap,
+            report_dir,
+            keep,
+        };
+        return Ok(parse_run(supervise.into_iter().chain(args))?
+            .map(|cli| Action::Schedule(Box::new(cli), schedule)));
+    }
+    if args.peek().is_some_and(|arg| arg == "with-service") {
+        args.next();
+        let (mut command, mut ready, mut status, mut timeout) = (None, None, 200, None);
+        while let Some(flag) = args.peek().and_then(|arg| arg.to_str()).map(str::to_owned) {
+            let mut value = || {
+                args.next();
+                args.next()
+                    .and_then(|value| value.into_string().ok())
+                    .ok_or_else(|| format!("{flag} expects a value"))
+            };
+            match flag.as_str() {
+                "--service" => command = Some(service::split_words(&value()?)?),
+                "--ready-port" => ready = Some(Ready::Port(service::parse_ready_port(&value()?)?)),
+                "--ready-http" => ready = Some(Ready::Http(value()?, 0)),
+                "--ready-status" => {
+                    let value = value()?;
+                    status = value
+                        .parse()
+                        .map_err(|_| format!("invalid --ready-status {value:?}"))?;
+                }
+                #[cfg(unix)]
+                "--ready-notify" => {
+                    args.next();
+                    ready = Some(Ready::Notify);
+                }
+                "--ready-timeout" => timeout = Some(parse_duration(&value()?)?),
+                _ => break,
+            }
+        }
+        let command = command
+            .filter(|command| !command.is_empty())
+            .ok_or("with-service expects --service CMD")?;
+        let ready = match ready {
+            Some(Ready::Http(url, _)) => Ready::Http(url, status),
+            Some(ready) => ready,
+            None => {
+                return Err(
+                    "with-service expects --ready-port, --ready-http or --ready-notify".to_owned(),
+                );
+            }
+        };
+        let service = Service {
+            command,
+            ready,
+            timeout: timeout.unwrap_or(DEFAULT_READY_TIMEOUT),
+        };
+        return Ok(parse_run(supervise.into_iter().chain(args))?
+            .map(|cli| Action::WithService(Box::new(cli), service)));
+    }
+    if args.peek().is_some_and(|arg| arg == "pipe") {
+        args.next();
+        let (mut command, mut stages) = (None, Vec::new());
+        while let Some(flag) = args.peek().and_then(|arg| arg.to_str()).map(str::to_owned) {
+            let mut value = || {
+                args.next();
+                args.next()
+                    .and_then(|value| value.into_string().ok())
+                    .ok_or_else(|| format!("{flag} expects a value"))
+            };
+            match flag.as_str() {
+                "--into" => command = Some(service::split_words(&value()?)?),
+                "--through" => stages.push(Stage::parse(&value()?)?),
+                _ => break,
+            }
+        }
+        let command = command
+            .filter(|command| !command.is_empty())
+            .ok_or("pipe expects --into CMD")?;
+        let pipe = Pipe { command, stages };
+        return Ok(parse_run(supervise.into_iter().chain(args))?
+            .map(|cli| Action::Pipe(Box::new(cli), pipe)));
+    }
+    if args.peek().is_some_and(|arg| arg == "tee") {
+        args.next();
+        let mut commands = Vec::new();
+        while args.peek().is_some_and(|arg| arg == "--to") {
+            args.next();
+            let command = args
+                .next()
+                .and_then(|value| value.into_string().ok())
+                .ok_or("--to expects a value")?;
+            let command = service::split_words(&command)?;
+            if command.is_empty() {
+                return Err("--to expects a command".to_owned());
+            }
+            commands.push(command);
+        }
+        if commands.is_empty() {
+            return Err("tee expects --to CMD".to_owned());
+        }
+        return Ok(parse_run(supervise.into_iter().chain(args))?
+            .map(|cli| Action::Tee(Box::new(cli), commands)));
+    }
+    if args.peek().is_some_and(|arg| arg == "show") {
+        args.next();
+        let (Some(file), None) = (args.next(), args.next()) else {
+            return Err("show expects a single FILE".to_owned());
+        };
+        return Ok(Some(Action::Show(file.into())));
+    }
+    if args.peek().is_some_and(|arg| arg == "rerun") {
+        args.next();
+        let diff = args.next_if(|arg| arg == "--diff").is_some();
+        let (Some(file), None) = (args.next(), args.next()) else {
+            return Err("rerun expects a single FILE".to_owned());
+        };
+        return Ok(Some(Action::Rerun(file.into(), diff)));
+    }
+    if args.peek().is_some_and(|arg| arg == "agent") {
+        args.next();
+        #[cfg(not(feature = "agent"))]
+        return Err("agent is only supported with the `agent` feature".to_owned());
+        #[cfg(feature = "agent")]
+        {
+            let (Some(flag), Some(path), None) = (args.next(), args.next(), args.next()) else {
+                return Err("agent expects --socket PATH".to_owned());
+            };
+            if flag != "--socket" {
+                return Err("agent expects --socket PATH".to_owned());
+            }
+            return Ok(Some(Action::Agent(path.into())));
+        }
+    }
+    Ok(parse_run(supervise.into_iter().chain(args))?.map(|cli| Action::Run(Box::new(cli))))
+}
+
+fn parse_run<I: Iterator<Item = OsString>>(mut args: I) -> Result<Option<Cli>, String> {
+    let mut timeout = None;
+    let mut first_output_within = None;
+    let mut output_closed = OutputClosed::Wait;
+    let mut until_eof = false;
+    let mut eof_cutoff = None;
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    let mut cpu_limit = None;
+    let mut grace = None;
+    let mut heartbeat = None;
+    #[cfg(unix)]
+    let mut wait_ready = None;
+    let mut wait_for_port = None;
+    let mut wait_for_http = None;
+    let mut expect_status = 200;
+    let mut ready_timeout = None;
+    let mut label = None;
+    let mut correlation_id = None;
+    let mut success_codes = None;
+    let mut broken_pipe = BrokenPipe::Kill;
+    let mut backpressure = Backpressure::Block;
+    let mut flush = Flush::Chunk;
+    let mut stdout = Disposition::Capture;
+    let mut stderr = Disposition::Capture;
+    let mut merge_output = false;
+    #[cfg(unix)]
+    let mut pty = false;
+    #[cfg(unix)]
+    let mut pty_passthrough = false;
+    let mut decode = None;
+    let mut strip_ansi = false;
+    let mut redact = Vec::new();
+    let mut hexdump = false;
+    let mut squash_repeats = false;
+    let mut pretty_json = false;
+    let mut classifiers = Vec::new();
+    let mut fail_on_errors = false;
+    let mut max_line = None;
+    let mut max_read_buffer = None;
+    let mut chunk_pool = None;
+    #[cfg(unix)]
+    let mut capture_files = [None, None];
+    let mut env = Vec::new();
+    let mut paranoid = false;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut monitor_network = false;
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    let mut track_file_writes = false;
+    let mut cwd = None;
+    #[cfg(feature = "wasi")]
+    let mut wasi = false;
+    let mut stdin = None;
+    let mut stdin_rate = None;
+    let mut stdin_line_delay = None;
+    let mut stdin_close = None;
+    let mut restart = Restart::default();
+    #[cfg(feature = "notify")]
+    let mut watch = Vec::new();
+    #[cfg(unix)]
+    let mut kill_signal = None;
+    #[cfg(unix)]
+    let mut signals = Vec::new();
+    #[cfg(windows)]
+    let mut ctrl_break = false;
+    #[cfg(windows)]
+    let mut kill_on_parent_death = false;
+    #[cfg(windows)]
+    let mut mitigations = Vec::new();
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut pdeathsig = None;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut unshare = Vec::new();
+    #[cfg(all(feature = "seccomp", target_os = "linux"))]
+    let mut syscall_policies = Vec::new();
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut cgroup = None;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut transient_cgroup = None;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut memory_max = None;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut cpu_max = None;
+    #[cfg(unix)]
+    let mut chroot = None;
+    #[cfg(unix)]
+    let mut umask = None;
+    #[cfg(unix)]
+    let mut setsid = false;
+    #[cfg(unix)]
+    let mut nice = None;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let mut ionice = None;
+    #[cfg(windows)]
+    let mut priority_class = None;
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    let mut cpus = None;
+    #[cfg(unix)]
+    let mut uid = None;
+    #[cfg(unix)]
+    let mut gid = None;
+    #[cfg(unix)]
+    let mut groups = None;
+    #[cfg(windows)]
+    let mut run_as = None;
+    #[cfg(windows)]
+    let mut restricted_token = false;
+    #[cfg(windows)]
+    let mut creation_flags = 0;
+    #[cfg(windows)]
+    let mut show_window = None;
+    let mut detach = false;
+    let mut supervise = false;
+    let mut logs = Logs {
+        dir: ".".into(),
+        max_size: 10 << 20,
+        keep: 5,
+    };
+    let mut metrics_file = None;
+    let mut metrics_addr = None;
+    let mut control = None;
+    let mut events = None;
+    let mut trace_io = None;
+    let mut tee = Vec::new();
+    #[cfg(feature = "upload")]
+    let mut upload = None;
+    #[cfg(feature = "upload")]
+    let mut upload_part_size = None;
+    let mut record = None;
+    #[cfg(unix)]
+    let mut redact_passwords = false;
+    let mut report = None;
+    let mut report_env = false;
+    let mut run_dir = None;
+    let mut ci_group = false;
+    let mut ci_errors = Vec::new();
+    let mut problem_matchers = Vec::new();
+    let mut problem_annotations = false;
+    let mut junit = None;
+    let mut junit_cases = CasePatterns::default();
+    let mut tap = false;
+    let mut variables = Variables::default();
+    let mut summary = false;
+    let mut json = false;
+
+    let program = loop {
+        let Some(arg) = args.next() else {
+            return Err("missing PROGRAM".to_owned());
+        };
+        let Some(flag) = arg.to_str().filter(|arg| arg.starts_with('-')) else {
+            break arg;
+        };
+
+        // `--flag=value` and `--flag value` are both accepted.
+        let (flag, mut inline) = match flag.split_once('=') {
+            Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+            None => (flag.to_owned(), None),
+        };
+        let mut value = || -> Result<String, String> {
+            inline
+                .take()
+                .or_else(|| args.next().and_then(|value| value.into_string().ok()))
+                .ok_or_else(|| format!("{flag} expects a value"))
+        };
+
+        match flag.as_str() {
+            "--" => match args.next() {
+                Some(program) => break program,
+                None => return Err("missing PROGRAM".to_owned()),
+            },
+            "-h" | "--help" => return Ok(None),
+            "--timeout" => timeout = Some(parse_duration(&value()?)?),
+            "--first-output-within" => first_output_within = Some(parse_duration(&value()?)?),
+            #[cfg(any(target_os = "linux", target_os = "android", windows))]
+            "--cpu-limit" => cpu_limit = Some(parse_duration(&value()?)?),
+            #[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+            "--cpu-limit" => {
+                return Err("--cpu-limit is only supported on Linux and Windows".to_owned());
+            }
+            "--grace" => grace = Some(parse_duration(&value()?)?),
+            "--env" => {
+                let value = value()?;
+                let (key, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("--env expects KEY=VALUE, not {value:?}"))?;
+                env.push((key.into(), value.into()));
+            }
+            "--paranoid" => paranoid = true,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--monitor-network" => monitor_network = true,
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            "--monitor-network" => {
+                return Err("--monitor-network is only supported on Linux".to_owned());
+            }
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            "--track-file-writes" => track_file_writes = true,
+            #[cfg(not(all(feature = "file-access", target_os = "linux")))]
+            "--track-file-writes" => {
+                return Err(
+                    "--track-file-writes is only supported on Linux, with the `file-access` feature"
+                        .to_owned(),
+                );
+            }
+            "--cwd" => cwd = Some(value()?.into()),
+            #[cfg(feature = "wasi")]
+            "--wasi" => wasi = true,
+            #[cfg(not(feature = "wasi"))]
+            "--wasi" => return Err("--wasi is only supported with the `wasi` feature".to_owned()),
+            "--stdin-null" => {
+                if stdin.is_some() {
+                    return Err("--stdin-null can't be given with other stdin sources".to_owned());
+                }
+                stdin = Some(Stdin::Null);
+            }
+            "--stdin-file" | "--stdin-text" | "--stdin-cmd" => {
+                let part = match flag.as_str() {
+                    "--stdin-file" => Stdin::File(value()?.into()),
+                    "--stdin-text" => Stdin::Text(value()?),
+                    _ => match service::split_words(&value()?)? {
+                        command if command.is_empty() => {
+                            return Err("--stdin-cmd expects a command".to_owned());
+                        }
+                        command => Stdin::Command(command),
+                    },
+                };
+                stdin = Some(match stdin.take() {
+                    None => part,
+                    Some(Stdin::Null) => {
+                        return Err(
+                            "--stdin-null can't be given with other stdin sources".to_owned()
+                        );
+                    }
+                    Some(Stdin::Concat(mut parts)) => {
+                        parts.push(part);
+                        Stdin::Concat(parts)
+                    }
+                    Some(first) => Stdin::Concat(vec![first, part]),
+                });
+            }
+            "--stdin-rate" => match parse_size(&value()?)? {
+                0 => return Err("--stdin-rate has to be more than 0".to_owned()),
+                rate => stdin_rate = Some(rate),
+            },
+            "--stdin-line-delay" => stdin_line_delay = Some(parse_duration(&value()?)?),
+            "--stdin-close" => {
+                stdin_close = Some(match value()?.as_str() {
+                    "immediately" => StdinClose::Immediately,
+                    "after-input" => StdinClose::AfterInput,
+                    "never" => StdinClose::Never,
+                    when => return Err(format!("invalid --stdin-close {when:?}")),
+                })
+            }
+            "--stdin-close-on" => stdin_close = Some(StdinClose::OnOutput(value()?.into_bytes())),
+            "--restart" => {
+                restart.policy = match value()?.as_str() {
+                    "never" => RestartPolicy::Never,
+                    "on-failure" => RestartPolicy::OnFailure,
+                    "always" => RestartPolicy::Always,
+                    policy => return Err(format!("invalid restart policy {policy:?}")),
+                }
+            }
+            "--max-restarts" => {
+                let value = value()?;
+                let max = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of restarts {value:?}"))?;
+                restart.max = Some(max);
+            }
+            "--success-codes" => {
+                let value = value()?;
+                let codes = value
+                    .split(',')
+                    .map(|code| code.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("invalid --success-codes {value:?}"))?;
+                success_codes = Some(codes);
+            }
+            #[cfg(feature = "notify")]
+            "--watch" => watch.push(value()?.into()),
+            #[cfg(not(feature = "notify"))]
+            "--watch" => {
+                return Err("--watch is only supported with the `notify` feature".to_owned());
+            }
+            "--heartbeat" => heartbeat = Some(parse_duration(&value()?)?),
+            #[cfg(unix)]
+            "--wait-ready" => wait_ready = Some(parse_duration(&value()?)?),
+            #[cfg(not(unix))]
+            "--wait-ready" => return Err("--wait-ready is only supported on Unix".to_owned()),
+            "--wait-for-port" => wait_for_port = Some(parse_port_wait(&value()?)?),
+            "--wait-for-http" => wait_for_http = Some(value()?),
+            "--expect-status" => {
+                let value = value()?;
+                expect_status = value
+                    .parse()
+                    .map_err(|_| format!("invalid --expect-status {value:?}"))?;
+            }
+            "--ready-timeout" => ready_timeout = Some(parse_duration(&value()?)?),
+            "--label" => label = Some(value()?),
+            "--correlation-id" => correlation_id = Some(value()?),
+            "--on-broken-pipe" => {
+                broken_pipe = match value()?.as_str() {
+                    "kill" => BrokenPipe::Kill,
+                    "ignore" => BrokenPipe::Ignore,
+                    "error" => BrokenPipe::Error,
+                    policy => return Err(format!("invalid --on-broken-pipe {policy:?}")),
+                }
+            }
+            "--on-output-closed" => {
+                let value = value()?;
+                output_closed = match value.split_once(':') {
+                    None if value == "wait" => OutputClosed::Wait,
+                    None if value == "detach" => OutputClosed::Detach,
+                    Some(("timeout", duration)) => OutputClosed::Timeout(parse_duration(duration)?),
+                    _ => return Err(format!("invalid --on-output-closed {value:?}")),
+                }
+            }
+            "--until-eof" => until_eof = true,
+            "--eof-cutoff" => {
+                until_eof = true;
+                eof_cutoff = Some(parse_duration(&value()?)?);
+            }
+            "--backpressure" => {
+                let value = value()?;
+                backpressure = match value.split_once(':') {
+                    None if value == "block" => Backpressure::Block,
+                    None if value == "drop" => Backpressure::Drop,
+                    Some(("buffer", size)) => Backpressure::Buffer(parse_size(size)? as usize),
+                    _ => return Err(format!("invalid --backpressure {value:?}")),
+                }
+            }
+            "--flush" => {
+                let value = value()?;
+                flush = match value.split_once(':') {
+                    None if value == "chunk" => Flush::Chunk,
+                    None if value == "line" => Flush::Line,
+                    None if value == "exit" => Flush::Exit,
+                    Some(("every", interval)) => Flush::Every(parse_duration(interval)?),
+                    _ => return Err(format!("invalid --flush {value:?}")),
+                }
+            }
+            "--stdout" => stdout = parse_disposition("--stdout", &value()?)?,
+            "--stderr" => stderr = parse_disposition("--stderr", &value()?)?,
+            "--merge-output" => merge_output = true,
+            #[cfg(unix)]
+            "--pty" => pty = true,
+            #[cfg(unix)]
+            "--pty-passthrough" => pty_passthrough = true,
+            #[cfg(not(unix))]
+            "--pty" | "--pty-passthrough" => {
+                return Err(format!("{flag} is only supported on Unix"));
+            }
+            "--decode" => {
+                decode = match value()?.as_str() {
+                    "latin1" => Some(Decode::Latin1),
+                    "utf-16le" => Some(Decode::Utf16Le),
+                    encoding => return Err(format!("invalid --decode {encoding:?}")),
+                }
+            }
+            "--strip-ansi" => strip_ansi = true,
+            "--redact" => redact.push(value()?),
+            "--hexdump" => hexdump = true,
+            "--squash-repeats" => squash_repeats = true,
+            "--pretty-json" => pretty_json = true,
+            "--classify" => {
+                let value = value()?;
+                let (severity, text) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected SEVERITY=TEXT, got {value:?}"))?;
+                let severity = match severity {
+                    "error" => Severity::Error,
+                    "warning" | "warn" => Severity::Warning,
+                    "info" => Severity::Info,
+                    _ => return Err(format!("invalid severity {severity:?}")),
+                };
+                if text.is_empty() {
+                    return Err("--classify needs some TEXT to look for".to_owned());
+                }
+                classifiers.push((severity, text.to_owned()));
+            }
+            "--fail-on-errors" => fail_on_errors = true,
+            "--max-line" => max_line = Some(parse_size(&value()?)? as usize),
+            "--max-read-buffer" => max_read_buffer = Some(parse_size(&value()?)? as usize),
+            "--chunk-pool" => {
+                let value = value()?;
+                let buffers = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of buffers {value:?}"))?;
+                chunk_pool = Some(buffers);
+            }
+            #[cfg(unix)]
+            "--capture-file" => capture_files[0] = Some(value()?.into()),
+            #[cfg(unix)]
+            "--capture-stderr-file" => capture_files[1] = Some(value()?.into()),
+            #[cfg(not(unix))]
+            "--capture-file" | "--capture-stderr-file" => {
+                return Err(format!("{flag} is only supported on Unix"));
+            }
+            #[cfg(unix)]
+            "--kill-signal" => kill_signal = Some(parse_signal(&value()?)?),
+            #[cfg(not(unix))]
+            "--kill-signal" => return Err("--kill-signal is only supported on Unix".to_owned()),
+            #[cfg(unix)]
+            "--signal" => signals.push(signals::parse(&value()?)?),
+            #[cfg(not(unix))]
+            "--signal" => return Err("--signal is only supported on Unix".to_owned()),
+            #[cfg(windows)]
+            "--ctrl-break" => ctrl_break = true,
+            #[cfg(not(windows))]
+            "--ctrl-break" => return Err("--ctrl-break is only supported on Windows".to_owned()),
+            #[cfg(windows)]
+            "--kill-on-parent-death" => kill_on_parent_death = true,
+            #[cfg(not(windows))]
+            "--kill-on-parent-death" => {
+                return Err("--kill-on-parent-death is only supported on Windows".to_owned());
+            }
+            #[cfg(windows)]
+            "--mitigation" => {
+                for policy in value()?.split(',') {
+                    mitigations.push(parse_mitigation_policy(policy)?);
+                }
+            }
+            #[cfg(not(windows))]
+            "--mitigation" => return Err("--mitigation is only supported on Windows".to_owned()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--pdeathsig" => pdeathsig = Some(parse_signal(&value()?)?),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            "--pdeathsig" => return Err("--pdeathsig is only supported on Linux".to_owned()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--unshare" => {
+                for namespace in value()?.split(',') {
+                    unshare.push(parse_namespace(namespace)?);
+                }
+            }
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            "--unshare" => return Err("--unshare is only supported on Linux".to_owned()),
+            #[cfg(all(feature = "seccomp", target_os = "linux"))]
+            "--syscall-policy" => {
+                for policy in value()?.split(',') {
+                    syscall_policies.push(parse_syscall_policy(policy)?);
+                }
+            }
+            #[cfg(not(all(feature = "seccomp", target_os = "linux")))]
+            "--syscall-policy" => {
+                return Err(
+                    "--syscall-policy is only supported on Linux, with the `seccomp` feature"
+                        .to_owned(),
+                );
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--cgroup" => cgroup = Some(value()?.into()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--transient-cgroup" => transient_cgroup = Some(value()?.into()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--memory-max" => memory_max = Some(parse_size(&value()?)?),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--cpu-max" => {
+                let value = value()?;
+                cpu_max = Some(
+                    value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|cpus| *cpus > 0.0)
+                        .ok_or_else(|| format!("invalid CPU count {value:?}"))?,
+                );
+            }
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            "--cgroup" | "--transient-cgroup" | "--memory-max" | "--cpu-max" => {
+                return Err(format!("{flag} is only supported on Linux"));
+            }
+            #[cfg(unix)]
+            "--chroot" => chroot = Some(value()?.into()),
+            #[cfg(unix)]
+            "--umask" => {
+                let value = value()?;
+                umask = Some(
+                    u32::from_str_radix(&value, 8)
+                        .ok()
+                        .filter(|mode| *mode <= 0o777)
+                        .ok_or_else(|| format!("invalid umask {value:?}"))?,
+                );
+            }
+            #[cfg(unix)]
+            "--setsid" => setsid = true,
+            #[cfg(not(unix))]
+            "--chroot" | "--umask" | "--setsid" => {
+                return Err(format!("{flag} is only supported on Unix"));
+            }
+            #[cfg(unix)]
+            "--nice" => {
+                let value = value()?;
+                nice = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid niceness {value:?}"))?,
+                );
+            }
+            #[cfg(not(unix))]
+            "--nice" => return Err("--nice is only supported on Unix".to_owned()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "--ionice" => ionice = Some(parse_io_priority(&value()?)?),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            "--ionice" => return Err("--ionice is only supported on Linux".to_owned()),
+            #[cfg(windows)]
+            "--priority-class" => priority_class = Some(parse_priority_class(&value()?)?),
+            #[cfg(not(windows))]
+            "--priority-class" => {
+                return Err("--priority-class is only supported on Windows".to_owned());
+            }
+            #[cfg(any(target_os = "linux", target_os = "android", windows))]
+            "--cpus" => cpus = Some(parse_cpu_list(&value()?)?),
+            #[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+            "--cpus" => return Err("--cpus is only supported on Linux and Windows".to_owned()),
+            #[cfg(unix)]
+            "--uid" => uid = Some(parse_user(&value()?)?),
+            #[cfg(unix)]
+            "--gid" => gid = Some(parse_group(&value()?)?),
+            #[cfg(unix)]
+            "--groups" => {
+                groups = Some(
+                    value()?
+                        .split(',')
+                        .map(parse_group)
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+            #[cfg(not(unix))]
+            "--uid" | "--gid" | "--groups" => {
+                return Err(format!("{flag} is only supported on Unix"));
+            }
+            #[cfg(windows)]
+            "--run-as" => run_as = Some(parse_run_as(&value()?)?),
+            #[cfg(windows)]
+            "--restricted-token" => restricted_token = true,
+            #[cfg(windows)]
+            "--no-window" => creation_flags |= winapi::um::winbase::CREATE_NO_WINDOW,
+            #[cfg(windows)]
+            "--detached-console" => creation_flags |= winapi::um::winbase::DETACHED_PROCESS,
+            #[cfg(windows)]
+            "--hide-window" => show_window = Some(winapi::um::winuser::SW_HIDE as u16),
+            #[cfg(not(windows))]
+            "--run-as" | "--restricted-token" | "--no-window" | "--detached-console"
+            | "--hide-window" => {
+                return Err(format!("{flag} is only supported on Windows"));
+            }
+            "--detach" => detach = true,
+            SUPERVISE_FLAG => supervise = true,
+            "--log-dir" => logs.dir = value()?.into(),
+            "--log-size" => logs.max_size = parse_size(&value()?)?,
+            "--log-keep" => {
+                let value = value()?;
+                logs.keep = value
+                    .parse()
+                    .map_err(|_| format!("invalid number of logs {value:?}"))?;
+            }
+            "--metrics-file" => metrics_file = Some(value()?.into()),
+            "--metrics-addr" => metrics_addr = Some(value()?),
+            "--control" => control = Some(value()?.into()),
+            "--events" => events = Some(value()?.into()),
+            "--trace-io" => trace_io = Some(value()?.into()),
+            "--tee" => tee.push(value()?.into()),
+            #[cfg(feature = "upload")]
+            "--upload" => upload = Some(value()?),
+            #[cfg(feature = "upload")]
+            "--upload-part-size" => upload_part_size = Some(parse_size(&value()?)? as usize),
+            #[cfg(not(feature = "upload"))]
+            "--upload" | "--upload-part-size" => {
+                return Err("--upload is only supported with the `upload` feature".to_owned());
+            }
+            "--record" => record = Some(value()?.into()),
+            #[cfg(unix)]
+            "--redact-passwords" => redact_passwords = true,
+            #[cfg(not(unix))]
+            "--redact-passwords" => {
+                return Err("--redact-passwords is only supported on Unix".to_owned());
+            }
+            "--report" => report = Some(value()?.into()),
+            "--report-env" => report_env = true,
+            "--run-dir" => run_dir = Some(value()?.into()),
+            "--ci-group" => ci_group = true,
+            "--ci-error" => ci_errors.push(value()?),
+            "--problem-matcher" => problem_matchers.push(Matcher::parse(&value()?)?),
+            "--problem-annotations" => problem_annotations = true,
+            "--junit" => junit = Some(value()?.into()),
+            "--junit-pass" => junit_cases.pass.push(case_pattern(&value()?)?),
+            "--junit-fail" => junit_cases.fail.push(case_pattern(&value()?)?),
+            "--tap" => tap = true,
+            "--set" => variables.set(&value()?)?,
+            "--values" => variables.load(&value()?)?,
+            "--template" => variables.enabled = true,
+            "--summary" => summary = true,
+            "--json" => json = true,
+            _ => return Err(format!("unknown option {flag}")),
+        }
+    };
+
+    let program = variables.fill_os(program)?;
+    if wait_for_port.is_some() && wait_for_http.is_some() {
+        return Err("--wait-for-port and --wait-for-http can't be combined".to_owned());
+    }
+    #[cfg(unix)]
+    if pty_passthrough && !pty {
+        return Err("--pty-passthrough only works along with --pty".to_owned());
+    }
+    #[cfg(feature = "upload")]
+    let upload = match (upload, upload_part_size) {
+        (Some(url), part_size) => Some(parse_upload(url, part_size)),
+        (None, Some(_)) => {
+            return Err("--upload-part-size only works along with --upload".to_owned());
+        }
+        (None, None) => None,
+    };
+
+    Ok(Some(Cli {
+        program,
+        args: args
+            .map(|arg| variables.fill_os(arg))
+            .collect::<Result<_, _>>()?,
+        timeout,
+        first_output_within,
+        output_closed,
+        until_eof,
+        eof_cutoff,
+        #[cfg(any(target_os = "linux", target_os = "android", windows))]
+        cpu_limit,
+        grace,
+        heartbeat,
+        #[cfg(unix)]
+        wait_ready,
+        wait_for_port,
+        wait_for_http,
+        expect_status,
+        ready_timeout,
+        label,
+        correlation_id,
+        success_codes,
+        broken_pipe,
+        backpressure,
+        flush,
+        stdout,
+        stderr,
+        merge_output,
+        #[cfg(unix)]
+        pty,
+        #[cfg(unix)]
+        pty_passthrough,
+        decode,
+        strip_ansi,
+        redact,
+        hexdump,
+        squash_repeats,
+        pretty_json,
+        classifiers,
+        fail_on_errors,
+        max_line,
+        max_read_buffer,
+        chunk_pool,
+        #[cfg(unix)]
+        capture_files,
+        env,
+        paranoid,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        monitor_network,
+        #[cfg(all(feature = "file-access", target_os = "linux"))]
+        track_file_writes,
+        cwd,
+        #[cfg(feature = "wasi")]
+        wasi,
+        stdin,
+        stdin_rate,
+        stdin_line_delay,
+        stdin_close,
+        restart,
+        #[cfg(feature = "notify")]
+        watch,
+        #[cfg(unix)]
+        kill_signal,
+        #[cfg(unix)]
+        signals,
+        #[cfg(windows)]
+        ctrl_break,
+        #[cfg(windows)]
+        kill_on_parent_death,
+        #[cfg(windows)]
+        mitigations,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        pdeathsig,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        unshare,
+        #[cfg(all(feature = "seccomp", target_os = "linux"))]
+        syscall_policies,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        cgroup,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        transient_cgroup,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        memory_max,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        cpu_max,
+        #[cfg(unix)]
+        chroot,
+        #[cfg(unix)]
+        umask,
+        #[cfg(unix)]
+        setsid,
+        #[cfg(unix)]
+        nice,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        ionice,
+        #[cfg(windows)]
+        priority_class,
+        #[cfg(any(target_os = "linux", target_os = "android", windows))]
+        cpus,
+        #[cfg(unix)]
+        uid,
+        #[cfg(unix)]
+        gid,
+        #[cfg(unix)]
+        groups,
+        #[cfg(windows)]
+        run_as,
+        #[cfg(windows)]
+        restricted_token,
+        #[cfg(windows)]
+        creation_flags,
+        #[cfg(windows)]
+        show_window,
+        detach,
+        supervise,
+        logs,
+        metrics_file,
+        metrics_addr,
+        control,
+        events,
+        trace_io,
+        tee,
+        #[cfg(feature = "upload")]
+        upload,
+        record,
+        #[cfg(unix)]
+        redact_passwords,
+        report,
+        report_env,
+        run_dir,
+        ci_group,
+        ci_errors,
+        problem_matchers,
+        problem_annotations,
+        junit,
+        junit_cases,
+        tap,
+        summary,
+        json,
+    }))
+}
+
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration {value:?}"))?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration unit in {value:?}")),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Accepts a number of bytes with an optional binary `K`, `M` or `G` suffix, like `512M`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, shift) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 30),
+        _ => (value, 0),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size {value:?}"))
+}
+
+/// Accepts `capture`, `inherit` or `null`, for `flag`.
+fn parse_disposition(flag: &str, value: &str) -> Result<Disposition, String> {
+    match value {
+        "capture" => Ok(Disposition::Capture),
+        "inherit" => Ok(Disposition::Inherit),
+        "null" => Ok(Disposition::Null),
+        _ => Err(format!("invalid {flag} {value:?}")),
+    }
+}
+
+/// Accepts `SIGTERM`, `TERM` or `15`.
+#[cfg(unix)]
+pub fn parse_signal(value: &str) -> Result<Signal, String> {
+    if let Ok(number) = value.parse::<i32>() {
+        return Signal::try_from(number).map_err(|_| format!("invalid signal number {number}"));
+    }
+    let name = value.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{name}")
+    };
+    name.parse()
+        .map_err(|_| format!("unknown signal {value:?}"))
+}
+
+/// Accepts `realtime`, `best-effort` or `idle`, optionally followed by `:LEVEL`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn parse_io_priority(value: &str) -> Result<IoPriority, String> {
+    let (class, level) = match value.split_once(':') {
+        Some((class, level)) => {
+            let level = level
+                .parse()
+                .map_err(|_| format!("invalid I/O priority level {level:?}"))?;
+            (class, level)
+        }
+        None => (value, 4),
+    };
+    match class {
+        "realtime" | "rt" => Ok(IoPriority::RealTime(level)),
+        "best-effort" | "be" => Ok(IoPriority::BestEffort(level)),
+        "idle" => Ok(IoPriority::Idle),
+        _ => Err(format!("unknown I/O scheduling class {class:?}")),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn parse_namespace(value: &str) -> Result<Namespace, String> {
+    match value {
+        "net" | "network" => Ok(Namespace::Network),
+        "mount" | "mnt" => Ok(Namespace::Mount),
+        "pid" => Ok(Namespace::Pid),
+        "ipc" => Ok(Namespace::Ipc),
+        "uts" => Ok(Namespace::Uts),
+        "user" => Ok(Namespace::User),
+        _ => Err(format!("unknown namespace {value:?}")),
+    }
+}
+
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+pub fn parse_syscall_policy(value: &str) -> Result<SyscallPolicy, String> {
+    match value {
+        "no-network" | "no-net" => Ok(SyscallPolicy::NoNetwork),
+        "no-exec" => Ok(SyscallPolicy::NoExec),
+        _ => Err(format!("unknown syscall policy {value:?}")),
+    }
+}
+
+#[cfg(windows)]
+pub fn parse_priority_class(value: &str) -> Result<PriorityClass, String> {
+    match value {
+        "idle" => Ok(PriorityClass::Idle),
+        "below-normal" => Ok(PriorityClass::BelowNormal),
+        "normal" => Ok(PriorityClass::Normal),
+        "above-normal" => Ok(PriorityClass::AboveNormal),
+        "high" => Ok(PriorityClass::High),
+        "realtime" => Ok(PriorityClass::Realtime),
+        _ => Err(format!("unknown priority class {value:?}")),
+    }
+}
+
+#[cfg(windows)]
+pub fn parse_mitigation_policy(value: &str) -> Result<MitigationPolicy, String> {
+    match value {
+        "no-child-processes" => Ok(MitigationPolicy::NoChildProcesses),
+        "force-aslr" => Ok(MitigationPolicy::ForceAslr),
+        "high-entropy-aslr" => Ok(MitigationPolicy::HighEntropyAslr),
+        "strict-handles" => Ok(MitigationPolicy::StrictHandleChecks),
+        "no-dynamic-code" => Ok(MitigationPolicy::NoDynamicCode),
+        "no-win32k" => Ok(MitigationPolicy::NoWin32kCalls),
+        "no-extension-points" => Ok(MitigationPolicy::NoExtensionPoints),
+        "microsoft-signed-only" => Ok(MitigationPolicy::MicrosoftSignedOnly),
+        "no-remote-images" => Ok(MitigationPolicy::NoRemoteImages),
+        _ => Err(format!("unknown mitigation policy {value:?}")),
+    }
+}
+
+/// Accepts comma-separated CPU numbers and ranges, like `0,2-3`.
+#[cfg(any(target_os = "linux", target_os = "android", windows))]
+/// `host:port`, optionally followed by `:DUR`, for `--wait-for-port`. IPv6 hosts go in brackets, like `[::1]:5432`.
+pub fn parse_port_wait(value: &str) -> Result<(String, Duration), String> {
+    let invalid = || format!("invalid --wait-for-port {value:?}");
+    let is_host = |host: &str| !host.is_empty() && (!host.contains(':') || host.ends_with(']'));
+    let is_port = |port: &str| port.parse::<u16>().is_ok();
+    let (rest, last) = value.rsplit_once(':').ok_or_else(invalid)?;
+    if is_host(rest) && is_port(last) {
+        return Ok((value.to_owned(), DEFAULT_READY_TIMEOUT));
+    }
+    match rest.rsplit_once(':') {
+        Some((host, port)) if is_host(host) && is_port(port) => {
+            Ok((rest.to_owned(), parse_duration(last)?))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", windows))]
+pub fn parse_cpu_list(value: &str) -> Result<Vec<usize>, String> {
+    let cpu = |cpu: &str| -> Result<usize, String> {
+        match cpu.parse::<usize>() {
+            Ok(cpu) if cpu < pipe2::CPU_LIMIT => Ok(cpu),
+            Ok(cpu) => Err(format!(
+                "CPU {cpu} in {value:?} is out of range, the highest one is {}",
+                pipe2::CPU_LIMIT - 1
+            )),
+            Err(_) => Err(format!("invalid CPU list {value:?}")),
+        }
+    };
+    let mut cpus = Vec::new();
+    for part in value.split(',') {
+        if part.is_empty() {
+            return Err(format!("CPU list {value:?} has an empty entry"));
+        }
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (cpu(start)?, cpu(end)?);
+                if start > end {
+                    return Err(format!("the range {part} in {value:?} is reversed"));
+                }
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(cpu(part)?),
+        }
+    }
+    Ok(cpus)
+}
+
+#[cfg(unix)]
+pub fn parse_user(value: &str) -> Result<u32, String> {
+    if let Ok(uid) = value.parse() {
+        return Ok(uid);
+    }
+    match nix::unistd::User::from_name(value) {
+        Ok(Some(user)) => Ok(user.uid.as_raw()),
+        _ => Err(format!("unknown user {value:?}")),
+    }
+}
+
+#[cfg(unix)]
+pub fn parse_group(value: &str) -> Result<u32, String> {
+    if let Ok(gid) = value.parse() {
+        return Ok(gid);
+    }
+    match nix::unistd::Group::from_name(value) {
+        Ok(Some(group)) => Ok(group.gid.as_raw()),
+        _ => Err(format!("unknown group {value:?}")),
+    }
+}
+
+/// The credentials, and the region they're for, come from the variables the AWS tools go by, so they don't end up in
+/// the process list.
+#[cfg(feature = "upload")]
+fn parse_upload(url: String, part_size: Option<usize>) -> Upload {
+    let mut upload = Upload::new(url);
+    if let Some(part_size) = part_size {
+        upload.part_size(part_size);
+    }
+    let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if let (Some(access_key), Some(secret_key)) =
+        (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY"))
+    {
+        upload.credentials(access_key, secret_key);
+        if let Some(token) = var("AWS_SESSION_TOKEN") {
+            upload.session_token(token);
+        }
+    }
+    if let Some(region) = var("AWS_REGION").or_else(|| var("AWS_DEFAULT_REGION")) {
+        upload.region(region);
+    }
+    upload
+}
+
+/// Accepts `USER` or `DOMAIN\USER`; the password comes from `PIPE2_PASSWORD`, so it doesn't end up in the process list.
+#[cfg(windows)]
+pub fn parse_run_as(value: &str) -> Result<RunAs, String> {
+    let (domain, user) = match value.split_once('\\') {
+        Some((domain, user)) => (Some(domain.to_owned()), user.to_owned()),
+        None => (None, value.to_owned()),
+    };
+    let password = std::env::var_os("PIPE2_PASSWORD")
+        .ok_or_else(|| "--run-as expects the password in PIPE2_PASSWORD".to_owned())?;
+    Ok(RunAs {
+        user,
+        domain,
+        password,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use pipe2::MockClock;
+
+    use super::*;
+
+    #[test]
+    fn restart_delay() {
+        let clock = MockClock::new();
+        let restart = Restart::default();
+        assert!(restart.delay(&clock, || false));
+        assert_eq!(clock.elapsed(), RESTART_DELAY);
+    }
+
+    #[test]
+    fn restart_delay_stopped() {
+        let clock = MockClock::new();
+        let checks = Cell::new(0);
+        let stopped = || {
+            checks.set(checks.get() + 1);
+            checks.get() > 3
+        };
+        assert!(!Restart::default().delay(&clock, stopped));
+        assert_eq!(clock.elapsed(), Duration::from_millis(30));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    #[test]
+    fn cpu_list() {
+        assert_eq!(parse_cpu_list("0,2-4,7"), Ok(vec![0, 2, 3, 4, 7]));
+        assert_eq!(parse_cpu_list("3-3"), Ok(vec![3]));
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("0,,2").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("2000").is_err());
+        assert!(parse_cpu_list("0-18446744073709551615").is_err());
+        assert!(parse_cpu_list(&(pipe2::CPU_LIMIT - 1).to_string()).is_ok());
+    }
+}
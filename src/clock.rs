@@ -0,0 +1,77 @@
+//! Where the time comes from for timeouts, the grace period, heartbeats, the waits between polls, `stdin` pacing and
+//! event timestamps: the system's, or a [`MockClock`] that only moves when told to, for testing what happens after an
+//! hour without waiting an hour.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A source of time, see [`Pipe2::clock`](crate::Pipe2::clock).
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Waits for `duration` to go by, between polls of the child.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system's clock, and the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A clock that stands still until it's moved forward, by [`MockClock::advance`] or by sleeping on it, which returns
+/// right away. Clones share the same time, so one can be handed to the builder and the other kept to move it along.
+///
+/// With it, a run only takes as long as the child does: [`Child::wait`](crate::Child::wait) polls the child as usual,
+/// and each wait between polls moves the clock along instead of waiting, so a timeout of an hour is up after a few
+/// hundred thousand polls.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::default(),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+
+    /// How far the clock was moved forward since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+        // NOTE: the child and the threads around it still run in real time, and deserve a chance to.
+        std::thread::yield_now();
+    }
+}
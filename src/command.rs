@@ -0,0 +1,1883 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(unix)]
+use nix::sys::signal::Signal;
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, AsRawHandle, OwnedHandle};
+
+use crate::cancel::CancellationHandle;
+#[cfg(unix)]
+use crate::capture;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::cgroup::{Cgroup, CgroupConfig};
+use crate::channel::{CHANNEL_ENV, Channel};
+use crate::child::{Child, Output, Settings};
+use crate::clock::Clock;
+use crate::control::ControlSocket;
+use crate::echo::BrokenPipe;
+use crate::events::EventSink;
+#[cfg(unix)]
+use crate::fifo::Fifo;
+#[cfg(all(feature = "file-access", target_os = "linux"))]
+use crate::file_access::FileAccess;
+use crate::inherit::Inherited;
+#[cfg(windows)]
+use crate::mitigation::MitigationPolicy;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::namespace::Namespace;
+#[cfg(unix)]
+use crate::notify::{NOTIFY_ENV, NotifySocket};
+use crate::on_line::{self, LineAction, OnLine};
+use crate::outlet::{Backpressure, Flush};
+#[cfg(unix)]
+use crate::posix_spawn;
+#[cfg(unix)]
+use crate::pre_exec::PreExec;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::priority::IoPriority;
+#[cfg(windows)]
+use crate::priority::PriorityClass;
+use crate::probe::Probe;
+use crate::process::Process;
+#[cfg(unix)]
+use crate::process::UnixProcess;
+#[cfg(unix)]
+use crate::pty::Pty;
+use crate::sanitize::{self, Sanitized};
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+use crate::seccomp::{self, SyscallPolicy};
+use crate::severity::Severity;
+use crate::stdin::{Feeding, StdinClose, StdinPart, StdinQueue, StdinSource};
+#[cfg(unix)]
+use crate::stream::nonblocking;
+use crate::stream::{ChildStream, Closed, Disposition, OutputClosed, Stream};
+use crate::transform::ChunkTransform;
+#[cfg(feature = "wasi")]
+use crate::wasi;
+#[cfg(windows)]
+use crate::windows_pipe_utils::{NamedPipe, PIPE_BUFFER_SIZE};
+#[cfg(windows)]
+use crate::windows_runas::{self, RunAs, StartupHook, StartupInfo};
+
+/// One more than the highest CPU [`Pipe2::cpu_affinity`] can restrict the child to.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const CPU_LIMIT: usize = libc::CPU_SETSIZE as usize;
+/// One more than the highest CPU [`Pipe2::cpu_affinity`] can restrict the child to.
+#[cfg(windows)]
+pub const CPU_LIMIT: usize = usize::BITS as usize;
+
+/// Builder for a child process whose `stdout`/`stderr` are read *while* it runs.
+///
+/// Mirrors the parts of [`std::process::Command`] that make sense here. The configuration is kept around rather than
+/// handed to a `Command` right away, so the same builder can spawn more than once.
+pub struct Pipe2 {
+    program: OsString,
+    args: Vec<OsString>,
+    envs: Vec<(OsString, Option<OsString>)>,
+    env_clear: bool,
+    paranoid: bool,
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    track_file_writes: bool,
+    current_dir: Option<PathBuf>,
+    settings: Settings,
+    channel: bool,
+    merge_output: bool,
+    stdout: Disposition,
+    stderr: Disposition,
+    events: Option<EventSink>,
+    recording: Option<EventSink>,
+    control: Option<PathBuf>,
+    #[cfg(windows)]
+    pipe_buffer_size: Option<u32>,
+    #[cfg(windows)]
+    priority_class: Option<PriorityClass>,
+    #[cfg(windows)]
+    affinity: Option<usize>,
+    /// Why the CPUs given to [`Pipe2::cpu_affinity`] can't be used, for the spawn to fail with.
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    affinity_error: Option<String>,
+    #[cfg(windows)]
+    kill_on_parent_death: bool,
+    #[cfg(windows)]
+    mitigations: Vec<MitigationPolicy>,
+    #[cfg(windows)]
+    run_as: Option<RunAs>,
+    #[cfg(windows)]
+    creation_flags: u32,
+    #[cfg(windows)]
+    show_window: Option<u16>,
+    #[cfg(windows)]
+    startup_info: Vec<StartupHook>,
+    #[cfg(unix)]
+    pre_exec: PreExec,
+    #[cfg(unix)]
+    posix_spawn: bool,
+    #[cfg(feature = "wasi")]
+    wasi: bool,
+    #[cfg(unix)]
+    notify: bool,
+    #[cfg(unix)]
+    pty: bool,
+    #[cfg(unix)]
+    pty_passthrough: bool,
+    /// See [`Pipe2::capture_to_file`] and [`Pipe2::capture_to_memfd`], for `stdout` and `stderr`.
+    #[cfg(unix)]
+    capture_to: [Option<capture::Target>; 2],
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    cgroup: CgroupConfig,
+    #[cfg(unix)]
+    stdin_fifo: Option<Fifo>,
+    #[cfg(unix)]
+    stdout_fifo: Option<Fifo>,
+    stdin: Option<StdinSource>,
+    feeding: Feeding,
+    inherited: Inherited,
+}
+
+impl Pipe2 {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            env_clear: false,
+            paranoid: false,
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            track_file_writes: false,
+            current_dir: None,
+            settings: Settings::default(),
+            channel: false,
+            merge_output: false,
+            stdout: Disposition::default(),
+            stderr: Disposition::default(),
+            events: None,
+            recording: None,
+            control: None,
+            #[cfg(windows)]
+            pipe_buffer_size: None,
+            #[cfg(windows)]
+            priority_class: None,
+            #[cfg(windows)]
+            affinity: None,
+            #[cfg(any(target_os = "linux", target_os = "android", windows))]
+            affinity_error: None,
+            #[cfg(windows)]
+            kill_on_parent_death: false,
+            #[cfg(windows)]
+            mitigations: Vec::new(),
+            #[cfg(windows)]
+            run_as: None,
+            #[cfg(windows)]
+            creation_flags: 0,
+            #[cfg(windows)]
+            show_window: None,
+            #[cfg(windows)]
+            startup_info: Vec::new(),
+            #[cfg(unix)]
+            pre_exec: PreExec::default(),
+            #[cfg(unix)]
+            posix_spawn: false,
+            #[cfg(feature = "wasi")]
+            wasi: false,
+            #[cfg(unix)]
+            notify: false,
+            #[cfg(unix)]
+            pty: false,
+            #[cfg(unix)]
+            pty_passthrough: false,
+            #[cfg(unix)]
+            capture_to: [None, None],
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            cgroup: CgroupConfig::default(),
+            #[cfg(unix)]
+            stdin_fifo: None,
+            #[cfg(unix)]
+            stdout_fifo: None,
+            stdin: None,
+            feeding: Feeding::default(),
+            inherited: Inherited::default(),
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Self {
+        self.envs
+            .push((key.as_ref().to_owned(), Some(val.as_ref().to_owned())));
+        self
+    }
+
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.envs.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.envs.clear();
+        self.env_clear = true;
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Where the child's `stdout` goes: captured (the default), passed straight through to ours, or discarded. Only
+    /// captured output is echoed, and shows up in the [`Output`].
+    pub fn stdout(&mut self, disposition: Disposition) -> &mut Self {
+        self.stdout = disposition;
+        self
+    }
+
+    /// Like [`Pipe2::stdout`], for `stderr`; capturing `stderr` while `stdout` is inherited lets a TUI child keep the
+    /// terminal.
+    pub fn stderr(&mut self, disposition: Disposition) -> &mut Self {
+        self.stderr = disposition;
+        self
+    }
+
+    /// Gives the child one pipe for both `stdout` and `stderr`, like `2>&1` in a shell, so that they come in exactly
+    /// the order the child wrote them, which two pipes can't promise. It's all captured (and echoed) as `stdout` then,
+    /// and `stderr` is left empty.
+    pub fn merge_output(&mut self, merge: bool) -> &mut Self {
+        self.merge_output = merge;
+        self
+    }
+
+    /// Whether the output is also relayed to our own `stdout`/`stderr` as soon as it's read. On by default.
+    ///
+    /// While other children are echoing too, only whole lines are written, so that they never end up in the middle of
+    /// each other's lines; the start of a line waits for the rest.
+    pub fn echo(&mut self, echo: bool) -> &mut Self {
+        self.settings.echo = echo;
+        self
+    }
+
+    /// Keeps draining the pipes after the child exits, until they're at EOF, rather than stopping at the exit: whatever
+    /// the child started in the background (`sh -c 'job &'`) may have inherited them, and still be writing. Use
+    /// [`Pipe2::eof_cutoff`] to bound the wait.
+    pub fn drain_until_eof(&mut self, until_eof: bool) -> &mut Self {
+        self.settings.until_eof = until_eof;
+        self
+    }
+
+    /// Stops waiting for EOF `cutoff` after the child exits, with [`Pipe2::drain_until_eof`] on.
+    pub fn eof_cutoff(&mut self, cutoff: Duration) -> &mut Self {
+        self.settings.eof_cutoff = Some(cutoff);
+        self
+    }
+
+    /// What to do when the child closes its `stdout` and `stderr` but keeps running. By default, it's waited on all
+    /// the same. Streams that aren't captured (see [`Pipe2::stdout`]) count as closed from the start.
+    pub fn on_output_closed(&mut self, policy: OutputClosed) -> &mut Self {
+        self.settings.output_closed = policy;
+        self
+    }
+
+    /// What to do when the echo can't be written because our own `stdout` or `stderr` was closed, like when piped
+    /// into `head`. By default, the run fails with the write's error.
+    ///
+    /// Rust ignores `SIGPIPE` in its programs, so that a closed pipe is a write error to handle rather than the end of
+    /// the process; the child gets it back as the default, and [`BrokenPipe::Kill`] hands it down to it.
+    pub fn on_broken_pipe(&mut self, policy: BrokenPipe) -> &mut Self {
+        self.settings.broken_pipe = policy;
+        self
+    }
+
+    /// What to do with the echo when our own `stdout` or `stderr` can't take it as fast as the child writes. It's
+    /// written on a thread of its own, through a queue; once that's full, the capture loop either waits for it (the
+    /// default), or carries on and drops what doesn't fit, see [`Backpressure`].
+    pub fn backpressure(&mut self, backpressure: Backpressure) -> &mut Self {
+        self.settings.backpressure = backpressure;
+        self
+    }
+
+    /// How often the echo is flushed, see [`Flush`]. Every chunk is by default, which is what an interactive child
+    /// wants; one that writes a lot of small chunks costs fewer writes with [`Flush::Line`] or [`Flush::Every`].
+    pub fn flush(&mut self, flush: Flush) -> &mut Self {
+        self.settings.flush = flush;
+        self
+    }
+
+    /// Echoes chunks that aren't printable text (invalid UTF-8, or control characters other than tabs, line breaks
+    /// and color codes) as a `hexdump -C`-style dump rather than the raw bytes, for children that speak binary
+    /// protocols. What's captured is still exactly what the child wrote.
+    pub fn hexdump(&mut self, hexdump: bool) -> &mut Self {
+        self.settings.hexdump = hexdump;
+        self
+    }
+
+    /// Cuts lines longer than `max` bytes short, both in what's echoed and what's captured, replacing the rest with
+    /// `…[+N bytes]`; for children that print huge single lines, like minified JSON. A cut that would split a UTF-8
+    /// character is made before it.
+    pub fn max_line_length(&mut self, max: usize) -> &mut Self {
+        self.settings.max_line = Some(max);
+        self
+    }
+
+    /// Adds a stage that rewrites the output as it's read, before it's captured or echoed, like
+    /// [`StripAnsi`](crate::StripAnsi) or [`Redact`](crate::Redact). `make` is called for each stream of each run, so
+    /// that every one has a stage of its own. Stages run in the order they're added, and
+    /// [`Pipe2::max_line_length`] comes after all of them.
+    pub fn transform<F, T>(&mut self, make: F) -> &mut Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: ChunkTransform + 'static,
+    {
+        self.settings.transforms.push(Arc::new(move |_, _| {
+            Box::new(make()) as Box<dyn ChunkTransform>
+        }));
+        self
+    }
+
+    /// Hands every line of output to `f`, without its line ending, and does with it what `f` says: keep it, put
+    /// something else in its place, drop it, or stop the child, say on a line that shows something went wrong. It's a
+    /// stage like [`Pipe2::transform`] adds, and comes in the same order. Lines are then echoed once they're complete.
+    ///
+    /// [`LineAction::Abort`] stops the child like [`Child::kill`] does, and the run ends with
+    /// [`ExitReason::Aborted`](crate::ExitReason::Aborted) and [`Output::aborted`] set. Only that run is stopped; the
+    /// next one starts over.
+    pub fn on_line<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(Stream, &[u8]) -> LineAction + Send + Sync + 'static,
+    {
+        let hook: on_line::Hook = Arc::new(f);
+        self.settings
+            .transforms
+            .push(Arc::new(move |stream, abort| {
+                Box::new(OnLine::new(hook.clone(), stream, abort.clone()))
+                    as Box<dyn ChunkTransform>
+            }));
+        self
+    }
+
+    /// Collapses runs of identical lines in the echo into `last message repeated N times`, like syslog does, to keep
+    /// retry loops readable; everything is still captured. Lines are then echoed once they're complete.
+    pub fn squash_repeats(&mut self, squash: bool) -> &mut Self {
+        self.settings.squash_repeats = squash;
+        self
+    }
+
+    /// Pretty-prints the `stdout` lines that are JSON objects in the echo, colored on a terminal, for services that
+    /// log structured JSON. The capture keeps the compact lines as written.
+    pub fn pretty_json(&mut self, pretty: bool) -> &mut Self {
+        self.settings.pretty_json = pretty;
+        self
+    }
+
+    /// Names the run, for telling it apart from others wherever it shows up: echoed lines start with `[label] `, and
+    /// the label goes into the [`Pipe2::event_log`] and the heartbeat line too. Lines are then echoed once they're
+    /// complete.
+    pub fn label<S: Into<String>>(&mut self, label: S) -> &mut Self {
+        self.settings.label = Some(label.into());
+        self
+    }
+
+    /// The exit codes that count as a success in [`Output::success`], for programs that exit with something other
+    /// than 0 to say they did well, like `robocopy`; 0 alone by default. Leaving 0 out makes it count as a failure.
+    pub fn success_codes(&mut self, codes: &[i32]) -> &mut Self {
+        self.settings.success_codes = codes.to_vec();
+        self
+    }
+
+    /// Counts the lines containing `text` as having `severity`, in [`Output::severities`], and colors them in the echo
+    /// when it goes to a terminal. A line matched by several classifiers counts as the most serious of them.
+    pub fn classify<S: Into<String>>(&mut self, severity: Severity, text: S) -> &mut Self {
+        self.settings.classifiers.push((severity, text.into()));
+        self
+    }
+
+    /// Kills the child (see [`Child::kill`]) if it's still running after `timeout`.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.settings.timeout = Some(timeout);
+        self
+    }
+
+    /// Kills the child (see [`Child::kill`]) if it hasn't written a single byte to `stdout` or `stderr` after
+    /// `within`, for children that hang right away, like on a prompt nobody is going to answer, well before a
+    /// [`Pipe2::timeout`] would catch them.
+    pub fn first_output_within(&mut self, within: Duration) -> &mut Self {
+        self.settings.first_output_within = Some(within);
+        self
+    }
+
+    /// A handle for stopping the run from another thread while [`Pipe2::run`] (or [`Child::wait`]) blocks this one.
+    /// The child is stopped like [`Child::kill`] does, its output is still drained, and the run returns with
+    /// [`Output::cancelled`] set. The handle stays cancelled: every later run of this builder stops right away.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.settings.cancellation.clone()
+    }
+
+    /// Kills the child (see [`Child::kill`]) once it has used `limit` of CPU time, user and system together, however
+    /// long that takes on the clock. It's sampled every 100ms, so the child can go a little over. Only the child
+    /// itself is counted, not any processes it starts.
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    pub fn cpu_limit(&mut self, limit: Duration) -> &mut Self {
+        self.settings.cpu_limit = Some(limit);
+        self
+    }
+
+    /// Keeps an eye on the child's network connections: its sockets, and those of whatever it starts, are sampled every
+    /// 100ms, each TCP or UDP one that shows up is logged as a `connection` event, and all of them end up in
+    /// [`Output::connections`]. A connection opened and closed between two samples goes unnoticed.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn monitor_network(&mut self, monitor: bool) -> &mut Self {
+        self.settings.monitor_network = monitor;
+        self
+    }
+
+    /// Keeps track of the files the child and whatever it starts write to, logged as `file_written` events as they're
+    /// noticed and all listed in [`Output::files_written`]. With the privileges for it, fanotify sees every file they
+    /// close after writing; otherwise, their open files are sampled every 100ms and short-lived ones can go unnoticed.
+    #[cfg(all(feature = "file-access", target_os = "linux"))]
+    pub fn track_file_writes(&mut self, track: bool) -> &mut Self {
+        self.track_file_writes = track;
+        self
+    }
+
+    /// Prints a status line (elapsed time, bytes read so far) to `stderr` whenever the child has been silent for
+    /// `interval`, for CI systems that give up on jobs with no output for too long.
+    pub fn heartbeat(&mut self, interval: Duration) -> &mut Self {
+        self.settings.heartbeat = Some(interval);
+        self
+    }
+
+    /// How long [`Child::kill`] gives the child to exit after the [`Pipe2::kill_signal`], before `SIGKILL`ing it.
+    /// 5 seconds by default.
+    pub fn grace_period(&mut self, grace: Duration) -> &mut Self {
+        self.settings.grace = grace;
+        self
+    }
+
+    /// The signal [`Child::kill`] (and so the timeout) sends first. `SIGTERM` by default.
+    ///
+    /// Some daemons treat `SIGINT` as a graceful shutdown and `SIGTERM` as an immediate one, or only clean up on
+    /// `SIGHUP`/`SIGUSR1`.
+    #[cfg(unix)]
+    pub fn kill_signal(&mut self, signal: Signal) -> &mut Self {
+        self.settings.kill_signal = signal;
+        self
+    }
+
+    /// Opens a bidirectional [`Channel`] with the child, reachable from [`Child::channel`] on our side. The child
+    /// learns where its end is from the [`CHANNEL_ENV`](crate::CHANNEL_ENV) environment variable.
+    pub fn channel(&mut self, channel: bool) -> &mut Self {
+        self.channel = channel;
+        self
+    }
+
+    /// Gives the child a socket to say it's ready on, the way systemd's `Type=notify` services do: its path is in the
+    /// [`NOTIFY_ENV`](crate::NOTIFY_ENV) environment variable (`NOTIFY_SOCKET`), and the child sends `READY=1` to it,
+    /// with `sd_notify` or `systemd-notify --ready`. See [`Child::wait_ready`].
+    #[cfg(unix)]
+    pub fn notify_ready(&mut self, notify: bool) -> &mut Self {
+        self.notify = notify;
+        self
+    }
+
+    /// Captures `stream` into a memory-mapped file at `path` instead of memory, for captures so big that growing a
+    /// buffer for them means copying it over and over. The file grows a MiB or more at a time, and is cut down to what
+    /// was captured at the end; if we die before that, everything captured until then is in there, followed by zeros.
+    /// The [`Output`] leaves the capture in the file, at [`Output::stdout_file`] or [`Output::stderr_file`].
+    #[cfg(unix)]
+    pub fn capture_to_file<P: AsRef<Path>>(&mut self, stream: Stream, path: P) -> &mut Self {
+        let index = match stream {
+            Stream::Stdout => 0,
+            Stream::Stderr => 1,
+        };
+        self.capture_to[index] = Some(capture::Target::File(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Captures `stream` into a memfd, like [`Pipe2::capture_to_file`] does into a file, and seals it read-only once the
+    /// run is over, for [`Output::stdout_memfd`] or [`Output::stderr_memfd`] to hand out. A collector process that's
+    /// passed the memfd can map the capture straight from it, rather than having it copied over a pipe or a socket.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn capture_to_memfd(&mut self, stream: Stream) -> &mut Self {
+        let (index, name) = match stream {
+            Stream::Stdout => (0, c"pipe2-stdout"),
+            Stream::Stderr => (1, c"pipe2-stderr"),
+        };
+        self.capture_to[index] = Some(capture::Target::Memfd(name));
+        self
+    }
+
+    /// Goes by `clock` for the timeouts, the grace period, heartbeats, the waits between polls, the pace `stdin` is fed
+    /// at and the `elapsed` time of events, rather than the system's clock; a [`MockClock`](crate::MockClock) lets tests of a supervisor built on pipe2 go through them
+    /// without waiting for real.
+    pub fn clock<C: Clock + 'static>(&mut self, clock: C) -> &mut Self {
+        self.settings.clock = Arc::new(clock);
+        self
+    }
+
+    /// Keeps up to `buffers` buffers around for the chunks the child writes, 16 unless told otherwise: the ones the echo
+    /// is queued in once they're written, and the ones handed back through [`Child::recycle`] or
+    /// [`Events::recycle`](crate::Events::recycle). A capture of many MB/s then reuses the same few buffers rather than
+    /// allocating one for every read; `0` turns that off.
+    pub fn chunk_pool(&mut self, buffers: usize) -> &mut Self {
+        self.settings.chunk_pool = buffers;
+        self
+    }
+
+    /// Lets the buffer the child's pipes are read into grow up to `bytes`, 1 MiB unless told otherwise. It starts out
+    /// at 1 KiB, and is doubled whenever reads keep filling it, so a child writing lots gets read in fewer, bigger
+    /// chunks without any tuning; `1024` keeps it where it is. [`Output::read_sizes`] tells how big the reads were.
+    pub fn max_read_buffer(&mut self, bytes: usize) -> &mut Self {
+        self.settings.max_read_buffer = bytes;
+        self
+    }
+
+    /// Runs the child on a pseudo-terminal instead of pipes, for programs that only act like themselves on a terminal,
+    /// like `vim` and `htop`, or that drop their colors when writing to a pipe. It's the child's `stdin`, `stdout`,
+    /// `stderr` and controlling terminal, in a session of its own, and starts out as big as the terminal we're on (24
+    /// rows of 80 columns if we're not on one); [`Child::resize_pty`] changes that. Everything the child writes is
+    /// captured as `stdout`, with the `\r\n` line endings terminals use. Both streams have to be captured, and `stdin`
+    /// can't be fed.
+    #[cfg(unix)]
+    pub fn pty(&mut self, pty: bool) -> &mut Self {
+        self.pty = pty;
+        self
+    }
+
+    /// With [`Pipe2::pty`], passes everything that comes in on our own `stdin` on to the child as it's typed, the way
+    /// `script` does, so that an interactive program can be wrapped without the user noticing. If `stdin` is a
+    /// terminal, it's put in raw mode until the child exits, leaving the line editing and `^C` to the child's terminal;
+    /// what the child writes is still captured. An EOF on `stdin` reaches the child as `^D`.
+    #[cfg(unix)]
+    pub fn pty_passthrough(&mut self, passthrough: bool) -> &mut Self {
+        self.pty_passthrough = passthrough;
+        self
+    }
+
+    /// Counts the child as ready once something accepts TCP connections at `address`, a `host:port` like
+    /// `127.0.0.1:5432`, for services that don't say when they're up; see [`Child::wait_ready`]. It's tried every
+    /// 100ms from a thread of its own, so the output keeps being drained meanwhile.
+    pub fn ready_on_port<S: Into<String>>(&mut self, address: S) -> &mut Self {
+        self.settings.ready_probe = Some(Probe::Port(address.into()));
+        self
+    }
+
+    /// Counts the child as ready once a `GET` of `url` is answered with `status`, like a web service's health check;
+    /// see [`Child::wait_ready`]. Only `http://` URLs can be checked, and one that can't makes the spawn fail. It's
+    /// tried every 100ms from a thread of its own, like [`Pipe2::ready_on_port`].
+    pub fn ready_on_http<S: Into<String>>(&mut self, url: S, status: u16) -> &mut Self {
+        self.settings.ready_probe = Some(Probe::Http(url.into(), status));
+        self
+    }
+
+    /// Hands `fd` to the child as descriptor number `target` (which must not be 0, 1 or 2).
+    ///
+    /// The descriptor is kept open by the builder, so it's passed again on every spawn.
+    #[cfg(unix)]
+    pub fn pass_fd<F: Into<OwnedFd>>(&mut self, fd: F, target: RawFd) -> &mut Self {
+        self.inherited.push(fd.into(), target);
+        self
+    }
+
+    /// Marks every other descriptor open in this process as `FD_CLOEXEC` in the child, so that only its stdio and the
+    /// ones given to [`Pipe2::pass_fd`] survive `exec`, regardless of how they were opened here.
+    #[cfg(unix)]
+    pub fn close_other_fds(&mut self, close: bool) -> &mut Self {
+        self.inherited.close_others(close);
+        self
+    }
+
+    /// Keeps what the child inherits down to what it's meant to get, for running tools that are only half trusted:
+    /// `LD_PRELOAD`, `LD_AUDIT`, `LD_LIBRARY_PATH` and `DYLD_*` are removed from its environment, and whatever entries
+    /// of its `PATH` aren't absolute, and it's left with no descriptors or handles but its stdio and the ones passed to
+    /// it. What was removed ends up in [`Output::sanitized`](crate::Output::sanitized).
+    ///
+    /// On Unix, this implies [`Pipe2::close_other_fds`]; on Windows, the child is created with a handle list, like a
+    /// [`Pipe2::startup_info`] hook would have it.
+    pub fn paranoid(&mut self, paranoid: bool) -> &mut Self {
+        self.paranoid = paranoid;
+        #[cfg(unix)]
+        if paranoid {
+            self.inherited.close_others(true);
+        }
+        self
+    }
+
+    /// Hands `handle` to the child. It shows up there under the same value, which is up to the caller to communicate
+    /// (through an argument or the environment, typically).
+    #[cfg(windows)]
+    pub fn pass_handle<H: Into<OwnedHandle>>(&mut self, handle: H) -> &mut Self {
+        self.inherited.push(handle.into());
+        self
+    }
+
+    /// Has the child receive `signal` when we die, so that it doesn't linger on with broken pipes if we get killed
+    /// (by the OOM killer, say).
+    ///
+    /// NOTE: Linux ties this to the *thread* that spawned the child rather than the process, so spawning from a
+    /// short-lived thread delivers the signal as soon as that thread exits. In a PID namespace of its own (see
+    /// [`Pipe2::unshare`]), the child is its PID 1, which only gets `SIGKILL` or a signal it has a handler for.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn parent_death_signal(&mut self, signal: Signal) -> &mut Self {
+        self.pre_exec.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// Kills the child, and everything it started, when we die, the way `parent_death_signal` does on Linux:
+    /// the child goes in a Job Object of ours that kills whatever is in it once it's closed, which the system does as
+    /// we exit, even if we're killed.
+    ///
+    /// NOTE: like the CPU affinity, the job can only be applied once the child exists; anything it starts right away
+    /// may get out before then.
+    #[cfg(windows)]
+    pub fn kill_on_parent_death(&mut self, kill: bool) -> &mut Self {
+        self.kill_on_parent_death = kill;
+        self
+    }
+
+    /// Applies the mitigation `policy` to the child as it's created, through its `STARTUPINFOEX` attribute list; call
+    /// once for each policy. Meant for supervising untrusted plug-in executables: the child can't lift them.
+    ///
+    /// Like any attribute, these don't work with [`Pipe2::run_as_user`].
+    #[cfg(windows)]
+    pub fn mitigation_policy(&mut self, policy: MitigationPolicy) -> &mut Self {
+        if !self.mitigations.contains(&policy) {
+            self.mitigations.push(policy);
+        }
+        self
+    }
+
+    /// Gives the child a fresh copy of `namespace`, isolating it from ours; call once for each namespace.
+    ///
+    /// When we're not root, a user namespace is added too, so that this works without privileges.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn unshare(&mut self, namespace: Namespace) -> &mut Self {
+        if !self.pre_exec.namespaces.contains(&namespace) {
+            self.pre_exec.namespaces.push(namespace);
+        }
+        self
+    }
+
+    /// Applies the pre-built seccomp `policy` to the child right before `exec`; call once for each policy. A child that
+    /// breaks it is killed on the spot, and its run ends with [`ExitReason::PolicyViolation`](crate::ExitReason::PolicyViolation).
+    ///
+    /// With [`SyscallPolicy::NoExec`], a program without a `/` in it is looked up on `PATH` here rather than by the
+    /// child, which is then started with the full path (and the program as given for `argv[0]`).
+    #[cfg(all(feature = "seccomp", target_os = "linux"))]
+    pub fn syscall_policy(&mut self, policy: SyscallPolicy) -> &mut Self {
+        if !self.pre_exec.syscall_policies.contains(&policy) {
+            self.pre_exec.syscall_policies.push(policy);
+        }
+        self.settings.syscall_policy = true;
+        self
+    }
+
+    /// Runs the child with `path` as its root directory, so it only sees (and resolves its program in) what's been
+    /// prepared under there. Needs root, or `CAP_SYS_CHROOT`.
+    ///
+    /// With [`Pipe2::current_dir`], the directory is taken to be inside the new root; the child starts in `/` of it
+    /// otherwise. The change of root happens before [`Pipe2::uid`] and friends drop our privileges.
+    #[cfg(unix)]
+    pub fn chroot<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.pre_exec.root = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the child's file mode creation mask, instead of it inheriting ours.
+    #[cfg(unix)]
+    pub fn umask(&mut self, mode: u32) -> &mut Self {
+        self.pre_exec.umask = Some(mode as libc::mode_t);
+        self
+    }
+
+    /// Starts the child in a session of its own (`setsid`), without our controlling terminal.
+    ///
+    /// NOTE: that also takes it out of our process group, so a Ctrl-C in the terminal no longer reaches it directly.
+    #[cfg(unix)]
+    pub fn new_session(&mut self, new_session: bool) -> &mut Self {
+        self.pre_exec.new_session = new_session;
+        self
+    }
+
+    /// Runs the child at the given niceness, from -20 (most favorable) to 19. Going below our own needs privileges.
+    #[cfg(unix)]
+    pub fn nice(&mut self, nice: i32) -> &mut Self {
+        self.pre_exec.nice = Some(nice);
+        self
+    }
+
+    /// Sets the child's I/O scheduling class, like `ionice` does.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn io_priority(&mut self, priority: IoPriority) -> &mut Self {
+        self.pre_exec.io_priority = Some(priority);
+        self
+    }
+
+    /// Creates the child with the given priority class.
+    #[cfg(windows)]
+    pub fn priority_class(&mut self, class: PriorityClass) -> &mut Self {
+        self.priority_class = Some(class);
+        self
+    }
+
+    /// Restricts the child to the given CPUs, numbered from 0. An empty list, or a CPU past [`CPU_LIMIT`], makes the
+    /// spawn fail.
+    ///
+    /// NOTE: on Windows, the mask can only be applied once the child exists, so it may run its first instructions
+    /// elsewhere; it's also limited to the first 64 CPUs (the ones in our processor group).
+    #[cfg(any(target_os = "linux", target_os = "android", windows))]
+    pub fn cpu_affinity<I: IntoIterator<Item = usize>>(&mut self, cpus: I) -> &mut Self {
+        let cpus: Vec<usize> = cpus.into_iter().collect();
+        self.affinity_error = if cpus.is_empty() {
+            Some("the child can't be restricted to no CPUs at all".to_owned())
+        } else {
+            cpus.iter().find(|&&cpu| cpu >= CPU_LIMIT).map(|cpu| {
+                format!(
+                    "CPU {cpu} is out of range, the highest one is {}",
+                    CPU_LIMIT - 1
+                )
+            })
+        };
+        if self.affinity_error.is_some() {
+            return self;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            for cpu in cpus {
+                unsafe { libc::CPU_SET(cpu, &mut cpu_set) };
+            }
+            self.pre_exec.cpu_set = Some(cpu_set);
+        }
+
+        #[cfg(windows)]
+        {
+            let mask = cpus.into_iter().fold(0usize, |mask, cpu| mask | (1 << cpu));
+            self.affinity = Some(mask);
+        }
+
+        self
+    }
+
+    /// Places the child in the existing cgroup v2 at `path`, like `/sys/fs/cgroup/build`, before it starts running.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn cgroup<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.cgroup.existing(path.as_ref().to_owned());
+        self
+    }
+
+    /// Places the child in a cgroup of its own, created under `parent` for each spawn and removed once the child is
+    /// gone. `parent` has to be delegated to us, like the one systemd hands out with `Delegate=yes`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn transient_cgroup<P: AsRef<Path>>(&mut self, parent: P) -> &mut Self {
+        self.cgroup.transient(parent.as_ref().to_owned());
+        self
+    }
+
+    /// Sets `memory.max` of the child's cgroup; past it, the child is reclaimed from and eventually OOM-killed.
+    ///
+    /// NOTE: the limit applies to the whole cgroup, so for one given with [`Pipe2::cgroup`], everything else in
+    /// there counts towards it too.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn memory_max(&mut self, bytes: u64) -> &mut Self {
+        self.cgroup.memory_max = Some(bytes);
+        self
+    }
+
+    /// Sets `cpu.max` of the child's cgroup: at most `quota` of CPU time every `period`. A quota of twice the period
+    /// allows two CPUs' worth.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn cpu_max(&mut self, quota: Duration, period: Duration) -> &mut Self {
+        self.cgroup.cpu_max = Some((quota, period));
+        self
+    }
+
+    /// Runs the child as user `uid`.
+    ///
+    /// Applied after everything else in the child's setup, so the rest still happens with our privileges. If no
+    /// [`Pipe2::groups`] are given and we're root, the child's supplementary groups are cleared, rather than keeping
+    /// ours.
+    #[cfg(unix)]
+    pub fn uid(&mut self, uid: u32) -> &mut Self {
+        self.pre_exec.uid = Some(uid);
+        self
+    }
+
+    /// Runs the child with `gid` as its group.
+    #[cfg(unix)]
+    pub fn gid(&mut self, gid: u32) -> &mut Self {
+        self.pre_exec.gid = Some(gid);
+        self
+    }
+
+    /// Sets the child's supplementary groups.
+    #[cfg(unix)]
+    pub fn groups(&mut self, groups: &[u32]) -> &mut Self {
+        self.pre_exec.groups = Some(groups.to_vec());
+        self
+    }
+
+    /// Runs `hook` in the child between `fork` and `exec`, like [`CommandExt::pre_exec`] does, for setup pipe2 has no
+    /// setting for, like extra `dup2`s or `prctl` calls. The child's stdio and the descriptors given to
+    /// [`Pipe2::pass_fd`] are already in place by then; hooks run in the order they were added, after pipe2's own steps but before [`Pipe2::uid`] and friends give up our
+    /// privileges. An error returned from a hook fails the spawn with it. The hook is kept by the builder, so it runs
+    /// on every spawn.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`CommandExt::pre_exec`]: `hook` runs in a copy of this process that has only one thread, so
+    /// it may only make async-signal-safe calls, which rules out allocating or taking locks.
+    ///
+    /// [`CommandExt::pre_exec`]: std::os::unix::process::CommandExt::pre_exec
+    #[cfg(unix)]
+    pub unsafe fn pre_exec<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn() -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.pre_exec.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Connects the child's `stdin` to the FIFO at `path`, creating it first if it doesn't exist and `create` is set.
+    ///
+    /// Like `cmd < fifo` in a shell, spawning waits until something opens the FIFO for writing.
+    #[cfg(unix)]
+    pub fn stdin_fifo<P: AsRef<Path>>(&mut self, path: P, create: bool) -> &mut Self {
+        self.stdin_fifo = Some(Fifo::new(path.as_ref().to_owned(), create));
+        self
+    }
+
+    /// Connects the child's `stdout` to the FIFO at `path`, creating it first if it doesn't exist and `create` is set.
+    /// Its `stdout` isn't captured then, only `stderr` is.
+    ///
+    /// Like `cmd > fifo` in a shell, spawning waits until something opens the FIFO for reading.
+    #[cfg(unix)]
+    pub fn stdout_fifo<P: AsRef<Path>>(&mut self, path: P, create: bool) -> &mut Self {
+        self.stdout_fifo = Some(Fifo::new(path.as_ref().to_owned(), create));
+        self
+    }
+
+    /// Feeds the file at `path` to the child's `stdin`, and closes it once all of it has been written.
+    ///
+    /// The file is written as the child reads it, with the same non-blocking writes as the
+    /// [`channel`](Pipe2::channel), so a child that's slow to read never holds up the capture.
+    pub fn stdin_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.stdin = Some(StdinSource::File(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Like [`Pipe2::stdin_file`], with `bytes` instead of a file.
+    pub fn stdin_bytes<B: Into<Vec<u8>>>(&mut self, bytes: B) -> &mut Self {
+        self.stdin = Some(StdinSource::Bytes(bytes.into().into()));
+        self
+    }
+
+    /// Feeds the child's `stdin` from each of `parts` in turn, files, bytes and other commands' output alike, like
+    /// `cat a - b | child` without the shell. A part that fails partway (a file that can't be read anymore, a command
+    /// that exits with an error) stops the child rather than leaving it with half of its input: the run ends with
+    /// [`ExitReason::InputFailed`](crate::ExitReason::InputFailed), and [`Output::stdin_error`] says which part it
+    /// was.
+    pub fn stdin_sources<I: IntoIterator<Item = StdinPart>>(&mut self, parts: I) -> &mut Self {
+        self.stdin = Some(StdinSource::Parts(parts.into_iter().collect()));
+        self
+    }
+
+    /// Feeds the child's `stdin` from `queue`, as it's filled, see [`Pipeline`](crate::Pipeline).
+    pub(crate) fn stdin_queue(&mut self, queue: StdinQueue) -> &mut Self {
+        self.stdin = Some(StdinSource::Queue(queue));
+        self
+    }
+
+    /// Keeps `stdout` out of the echo, as it goes on to another child instead, see [`Pipeline`](crate::Pipeline).
+    pub(crate) fn pipe_stdout(&mut self) -> &mut Self {
+        self.stdout = Disposition::Capture;
+        self.settings.piped_stdout = true;
+        self
+    }
+
+    /// Gives the child an empty `stdin`, like `< /dev/null`, instead of ours.
+    pub fn stdin_null(&mut self) -> &mut Self {
+        self.stdin = Some(StdinSource::Null);
+        self
+    }
+
+    /// Prints how far along feeding a [`Pipe2::stdin_file`] is to `stderr` about once a second, for files of 16 MiB
+    /// or more.
+    pub fn stdin_progress(&mut self, progress: bool) -> &mut Self {
+        self.feeding.progress = progress;
+        self
+    }
+
+    /// Feeds the child's `stdin` at most `bytes_per_sec` bytes a second, for children that can't keep up with
+    /// everything at once, like serial consoles.
+    pub fn stdin_rate(&mut self, bytes_per_sec: u64) -> &mut Self {
+        self.feeding.rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Waits for `delay` after each line written to the child's `stdin` before writing the next one, like someone
+    /// typing them into a REPL; 100ms is 10 lines a second.
+    pub fn stdin_line_delay(&mut self, delay: Duration) -> &mut Self {
+        self.feeding.line_delay = Some(delay);
+        self
+    }
+
+    /// Says when the child's `stdin` gets closed: with [`StdinClose::AfterInput`], the default, that's once whatever
+    /// was given to [`Pipe2::stdin_file`] or [`Pipe2::stdin_bytes`] has been written. Without either of those, the
+    /// child gets an empty pipe of ours for the other policies rather than our `stdin`, closed when they say so.
+    pub fn stdin_close(&mut self, close: StdinClose) -> &mut Self {
+        self.feeding.close = close;
+        self
+    }
+
+    /// What the child's `stdin` is fed from, if it isn't ours.
+    fn stdin_source(&self) -> io::Result<Option<StdinSource>> {
+        match (&self.stdin, &self.feeding.close) {
+            (Some(StdinSource::Null), _) | (None, StdinClose::Immediately) => {
+                Ok(Some(StdinSource::Null))
+            }
+            (Some(_), StdinClose::Immediately) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdin can't be both fed and closed right away",
+            )),
+            (None, StdinClose::AfterInput) => Ok(None),
+            (None, _) => Ok(Some(StdinSource::Bytes(Vec::new().into()))),
+            (Some(source), _) => Ok(Some(source.clone())),
+        }
+    }
+
+    /// Gives the child named pipes with `size` bytes of kernel buffer for its `stdout`/`stderr`, created by us, instead
+    /// of the anonymous pipes std sets up.
+    ///
+    /// NOTE: the default anonymous pipes come with a small buffer, so a child that produces output faster than the
+    /// capture loop comes around to read it keeps running into the write quota described in the README. A larger
+    /// buffer gives it more room in between polls.
+    #[cfg(windows)]
+    pub fn pipe_buffer_size(&mut self, size: u32) -> &mut Self {
+        self.pipe_buffer_size = Some(size);
+        self
+    }
+
+    /// Creates the child in its own process group so that [`Child::kill`] can send it a `CTRL_BREAK_EVENT` first,
+    /// giving console applications a chance to clean up before being terminated.
+    #[cfg(windows)]
+    pub fn ctrl_break(&mut self, ctrl_break: bool) -> &mut Self {
+        self.settings.ctrl_break = ctrl_break;
+        self
+    }
+
+    /// Runs the child as another user, logging on with `password` through `CreateProcessWithLogonW`.
+    ///
+    /// The child's `stdout`/`stderr` are named pipes created by us then, so they stay readable here whoever the child
+    /// runs as. `CreateProcessWithLogonW` doesn't pass on any other handles, so this can't be combined with
+    /// [`Pipe2::channel`] or [`Pipe2::pass_handle`].
+    #[cfg(windows)]
+    pub fn run_as_user<U, P>(&mut self, user: U, domain: Option<&OsStr>, password: P) -> &mut Self
+    where
+        U: AsRef<OsStr>,
+        P: AsRef<OsStr>,
+    {
+        self.run_as = Some(RunAs::Logon {
+            user: user.as_ref().to_owned(),
+            domain: domain.map(OsStr::to_owned),
+            password: password.as_ref().to_owned(),
+        });
+        self
+    }
+
+    /// Runs the child as us, but with a restricted token: administrator groups disabled and privileges removed.
+    #[cfg(windows)]
+    pub fn restricted_token(&mut self, restricted: bool) -> &mut Self {
+        self.run_as = restricted.then_some(RunAs::Restricted);
+        self
+    }
+
+    /// Sets [process creation flags](https://learn.microsoft.com/en-us/windows/win32/procthread/process-creation-flags)
+    /// on top of the ones pipe2 sets itself, like [`std::os::windows::process::CommandExt::creation_flags`].
+    ///
+    /// NOTE: `CREATE_NO_WINDOW` keeps console children from flashing up a console window when we don't have one to
+    /// share, and `DETACHED_PROCESS` gives them no console at all. Either way they're no longer on our console, so
+    /// [`Pipe2::ctrl_break`] can't reach them.
+    #[cfg(windows)]
+    pub fn creation_flags(&mut self, flags: u32) -> &mut Self {
+        self.creation_flags = flags;
+        self
+    }
+
+    /// Sets how the child's first window is shown, as an `SW_*` value like `SW_HIDE`.
+    ///
+    /// std has no way of setting this, so the child is created by pipe2 itself then.
+    #[cfg(windows)]
+    pub fn show_window(&mut self, cmd_show: u16) -> &mut Self {
+        self.show_window = Some(cmd_show);
+        self
+    }
+
+    /// Runs `hook` on the [`StartupInfo`] the child is about to be created with, for what std doesn't expose: the
+    /// desktop it starts on, an explicit list of the handles it inherits, or attributes like a mitigation policy.
+    /// Hooks run on every spawn, in the order they were added, and an error from one fails the spawn with it.
+    ///
+    /// The child is created by pipe2 itself then, its stdio wired up the same way, but only captured.
+    #[cfg(windows)]
+    pub fn startup_info<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&mut StartupInfo) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.startup_info.push(Box::new(hook));
+        self
+    }
+
+    #[cfg(windows)]
+    fn all_creation_flags(&self) -> u32 {
+        use winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
+
+        let mut flags = self.creation_flags;
+        if self.settings.ctrl_break {
+            flags |= CREATE_NEW_PROCESS_GROUP;
+        }
+        if let Some(class) = self.priority_class {
+            flags |= class.creation_flag();
+        }
+        flags
+    }
+
+    /// The child's complete environment, if it differs from ours at all.
+    #[cfg(windows)]
+    fn environment(&self, extra: Option<(&OsStr, OsString)>) -> Option<Vec<(OsString, OsString)>> {
+        if !self.env_clear && self.envs.is_empty() && extra.is_none() {
+            return None;
+        }
+
+        let mut env: Vec<(OsString, OsString)> = if self.env_clear {
+            Vec::new()
+        } else {
+            std::env::vars_os().collect()
+        };
+        let changes = self.envs.iter().cloned();
+        let extra = extra.map(|(key, val)| (key.to_owned(), Some(val)));
+        for (key, val) in changes.chain(extra) {
+            // NOTE: variable names are case-insensitive on Windows.
+            let same = |(other, _): &(OsString, OsString)| other.eq_ignore_ascii_case(&key);
+            env.retain(|var| !same(var));
+            if let Some(val) = val {
+                env.push((key, val));
+            }
+        }
+        Some(env)
+    }
+
+    #[cfg(windows)]
+    fn set_affinity(&self, child: &impl AsRawHandle) -> io::Result<()> {
+        match self.affinity {
+            Some(mask) => crate::windows_process_utils::set_affinity(child, mask),
+            None => Ok(()),
+        }
+    }
+
+    /// What can only be done to the child once it exists: its CPU affinity, and the job that kills it when we die.
+    #[cfg(windows)]
+    fn set_up(&self, child: &impl AsRawHandle) -> io::Result<()> {
+        self.set_affinity(child)?;
+        if self.kill_on_parent_death {
+            crate::windows_process_utils::kill_on_our_exit(child)?;
+        }
+        Ok(())
+    }
+
+    /// Spawns the child through [`windows_runas`], for what std has no way of doing.
+    #[cfg(windows)]
+    fn spawn_raw(&self, control: Option<ControlSocket>) -> io::Result<Child> {
+        let run_as = self.run_as.as_ref();
+        if run_as.is_some_and(|run_as| !run_as.inherits_handles())
+            && (self.channel || !self.inherited.is_empty())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "a channel or passed handles can't be combined with running as another user",
+            ));
+        }
+        if self.stdout != Disposition::Capture || self.stderr != Disposition::Capture {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "stdout and stderr can only be captured when running as another user or with a window or startup setting",
+            ));
+        }
+
+        let (stdin_client, feeder) = match &self.stdin_source()? {
+            Some(source) => {
+                let (client, feeder) =
+                    source.windows_stdin(&self.feeding, self.settings.clock.clone())?;
+                (Some(client), feeder)
+            }
+            None => (None, None),
+        };
+        let size = self.pipe_buffer_size.unwrap_or(PIPE_BUFFER_SIZE);
+        let (stdout, stdout_client) = NamedPipe::inbound(size)?;
+        let (stderr, stderr_client): (Box<dyn ChildStream + Send>, _) = if self.merge_output {
+            (Box::new(Closed), None)
+        } else {
+            let (stderr, stderr_client) = NamedPipe::inbound(size)?;
+            (Box::new(stderr), Some(stderr_client))
+        };
+        let (channel, theirs) = if self.channel {
+            let (ours, theirs) = Channel::pair()?;
+            (Some(ours), Some(theirs))
+        } else {
+            (None, None)
+        };
+
+        let mut extra = Vec::new();
+        let channel_env = theirs.as_ref().map(|theirs| {
+            extra.push(theirs.as_handle());
+            (
+                OsStr::new(CHANNEL_ENV),
+                (theirs.as_raw_handle() as usize).to_string().into(),
+            )
+        });
+        let startup_info =
+            if self.startup_info.is_empty() && !self.paranoid && self.mitigations.is_empty() {
+                None
+            } else {
+                let mut info = StartupInfo::default();
+                for hook in &self.startup_info {
+                    hook(&mut info)?;
+                }
+                if self.paranoid {
+                    info.inherit_listed_only();
+                }
+                for &policy in &self.mitigations {
+                    info.mitigation_policy(policy);
+                }
+                let passed = self.inherited.handles().chain(extra.iter().copied());
+                Some((info, passed.map(|handle| handle.as_raw_handle()).collect()))
+            };
+        let spawn = windows_runas::Spawn {
+            program: &self.program,
+            args: &self.args,
+            env: self.environment(channel_env),
+            current_dir: self.current_dir.as_deref(),
+            creation_flags: self.all_creation_flags(),
+            stdin: stdin_client.as_ref(),
+            stdout: &stdout_client,
+            stderr: stderr_client.as_ref().unwrap_or(&stdout_client),
+            show_window: self.show_window,
+            startup_info,
+        };
+
+        self.inherited.set_inheritable(true, &extra)?;
+        let child = windows_runas::spawn(run_as, spawn);
+        self.inherited.set_inheritable(false, &extra)?;
+        let mut child = child?;
+        if let Err(e) = self.set_up(&child) {
+            let _ = child.kill();
+            return Err(e);
+        }
+
+        // NOTE: the child's ends have to be closed here for the pipes (and the channel) to ever break.
+        drop((stdin_client, stdout_client, stderr_client, theirs));
+        Ok(Child::new(
+            Process::Raw(child),
+            Some(Box::new(stdout)),
+            stderr,
+            self.settings.clone(),
+            channel,
+        )
+        .with_stdin(feeder)
+        .with_events(self.events.clone())
+        .with_control(control)
+        .with_recording(self.recording.clone()))
+    }
+
+    fn command(&self) -> io::Result<Command> {
+        #[cfg(all(feature = "seccomp", target_os = "linux"))]
+        let mut command = if self
+            .pre_exec
+            .syscall_policies
+            .contains(&SyscallPolicy::NoExec)
+        {
+            use std::os::unix::process::CommandExt;
+            let path = match self.envs.iter().rev().find(|(key, _)| key == "PATH") {
+                Some((_, path)) => path.clone(),
+                None if self.env_clear => None,
+                None => std::env::var_os("PATH"),
+            };
+            let mut command = Command::new(seccomp::resolve(&self.program, path.as_deref())?);
+            command.arg0(&self.program);
+            command
+        } else {
+            Command::new(&self.program)
+        };
+        #[cfg(not(all(feature = "seccomp", target_os = "linux")))]
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        if self.env_clear {
+            command.env_clear();
+        }
+        for (key, val) in &self.envs {
+            match val {
+                Some(val) => command.env(key, val),
+                None => command.env_remove(key),
+            };
+        }
+        #[cfg(unix)]
+        let chroot = self.pre_exec.root.is_some();
+        #[cfg(not(unix))]
+        let chroot = false;
+        if let Some(dir) = &self.current_dir
+            && !chroot
+        {
+            command.current_dir(dir);
+        }
+        command
+            .stdout(self.stdout.stdio())
+            .stderr(self.stderr.stdio());
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            command.creation_flags(self.all_creation_flags());
+        }
+
+        Ok(command)
+    }
+
+    /// Tags the run with `id`, the caller's own ID for whatever it's part of, like the request that set it off. The
+    /// event log and the control socket's `status` carry it next to the [`Child::run_id`] pipe2 gives every run.
+    pub fn correlation_id<S: Into<String>>(&mut self, id: S) -> &mut Self {
+        self.settings.correlation_id = Some(id.into());
+        self
+    }
+
+    /// Writes a log of what happens during the run to `writer`, one JSON object per line: `spawned`, `first_output` and
+    /// `chunk` for each stream, `signal` for whatever [`Child::kill`] or [`Child::send_signal`] sends, `timeout`,
+    /// `first_output_timeout`, `cpu_limit`, `cancelled`, `aborted`, `output_closed` once both streams are at EOF while
+    /// the child runs, `output_closed_timeout`, `detached`, `eof_cutoff`, `read_buffer_grown`, `paused`, `resumed`,
+    /// `resized` for [`Child::resize_pty`], `stdin_closed` once a fed `stdin` has been written out, `echo_closed` when
+    /// our own `stdout` or `stderr` goes away, `echo_dropped` with how much of a stream the echo dropped under
+    /// [`Pipe2::backpressure`], `control` for the commands that came in on the [`Pipe2::control_socket`], `ready` once
+    /// the child says it's ready, and `exited`. Every line carries the `time` (seconds since the Unix epoch), the time
+    /// `elapsed` since the spawn (going by the [`Pipe2::clock`]), the child's `pid`, the [`Child::run_id`], the
+    /// [`Pipe2::correlation_id`] and [`Pipe2::label`] if there are any, and the `event`.
+    pub fn event_log<W: io::Write + Send + 'static>(&mut self, writer: W) -> &mut Self {
+        self.events = Some(Arc::new(Mutex::new(Box::new(writer))));
+        self
+    }
+
+    /// Writes a trace of the capture loop to `writer`, for when the output seems to get stuck: every poll, every read
+    /// with how big it was and how big it could have been, every read that would have blocked, the streams reaching EOF
+    /// or failing, and every event [`Pipe2::event_log`] has, one line each with the time elapsed since the spawn (going
+    /// by [`Pipe2::clock`]) and the child's pid. It's meant for bug reports rather than for machines; the lines can
+    /// change from one version to the next.
+    pub fn trace_io<W: io::Write + Send + 'static>(&mut self, writer: W) -> &mut Self {
+        self.settings.trace = Some(Arc::new(Mutex::new(Box::new(writer))));
+        self
+    }
+
+    /// Records the session to `writer` in asciinema's asciicast v2 format, for replaying it with `asciinema play` or
+    /// auditing it later: everything the child writes, both streams alike, with the time it came, and with
+    /// [`Pipe2::pty_passthrough`], everything typed for it as input events, along with the resizes of its
+    /// pseudo-terminal. Each run starts a recording of its own, header and all.
+    pub fn record_session<W: io::Write + Send + 'static>(&mut self, writer: W) -> &mut Self {
+        self.recording = Some(Arc::new(Mutex::new(Box::new(writer))));
+        self
+    }
+
+    /// Records what's typed while the child's terminal doesn't echo it, the way programs ask for passwords, as one
+    /// `*` per character in the [`Pipe2::record_session`] recording.
+    #[cfg(unix)]
+    pub fn redact_passwords(&mut self, redact: bool) -> &mut Self {
+        self.settings.redact_passwords = redact;
+        self
+    }
+
+    /// Spawns the child with `posix_spawn` instead of std's `Command`, which saves a little on every spawn, for runs
+    /// that spawn thousands of small children. Only runs that need nothing more than their arguments, environment
+    /// variables, an empty or inherited `stdin` and the [`Pipe2::stdout`]/[`Pipe2::stderr`] dispositions take this
+    /// path; anything else (a working directory, a channel, a fed `stdin`, changing `PATH`, any of the Unix process
+    /// settings) quietly spawns the usual way.
+    #[cfg(unix)]
+    pub fn posix_spawn(&mut self, posix_spawn: bool) -> &mut Self {
+        self.posix_spawn = posix_spawn;
+        self
+    }
+
+    /// Whether [`Pipe2::posix_spawn`] can be used for this run.
+    #[cfg(unix)]
+    fn posix_spawnable(&self) -> bool {
+        self.posix_spawn
+            && self.pre_exec.is_empty()
+            && self.inherited.is_empty()
+            && !self.channel
+            && !self.notify
+            && !self.pty
+            && self.capture_to.iter().all(Option::is_none)
+            && self.current_dir.is_none()
+            && self.stdin_fifo.is_none()
+            && self.stdout_fifo.is_none()
+            && matches!(self.stdin_source(), Ok(None | Some(StdinSource::Null)))
+            && !self.env_clear
+            && self.envs.iter().all(|(key, _)| key != "PATH")
+    }
+
+    /// Spawns the child through [`posix_spawn`](crate::posix_spawn), see [`Pipe2::posix_spawn`].
+    #[cfg(unix)]
+    fn spawn_posix(&self, control: Option<ControlSocket>) -> io::Result<Child> {
+        let mut env: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+        for (key, val) in &self.envs {
+            env.retain(|(other, _)| other != key);
+            if let Some(val) = val {
+                env.push((key.clone(), val.clone()));
+            }
+        }
+        let spawned = posix_spawn::spawn(posix_spawn::Spawn {
+            program: &self.program,
+            args: &self.args,
+            env,
+            stdin_null: matches!(self.stdin_source()?, Some(StdinSource::Null)),
+            stdout: self.stdout,
+            stderr: self.stderr,
+            merge_output: self.merge_output,
+        })?;
+        let stdout = spawned.stdout.map(nonblocking).transpose()?;
+        let stderr: Box<dyn ChildStream + Send> = match spawned.stderr {
+            Some(stderr) => Box::new(nonblocking(stderr)?),
+            None => Box::new(Closed),
+        };
+        Ok(Child::new(
+            Process::Unix(spawned.process),
+            stdout.map(|stdout| Box::new(stdout) as _),
+            stderr,
+            self.settings.clone(),
+            None,
+        )
+        .with_events(self.events.clone())
+        .with_control(control)
+        .with_recording(self.recording.clone()))
+    }
+
+    /// Runs the program as a WASI module (preview 1: a `.wasm` file, or its `.wat` text) with wasmtime, on a thread of
+    /// pipe2's own, instead of as a process, so that plugins compiled to WebAssembly are run, captured, fed, timed out
+    /// and stopped through the same API as native commands. The module gets the arguments and environment variables a
+    /// process would, [`Pipe2::current_dir`] as `.` and no other directory (none without one), and the same stdio. It
+    /// has no process ID ([`Child::id`] is 0), and isn't sent signals: [`Child::kill`] interrupts it right away, once
+    /// it's back in its own code, and its exit looks like `SIGKILL`'s; a trap is reported on its `stderr` and looks
+    /// like an abort. Settings that shape a process, like credentials, limits or namespaces, don't apply, and the
+    /// ones that hand it more than its stdio (a pseudo-terminal, a channel, FIFOs, descriptors) are refused.
+    #[cfg(feature = "wasi")]
+    pub fn wasi(&mut self, wasi: bool) -> &mut Self {
+        self.wasi = wasi;
+        self
+    }
+
+    /// Starts the module through [`wasi`](crate::wasi), see [`Pipe2::wasi`].
+    #[cfg(feature = "wasi")]
+    fn spawn_wasi(&self, control: Option<ControlSocket>) -> io::Result<Child> {
+        #[cfg(unix)]
+        let more_than_stdio = self.pty
+            || self.notify
+            || !self.inherited.is_empty()
+            || self.stdin_fifo.is_some()
+            || self.stdout_fifo.is_some()
+            || self.capture_to.iter().any(Option::is_some);
+        #[cfg(windows)]
+        let more_than_stdio = !self.inherited.is_empty();
+        if more_than_stdio || self.channel {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a WASI module only gets its stdio, not a pseudo-terminal, channel, FIFO or descriptors",
+            ));
+        }
+
+        let mut env: Vec<(OsString, OsString)> = if self.env_clear {
+            Vec::new()
+        } else {
+            std::env::vars_os().collect()
+        };
+        for (key, val) in &self.envs {
+            env.retain(|(other, _)| other != key);
+            if let Some(val) = val {
+                env.push((key.clone(), val.clone()));
+            }
+        }
+        // NOTE: a fed `stdin` goes through a pipe like a process's would, so that the feeding works the same.
+        let (stdin, feeder) = match self.stdin_source()? {
+            None => (None, None),
+            #[cfg(unix)]
+            Some(source) => {
+                let (reader, writer) = io::pipe()?;
+                let feeder = match source.open(&self.feeding)? {
+                    Some(input) => {
+                        let writer = std::process::ChildStdin::from(OwnedFd::from(writer));
+                        Some(input.feed(nonblocking(writer)?, self.settings.clock.clone()))
+                    }
+                    // NOTE: dropping our end right away is what gives it an empty `stdin`.
+                    None => None,
+                };
+                (Some(wasi::into_file(reader)), feeder)
+            }
+            #[cfg(windows)]
+            Some(source) => {
+                let (theirs, feeder) =
+                    source.windows_stdin(&self.feeding, self.settings.clock.clone())?;
+                (Some(wasi::into_file(theirs)), feeder)
+            }
+        };
+        let spawned = wasi::spawn(wasi::Spawn {
+            module: &self.program,
+            args: &self.args,
+            env,
+            dir: self.current_dir.as_deref(),
+            stdin,
+            stdout: self.stdout,
+            stderr: self.stderr,
+            merge_output: self.merge_output,
+        })?;
+
+        #[cfg(unix)]
+        let stdout = spawned.stdout.map(nonblocking).transpose()?;
+        #[cfg(windows)]
+        let stdout = spawned.stdout;
+        let stderr: Box<dyn ChildStream + Send> = match spawned.stderr {
+            #[cfg(unix)]
+            Some(stderr) => Box::new(nonblocking(stderr)?),
+            #[cfg(windows)]
+            Some(stderr) => Box::new(stderr),
+            None => Box::new(Closed),
+        };
+        Ok(Child::new(
+            Process::Wasi(spawned.process),
+            stdout.map(|stdout| Box::new(stdout) as _),
+            stderr,
+            self.settings.clone(),
+            None,
+        )
+        .with_stdin(feeder)
+        .with_events(self.events.clone())
+        .with_control(control)
+        .with_recording(self.recording.clone()))
+    }
+
+    /// Accepts commands for the child on a Unix socket at `path` (on Windows, a named pipe by that name, like
+    /// `\\.\pipe\pipe2`) while it runs, so that other tools can check on it or stop it. Each connection sends one
+    /// command on a line and gets one line back: `status` answers with a JSON object holding the `pid`, whether it's
+    /// `running` or `paused`, the time `elapsed` and the `stdout_bytes` and `stderr_bytes` read so far; `stop` does
+    /// what [`Child::kill`] does, and `kill` kills the child right away. Commands are served while polling.
+    pub fn control_socket<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.control = Some(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        // NOTE: fanotify has to be watching before the child starts, or whatever it writes right away is missed.
+        #[cfg(all(feature = "file-access", target_os = "linux"))]
+        let files = self.track_file_writes.then(FileAccess::new);
+        let child = self.spawn_sanitized()?;
+        #[cfg(all(feature = "file-access", target_os = "linux"))]
+        let child = child.with_file_access(files);
+        Ok(child)
+    }
+
+    fn spawn_sanitized(&mut self) -> io::Result<Child> {
+        if !self.paranoid {
+            return self.spawn_child();
+        }
+        let (sanitized, removals) = sanitize::environment(self.env_clear, &self.envs);
+        #[cfg(unix)]
+        let sanitized = Sanitized {
+            fds: sanitize::inherited_fds()?,
+            ..sanitized
+        };
+        // NOTE: the removals only go with this spawn; the next one works them out again, from the environment then.
+        let changes = self.envs.len();
+        self.envs.extend(removals);
+        let child = self.spawn_child();
+        self.envs.truncate(changes);
+        Ok(child?.with_sanitized(sanitized))
+    }
+
+    fn spawn_child(&mut self) -> io::Result<Child> {
+        // NOTE: bound first, so that a socket that can't be doesn't leave a child running without it.
+        let control = self
+            .control
+            .as_deref()
+            .map(ControlSocket::bind)
+            .transpose()?;
+
+        if self.merge_output
+            && (self.stdout != Disposition::Capture || self.stderr != Disposition::Capture)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdout and stderr can only share a pipe if both are captured",
+            ));
+        }
+        if let Some(probe) = &self.settings.ready_probe {
+            probe.check()?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android", windows))]
+        if let Some(e) = &self.affinity_error {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e.clone()));
+        }
+        #[cfg(feature = "wasi")]
+        if self.wasi {
+            return self.spawn_wasi(control);
+        }
+        #[cfg(unix)]
+        if self.pty_passthrough && !self.pty {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdin can only be passed through to a pseudo-terminal",
+            ));
+        }
+        #[cfg(unix)]
+        if self.pty
+            && (self.merge_output
+                || self.stdout != Disposition::Capture
+                || self.stderr != Disposition::Capture
+                || self.stdin.is_some()
+                || self.stdin_fifo.is_some()
+                || self.stdout_fifo.is_some())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a pseudo-terminal takes over all of the child's stdio, which has to be captured and not fed",
+            ));
+        }
+
+        #[cfg(windows)]
+        if self.run_as.is_some()
+            || self.show_window.is_some()
+            || !self.startup_info.is_empty()
+            || self.paranoid
+            || !self.mitigations.is_empty()
+        {
+            return self.spawn_raw(control);
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let cgroup = self.cgroup.prepare()?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            self.pre_exec.cgroup_procs = cgroup.as_ref().and_then(Cgroup::procs_fd);
+        }
+
+        #[cfg(unix)]
+        if self.posix_spawnable() {
+            return self.spawn_posix(control);
+        }
+
+        let mut command = self.command()?;
+        #[cfg(unix)]
+        let capture_files = [
+            self.capture_to[0]
+                .as_ref()
+                .map(capture::Target::open)
+                .transpose()?,
+            self.capture_to[1]
+                .as_ref()
+                .map(capture::Target::open)
+                .transpose()?,
+        ];
+        #[cfg(unix)]
+        let notify = if self.notify {
+            let notify = NotifySocket::bind()?;
+            command.env(NOTIFY_ENV, notify.path());
+            Some(notify)
+        } else {
+            None
+        };
+        let (channel, theirs) = if self.channel {
+            let (ours, theirs) = Channel::pair()?;
+            (Some(ours), Some(theirs))
+        } else {
+            (None, None)
+        };
+
+        #[cfg(unix)]
+        if self.stdin_fifo.is_some() && self.stdin.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdin can't come from both a FIFO and a file or bytes",
+            ));
+        }
+        #[cfg(unix)]
+        if let Some(fifo) = &self.stdin_fifo {
+            command.stdin(fifo.open_read()?);
+        }
+        #[cfg(unix)]
+        let source = match &self.stdin_fifo {
+            Some(_) => None,
+            None => self.stdin_source()?,
+        };
+        #[cfg(unix)]
+        let input = match &source {
+            Some(StdinSource::Null) => {
+                command.stdin(Stdio::null());
+                None
+            }
+            Some(source) => {
+                command.stdin(Stdio::piped());
+                source.open(&self.feeding)?
+            }
+            None => None,
+        };
+        #[cfg(windows)]
+        let feeder = match &self.stdin_source()? {
+            Some(source) => {
+                let (client, feeder) =
+                    source.windows_stdin(&self.feeding, self.settings.clock.clone())?;
+                command.stdin(client);
+                feeder
+            }
+            None => None,
+        };
+        #[cfg(unix)]
+        if self.stdout_fifo.is_some() && self.merge_output {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdout can't go to both a FIFO and the pipe it shares with stderr",
+            ));
+        }
+        #[cfg(unix)]
+        if let Some(fifo) = &self.stdout_fifo {
+            command.stdout(fifo.open_write()?);
+        }
+
+        // NOTE: bigger pipe buffers on Windows mean named pipes, which are set up further down.
+        #[cfg(windows)]
+        let merge = self.merge_output && self.pipe_buffer_size.is_none();
+        #[cfg(unix)]
+        let merge = self.merge_output;
+        let merged = if merge {
+            let (reader, writer) = io::pipe()?;
+            command.stdout(writer.try_clone()?).stderr(writer);
+            Some(reader)
+        } else {
+            None
+        };
+        #[cfg(unix)]
+        let pty = if self.pty {
+            let (mut pty, theirs) = Pty::open()?;
+            command
+                .stdin(theirs.try_clone()?)
+                .stdout(theirs.try_clone()?)
+                .stderr(theirs);
+            // NOTE: if the spawn fails, dropping `pty` puts our terminal back.
+            if self.pty_passthrough {
+                pty.start_passthrough()?;
+            }
+            Some(pty)
+        } else {
+            None
+        };
+
+        #[cfg(unix)]
+        let mut child = {
+            let mut extra = Vec::new();
+            if let Some(theirs) = &theirs {
+                let target = self.inherited.next_target();
+                command.env(CHANNEL_ENV, target.to_string());
+                extra.push((theirs.as_raw_fd(), target));
+            }
+            let fds = self.inherited.placement(&extra)?;
+            if self.pty {
+                let mut pre_exec = self.pre_exec.clone();
+                pre_exec.new_session = true;
+                pre_exec.controlling_terminal = true;
+                pre_exec.install(&mut command, self.current_dir.as_deref(), fds)?;
+            } else {
+                self.pre_exec
+                    .install(&mut command, self.current_dir.as_deref(), fds)?;
+            }
+            command.spawn()?
+        };
+
+        #[cfg(windows)]
+        let named_pipes = match self.pipe_buffer_size {
+            Some(size) => {
+                let stdout = match self.stdout {
+                    Disposition::Capture => {
+                        let (stdout, stdout_client) = NamedPipe::inbound(size)?;
+                        if self.merge_output {
+                            command.stderr(stdout_client.try_clone()?);
+                        }
+                        command.stdout(stdout_client);
+                        Some(Box::new(stdout) as Box<dyn ChildStream + Send>)
+                    }
+                    _ => None,
+                };
+                let stderr: Box<dyn ChildStream + Send> = match self.stderr {
+                    Disposition::Capture if !self.merge_output => {
+                        let (stderr, stderr_client) = NamedPipe::inbound(size)?;
+                        command.stderr(stderr_client);
+                        Box::new(stderr)
+                    }
+                    _ => Box::new(Closed),
+                };
+                Some((stdout, stderr))
+            }
+            None => None,
+        };
+
+        #[cfg(windows)]
+        let mut child = {
+            let mut extra = Vec::new();
+            if let Some(theirs) = &theirs {
+                command.env(CHANNEL_ENV, (theirs.as_raw_handle() as usize).to_string());
+                extra.push(theirs.as_handle());
+            }
+            self.inherited.set_inheritable(true, &extra)?;
+            let child = command.spawn();
+            self.inherited.set_inheritable(false, &extra)?;
+            let mut child = child?;
+            if let Err(e) = self.set_up(&child) {
+                let _ = child.kill();
+                return Err(e);
+            }
+            child
+        };
+
+        // NOTE: our copy of the child's end has to go, or we'd never see the channel's EOF.
+        drop(theirs);
+
+        #[cfg(windows)]
+        if let Some((stdout, stderr)) = named_pipes {
+            // NOTE: `command` still holds the child's ends; they have to be closed for the pipes to ever break.
+            drop(command);
+            return Ok(Child::new(
+                Process::Std(child),
+                stdout,
+                stderr,
+                self.settings.clone(),
+                channel,
+            )
+            .with_stdin(feeder)
+            .with_events(self.events.clone())
+            .with_control(control)
+            .with_recording(self.recording.clone()));
+        }
+        // NOTE: same for the named pipe `stdin` gets.
+        #[cfg(windows)]
+        drop(command);
+
+        // NOTE: `command` holds the child's ends of the shared pipe too, on Unix.
+        #[cfg(unix)]
+        drop(command);
+
+        let (stdout, stderr): (
+            Option<Box<dyn ChildStream + Send>>,
+            Box<dyn ChildStream + Send>,
+        ) = match merged {
+            #[cfg(unix)]
+            Some(merged) => (Some(Box::new(nonblocking(merged)?)), Box::new(Closed)),
+            #[cfg(windows)]
+            Some(merged) => (Some(Box::new(merged)), Box::new(Closed)),
+            None => {
+                let stdout = child.stdout.take();
+                let stderr = child.stderr.take();
+                #[cfg(unix)]
+                let (stdout, stderr) = (
+                    stdout.map(nonblocking).transpose()?,
+                    stderr.map(nonblocking).transpose()?,
+                );
+                (
+                    stdout.map(|stdout| Box::new(stdout) as _),
+                    stderr.map_or_else(|| Box::new(Closed) as _, |stderr| Box::new(stderr) as _),
+                )
+            }
+        };
+        #[cfg(unix)]
+        let (stdout, stderr): (
+            Option<Box<dyn ChildStream + Send>>,
+            Box<dyn ChildStream + Send>,
+        ) = match &pty {
+            Some(pty) => (Some(Box::new(pty.reader()?)), Box::new(Closed)),
+            None => (stdout, stderr),
+        };
+        #[cfg(unix)]
+        let feeder = match (input, child.stdin.take()) {
+            (Some(input), Some(stdin)) => {
+                Some(input.feed(nonblocking(stdin)?, self.settings.clock.clone()))
+            }
+            _ => None,
+        };
+
+        #[cfg(unix)]
+        let child = Process::Unix(UnixProcess::new(child.id()));
+        #[cfg(windows)]
+        let child = Process::Std(child);
+        let child = Child::new(child, stdout, stderr, self.settings.clone(), channel)
+            .with_stdin(feeder)
+            .with_events(self.events.clone())
+            .with_control(control);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let child = child.with_cgroup(cgroup);
+        #[cfg(unix)]
+        let child = child
+            .with_notify(notify)
+            .with_pty(pty)
+            .with_capture_files(capture_files);
+        Ok(child.with_recording(self.recording.clone()))
+    }
+
+    /// Spawns the child and drains its pipes until it exits.
+    pub fn run(&mut self) -> io::Result<Output> {
+        self.spawn()?.wait()
+    }
+
+    /// Spawns the child and hands it to `body`, making sure it's gone by the time `scope` returns: once `body`
+    /// returns, the child is stopped like [`Child::kill`] does if it's still running, and its pipes are drained.
+    /// Returns what `body` returned along with the output.
+    ///
+    /// If `body` panics, the child is stopped and drained all the same before the panic carries on, so it never
+    /// outlives the scope; its output is lost then.
+    pub fn scope<R>(&mut self, body: impl FnOnce(&mut Child) -> R) -> io::Result<(R, Output)> {
+        let mut scoped = Scoped(Some(self.spawn()?));
+        let result = body(
+            scoped
+                .0
+                .as_mut()
+                .expect("the child is only taken out below"),
+        );
+        // NOTE: the child stays in the guard until `wait` has it, so that a failed `kill` still leaves it stopped
+        // and drained.
+        let child = scoped
+            .0
+            .as_mut()
+            .expect("the child is only taken out below");
+        child.kill()?;
+        let child = scoped.0.take().expect("the child is only taken out here");
+        Ok((result, child.wait()?))
+    }
+}
+
+/// Stops and drains a [`Pipe2::scope`]'s child if it's dropped while still holding it, like when the scope's body
+/// panics.
+struct Scoped(Option<Child>);
+
+impl Drop for Scoped {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.0.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! `pipe2.toml` and `pipe2 run TASK`: named commands, with the options to run them with, kept in a file instead of
+//! long command lines.
+//!
+//! ```toml
+//! [tasks.server]
+//! program = "./server"
+//! args = ["--port", "8080"]
+//! env = { RUST_LOG = "debug" }
+//! cwd = "services/api"
+//! timeout = "1h"
+//! restart = "on-failure"
+//! max_restarts = 5
+//! report = "server.json"
+//! options = ["--heartbeat", "30s"]
+//! ```
+//!
+//! A task is turned into the command line that would do the same, so every option has the same meaning in both.
+//! `label`, `stdin_file`, `report`, `junit`, `events`, `metrics_file`, `metrics_addr`, `log_dir`, `run_dir`, and the
+//! booleans `summary`, `report_env` and `ci_group` stand for the options of the same name; `options` takes any others as
+//! is.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::template::Variables;
+
+/// Looked for in the current directory, unless `PIPE2_CONFIG` points somewhere else.
+pub const FILE: &str = "pipe2.toml";
+
+/// The file; anything besides `tasks` is left for other tools.
+#[derive(Deserialize)]
+struct Config {
+    #[serde(default)]
+    tasks: BTreeMap<String, Task>,
+}
+
+/// One `[tasks.NAME]`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Task {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: BTreeMap<String, Arg>,
+    #[serde(default)]
+    options: Vec<String>,
+    cwd: Option<Arg>,
+    label: Option<Arg>,
+    success_codes: Option<Arg>,
+    stdin_file: Option<Arg>,
+    timeout: Option<Arg>,
+    restart: Option<Arg>,
+    max_restarts: Option<Arg>,
+    report: Option<Arg>,
+    junit: Option<Arg>,
+    events: Option<Arg>,
+    metrics_file: Option<Arg>,
+    metrics_addr: Option<Arg>,
+    log_dir: Option<Arg>,
+    run_dir: Option<Arg>,
+    #[serde(default)]
+    summary: bool,
+    #[serde(default)]
+    report_env: bool,
+    #[serde(default)]
+    ci_group: bool,
+}
+
+/// A string or a number, for an option's value.
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a string or a number")]
+enum Arg {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl Arg {
+    /// As it'd be given on the command line.
+    fn to_arg(&self) -> String {
+        match self {
+            Self::String(value) => value.clone(),
+            Self::Integer(value) => value.to_string(),
+            Self::Float(value) => value.to_string(),
+        }
+    }
+}
+
+impl Task {
+    /// The options, then `--`, the program and its arguments.
+    fn args(&self, variables: &Variables) -> Result<Vec<String>, String> {
+        let mut all = Vec::new();
+        for (name, value) in &self.env {
+            all.push("--env".to_owned());
+            all.push(format!("{name}={}", variables.fill(&value.to_arg())?));
+        }
+        let options = [
+            ("--cwd", &self.cwd),
+            ("--label", &self.label),
+            ("--success-codes", &self.success_codes),
+            ("--stdin-file", &self.stdin_file),
+            ("--timeout", &self.timeout),
+            ("--restart", &self.restart),
+            ("--max-restarts", &self.max_restarts),
+            ("--report", &self.report),
+            ("--junit", &self.junit),
+            ("--events", &self.events),
+            ("--metrics-file", &self.metrics_file),
+            ("--metrics-addr", &self.metrics_addr),
+            ("--log-dir", &self.log_dir),
+            ("--run-dir", &self.run_dir),
+        ];
+        for (option, value) in options {
+            if let Some(value) = value {
+                all.push(option.to_owned());
+                all.push(variables.fill(&value.to_arg())?);
+            }
+        }
+        let flags = [
+            ("--summary", self.summary),
+            ("--report-env", self.report_env),
+            ("--ci-group", self.ci_group),
+        ];
+        all.extend(
+            flags
+                .into_iter()
+                .filter(|(_, on)| *on)
+                .map(|(flag, _)| flag.to_owned()),
+        );
+        all.extend(self.options.iter().cloned());
+        all.push("--".to_owned());
+        all.push(variables.fill(&self.program)?);
+        for arg in &self.args {
+            all.push(variables.fill(arg)?);
+        }
+        Ok(all)
+    }
+}
+
+/// The arguments `pipe2 run TASK EXTRA...` stands for, from the configuration file.
+///
+/// With templating on, placeholders are filled in everywhere but in `options`, which are passed on as written.
+pub fn task_args(
+    task: &str,
+    extra: impl Iterator<Item = OsString>,
+    variables: &Variables,
+) -> Result<Vec<OsString>, String> {
+    let path = std::env::var_os("PIPE2_CONFIG").unwrap_or_else(|| FILE.into());
+    let path = Path::new(&path);
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+    let tasks = parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+    let found = tasks
+        .get(task)
+        .ok_or_else(|| format!("no task {task:?} in {}", path.display()))?;
+
+    let mut all: Vec<OsString> = found
+        .args(variables)?
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+    for arg in extra {
+        all.push(variables.fill_os(arg)?);
+    }
+    Ok(all)
+}
+
+/// Every task in the file, by name.
+fn parse(text: &str) -> Result<BTreeMap<String, Task>, String> {
+    let config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+    Ok(config.tasks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str, task: &str) -> Result<Vec<String>, String> {
+        let tasks = parse(text)?;
+        tasks[task].args(&Variables::default())
+    }
+
+    #[test]
+    fn task() {
+        let text = r#"
+            # a comment
+            [tasks.server]
+            program = "./server"
+            args = [
+                "--port", # the port
+                "8080",
+            ]
+            env = { RUST_LOG = "debug", THREADS = 4 }
+            timeout = "1h"
+            max_restarts = 5
+            summary = true
+            report_env = false
+            options = ["--heartbeat", "30s"]
+        "#;
+        assert_eq!(
+            args(text, "server").unwrap(),
+            [
+                "--env",
+                "RUST_LOG=debug",
+                "--env",
+                "THREADS=4",
+                "--timeout",
+                "1h",
+                "--max-restarts",
+                "5",
+                "--summary",
+                "--heartbeat",
+                "30s",
+                "--",
+                "./server",
+                "--port",
+                "8080",
+            ]
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let text = r#"
+            [tasks."build.release"]
+            program = 'cargo'
+            args = ["build", "--release"]
+
+            [tasks."build.release".env]
+            "CARGO_TERM_COLOR" = "always"
+
+            [other]
+            ignored = true
+        "#;
+        assert_eq!(
+            args(text, "build.release").unwrap(),
+            [
+                "--env",
+                "CARGO_TERM_COLOR=always",
+                "--",
+                "cargo",
+                "build",
+                "--release"
+            ]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let text = r#"
+            [tasks.echo]
+            program = "echo"
+            args = ["a\tb \"c\" # d", 'C:\path', """
+multi"""]
+        "#;
+        assert_eq!(
+            args(text, "echo").unwrap(),
+            ["--", "echo", "a\tb \"c\" # d", "C:\\path", "multi"]
+        );
+    }
+
+    #[test]
+    fn fills_in() {
+        let text = r#"
+            [tasks.build]
+            program = "cargo"
+            args = ["build", "--target", "{target}"]
+            options = ["--label", "{target}"]
+        "#;
+        let mut variables = Variables::default();
+        variables.set("target=wasm32-wasip1").unwrap();
+        let tasks = parse(text).unwrap();
+        assert_eq!(
+            tasks["build"].args(&variables).unwrap(),
+            [
+                "--label",
+                "{target}",
+                "--",
+                "cargo",
+                "build",
+                "--target",
+                "wasm32-wasip1"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        // NOTE: a duplicate key, a duplicate table, a key that isn't an option, a missing program, and values of the
+        // wrong type.
+        for text in [
+            "[tasks.a]\nprogram = \"a\"\nprogram = \"b\"",
+            "[tasks.a]\nprogram = \"a\"\n[tasks.a]\nargs = []",
+            "[tasks.a]\nprogram = \"a\"\nretsart = \"always\"",
+            "[tasks.a]\nargs = []",
+            "[tasks.a]\nprogram = \"a\"\ntimeout = true",
+            "[tasks.a]\nprogram = \"a\"\nargs = \"b\"",
+            "[tasks.a]\nprogram = \"a\"\nsummary = \"yes\"",
+            "[tasks.a\nprogram = \"a\"",
+            "[tasks.a]\nprogram = \"a",
+        ] {
+            assert!(parse(text).is_err(), "{text:?}");
+        }
+    }
+}
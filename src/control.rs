@@ -0,0 +1,205 @@
+//! A socket other tools can manage the child through, see [`Pipe2::control_socket`](crate::Pipe2::control_socket).
+//!
+//! Unix uses a Unix domain socket at the given path, Windows a named pipe by that name (`\\.\pipe\...`). Each
+//! connection sends one command on a line, gets one line back, and is closed: `status` answers with a JSON object
+//! about the child, `stop` stops it the way [`Child::kill`](crate::Child::kill) does, and `kill` kills it right away.
+//! Like everything else, connections are served from [`Child::poll`](crate::Child::poll), without ever blocking it.
+
+use std::io;
+use std::path::Path;
+
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+#[cfg(windows)]
+use std::os::windows::io::OwnedHandle;
+
+/// Commands are cut off past this many bytes; none of them is anywhere near it.
+const MAX_COMMAND: usize = 256;
+
+/// What can be asked over the socket.
+pub(crate) enum Command {
+    Status,
+    Stop,
+    Kill,
+}
+
+impl Command {
+    fn parse(line: &[u8]) -> Result<Self, String> {
+        let line = String::from_utf8_lossy(line);
+        match line.trim() {
+            "status" => Ok(Self::Status),
+            "stop" => Ok(Self::Stop),
+            "kill" => Ok(Self::Kill),
+            command => Err(format!("error: unknown command {command:?}")),
+        }
+    }
+}
+
+pub(crate) struct ControlSocket {
+    #[cfg(unix)]
+    listener: UnixListener,
+    #[cfg(unix)]
+    path: PathBuf,
+    /// Connections whose command hasn't come in whole yet, with what they've sent so far.
+    #[cfg(unix)]
+    clients: Vec<(UnixStream, Vec<u8>)>,
+    #[cfg(windows)]
+    pipe: OwnedHandle,
+    /// What the connected client sent so far, if there's one.
+    #[cfg(windows)]
+    client: Option<Vec<u8>>,
+    /// Whether the client got its answer, and only has to go before the next one can connect.
+    #[cfg(windows)]
+    replied: bool,
+}
+
+impl ControlSocket {
+    /// Starts listening at `path`. A socket left behind there by a process that's gone is replaced; one that's still
+    /// being listened on is not, and neither is anything else that's there.
+    #[cfg(unix)]
+    pub(crate) fn bind(path: &Path) -> io::Result<Self> {
+        remove_stale_socket(path)?;
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path: path.to_owned(),
+            clients: Vec::new(),
+        })
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn bind(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            pipe: crate::windows_pipe_utils::control_pipe(path.as_os_str())?,
+            client: None,
+            replied: false,
+        })
+    }
+
+    /// Takes in new connections and whatever they sent, and answers the commands that came in whole with `handle`.
+    #[cfg(unix)]
+    pub(crate) fn serve(
+        &mut self,
+        mut handle: impl FnMut(Command) -> io::Result<String>,
+    ) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(true)?;
+                    self.clients.push((stream, Vec::new()));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut scratchpad = [0u8; MAX_COMMAND];
+        let mut i = 0;
+        while i < self.clients.len() {
+            let (stream, received) = &mut self.clients[i];
+            let eof = match stream.read(&mut scratchpad) {
+                Ok(0) => true,
+                Ok(n) => {
+                    received.extend_from_slice(&scratchpad[..n]);
+                    false
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => false,
+                // NOTE: a client that went wrong is just dropped; it's no reason to stop the run.
+                Err(_) => true,
+            };
+            let line = match received.iter().position(|&byte| byte == b'\n') {
+                Some(end) => Some(&received[..end]),
+                None if eof || received.len() >= MAX_COMMAND => Some(&received[..]),
+                None => None,
+            };
+            let Some(line) = line else {
+                i += 1;
+                continue;
+            };
+            let reply = match Command::parse(line) {
+                Ok(command) => handle(command)?,
+                Err(e) => e,
+            };
+            let (mut stream, _) = self.clients.swap_remove(i);
+            // NOTE: the reply is tiny, so it fits in the socket buffer whether or not the client reads it.
+            let _ = stream.write_all(format!("{reply}\n").as_bytes());
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn serve(
+        &mut self,
+        mut handle: impl FnMut(Command) -> io::Result<String>,
+    ) -> io::Result<()> {
+        use crate::windows_pipe_utils::*;
+
+        if self.client.is_none() && accept(&self.pipe)? {
+            self.client = Some(Vec::new());
+        }
+        let Some(received) = &mut self.client else {
+            return Ok(());
+        };
+        // NOTE: disconnecting throws away whatever the client hasn't read yet, so it's only done once the client has
+        // closed its end.
+        let mut scratchpad = [0u8; MAX_COMMAND];
+        if !self.replied && can_read(&self.pipe)? {
+            let n = read_pipe(&mut self.pipe, &mut scratchpad)?;
+            received.extend_from_slice(&scratchpad[..n]);
+        } else if peer_closed(&self.pipe) {
+            (self.client, self.replied) = (None, false);
+            return disconnect(&self.pipe);
+        }
+        if self.replied {
+            return Ok(());
+        }
+        let line = match received.iter().position(|&byte| byte == b'\n') {
+            Some(end) => &received[..end],
+            None if received.len() >= MAX_COMMAND => &received[..],
+            None => return Ok(()),
+        };
+        let reply = match Command::parse(line) {
+            Ok(command) => handle(command)?,
+            Err(e) => e,
+        };
+        let _ = write_pipe(&mut self.pipe, format!("{reply}\n").as_bytes());
+        self.replied = true;
+        Ok(())
+    }
+}
+
+/// Clears the way for a socket at `path`, removing one left behind there by a process that's gone. One that's still
+/// being listened on is left for `bind` to fail on, and anything that isn't a socket fails with `AlreadyExists`.
+#[cfg(unix)]
+pub fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists, and isn't a socket", path.display()),
+        ));
+    }
+    if UnixStream::connect(path).is_err() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,254 @@
+//! `--detach`: pipe2 starts a copy of itself in the background to supervise the program, with its output going to
+//! rotating log files, prints that copy's PID and exits.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use pipe2::{Child, Pipe2, SystemClock};
+
+use crate::cli::Restart;
+use crate::metrics::Exporter;
+
+/// Passed to the background copy, ahead of the original arguments.
+pub const SUPERVISE_FLAG: &str = "--supervise";
+
+/// Starts the supervisor and waits until it has either started the program or failed to.
+pub fn detach() -> io::Result<()> {
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg(SUPERVISE_FLAG)
+        .args(std::env::args_os().skip(1))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    // NOTE: a session of its own, so the supervisor loses our controlling terminal and outlives a closed one.
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        command.pre_exec(|| {
+            nix::unistd::setsid()?;
+            Ok(())
+        });
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        use winapi::um::winbase::{CREATE_NEW_PROCESS_GROUP, DETACHED_PROCESS};
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let mut supervisor = command.spawn()?;
+    let mut line = String::new();
+    BufReader::new(supervisor.stdout.take().expect("Failed to capture stdout"))
+        .read_line(&mut line)?;
+    match line.trim_end().strip_prefix("error: ") {
+        Some(e) => Err(io::Error::other(e.to_owned())),
+        None if line.is_empty() => Err(io::Error::other(
+            "the supervisor exited before starting the program",
+        )),
+        None => {
+            println!("{}", supervisor.id());
+            Ok(())
+        }
+    }
+}
+
+/// The supervisor's side: runs the program, reporting back to [`detach`] over `stdout` once it's started, and
+/// returns its exit code.
+///
+/// The program is started again as long as `restart` says so, and it wasn't stopped by a signal to us.
+pub fn supervise(
+    pipe2: &mut Pipe2,
+    logs: &Logs,
+    restart: Restart,
+    mut metrics: Option<&mut Exporter>,
+) -> i32 {
+    let started = pipe2
+        .echo(false)
+        .spawn()
+        .and_then(|child| Ok((child, logs.open()?)));
+    let (mut child, (mut stdout, mut stderr)) = match started {
+        Ok(started) => started,
+        Err(e) => {
+            println!("error: {e}");
+            return 1;
+        }
+    };
+    println!("started");
+    // NOTE: whoever ran `--detach` is gone after reading the line above; writing there again would only fail.
+    let _ = io::stdout().flush();
+
+    #[cfg(unix)]
+    let stop = stop_on_signals();
+    #[cfg(unix)]
+    let stopped = || stop.load(std::sync::atomic::Ordering::Relaxed);
+    #[cfg(not(unix))]
+    let stopped = || false;
+
+    let mut restarts = 0;
+    loop {
+        let status = watch(
+            &mut child,
+            &mut stdout,
+            &mut stderr,
+            &stopped,
+            metrics.as_deref_mut(),
+        );
+        let Some(status) = status else {
+            return 1;
+        };
+        if !restart.again(status.success(), restarts) {
+            return status.code().unwrap_or(1);
+        }
+        restarts += 1;
+        if let Some(metrics) = metrics.as_deref_mut() {
+            metrics.restarted();
+        }
+        if !restart.delay(&SystemClock, stopped) {
+            return status.code().unwrap_or(1);
+        }
+        // NOTE: the logs say why it couldn't be started again; there's no one else left to tell.
+        child = match pipe2.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let _ =
+                    stderr.write(format!("pipe2: couldn't restart the program: {e}\n").as_bytes());
+                return 1;
+            }
+        };
+    }
+}
+
+/// Polls `child` until it exits, writing its output to the logs. `None` if polling failed.
+fn watch(
+    child: &mut Child,
+    stdout: &mut RotatingLog,
+    stderr: &mut RotatingLog,
+    stopped: &dyn Fn() -> bool,
+    mut metrics: Option<&mut Exporter>,
+) -> Option<ExitStatus> {
+    loop {
+        if stopped() {
+            let _ = child.kill();
+        }
+
+        let status = child.poll().ok()?;
+        if let Some(metrics) = metrics.as_deref_mut() {
+            metrics.update(child, status);
+        }
+        let (out, err) = (child.take_stdout(), child.take_stderr());
+        let _ = stdout.write(&out);
+        let _ = stderr.write(&err);
+
+        if status.is_some() {
+            return status;
+        }
+        // NOTE: each poll only reads so much, so there's no sleeping while the program keeps writing.
+        if out.is_empty() && err.is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Has `SIGTERM`, `SIGINT` and `SIGHUP` stop the program the way [`pipe2::Child::kill`] does, rather than leaving it
+/// behind without a supervisor.
+#[cfg(unix)]
+fn stop_on_signals() -> &'static std::sync::atomic::AtomicBool {
+    use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static STOP: AtomicBool = AtomicBool::new(false);
+    extern "C" fn handler(_: libc::c_int) {
+        STOP.store(true, Ordering::Relaxed);
+    }
+
+    let action = SigAction::new(
+        SigHandler::Handler(handler),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP] {
+        let _ = unsafe { sigaction(signal, &action) };
+    }
+    &STOP
+}
+
+/// Where the program's output goes, and how much of it is kept.
+pub struct Logs {
+    pub dir: PathBuf,
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+impl Logs {
+    fn open(&self) -> io::Result<(RotatingLog, RotatingLog)> {
+        fs::create_dir_all(&self.dir)?;
+        Ok((
+            RotatingLog::open(self.dir.join("stdout.log"), self)?,
+            RotatingLog::open(self.dir.join("stderr.log"), self)?,
+        ))
+    }
+}
+
+/// Appends to `path`, moving it to `path.1` (and `path.1` to `path.2`, and so on) once it grows past the size limit.
+struct RotatingLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl RotatingLog {
+    fn open(path: PathBuf, logs: &Logs) -> io::Result<Self> {
+        let file = append(&path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            path,
+            file,
+            max_size: logs.max_size,
+            keep: logs.keep,
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.size > 0 && self.size + data.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| rotated(&self.path, n);
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(numbered(n), numbered(n + 1));
+            }
+            fs::rename(&self.path, numbered(1))?;
+            self.file = append(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
@@ -0,0 +1,203 @@
+//! `pipe2 each`: runs the command once per item read from `stdin`, like `xargs`, a few at a time.
+//!
+//! Each run's output is captured and written out in one piece once it's done, so runs going at the same time don't
+//! interleave, and the failures are listed at the end. With `--live`, it's echoed as it comes instead, where runs
+//! going at the same time only interleave whole lines. With `--summary`, a table of every run follows.
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use pipe2::{Child, Output, Pipe2, RunSummary};
+
+use crate::cli::Cli;
+
+/// Replaced by the item in the arguments; without one, the item is added as the last argument.
+pub const PLACEHOLDER: &str = "{}";
+
+/// `each`'s own options.
+pub struct Batch {
+    pub max_procs: usize,
+    /// Items are separated by NUL rather than newlines, as with `find -print0`.
+    pub null: bool,
+    /// Output is echoed as it comes, rather than once each run is done.
+    pub live: bool,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self {
+            max_procs: 1,
+            null: false,
+            live: false,
+        }
+    }
+}
+
+/// Runs everything, and returns 0 if every run succeeded, or 123 like `xargs` if any didn't.
+pub fn run(cli: &Cli, batch: &Batch) -> io::Result<i32> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+    let delimiter = if batch.null { b'\0' } else { b'\n' };
+    let mut queue: VecDeque<OsString> = input
+        .split(|&byte| byte == delimiter)
+        .map(|item| match delimiter {
+            b'\n' => item.strip_suffix(b"\r").unwrap_or(item),
+            _ => item,
+        })
+        .filter(|item| !item.is_empty())
+        .map(os_string)
+        .collect();
+    let total = queue.len();
+
+    let mut running: Vec<Running> = Vec::new();
+    let mut finished: VecDeque<Finished> = VecDeque::new();
+    let mut failures = Vec::new();
+    let mut summaries = Vec::new();
+    let (writing, writer) = writer();
+    while !queue.is_empty() || !running.is_empty() || !finished.is_empty() {
+        while running.len() < batch.max_procs.max(1)
+            && let Some(item) = queue.pop_front()
+        {
+            match command(cli, &item, batch.live).spawn() {
+                Ok(child) => running.push((item, Instant::now(), child)),
+                Err(e) => failures.push((item, e.to_string())),
+            }
+        }
+
+        reap(&mut running, &mut finished)?;
+        let Some((item, duration, child)) = finished.pop_front() else {
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+        let output = child.wait()?;
+        summaries.push(RunSummary::new(item.to_string_lossy(), &output, duration));
+        if let Some(failure) = failure(&output) {
+            failures.push((item, failure));
+        }
+        // NOTE: a send only fails once the writer has given up, and joining it below says why.
+        if !batch.live && writing.send((output.stdout, output.stderr)).is_err() {
+            break;
+        }
+    }
+    drop(writing);
+    writer.join().expect("the writer doesn't panic")?;
+
+    if cli.summary {
+        eprint!("\n{}", RunSummary::table(&summaries));
+    }
+    if failures.is_empty() {
+        return Ok(0);
+    }
+    eprintln!("pipe2: {} of {total} runs failed:", failures.len());
+    for (item, failure) in &failures {
+        eprintln!("  {} ({failure})", item.to_string_lossy());
+    }
+    Ok(123)
+}
+
+/// A run going on: its item, when it was spawned, and the child.
+type Running = (OsString, Instant, Child);
+
+/// A run that's over, waiting for its output to be written out: its item, how long it took, and the child.
+type Finished = (OsString, Duration, Child);
+
+/// A finished run's `stdout` and `stderr`, for the writer.
+type Captured = (Vec<u8>, Vec<u8>);
+
+/// Polls every run going on, which reaps those that have exited, and sets them aside in the order they did.
+///
+/// Every run is polled on every pass, rather than only up to the first one found to be over, so none is left a zombie,
+/// or with its pipes filling up, until the next pass.
+fn reap(running: &mut Vec<Running>, finished: &mut VecDeque<Finished>) -> io::Result<()> {
+    let mut index = 0;
+    while index < running.len() {
+        if running[index].2.poll()?.is_some() {
+            let (item, spawned, child) = running.remove(index);
+            finished.push_back((item, spawned.elapsed(), child));
+        } else {
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+/// A thread writing out each finished run's `stdout` and `stderr` in turn, so that a slow reader of ours doesn't hold up
+/// reaping the runs that exit meanwhile.
+fn writer() -> (Sender<Captured>, JoinHandle<io::Result<()>>) {
+    let (sender, receiver) = mpsc::channel::<Captured>();
+    let thread = thread::spawn(move || {
+        for (stdout, stderr) in receiver {
+            io::stdout().write_all(&stdout)?;
+            io::stdout().flush()?;
+            io::stderr().write_all(&stderr)?;
+        }
+        Ok(())
+    });
+    (sender, thread)
+}
+
+/// The command for one item, with its output captured, and only relayed if `live`.
+fn command(cli: &Cli, item: &OsString, live: bool) -> Pipe2 {
+    let mut args: Vec<OsString> = cli.args.iter().map(|arg| substitute(arg, item)).collect();
+    if !cli
+        .args
+        .iter()
+        .any(|arg| arg.to_string_lossy().contains(PLACEHOLDER))
+    {
+        args.push(item.clone());
+    }
+    let mut pipe2 = Pipe2::new(substitute(&cli.program, item));
+    pipe2.args(args);
+    cli.configure(&mut pipe2);
+    pipe2.echo(live);
+    // NOTE: runs are many and small here, which is what `posix_spawn` is for.
+    #[cfg(unix)]
+    pipe2.posix_spawn(true);
+    pipe2
+}
+
+fn substitute(arg: &OsString, item: &OsString) -> OsString {
+    match (arg.to_str(), item.to_str()) {
+        (Some(arg), Some(item)) => arg.replace(PLACEHOLDER, item).into(),
+        // NOTE: an item that isn't UTF-8 can still be passed whole, just not spliced into a longer argument.
+        (Some(PLACEHOLDER), None) => item.clone(),
+        _ => arg.clone(),
+    }
+}
+
+fn failure(output: &Output) -> Option<String> {
+    if output.timed_out {
+        return Some("timed out".to_owned());
+    }
+    if output.first_output_timed_out {
+        return Some("wrote nothing in time".to_owned());
+    }
+    if output.output_closed_timed_out {
+        return Some("kept running too long after closing its output".to_owned());
+    }
+    if output.cpu_limit_exceeded {
+        return Some("exceeded its CPU limit".to_owned());
+    }
+    if output.success {
+        return None;
+    }
+    match output.status.code() {
+        Some(code) => Some(format!("exit code {code}")),
+        None => Some(output.status.to_string()),
+    }
+}
+
+#[cfg(unix)]
+fn os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes.to_vec())
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
@@ -0,0 +1,246 @@
+//! Relaying the output to our own `stdout`/`stderr` as it's read, which can differ from what's captured: the capture
+//! always gets the exact bytes, the terminal gets something it can show.
+
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+
+use crate::child::Settings;
+use crate::severity::{self, Classifier};
+
+/// What happens when the echo can't be written anymore, because our own `stdout` or `stderr` was closed, like when
+/// piped into `head`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BrokenPipe {
+    /// Fail the run with the write's error.
+    #[default]
+    Error,
+    /// Stop echoing that stream, and keep capturing it until the child is done.
+    Ignore,
+    /// Stop echoing that stream, and stop the child the way it would have been if it were writing to the closed pipe
+    /// itself: with `SIGPIPE` on Unix (and `SIGKILL` after the grace period), terminated on Windows.
+    Kill,
+}
+
+/// One stream's echo, with whatever it needs to remember between chunks.
+pub(crate) struct Echo {
+    hexdump: bool,
+    /// Where the next chunk starts in the stream, for the hexdump's offsets.
+    offset: u64,
+    /// The start of a UTF-8 character that was split across reads, held back until the rest of it arrives.
+    pending: Vec<u8>,
+    squash_repeats: bool,
+    /// The start of a line that hasn't ended yet, when the echo goes line by line.
+    line: Vec<u8>,
+    /// The last line echoed, and how many times it came again right after.
+    last: Option<(Vec<u8>, u64)>,
+    pretty_json: bool,
+    /// Whether the terminal gets colors.
+    color: bool,
+    /// For coloring lines by severity, on a terminal.
+    classifiers: Vec<Classifier>,
+    /// Put in front of every line, as `[label] `.
+    label: Option<String>,
+}
+
+impl Echo {
+    /// The echo for `stdout` if `stdout` is set, `stderr` otherwise.
+    pub(crate) fn new(settings: &Settings, stdout: bool) -> Self {
+        let color = std::env::var_os("NO_COLOR").is_none()
+            && if stdout {
+                io::stdout().is_terminal()
+            } else {
+                io::stderr().is_terminal()
+            };
+        Self {
+            hexdump: settings.hexdump,
+            offset: 0,
+            pending: Vec::new(),
+            squash_repeats: settings.squash_repeats,
+            line: Vec::new(),
+            last: None,
+            pretty_json: settings.pretty_json && stdout,
+            color,
+            classifiers: if color {
+                settings.classifiers.clone()
+            } else {
+                Vec::new()
+            },
+            label: settings.label.clone(),
+        }
+    }
+
+    /// Whether whole lines are echoed at a time, rather than chunks as they come.
+    fn by_line(&self) -> bool {
+        self.squash_repeats
+            || self.pretty_json
+            || !self.classifiers.is_empty()
+            || self.label.is_some()
+    }
+
+    /// Relays a chunk just read from the child.
+    pub(crate) fn write(&mut self, out: &mut impl Write, chunk: &[u8]) -> io::Result<()> {
+        if !self.hexdump {
+            self.text(out, chunk)?;
+            return out.flush();
+        }
+
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(chunk);
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) => Some(text.len()),
+            // NOTE: only a character cut off at the end is waited on; anything else invalid makes the chunk binary.
+            Err(e) if e.error_len().is_none() => Some(e.valid_up_to()),
+            Err(_) => None,
+        };
+        let end = match text {
+            Some(end) if is_printable(&bytes[..end]) => {
+                self.text(out, &bytes[..end])?;
+                self.pending = bytes[end..].to_vec();
+                end
+            }
+            _ => {
+                self.end_lines(out)?;
+                out.write_all(hexdump(self.offset, &bytes).as_bytes())?;
+                bytes.len()
+            }
+        };
+        self.offset += end as u64;
+        out.flush()
+    }
+
+    /// Relays whatever was held back, once the stream is done.
+    pub(crate) fn finish(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.end_lines(out)?;
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            out.write_all(hexdump(self.offset, &pending).as_bytes())?;
+            self.offset += pending.len() as u64;
+        }
+        out.flush()
+    }
+
+    fn text(&mut self, out: &mut impl Write, text: &[u8]) -> io::Result<()> {
+        if !self.by_line() {
+            return out.write_all(text);
+        }
+        for piece in text.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(piece);
+            if piece.ends_with(b"\n") {
+                let line = std::mem::take(&mut self.line);
+                self.write_line(out, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Echoes a whole line, newline included.
+    fn write_line(&mut self, out: &mut impl Write, line: Vec<u8>) -> io::Result<()> {
+        if self.squash_repeats {
+            if let Some((last, repeats)) = &mut self.last
+                && *last == line
+            {
+                *repeats += 1;
+                return Ok(());
+            }
+            self.end_repeats(out)?;
+            self.show(out, &line)?;
+            self.last = Some((line, 0));
+            return Ok(());
+        }
+        self.show(out, &line)
+    }
+
+    /// Writes out a line that's been decided on, pretty-printed if it's JSON and that's asked for, or colored by its
+    /// severity.
+    fn show(&self, out: &mut impl Write, line: &[u8]) -> io::Result<()> {
+        self.prefix(out)?;
+        if self.pretty_json
+            && let Ok(text) = std::str::from_utf8(line)
+            && let Some(pretty) = crate::pretty_json::pretty(text, self.color)
+        {
+            return writeln!(out, "{pretty}");
+        }
+        if let Some(severity) = severity::classify(&self.classifiers, line) {
+            let (text, newline) = match line.strip_suffix(b"\n") {
+                Some(text) => (text, "\n"),
+                None => (line, ""),
+            };
+            out.write_all(severity.color().as_bytes())?;
+            out.write_all(text)?;
+            return write!(out, "\x1b[0m{newline}");
+        }
+        out.write_all(line)
+    }
+
+    /// Says how many times the last line was repeated, if it was.
+    fn end_repeats(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let repeats = match self.last.take() {
+            Some((_, repeats @ 1..)) => repeats,
+            _ => return Ok(()),
+        };
+        self.prefix(out)?;
+        match repeats {
+            1 => writeln!(out, "last message repeated 1 time"),
+            _ => writeln!(out, "last message repeated {repeats} times"),
+        }
+    }
+
+    /// The label that starts every line, dimmed on a terminal.
+    fn prefix(&self, out: &mut impl Write) -> io::Result<()> {
+        match &self.label {
+            Some(label) if self.color => write!(out, "\x1b[2m[{label}]\x1b[0m "),
+            Some(label) => write!(out, "[{label}] "),
+            None => Ok(()),
+        }
+    }
+
+    /// Echoes whatever is still held back of the lines, for a stream that's done, or about to show something else.
+    fn end_lines(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.write_line(out, line)?;
+        }
+        self.end_repeats(out)
+    }
+}
+
+/// Valid UTF-8 without control characters, apart from tabs, line breaks and the escape that starts color codes.
+fn is_printable(text: &[u8]) -> bool {
+    std::str::from_utf8(text).is_ok_and(|text| {
+        text.chars()
+            .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t' | '\x1b'))
+    })
+}
+
+/// `hexdump -C`-style lines for `bytes`, found at `offset` in the stream:
+///
+/// ```text
+/// 00000010  48 65 6c 6c 6f 00 01 02  03 0a 77 6f 72 6c 64 21  |Hello.....world!|
+/// ```
+fn hexdump(offset: u64, bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", offset + i as u64 * 16);
+        for j in 0..16 {
+            if j == 8 {
+                dump.push(' ');
+            }
+            match line.get(j) {
+                Some(byte) => {
+                    let _ = write!(dump, " {byte:02x}");
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        for &byte in line {
+            dump.push(if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            });
+        }
+        dump.push_str("|\n");
+    }
+    dump
+}
@@ -0,0 +1,88 @@
+//! A machine-readable log of what happened during a run, one JSON object per line, apart from the output itself.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value, json};
+
+use crate::clock::Clock;
+use crate::run_id::RunId;
+
+/// Shared between every child spawned from the same builder, so their events end up interleaved in one log.
+pub(crate) type EventSink = Arc<Mutex<Box<dyn Write + Send>>>;
+
+pub(crate) struct EventLog {
+    sink: EventSink,
+    /// What `elapsed` goes by, see [`Pipe2::clock`](crate::Pipe2::clock); `time` is always the system's.
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    pid: u32,
+    label: Option<String>,
+    run_id: RunId,
+    correlation_id: Option<String>,
+}
+
+impl EventLog {
+    pub(crate) fn new(
+        sink: EventSink,
+        clock: Arc<dyn Clock>,
+        pid: u32,
+        label: Option<String>,
+        run_id: RunId,
+        correlation_id: Option<String>,
+    ) -> Self {
+        let log = Self {
+            sink,
+            started: clock.now(),
+            clock,
+            pid,
+            label,
+            run_id,
+            correlation_id,
+        };
+        log.emit("spawned", json!({}));
+        log
+    }
+
+    /// Writes `{"time": ..., "elapsed": ..., "pid": ..., "run_id": ..., "correlation_id": ..., "label": ...,
+    /// "event": event, ...fields}`, without the `correlation_id` or `label` if there's none.
+    ///
+    /// NOTE: failing to write an event doesn't fail the run; the log is there to observe it, not to take part.
+    pub(crate) fn emit(&self, event: &str, fields: Value) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut line = Map::new();
+        line.insert("time".to_owned(), json!(time));
+        line.insert(
+            "elapsed".to_owned(),
+            json!(
+                self.clock
+                    .now()
+                    .saturating_duration_since(self.started)
+                    .as_secs_f64()
+            ),
+        );
+        line.insert("pid".to_owned(), json!(self.pid));
+        line.insert("run_id".to_owned(), json!(self.run_id.to_string()));
+        if let Some(correlation_id) = &self.correlation_id {
+            line.insert("correlation_id".to_owned(), json!(correlation_id));
+        }
+        if let Some(label) = &self.label {
+            line.insert("label".to_owned(), json!(label));
+        }
+        line.insert("event".to_owned(), json!(event));
+        if let Value::Object(fields) = fields {
+            line.extend(fields);
+        }
+
+        let Ok(mut sink) = self.sink.lock() else {
+            return;
+        };
+        let mut bytes = Value::Object(line).to_string().into_bytes();
+        bytes.push(b'\n');
+        let _ = sink.write_all(&bytes).and_then(|()| sink.flush());
+    }
+}
@@ -0,0 +1,232 @@
+//! A scripted stand-in for a real child, for testing capture behavior reproducibly on every platform: what it writes to
+//! which stream, with which pauses in between, how it exits, and whether it shrugs off being asked to stop.
+//!
+//! The script travels as the arguments of a small helper binary, `pipe2-fake-child`, which runs it through
+//! [`FakeChild::main`]; a crate with a helper binary of its own can call that from its `main` instead.
+
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::child::Output;
+use crate::command::Pipe2;
+use crate::stream::Stream;
+
+/// A script for the helper binary to act out, built up step by step; see [`FakeChild::command`].
+#[derive(Clone, Debug, Default)]
+pub struct FakeChild {
+    steps: Vec<Step>,
+    exit_code: i32,
+    ignore_signals: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Write(Stream, Vec<u8>),
+    Sleep(Duration),
+}
+
+impl FakeChild {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `bytes` to `stdout`, in one go.
+    pub fn stdout<B: AsRef<[u8]>>(&mut self, bytes: B) -> &mut Self {
+        self.write(Stream::Stdout, bytes)
+    }
+
+    /// Writes `bytes` to `stderr`, in one go.
+    pub fn stderr<B: AsRef<[u8]>>(&mut self, bytes: B) -> &mut Self {
+        self.write(Stream::Stderr, bytes)
+    }
+
+    /// Writes `bytes` to `stream`, in one go.
+    pub fn write<B: AsRef<[u8]>>(&mut self, stream: Stream, bytes: B) -> &mut Self {
+        self.steps
+            .push(Step::Write(stream, bytes.as_ref().to_vec()));
+        self
+    }
+
+    /// Writes `count` numbered lines, `line 1` to `line {count}`, to `stream`, one write each.
+    pub fn lines(&mut self, stream: Stream, count: usize) -> &mut Self {
+        for i in 1..=count {
+            self.write(stream, format!("line {i}\n"));
+        }
+        self
+    }
+
+    /// Waits for `duration` before going on.
+    pub fn sleep(&mut self, duration: Duration) -> &mut Self {
+        self.steps.push(Step::Sleep(duration));
+        self
+    }
+
+    /// Exits with `code` once the script is through; 0 unless told otherwise.
+    pub fn exit_code(&mut self, code: i32) -> &mut Self {
+        self.exit_code = code;
+        self
+    }
+
+    /// Ignores `SIGTERM`, `SIGINT` and `SIGHUP` (Unix) or `CTRL_C_EVENT` and `CTRL_BREAK_EVENT` (Windows), so that only
+    /// a kill stops it.
+    pub fn ignore_signals(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_signals = ignore;
+        self
+    }
+
+    /// A builder running the script with `helper`, the path to `pipe2-fake-child` (or a binary calling
+    /// [`FakeChild::main`]), to be configured further like any other.
+    pub fn command<P: AsRef<OsStr>>(&self, helper: P) -> Pipe2 {
+        let mut pipe2 = Pipe2::new(helper);
+        pipe2.args(self.args());
+        pipe2
+    }
+
+    /// The script as the helper's arguments: `out:HEX`, `err:HEX`, `sleep:MS`, `ignore-signals` and `exit:CODE`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.ignore_signals {
+            args.push("ignore-signals".to_owned());
+        }
+        for step in &self.steps {
+            args.push(match step {
+                Step::Write(Stream::Stdout, bytes) => format!("out:{}", hex(bytes)),
+                Step::Write(Stream::Stderr, bytes) => format!("err:{}", hex(bytes)),
+                Step::Sleep(duration) => format!("sleep:{}", duration.as_millis()),
+            });
+        }
+        args.push(format!("exit:{}", self.exit_code));
+        args
+    }
+
+    /// Everything the script writes to `stream`.
+    pub fn expected(&self, stream: Stream) -> Vec<u8> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Write(written, bytes) if *written == stream => Some(&bytes[..]),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    /// Panics, saying where they part ways, unless `output` is exactly what the script writes and exits with.
+    #[track_caller]
+    pub fn assert_output(&self, output: &Output) {
+        for (stream, captured) in [
+            (Stream::Stdout, &output.stdout),
+            (Stream::Stderr, &output.stderr),
+        ] {
+            let expected = self.expected(stream);
+            if *captured != expected {
+                let at = captured
+                    .iter()
+                    .zip(&expected)
+                    .position(|(a, b)| a != b)
+                    .unwrap_or(captured.len().min(expected.len()));
+                panic!(
+                    "{stream:?} differs at byte {at}: captured {} bytes, expected {}\n  captured: {:?}\n  expected: {:?}",
+                    captured.len(),
+                    expected.len(),
+                    excerpt(captured, at),
+                    excerpt(&expected, at),
+                );
+            }
+        }
+        assert_eq!(
+            output.status.code(),
+            Some(self.exit_code),
+            "the fake child exited with {}",
+            output.status
+        );
+    }
+
+    /// Acts out the script in the arguments this process was started with, and exits; the whole of the helper
+    /// binary.
+    pub fn main() -> ! {
+        let args = std::env::args().skip(1).collect::<Vec<_>>();
+        match run(&args) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("pipe2-fake-child: {e}");
+                std::process::exit(101);
+            }
+        }
+    }
+}
+
+fn run(args: &[String]) -> io::Result<i32> {
+    let mut code = 0;
+    for arg in args {
+        let (step, value) = arg.split_once(':').unwrap_or((arg, ""));
+        match step {
+            "out" => emit(io::stdout().lock(), &unhex(value)?)?,
+            "err" => emit(io::stderr().lock(), &unhex(value)?)?,
+            "sleep" => {
+                let ms = value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid sleep {value:?}")))?;
+                std::thread::sleep(Duration::from_millis(ms));
+            }
+            "exit" => {
+                code = value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid exit code {value:?}")))?;
+            }
+            "ignore-signals" => ignore_signals(),
+            _ => return Err(invalid(format!("unknown step {arg:?}"))),
+        }
+    }
+    Ok(code)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn emit(mut out: impl Write, bytes: &[u8]) -> io::Result<()> {
+    out.write_all(bytes)?;
+    out.flush()
+}
+
+#[cfg(unix)]
+fn ignore_signals() {
+    use nix::sys::signal::{SigHandler, Signal, signal};
+
+    for sig in [Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP] {
+        let _ = unsafe { signal(sig, SigHandler::SigIgn) };
+    }
+}
+
+#[cfg(windows)]
+fn ignore_signals() {
+    crate::windows_process_utils::ignore_console_signals();
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> io::Result<Vec<u8>> {
+    let invalid = || invalid(format!("invalid hex {hex:?}"));
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// A few bytes on either side of `at`, to show where two captures part ways.
+fn excerpt(bytes: &[u8], at: usize) -> String {
+    let start = at.saturating_sub(16);
+    let end = (at + 16).min(bytes.len());
+    String::from_utf8_lossy(&bytes[start.min(end)..end]).into_owned()
+}
@@ -0,0 +1,136 @@
+//! `cmd | tee >(a) >(b)` without a shell, so on Windows too: everything one child writes on `stdout` is relayed and
+//! captured as usual, and handed to the `stdin` of each of the other children as well. See [`FanOut`].
+
+use std::io;
+use std::time::Duration;
+
+use crate::child::{Child, Output};
+use crate::command::Pipe2;
+use crate::pipeline::MAX_QUEUED;
+use crate::stdin::StdinQueue;
+
+/// A child (the source) whose `stdout` is copied into the `stdin` of every one of the other children (the sinks), each
+/// of them supervised the way [`Pipe2`] does.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use pipe2::{FanOut, Pipe2};
+///
+/// let mut checksum = Pipe2::new("sha256sum");
+/// checksum.label("sha256");
+/// let mut archive = Pipe2::new("sh");
+/// archive.args(["-c", "gzip > dump.gz"]);
+/// let output = FanOut::new(Pipe2::new("./dump")).sink(checksum).sink(archive).run()?;
+/// println!("{}", String::from_utf8_lossy(&output.sinks[0].stdout));
+/// # Ok(())
+/// # }
+/// ```
+pub struct FanOut {
+    source: Pipe2,
+    sinks: Vec<Pipe2>,
+}
+
+/// How every child of a [`FanOut`] went.
+#[derive(Debug, Clone)]
+pub struct FanOutOutput {
+    /// The source's [`Output`], `stdout` and all.
+    pub source: Output,
+    /// The sinks', in the order they were added.
+    pub sinks: Vec<Output>,
+}
+
+impl FanOut {
+    pub fn new(source: Pipe2) -> Self {
+        Self {
+            source,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Adds a child that gets a copy of the source's `stdout` on its `stdin`, closed once the source is done.
+    pub fn sink(&mut self, sink: Pipe2) -> &mut Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Spawns the source and every sink, and copies what the source writes to the sinks until they've all exited.
+    ///
+    /// A sink that exits early gets nothing more, and the others carry on, like with `tee -p`. One that's slow to read
+    /// holds up the source once a megabyte is waiting for it, as a pipe would.
+    pub fn run(&mut self) -> io::Result<FanOutOutput> {
+        let queues: Vec<StdinQueue> = self
+            .sinks
+            .iter_mut()
+            .map(|sink| {
+                let queue = StdinQueue::default();
+                sink.stdin_queue(queue.clone());
+                queue
+            })
+            .collect();
+        let mut source = self.source.spawn()?;
+        let mut sinks = Vec::new();
+        for sink in &mut self.sinks {
+            match sink.spawn() {
+                Ok(child) => sinks.push(child),
+                Err(e) => {
+                    stop(source)?;
+                    for child in sinks {
+                        stop(child)?;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut exited = vec![false; sinks.len()];
+        // NOTE: taken from the source as it comes, to be copied to the sinks, so it's put back together here.
+        let mut stdout = Vec::new();
+        loop {
+            let backed_up = queues
+                .iter()
+                .zip(&exited)
+                .any(|(queue, &exited)| !exited && queue.len() >= MAX_QUEUED);
+            if !backed_up {
+                let done = source.poll()?.is_some();
+                let chunk = source.take_stdout();
+                for (queue, &exited) in queues.iter().zip(&exited) {
+                    if !exited {
+                        queue.push(&chunk);
+                    }
+                }
+                stdout.extend_from_slice(&chunk);
+                source.recycle(chunk);
+                if done {
+                    break;
+                }
+            }
+            for (sink, exited) in sinks.iter_mut().zip(&mut exited) {
+                if !*exited {
+                    *exited = sink.poll()?.is_some();
+                }
+            }
+            source.clock().sleep(Duration::from_millis(10));
+        }
+
+        let mut source = source.wait()?;
+        for queue in &queues {
+            queue.push(&source.stdout);
+            queue.close();
+        }
+        stdout.extend_from_slice(&source.stdout);
+        source.stdout = stdout;
+        Ok(FanOutOutput {
+            source,
+            sinks: sinks
+                .into_iter()
+                .map(Child::wait)
+                .collect::<io::Result<_>>()?,
+        })
+    }
+}
+
+/// Stops a child that was spawned before another one failed to be, and drains it.
+fn stop(mut child: Child) -> io::Result<Output> {
+    child.kill()?;
+    child.wait()
+}
@@ -0,0 +1,234 @@
+//! A C ABI over [`Pipe2`] and [`Child`], for programs that aren't written in Rust: C, or Python through `ctypes`. The
+//! declarations are in `include/pipe2.h`.
+//!
+//! A child is spawned with its output captured rather than echoed, and handed over as an opaque pointer. What it
+//! writes, and how it exits, then comes out of [`pipe2_poll_event`] one event at a time, without ever blocking.
+//! Functions that fail return `NULL` or `-1`, and [`pipe2_last_error`] says why.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::fmt::Display;
+use std::process::ExitStatus;
+
+use crate::child::Child;
+use crate::command::Pipe2;
+use crate::iter::Event;
+
+/// Nothing happened since the last call.
+pub const PIPE2_EVENT_NONE: c_int = 0;
+/// The child wrote `data` to `stdout`.
+pub const PIPE2_EVENT_STDOUT: c_int = 1;
+/// The child wrote `data` to `stderr`.
+pub const PIPE2_EVENT_STDERR: c_int = 2;
+/// The child is done, with `exit_code`, or killed by `signal`; nothing comes after this.
+pub const PIPE2_EVENT_EXITED: c_int = 3;
+
+/// One thing that happened to the child, filled in by [`pipe2_poll_event`].
+#[repr(C)]
+pub struct Pipe2Event {
+    /// One of the `PIPE2_EVENT_*` constants.
+    pub kind: c_int,
+    /// What was written, valid until the next call with the same child, or until it's freed.
+    pub data: *const u8,
+    pub len: usize,
+    /// `-1` if the child didn't exit by itself.
+    pub exit_code: c_int,
+    /// The signal that killed the child, or `0` (Unix).
+    pub signal: c_int,
+}
+
+/// A child, and the events that were read from it but not handed over yet.
+pub struct Pipe2Child {
+    /// `None` once it's over.
+    child: Option<Child>,
+    pending: VecDeque<Event>,
+    /// The data of the last event handed over, kept alive for the caller.
+    current: Vec<u8>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(e: impl Display) {
+    // NOTE: an error with a NUL in it can't be a C string, and isn't worth failing over.
+    let message = CString::new(e.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Why the last call on this thread that failed did, as a string owned by the library, valid until the next call that
+/// fails on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn pipe2_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Spawns `program` with the `argc` arguments in `argv`, and returns the child, or `NULL` if it couldn't be spawned.
+///
+/// # Safety
+///
+/// `program` has to be a NUL-terminated string, and `argv` has to point to `argc` of them, or be `NULL` if `argc` is
+/// 0. They're copied, so they only have to live through the call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pipe2_spawn(
+    program: *const c_char,
+    argv: *const *const c_char,
+    argc: usize,
+) -> *mut Pipe2Child {
+    if program.is_null() || (argv.is_null() && argc > 0) {
+        fail("program and argv can't be NULL");
+        return std::ptr::null_mut();
+    }
+    let string = |s: *const c_char| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned();
+    let mut pipe2 = Pipe2::new(string(program));
+    for i in 0..argc {
+        pipe2.arg(string(unsafe { *argv.add(i) }));
+    }
+    // NOTE: the caller gets the output through the events, and decides where it goes.
+    pipe2.echo(false);
+    match pipe2.spawn() {
+        Ok(child) => Box::into_raw(Box::new(Pipe2Child {
+            child: Some(child),
+            pending: VecDeque::new(),
+            current: Vec::new(),
+        })),
+        Err(e) => {
+            fail(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Fills in `event` with the next thing that happened to the child, without waiting for it: returns `1` if there was
+/// something, `0` if there wasn't (`kind` is [`PIPE2_EVENT_NONE`] then), and `-1` if the child couldn't be polled.
+///
+/// Call it every few milliseconds until it hands over [`PIPE2_EVENT_EXITED`]; the child's pipes are only read from in
+/// here, so it can't get stuck on a full one as long as it's called.
+///
+/// # Safety
+///
+/// `child` has to come from [`pipe2_spawn`] and not be freed yet, and `event` has to point to a [`Pipe2Event`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pipe2_poll_event(child: *mut Pipe2Child, event: *mut Pipe2Event) -> c_int {
+    let (Some(child), Some(event)) = (unsafe { child.as_mut() }, unsafe { event.as_mut() }) else {
+        fail("child and event can't be NULL");
+        return -1;
+    };
+    *event = Pipe2Event {
+        kind: PIPE2_EVENT_NONE,
+        data: std::ptr::null(),
+        len: 0,
+        exit_code: -1,
+        signal: 0,
+    };
+    if child.pending.is_empty()
+        && let Err(e) = child.read()
+    {
+        fail(e);
+        return -1;
+    }
+
+    let Some(next) = child.pending.pop_front() else {
+        return 0;
+    };
+    match next {
+        Event::Stdout(chunk) => {
+            event.kind = PIPE2_EVENT_STDOUT;
+            child.current = chunk;
+        }
+        Event::Stderr(chunk) => {
+            event.kind = PIPE2_EVENT_STDERR;
+            child.current = chunk;
+        }
+        Event::Exited(status) => {
+            event.kind = PIPE2_EVENT_EXITED;
+            (event.exit_code, event.signal) = exit(status);
+            return 1;
+        }
+    }
+    event.data = child.current.as_ptr();
+    event.len = child.current.len();
+    1
+}
+
+/// Stops the child the way [`Child::kill`] does, and returns `0`, or `-1` if that failed. Its last output and its
+/// exit still come out of [`pipe2_poll_event`]; a child that's over already is left as it is.
+///
+/// # Safety
+///
+/// `child` has to come from [`pipe2_spawn`] and not be freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pipe2_kill(child: *mut Pipe2Child) -> c_int {
+    let Some(child) = (unsafe { child.as_mut() }) else {
+        fail("child can't be NULL");
+        return -1;
+    };
+    let Some(running) = &mut child.child else {
+        return 0;
+    };
+    match running.kill() {
+        Ok(()) => 0,
+        Err(e) => {
+            fail(e);
+            -1
+        }
+    }
+}
+
+/// Frees the child, killing it first and waiting for it if it's still running. `NULL` is ignored.
+///
+/// # Safety
+///
+/// `child` has to come from [`pipe2_spawn`], and isn't to be used after this.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pipe2_free(child: *mut Pipe2Child) {
+    if child.is_null() {
+        return;
+    }
+    let child = unsafe { Box::from_raw(child) };
+    if let Some(mut running) = child.child {
+        let _ = running.kill();
+        let _ = running.wait();
+    }
+}
+
+impl Pipe2Child {
+    /// Polls the child, and queues up what came of it.
+    fn read(&mut self) -> std::io::Result<()> {
+        let Some(running) = &mut self.child else {
+            return Ok(());
+        };
+        let exited = running.poll()?.is_some();
+        let (stdout, stderr) = (running.take_stdout(), running.take_stderr());
+        self.queue(stdout, stderr);
+        if exited {
+            let output = self
+                .child
+                .take()
+                .expect("the child is still there")
+                .wait()?;
+            self.queue(output.stdout, output.stderr);
+            self.pending.push_back(Event::Exited(output.status));
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, stdout: Vec<u8>, stderr: Vec<u8>) {
+        if !stdout.is_empty() {
+            self.pending.push_back(Event::Stdout(stdout));
+        }
+        if !stderr.is_empty() {
+            self.pending.push_back(Event::Stderr(stderr));
+        }
+    }
+}
+
+/// The exit code, or `-1`, and the signal, or `0`.
+fn exit(status: ExitStatus) -> (c_int, c_int) {
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&status).unwrap_or(0);
+    #[cfg(not(unix))]
+    let signal = 0;
+    (status.code().unwrap_or(-1), signal)
+}
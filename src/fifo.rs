@@ -0,0 +1,47 @@
+//! Connecting the child's `stdin`/`stdout` to FIFOs, so it can take part in existing `mkfifo`-based workflows.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+
+pub(crate) struct Fifo {
+    path: PathBuf,
+    create: bool,
+}
+
+impl Fifo {
+    pub(crate) fn new(path: PathBuf, create: bool) -> Self {
+        Self { path, create }
+    }
+
+    /// Opens the FIFO for the child to read from.
+    pub(crate) fn open_read(&self) -> io::Result<File> {
+        self.prepare()?;
+        File::open(&self.path)
+    }
+
+    /// Opens the FIFO for the child to write into.
+    pub(crate) fn open_write(&self) -> io::Result<File> {
+        self.prepare()?;
+        OpenOptions::new().write(true).open(&self.path)
+    }
+
+    fn prepare(&self) -> io::Result<()> {
+        match std::fs::metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_fifo() => Ok(()),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a FIFO", self.path.display()),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && self.create => {
+                mkfifo(&self.path, Mode::from_bits_truncate(0o600))?;
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
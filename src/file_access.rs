@@ -0,0 +1,222 @@
+//! Noticing the files the child writes to, see [`Pipe2::track_file_writes`](crate::Pipe2::track_file_writes).
+//!
+//! Where we're allowed to (root, or `CAP_SYS_ADMIN`), fanotify tells about every file closed after being written to
+//! on the mounts we can see, and the ones closed by the child or its descendants are kept. Otherwise, the descriptors
+//! they have open are sampled every so often, and the files among them open for writing are kept, which misses files
+//! that are opened and closed between two samples. Either way, it's best effort: what a tool produced, not an audit.
+
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::process::descendants;
+
+/// How often the child is looked at.
+pub(crate) const SAMPLE_EVERY: Duration = Duration::from_millis(100);
+
+/// Filesystems there's no point in watching, or that can't be watched.
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "proc",
+    "sysfs",
+    "cgroup",
+    "cgroup2",
+    "devpts",
+    "mqueue",
+    "securityfs",
+    "debugfs",
+    "tracefs",
+    "pstore",
+    "bpf",
+    "configfs",
+    "fusectl",
+    "binfmt_misc",
+    "hugetlbfs",
+    "autofs",
+];
+
+/// Everything seen written so far by the process it follows and its descendants.
+pub(crate) struct FileAccess {
+    pid: u32,
+    /// Every process of the child's ever seen, since fanotify tells about them after they're gone.
+    pids: BTreeSet<u32>,
+    fanotify: Option<OwnedFd>,
+    written: BTreeSet<PathBuf>,
+}
+
+impl FileAccess {
+    /// Starts watching, before there's a child to follow, so that nothing it does is missed.
+    pub(crate) fn new() -> Self {
+        Self {
+            pid: 0,
+            pids: BTreeSet::new(),
+            fanotify: fanotify().ok(),
+            written: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn follow(&mut self, pid: u32) {
+        self.pid = pid;
+        self.pids.insert(pid);
+    }
+
+    /// `fanotify` or `sampling`, for the event log.
+    pub(crate) fn method(&self) -> &'static str {
+        match self.fanotify {
+            Some(_) => "fanotify",
+            None => "sampling",
+        }
+    }
+
+    /// Looks at what the child is up to right now, and returns the files it wasn't seen writing before.
+    pub(crate) fn sample(&mut self) -> Vec<PathBuf> {
+        let pids = descendants(self.pid);
+        self.pids.extend(&pids);
+        let mut new = Vec::new();
+        match &self.fanotify {
+            Some(fanotify) => {
+                for (pid, path) in read_events(fanotify) {
+                    if self.pids.contains(&pid) && self.written.insert(path.clone()) {
+                        new.push(path);
+                    }
+                }
+            }
+            None => {
+                for pid in pids {
+                    for path in open_for_writing(pid) {
+                        if self.written.insert(path.clone()) {
+                            new.push(path);
+                        }
+                    }
+                }
+            }
+        }
+        new
+    }
+
+    /// Everything seen written, in order.
+    pub(crate) fn written(&self) -> Vec<PathBuf> {
+        self.written.iter().cloned().collect()
+    }
+}
+
+/// A fanotify group told about every file closed after being written to, on every mount it could be put on.
+fn fanotify() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::fanotify_init(
+            libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_NONBLOCK,
+            (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_LARGEFILE) as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fanotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut marked = 0;
+    for mount in mounts()? {
+        let Ok(path) = CString::new(mount.into_os_string().into_encoded_bytes()) else {
+            continue;
+        };
+        let ok = unsafe {
+            libc::fanotify_mark(
+                fanotify.as_raw_fd(),
+                libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+                libc::FAN_CLOSE_WRITE,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+            )
+        } == 0;
+        marked += usize::from(ok);
+    }
+    if marked == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fanotify)
+}
+
+/// The mount points we can see, but for pseudo-filesystems.
+fn mounts() -> io::Result<Vec<PathBuf>> {
+    // NOTE: `/proc/self/mountinfo` is `ID PARENT MAJOR:MINOR ROOT MOUNT-POINT OPTIONS [FIELDS...] - TYPE ...`, with
+    // spaces in paths escaped as `\040`.
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mount_point = line.split(' ').nth(4)?;
+            let (_, after) = line.split_once(" - ")?;
+            let fstype = after.split(' ').next()?;
+            (!PSEUDO_FILESYSTEMS.contains(&fstype))
+                .then(|| PathBuf::from(mount_point.replace("\\040", " ")))
+        })
+        .collect())
+}
+
+/// The files closed after being written to since the last time, with the process that closed each.
+fn read_events(fanotify: &OwnedFd) -> Vec<(u32, PathBuf)> {
+    let mut events = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = unsafe {
+            libc::read(
+                fanotify.as_raw_fd(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        };
+        // NOTE: it's non-blocking, so once it's drained, this fails with `EAGAIN`.
+        let Ok(n @ 1..) = usize::try_from(n) else {
+            break;
+        };
+        let mut offset = 0;
+        let size = std::mem::size_of::<libc::fanotify_event_metadata>();
+        while offset + size <= n {
+            let event: libc::fanotify_event_metadata =
+                unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            if event.fd >= 0 {
+                let file = unsafe { OwnedFd::from_raw_fd(event.fd) };
+                if let Ok(path) = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())) {
+                    events.push((event.pid as u32, path));
+                }
+            }
+            if event.event_len == 0 {
+                break;
+            }
+            offset += event.event_len as usize;
+        }
+    }
+    events
+}
+
+/// The regular files `pid` has open for writing, from `/proc/<pid>/fd` and the flags in `/proc/<pid>/fdinfo`.
+fn open_for_writing(pid: u32) -> Vec<PathBuf> {
+    let Ok(fds) = fs::read_dir(format!("/proc/{pid}/fd")) else {
+        return Vec::new();
+    };
+    let mut paths = Vec::new();
+    for fd in fds.flatten() {
+        let Ok(path) = fs::read_link(fd.path()) else {
+            continue;
+        };
+        if !path.is_absolute() || path.starts_with("/dev") || path.starts_with("/proc") {
+            continue;
+        }
+        let fdinfo = Path::new("/proc")
+            .join(pid.to_string())
+            .join("fdinfo")
+            .join(fd.file_name());
+        let flags = fs::read_to_string(fdinfo).ok().and_then(|fdinfo| {
+            let flags = fdinfo
+                .lines()
+                .find_map(|line| line.strip_prefix("flags:"))?;
+            i32::from_str_radix(flags.trim(), 8).ok()
+        });
+        if flags.is_some_and(|flags| flags & libc::O_ACCMODE != libc::O_RDONLY) {
+            paths.push(path);
+        }
+    }
+    paths
+}
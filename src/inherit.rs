@@ -0,0 +1,178 @@
+//! Descriptors/handles handed to the child on top of its `stdin`/`stdout`/`stderr`.
+
+use std::io;
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle, OwnedHandle};
+
+#[cfg(unix)]
+#[derive(Default)]
+pub(crate) struct Inherited {
+    fds: Vec<(OwnedFd, RawFd)>,
+    close_others: bool,
+}
+
+#[cfg(unix)]
+impl Inherited {
+    pub(crate) fn push(&mut self, fd: OwnedFd, target: RawFd) {
+        self.fds.push((fd, target));
+    }
+
+    pub(crate) fn close_others(&mut self, close: bool) {
+        self.close_others = close;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fds.is_empty() && !self.close_others
+    }
+
+    /// The lowest descriptor number above every target passed so far.
+    pub(crate) fn next_target(&self) -> RawFd {
+        self.fds
+            .iter()
+            .map(|&(_, target)| target + 1)
+            .max()
+            .unwrap_or(3)
+    }
+
+    /// Prepares the placement of every passed descriptor, plus the `extra` ones that only live for this spawn, at its
+    /// target number; [`PreExec::install`](crate::pre_exec::PreExec::install) makes it one of its steps.
+    pub(crate) fn placement(&self, extra: &[(RawFd, RawFd)]) -> io::Result<Option<Placement>> {
+        if self.fds.is_empty() && extra.is_empty() && !self.close_others {
+            return Ok(None);
+        }
+
+        let targets: Vec<(RawFd, RawFd)> = self
+            .fds
+            .iter()
+            .map(|(fd, target)| (fd.as_raw_fd(), *target))
+            .chain(extra.iter().copied())
+            .collect();
+        if let Some(&(_, target)) = targets.iter().find(|&&(_, target)| target <= 2) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "fd {target} is reserved for stdio and can't be passed as an inherited descriptor"
+                ),
+            ));
+        }
+
+        // NOTE: the set of open descriptors has to be collected here, since reading a directory allocates, and
+        // allocating between `fork` and `exec` can deadlock if another thread held the allocator lock.
+        let open = if self.close_others {
+            open_fds()?
+        } else {
+            Vec::new()
+        };
+        let base = targets.iter().map(|&(_, target)| target).max().unwrap_or(2) + 1;
+        let scratch = vec![-1; targets.len()];
+        Ok(Some(Placement {
+            open,
+            targets,
+            base,
+            scratch,
+        }))
+    }
+}
+
+/// Everything [`Placement::place`] needs, allocated ahead of the `fork`.
+#[cfg(unix)]
+pub(crate) struct Placement {
+    open: Vec<RawFd>,
+    targets: Vec<(RawFd, RawFd)>,
+    base: RawFd,
+    scratch: Vec<RawFd>,
+}
+
+#[cfg(unix)]
+impl Placement {
+    /// Runs between `fork` and `exec`.
+    pub(crate) fn place(&mut self) -> io::Result<()> {
+        // NOTE: rather than `close`, everything not asked for is marked `FD_CLOEXEC`; std keeps a `CLOEXEC` pipe open
+        // up to `exec` to report its failure, and closing that would make a failed `exec` look like a successful
+        // spawn.
+        for &fd in &self.open {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+
+        // NOTE: every source is moved above the highest target first, so a source that sits on another entry's target
+        // isn't clobbered before it gets placed.
+        for (slot, &(fd, _)) in self.scratch.iter_mut().zip(&self.targets) {
+            *slot = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, self.base) };
+            if *slot < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        // `dup2` clears `FD_CLOEXEC` on the new descriptor, which is what makes it survive `exec`.
+        for (&fd, &(_, target)) in self.scratch.iter().zip(&self.targets) {
+            if unsafe { libc::dup2(fd, target) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn open_fds() -> io::Result<Vec<RawFd>> {
+    let mut fds = Vec::new();
+    for entry in std::fs::read_dir("/dev/fd")? {
+        let fd = entry?
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<RawFd>().ok());
+        fds.extend(fd.filter(|&fd| fd > 2));
+    }
+    Ok(fds)
+}
+
+#[cfg(windows)]
+#[derive(Default)]
+pub(crate) struct Inherited {
+    handles: Vec<OwnedHandle>,
+}
+
+#[cfg(windows)]
+impl Inherited {
+    pub(crate) fn push(&mut self, handle: OwnedHandle) {
+        self.handles.push(handle);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    pub(crate) fn handles(&self) -> impl Iterator<Item = BorrowedHandle<'_>> {
+        self.handles.iter().map(|handle| handle.as_handle())
+    }
+
+    /// Marks (or unmarks) the passed handles, plus the `extra` ones that only live for this spawn, as inheritable.
+    ///
+    /// NOTE: std creates the child with `bInheritHandles = TRUE`, so any handle flagged `HANDLE_FLAG_INHERIT` at
+    /// that moment is handed over, under the same value. The flag is only held for the duration of the spawn, but a
+    /// process spawned concurrently from another thread can still pick these up in that window.
+    pub(crate) fn set_inheritable(
+        &self,
+        inherit: bool,
+        extra: &[BorrowedHandle],
+    ) -> io::Result<()> {
+        use winapi::um::handleapi::SetHandleInformation;
+        use winapi::um::winbase::HANDLE_FLAG_INHERIT;
+
+        for handle in self.handles().chain(extra.iter().copied()) {
+            let flags = if inherit { HANDLE_FLAG_INHERIT } else { 0 };
+            let ok = unsafe {
+                SetHandleInformation(handle.as_raw_handle() as _, HANDLE_FLAG_INHERIT, flags)
+            };
+            if ok == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! The child's output as an iterator, for consumers that would rather loop over it than poll.
+
+use std::collections::VecDeque;
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use crate::child::Child;
+
+/// Something that happened to the child, as [`Events`] yields it.
+#[derive(Debug)]
+pub enum Event {
+    /// What the child wrote to `stdout` since the last event.
+    Stdout(Vec<u8>),
+    /// What the child wrote to `stderr` since the last event.
+    Stderr(Vec<u8>),
+    /// The child is done, and nothing comes after this.
+    Exited(ExitStatus),
+}
+
+/// Blocks until the child writes something or exits, polling it meanwhile like [`Child::wait`] does; see
+/// [`Child::events`].
+///
+/// The chunks are handed over rather than captured, so they don't pile up in memory for a long-running child. An error
+/// ends the iteration, like it would end [`Child::wait`].
+pub struct Events {
+    child: Child,
+    pending: VecDeque<Event>,
+    done: bool,
+}
+
+impl Events {
+    pub(crate) fn new(child: Child) -> Self {
+        Self {
+            child,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// The child being gone through, to pause it, kill it, or anything else while iterating.
+    pub fn child(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Hands back the bytes of a [`Event::Stdout`] or [`Event::Stderr`] once they've been dealt with, to be reused for
+    /// the chunks that come next; see [`Child::recycle`].
+    pub fn recycle(&mut self, chunk: Vec<u8>) {
+        self.child.recycle(chunk);
+    }
+
+    /// Polls the child once, queueing whatever came of it.
+    fn poll(&mut self) -> io::Result<()> {
+        let status = self.child.poll()?;
+        if status.is_some() {
+            self.child.close()?;
+        }
+        let (stdout, stderr) = (self.child.take_stdout(), self.child.take_stderr());
+        if stdout.is_empty() {
+            self.child.recycle(stdout);
+        } else {
+            self.pending.push_back(Event::Stdout(stdout));
+        }
+        if stderr.is_empty() {
+            self.child.recycle(stderr);
+        } else {
+            self.pending.push_back(Event::Stderr(stderr));
+        }
+        if let Some(status) = status {
+            self.pending.push_back(Event::Exited(status));
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Events {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.poll() {
+                self.done = true;
+                return Some(Err(e));
+            }
+            if self.pending.is_empty() {
+                self.child.clock().sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
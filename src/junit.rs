@@ -0,0 +1,158 @@
+//! `--junit FILE`: the run as a JUnit XML report, which most CI systems know how to show.
+//!
+//! The program is one test case, passing if it exits with 0, unless `--junit-pass`/`--junit-fail` [`Pattern`]s are
+//! given, in which case every output line matching one of them is a test case of its own, named by its `{name}`.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use pipe2::Output;
+
+use crate::pattern::Pattern;
+
+/// Lines that are test cases of their own; without a `{name}`, the whole line names the case.
+#[derive(Default)]
+pub struct CasePatterns {
+    pub pass: Vec<Pattern>,
+    pub fail: Vec<Pattern>,
+}
+
+pub fn case_pattern(pattern: &str) -> Result<Pattern, String> {
+    Pattern::parse(pattern, &["name"], "test case pattern")
+}
+
+struct Case {
+    name: String,
+    time: Option<Duration>,
+    failure: Option<String>,
+}
+
+/// Writes the report on the run of `command`, its test cases under the `label` class if there's one.
+pub fn write(
+    path: &Path,
+    command: &str,
+    label: Option<&str>,
+    duration: Duration,
+    output: &Output,
+    patterns: &CasePatterns,
+) -> io::Result<()> {
+    let outcome = outcome(output);
+    let mut cases = Vec::new();
+    if !patterns.pass.is_empty() || !patterns.fail.is_empty() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        for line in stdout.lines().chain(stderr.lines()) {
+            let name = |patterns: &[Pattern]| {
+                patterns.iter().find_map(|pattern| {
+                    let captures = pattern.captures(line)?;
+                    Some(captures.first().map_or(line, |(_, name)| *name).to_owned())
+                })
+            };
+            if let Some(name) = name(&patterns.pass) {
+                cases.push(Case {
+                    name,
+                    time: None,
+                    failure: None,
+                });
+            } else if let Some(name) = name(&patterns.fail) {
+                let failure = Some(line.to_owned());
+                cases.push(Case {
+                    name,
+                    time: None,
+                    failure,
+                });
+            }
+        }
+    }
+    // NOTE: a failing program still fails the suite when none of the lines say so, and when there are no patterns.
+    if cases.is_empty() || (outcome.is_some() && cases.iter().all(|case| case.failure.is_none())) {
+        cases.push(Case {
+            name: command.to_owned(),
+            time: Some(duration),
+            failure: outcome,
+        });
+    }
+
+    let failures = cases.iter().filter(|case| case.failure.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" errors=\"0\" time=\"{:.3}\">",
+        escape(command),
+        cases.len(),
+        duration.as_secs_f64()
+    );
+    let classname = escape(label.unwrap_or("pipe2"));
+    for case in &cases {
+        let _ = write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{classname}\"",
+            escape(&case.name)
+        );
+        if let Some(time) = case.time {
+            let _ = write!(xml, " time=\"{:.3}\"", time.as_secs_f64());
+        }
+        match &case.failure {
+            Some(failure) => {
+                let _ = writeln!(
+                    xml,
+                    ">\n      <failure message=\"{}\"/>\n    </testcase>",
+                    escape(failure)
+                );
+            }
+            None => xml.push_str("/>\n"),
+        }
+    }
+    for (tag, captured) in [
+        ("system-out", &output.stdout),
+        ("system-err", &output.stderr),
+    ] {
+        let text = String::from_utf8_lossy(captured);
+        let _ = writeln!(xml, "    <{tag}>{}</{tag}>", escape(&text));
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    fs::write(path, xml)
+}
+
+/// Why the program failed, if it did.
+fn outcome(output: &Output) -> Option<String> {
+    if output.timed_out {
+        return Some("timed out".to_owned());
+    }
+    if output.first_output_timed_out {
+        return Some("wrote nothing in time".to_owned());
+    }
+    if output.output_closed_timed_out {
+        return Some("kept running too long after closing its output".to_owned());
+    }
+    if output.cpu_limit_exceeded {
+        return Some("exceeded its CPU limit".to_owned());
+    }
+    if output.success {
+        return None;
+    }
+    match output.status.code() {
+        Some(code) => Some(format!("exit code {code}")),
+        None => Some(format!("{}", output.status)),
+    }
+}
+
+/// Escapes markup, and drops the control characters XML 1.0 has no way to carry, like the ones in color codes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,131 @@
+//! Correct reading of `stdout`/`stderr` while a program is running.
+//!
+//! The child's pipes are polled without blocking and drained as soon as there's new data on them, so the output can be
+//! relayed live and captured separately, without the child ever stalling on a full pipe buffer. See the README for
+//! why this needs care on each platform.
+
+mod broadcast;
+mod cancel;
+mod capture;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod cgroup;
+mod channel;
+mod child;
+mod clock;
+mod command;
+mod control;
+mod date;
+mod echo;
+mod events;
+mod fake_child;
+mod fan_out;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(unix)]
+mod fifo;
+#[cfg(all(feature = "file-access", target_os = "linux"))]
+mod file_access;
+mod inherit;
+mod iter;
+mod line_limit;
+#[cfg(windows)]
+mod mitigation;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod namespace;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod network;
+#[cfg(unix)]
+mod notify;
+mod on_line;
+mod outlet;
+mod pipeline;
+#[cfg(unix)]
+mod posix_spawn;
+#[cfg(unix)]
+mod pre_exec;
+mod pretty_json;
+mod priority;
+mod probe;
+mod process;
+#[cfg(unix)]
+mod pty;
+#[cfg(feature = "python")]
+mod python;
+mod read_sizes;
+mod reason;
+mod recording;
+mod run_id;
+mod sanitize;
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+mod seccomp;
+mod severity;
+#[cfg(feature = "upload")]
+mod sha256;
+mod stdin;
+mod stream;
+mod summary;
+mod trace;
+mod transform;
+#[cfg(feature = "upload")]
+mod upload;
+#[cfg(feature = "wasi")]
+mod wasi;
+#[cfg(windows)]
+mod windows_pipe_utils;
+#[cfg(windows)]
+mod windows_process_utils;
+#[cfg(windows)]
+mod windows_runas;
+
+pub use broadcast::Subscriber;
+pub use cancel::CancellationHandle;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use capture::Memfd;
+pub use channel::{CHANNEL_ENV, Channel};
+pub use child::{Child, Output};
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(any(target_os = "linux", target_os = "android", windows))]
+pub use command::CPU_LIMIT;
+pub use command::Pipe2;
+#[cfg(unix)]
+pub use control::remove_stale_socket;
+pub use date::civil_from_days;
+pub use echo::BrokenPipe;
+pub use fake_child::FakeChild;
+pub use fan_out::{FanOut, FanOutOutput};
+pub use iter::{Event, Events};
+#[cfg(windows)]
+pub use mitigation::MitigationPolicy;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use namespace::Namespace;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use network::{Connection, Protocol};
+#[cfg(unix)]
+pub use nix::sys::signal::Signal;
+#[cfg(unix)]
+pub use notify::NOTIFY_ENV;
+pub use on_line::LineAction;
+pub use outlet::{Backpressure, Flush};
+pub use pipeline::{Pipeline, PipelineOutput};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use priority::IoPriority;
+#[cfg(windows)]
+pub use priority::PriorityClass;
+pub use process::ResourceUsage;
+#[cfg(unix)]
+pub use pty::terminal_size;
+pub use read_sizes::ReadSizes;
+pub use reason::ExitReason;
+pub use run_id::RunId;
+pub use sanitize::Sanitized;
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+pub use seccomp::SyscallPolicy;
+pub use severity::{Severities, Severity};
+pub use stdin::{StdinClose, StdinPart};
+pub use stream::{Disposition, OutputClosed, Stream};
+pub use summary::RunSummary;
+pub use transform::{ChunkTransform, Latin1, Redact, StripAnsi, Utf16Le};
+#[cfg(feature = "upload")]
+pub use upload::{MIN_PART_SIZE, Upload, Uploaded, Uploading};
+#[cfg(windows)]
+pub use windows_runas::StartupInfo;
@@ -0,0 +1,111 @@
+//! Cutting overly long lines short, for children that print megabytes on a single line (minified JSON, say).
+
+use std::io::Write;
+
+use crate::transform::ChunkTransform;
+
+/// Keeps the first `max` bytes of every line, and replaces the rest with `…[+N bytes]` once the line is over. A cut
+/// that would fall inside a valid UTF-8 character is made before it instead.
+pub(crate) struct LineLimit {
+    max: usize,
+    /// How much of the current line has gone by.
+    line: usize,
+    /// How much of it was cut.
+    dropped: u64,
+    /// The start of a character the last chunk ended in the middle of.
+    held: Vec<u8>,
+}
+
+impl LineLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            line: 0,
+            dropped: 0,
+            held: Vec::new(),
+        }
+    }
+
+    /// Appends what fits of `body`, the next part of the current line, to `out`.
+    fn keep(&mut self, body: &[u8], out: &mut Vec<u8>) {
+        let mut kept = body.len().min(self.max.saturating_sub(self.line));
+        if kept < body.len() {
+            kept = char_boundary(body, kept);
+        }
+        out.extend_from_slice(&body[..kept]);
+        self.line += body.len();
+        self.dropped += (body.len() - kept) as u64;
+    }
+}
+
+impl ChunkTransform for LineLimit {
+    /// Appends what's left of `chunk` to `out`, with the marker for each cut line that ends in it.
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        let mut text = std::mem::take(&mut self.held);
+        text.extend_from_slice(chunk);
+        for piece in text.split_inclusive(|&byte| byte == b'\n') {
+            let (mut body, newline) = match piece.split_last() {
+                Some((b'\n', body)) => (body, true),
+                _ => (piece, false),
+            };
+            // NOTE: the cut can only be kept out of a character once the next chunk has finished it.
+            if !newline && self.line < self.max {
+                let partial = partial_char(body);
+                self.held = body[body.len() - partial..].to_vec();
+                body = &body[..body.len() - partial];
+            }
+            self.keep(body, out);
+            if newline {
+                self.finish(out);
+                out.push(b'\n');
+            }
+        }
+    }
+
+    /// Ends the current line, for a stream that stopped in the middle of one.
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        let held = std::mem::take(&mut self.held);
+        self.keep(&held, out);
+        if self.dropped != 0 {
+            let _ = write!(out, "…[+{} bytes]", self.dropped);
+        }
+        self.line = 0;
+        self.dropped = 0;
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Backs `cut` off to the start of the character it falls inside of, if that's a valid UTF-8 one; otherwise, the input
+/// is taken for bytes and cut where it is.
+fn char_boundary(bytes: &[u8], cut: usize) -> usize {
+    let mut start = cut;
+    while is_continuation(bytes[start]) {
+        if start == 0 || cut - start == 3 {
+            return cut;
+        }
+        start -= 1;
+    }
+    let char = &bytes[start..bytes.len().min(start + 4)];
+    match std::str::from_utf8(char) {
+        Ok(_) => start,
+        Err(e) if e.valid_up_to() > 0 || e.error_len().is_none() => start,
+        Err(_) => cut,
+    }
+}
+
+/// How many bytes at the end of `bytes` are the valid start of a character that isn't finished yet.
+fn partial_char(bytes: &[u8]) -> usize {
+    for len in 1..=bytes.len().min(3) {
+        let start = bytes.len() - len;
+        if !is_continuation(bytes[start]) {
+            return match std::str::from_utf8(&bytes[start..]) {
+                Err(e) if e.valid_up_to() == 0 && e.error_len().is_none() => len,
+                _ => 0,
+            };
+        }
+    }
+    0
+}
@@ -1,177 +1,434 @@
-use std::io::{self, Write};
-use std::process::{Command, Stdio, exit};
-use std::time::Duration;
+use std::io;
+use std::process::exit;
+use std::time::{Duration, Instant, SystemTime};
 
+use pipe2::{ExitReason, Pipe2, SystemClock};
+use serde_json::json;
+
+use crate::ci::Ci;
+use crate::cli::Action;
+use crate::metrics::Exporter;
+use crate::report::{Environment, Report};
+use crate::run_dir::RunDir;
 #[cfg(unix)]
-use nix::fcntl::{FcntlArg, OFlag, fcntl};
+use crate::signals::Signals;
+use crate::tee::Tee;
+
+#[cfg(feature = "agent")]
+mod agent;
+mod ci;
+mod cli;
+mod config;
+mod daemon;
+mod each;
+mod junit;
+mod metrics;
+mod pattern;
+mod pipe;
+mod problems;
+mod report;
+mod rerun;
+mod run_dir;
+mod schedule;
+mod service;
 #[cfg(unix)]
-use std::io::Read;
-
-#[cfg(windows)]
-mod windows_pipe_utils {
-    use std::io;
-    use std::os::windows::io::AsRawHandle;
-
-    use winapi::shared::winerror::{ERROR_BROKEN_PIPE, ERROR_SUCCESS};
-    use winapi::um::errhandlingapi::{GetLastError, SetLastError};
-    use winapi::um::fileapi::ReadFile;
-    use winapi::um::namedpipeapi::PeekNamedPipe;
-
-    /// NOTE(gabriela): it's... fine. The operations before still complete.
-    fn reset_last_err_on_broken_pipe() {
-        unsafe {
-            if GetLastError() == ERROR_BROKEN_PIPE {
-                SetLastError(ERROR_SUCCESS);
-            }
+mod signals;
+mod tap;
+mod tee;
+mod template;
+#[cfg(feature = "notify")]
+mod watch;
+
+/// Stands in for the `--signal` handling, which only exists on Unix.
+#[cfg(not(unix))]
+type Signals = std::convert::Infallible;
+
+fn main() -> io::Result<()> {
+    let (mut cli, schedule) = match cli::parse(std::env::args_os().skip(1)) {
+        Ok(Some(Action::Run(cli))) => (cli, None),
+        Ok(Some(Action::Schedule(cli, schedule))) => (cli, Some(schedule)),
+        Ok(Some(Action::Each(cli, batch))) => exit(each::run(&cli, &batch)?),
+        Ok(Some(Action::WithService(cli, service))) => exit(service::run(&cli, &service)?),
+        Ok(Some(Action::Pipe(cli, pipe))) => exit(pipe::run(&cli, &pipe)?),
+        Ok(Some(Action::Tee(cli, commands))) => exit(pipe::tee(&cli, &commands)?),
+        Ok(Some(Action::Rerun(file, diff))) => exit(rerun::run(&file, diff)?),
+        #[cfg(feature = "agent")]
+        Ok(Some(Action::Agent(path))) => exit(agent::run(&path)?),
+        Ok(Some(Action::Show(file))) => {
+            Report::load(&file)?.print();
+            return Ok(());
+        }
+        Ok(None) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
         }
+        Err(e) => {
+            eprintln!("pipe2: {e}\n\n{}", cli::USAGE);
+            exit(2);
+        }
+    };
+
+    if schedule.is_some() && cli.detach {
+        eprintln!(
+            "pipe2: schedule can't be combined with --detach; leave running it to a service manager"
+        );
+        exit(2);
+    }
+    if cli.detach && !cli.supervise {
+        if let Err(e) = daemon::detach() {
+            eprintln!("pipe2: {e}");
+            exit(1);
+        }
+        return Ok(());
     }
 
-    pub fn can_read<R: AsRawHandle>(pipe: &R) -> io::Result<bool> {
-        let handle = pipe.as_raw_handle();
-        let mut bytes_avail = 0u32;
-        let ok = unsafe {
-            PeekNamedPipe(
-                handle as _,
-                std::ptr::null_mut(),
-                0,
-                std::ptr::null_mut(),
-                &mut bytes_avail,
-                std::ptr::null_mut(),
-            )
-        };
-        if ok != 0 {
-            reset_last_err_on_broken_pipe();
-        }
-        Ok(bytes_avail > 0)
-    }
-
-    pub fn read_pipe<R: AsRawHandle>(pipe: &mut R, buf: &mut [u8]) -> io::Result<usize> {
-        let handle = pipe.as_raw_handle();
-        let mut read = 0u32;
-        let ok = unsafe {
-            ReadFile(
-                handle as _,
-                buf.as_mut_ptr() as *mut _,
-                buf.len() as u32,
-                &mut read,
-                std::ptr::null_mut(),
-            )
-        };
-        if ok == 0 {
-            reset_last_err_on_broken_pipe();
+    if cli.tap {
+        exit(tap::run(&cli));
+    }
+
+    let run_dir = cli.run_dir.as_deref().map(RunDir::create).transpose()?;
+    if let Some(run_dir) = &run_dir {
+        cli.report
+            .get_or_insert_with(|| run_dir.path().join("report.json"));
+        cli.events
+            .get_or_insert_with(|| run_dir.path().join("events.ndjson"));
+    }
+
+    let mut pipe2 = Pipe2::new(&cli.program);
+    pipe2.args(&cli.args);
+    cli.configure(&mut pipe2);
+    match cli.events.as_deref() {
+        Some(path) if path == std::path::Path::new("-") => {
+            pipe2.event_log(io::stderr());
+        }
+        Some(path) => {
+            pipe2.event_log(std::fs::File::create(path)?);
         }
-        Ok(read as usize)
+        None => {}
+    }
+    match cli.trace_io.as_deref() {
+        Some(path) if path == std::path::Path::new("-") => {
+            pipe2.trace_io(io::stderr());
+        }
+        Some(path) => {
+            pipe2.trace_io(std::fs::File::create(path)?);
+        }
+        None => {}
+    }
+    if let Some(path) = &cli.record {
+        pipe2.record_session(std::fs::File::create(path)?);
     }
-}
 
-fn main() -> io::Result<()> {
-    let mut child = Command::new("ping")
-        .args(if cfg!(windows) {
-            &["-n", "10", "localhost"]
-        } else {
-            &["-c", "10", "localhost"]
-        })
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
+    let mut metrics = Exporter::start(
+        cli.metrics_file.clone(),
+        cli.metrics_addr.as_deref(),
+        cli.label.clone(),
+    )?;
+
+    if cli.supervise {
+        exit(daemon::supervise(
+            &mut pipe2,
+            &cli.logs,
+            cli.restart,
+            metrics.as_mut(),
+        ));
+    }
 
-    let mut stdout = child.stdout.take().expect("Failed to capture stdout");
-    let mut stderr = child.stderr.take().expect("Failed to capture stderr");
+    if let Some(schedule) = &schedule {
+        match schedule::run(&mut pipe2, &cli, schedule)? {}
+    }
+    #[cfg(feature = "notify")]
+    if !cli.watch.is_empty() {
+        match watch::run(&mut pipe2, &cli.watch, &cli.command_line())? {}
+    }
+
+    let ci = Ci::detect();
+    if let Some(ci) = ci.filter(|_| cli.ci_group) {
+        ci.start_group(&cli.command_line());
+    }
 
     #[cfg(unix)]
-    {
-        fcntl(&stdout, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
-        fcntl(&stderr, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).unwrap();
-    }
-
-    let mut stdout_buf = Vec::new();
-    let mut stderr_buf = Vec::new();
-    let mut scratchpad = vec![0u8; 1024];
-
-    // NOTE(gabriela): pipes are read during program execution, ensuring that no issues such as the pipe buffer
-    // becoming full and leading to blocking on process I/O operations happens.
-    //
-    // On Windows: https://learn.microsoft.com/en-us/windows/win32/api/winbase/nf-winbase-createnamedpipea
-    //
-    // "Whenever a pipe write operation occurs, the system first tries to charge the memory against the pipe write quota.
-    // If the remaining pipe write quota is enough to fulfill the request, the write operation completes immediately.
-    // If the remaining pipe write quota is too small to fulfill the request, the system will try to expand the buffers
-    // to accommodate the data using nonpaged pool reserved for the process. The write operation will block until the data
-    // is read from the pipe so that the additional buffer quota can be released."
-    //
-    // TL;DR: the `stdout`/`stderr` pipe buffers could get filled up if we don't read them *as* the process is executing,
-    // causing blocks on I/O.
-    let Some(exit) = loop {
-        #[cfg(unix)]
-        {
-            match stdout.read(&mut scratchpad[..]) {
-                Ok(0) => {}
-                Ok(n) => {
-                    io::stdout().write_all(&scratchpad[..n])?;
-                    io::stdout().flush()?;
-                    stdout_buf.extend_from_slice(&scratchpad[..n]);
+    let mut signals = if cli.signals.is_empty() && !cli.pty {
+        None
+    } else {
+        Some(Signals::install(&cli.signals, cli.pty)?)
+    };
+    #[cfg(not(unix))]
+    let mut signals: Option<Signals> = None;
+
+    let tees = cli
+        .tee
+        .iter()
+        .map(|path| Tee::create(path))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let (started, clock) = (SystemTime::now(), Instant::now());
+    let mut restarts = 0;
+    let output = loop {
+        let mut child = match pipe2.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                if cli.json {
+                    let result =
+                        json!({ "reason": ExitReason::SpawnError, "error": e.to_string() });
+                    eprintln!("{result}");
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                Err(e) => break Err(e),
+                return Err(e);
             }
+        };
+        let copying = tees
+            .iter()
+            .map(|tee| tee.start(&mut child))
+            .collect::<io::Result<Vec<_>>>()?;
+        #[cfg(feature = "upload")]
+        let uploading = cli.upload.as_ref().and_then(|upload| {
+            upload
+                .start(&mut child)
+                .inspect_err(|e| eprintln!("pipe2: couldn't start the upload: {e}"))
+                .ok()
+        });
+        let child = match cli.wait_for_ready() {
+            Some(timeout) => wait_ready(child, timeout)?,
+            None => child,
+        };
+        let output = watch(child, metrics.as_mut(), signals.as_mut())?;
+        for copying in copying {
+            let _ = copying.join();
+        }
+        #[cfg(feature = "upload")]
+        if let Some(uploading) = uploading {
+            report_upload(uploading.finish());
+        }
+        #[cfg(unix)]
+        let stopping = signals.as_ref().is_some_and(Signals::stopping);
+        #[cfg(not(unix))]
+        let stopping = false;
+        if stopping || !cli.restart.again(output.success, restarts) {
+            break output;
+        }
+        restarts += 1;
+        if let Some(metrics) = metrics.as_mut() {
+            metrics.restarted();
         }
+        eprintln!(
+            "pipe2: child exited with {}, restarting ({restarts})",
+            output.status
+        );
+        cli.restart.delay(&SystemClock, || false);
+    };
+    let duration = clock.elapsed();
 
-        #[cfg(windows)]
-        {
-            use windows_pipe_utils::*;
-            if can_read(&stdout)? {
-                let n = read_pipe(&mut stdout, &mut scratchpad[..])?;
-                if n != 0 {
-                    io::stdout().write_all(&scratchpad[..n])?;
-                    io::stdout().flush()?;
-                    stdout_buf.extend_from_slice(&scratchpad[..n]);
-                }
-            }
+    if let Some(ci) = ci {
+        if cli.ci_group {
+            ci.end_group();
         }
+        ci.annotate(&output.stdout, &cli.ci_errors);
+        ci.annotate(&output.stderr, &cli.ci_errors);
+    }
 
-        #[cfg(unix)]
-        {
-            match stderr.read(&mut scratchpad[..]) {
-                Ok(0) => {}
-                Ok(n) => {
-                    io::stderr().write_all(&scratchpad[..n])?;
-                    io::stderr().flush()?;
-                    stderr_buf.extend_from_slice(&scratchpad[..n]);
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                Err(e) => break Err(e),
-            }
+    let mut problems = problems::extract(&output.stdout, &cli.problem_matchers);
+    problems.extend(problems::extract(&output.stderr, &cli.problem_matchers));
+    if let Some(ci) = ci.filter(|_| cli.problem_annotations) {
+        ci.annotate_problems(&problems);
+    }
+
+    if let Some(file) = &cli.report {
+        let mut report = Report::new(
+            &cli.program,
+            &cli.args,
+            started,
+            duration,
+            &output,
+            problems,
+            !cli.classifiers.is_empty(),
+        );
+        report.label = cli.label.clone();
+        report.correlation_id = cli.correlation_id.clone();
+        if cli.report_env {
+            report.environment = Some(Environment::capture(&cli));
         }
+        if let Err(e) = report.save(file) {
+            eprintln!("pipe2: couldn't save the report to {}: {e}", file.display());
+        }
+    }
 
-        #[cfg(windows)]
-        {
-            use windows_pipe_utils::*;
-            if can_read(&stderr)? {
-                let n = read_pipe(&mut stderr, &mut scratchpad[..])?;
-                if n != 0 {
-                    io::stderr().write_all(&scratchpad[..n])?;
-                    io::stderr().flush()?;
-                    stderr_buf.extend_from_slice(&scratchpad[..n]);
-                }
+    if let Some(file) = &cli.junit {
+        let written = junit::write(
+            file,
+            &cli.command_line(),
+            cli.label.as_deref(),
+            duration,
+            &output,
+            &cli.junit_cases,
+        );
+        if let Err(e) = written {
+            eprintln!(
+                "pipe2: couldn't write the JUnit report to {}: {e}",
+                file.display()
+            );
+        }
+    }
+
+    if let Some(run_dir) = &run_dir {
+        let saved = run_dir
+            .save_output(&output)
+            .and_then(|()| run_dir.mark_latest());
+        if let Err(e) = saved {
+            eprintln!(
+                "pipe2: couldn't finish the run directory {}: {e}",
+                run_dir.path().display()
+            );
+        }
+    }
+
+    if cli.summary {
+        if output.detached {
+            eprintln!("\nChild closed its output and was left running");
+        } else {
+            eprintln!("\nChild exited with: {}", output.status);
+        }
+        let [stdout, stderr] = report::captured_bytes(&output);
+        eprintln!("Captured stdout bytes: {stdout}");
+        eprintln!("Captured stderr bytes: {stderr}");
+        if restarts > 0 {
+            eprintln!("Restarts: {restarts}");
+        }
+        if let Some(peak) = output.peak_memory {
+            eprintln!("Peak memory bytes: {peak}");
+        }
+        if output.read_sizes.total() > 0 {
+            eprintln!("Read sizes: {}", output.read_sizes);
+        }
+        if !cli.classifiers.is_empty() {
+            let severities = output.severities;
+            eprintln!(
+                "Classified lines: {} errors, {} warnings, {} info",
+                severities.errors, severities.warnings, severities.infos
+            );
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(connections) = &output.connections {
+            eprintln!("Network connections: {}", connections.len());
+            for connection in connections {
+                eprintln!("  {connection}");
+            }
+        }
+        #[cfg(all(feature = "file-access", target_os = "linux"))]
+        if let Some(files) = &output.files_written {
+            eprintln!("Files written: {}", files.len());
+            for path in files {
+                eprintln!("  {}", path.display());
             }
         }
+    }
 
-        match child.try_wait() {
-            Ok(None) => {}
-            Ok(Some(exit_code)) => break Ok(Some(exit_code)),
-            Err(e) => break Err(e),
-        };
+    if cli.json {
+        let [stdout_bytes, stderr_bytes] = report::captured_bytes(&output);
+        let result = json!({
+            "reason": output.reason,
+            "run_id": output.run_id.to_string(),
+            "correlation_id": cli.correlation_id,
+            "success": output.success,
+            "exit_code": output.status.code(),
+            "signal": report::signal(output.status),
+            "duration": duration.as_secs_f64(),
+            "restarts": restarts,
+            "stdout_bytes": stdout_bytes,
+            "stderr_bytes": stderr_bytes,
+        });
+        eprintln!("{result}");
+    }
 
-        std::thread::sleep(Duration::from_millis(10));
-    }?
-    else {
-        println!("Failed execution");
+    if cli.fail_on_errors && output.severities.errors > 0 && exit_code(&output) == 0 {
+        eprintln!(
+            "pipe2: the child succeeded, but {} of its lines were classified as errors",
+            output.severities.errors
+        );
         exit(1);
-    };
+    }
+    exit(exit_code(&output))
+}
 
-    println!("\nChild exited with: {exit}");
-    println!("Captured stdout bytes: {}", stdout_buf.len());
-    println!("Captured stderr bytes: {}", stderr_buf.len());
+/// Waits for the child to be ready, for `--wait-ready`, `--wait-for-port` and `--wait-for-http`, and stops it if it
+/// isn't in time.
+fn wait_ready(mut child: pipe2::Child, timeout: Duration) -> io::Result<pipe2::Child> {
+    let clock = Instant::now();
+    match child.wait_ready(timeout) {
+        Ok(true) => eprintln!("pipe2: ready after {:.3}s", clock.elapsed().as_secs_f64()),
+        Ok(false) => {
+            eprintln!(
+                "pipe2: not ready after {:.3}s, stopping it",
+                timeout.as_secs_f64()
+            );
+            child.kill()?;
+        }
+        Err(e) => eprintln!("pipe2: {e}"),
+    }
+    Ok(child)
+}
+
+/// Waits for the child to exit, keeping the metrics up to date and acting on the `--signal`s that come in, along with
+/// changes to the size of our terminal for `--pty`.
+fn watch(
+    mut child: pipe2::Child,
+    mut metrics: Option<&mut Exporter>,
+    #[cfg_attr(not(unix), allow(unused_mut))] mut signals: Option<&mut Signals>,
+) -> io::Result<pipe2::Output> {
+    if metrics.is_none() && signals.is_none() {
+        return child.wait();
+    }
+    loop {
+        let status = child.poll()?;
+        if let Some(metrics) = metrics.as_deref_mut() {
+            metrics.update(&child, status);
+        }
+        if status.is_some() {
+            return child.wait();
+        }
+        #[cfg(unix)]
+        if let Some(signals) = signals.as_deref_mut() {
+            signals.handle(&mut child)?;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
 
-    Ok(())
+/// Says where `--upload` ended up, or why it didn't.
+#[cfg(feature = "upload")]
+fn report_upload(uploaded: io::Result<pipe2::Uploaded>) {
+    match uploaded {
+        Ok(uploaded) => {
+            eprintln!(
+                "pipe2: uploaded {} bytes to {} in {} parts",
+                uploaded.bytes, uploaded.url, uploaded.parts
+            );
+        }
+        Err(e) => eprintln!("pipe2: the upload failed: {e}"),
+    }
+}
+
+/// Mirrors the child's exit code; 124 for a timeout (`--first-output-within` and `--on-output-closed` included) like
+/// coreutils' `timeout`, 152 for going over the CPU limit (like the `SIGXCPU` a `ulimit -t` sends), 0 for one of the
+/// `--success-codes`, and 128 + N for a child killed by signal N, like shells do.
+fn exit_code(output: &pipe2::Output) -> i32 {
+    if output.timed_out || output.first_output_timed_out || output.output_closed_timed_out {
+        return 124;
+    }
+    if output.cpu_limit_exceeded {
+        return 152;
+    }
+    if output.success {
+        return 0;
+    }
+    if let Some(code) = output.status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = output.status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
 }
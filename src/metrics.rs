@@ -0,0 +1,188 @@
+//! `--metrics-file` and `--metrics-addr`: counters about the supervised program in Prometheus' text format, for
+//! node_exporter's textfile collector or for scraping directly.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use pipe2::Child;
+
+/// How often the textfile is rewritten while the program runs.
+const WRITE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Counters {
+    /// Over every run, the current one included.
+    stdout_bytes: u64,
+    stderr_bytes: u64,
+    /// What the runs before the current one read, since each run's [`Child`] counts from zero.
+    earlier_bytes: (u64, u64),
+    restarts: u64,
+    running: bool,
+    last_exit_code: Option<i32>,
+}
+
+pub struct Exporter {
+    started: Instant,
+    counters: Arc<Mutex<Counters>>,
+    file: Option<PathBuf>,
+    last_written: Option<Instant>,
+    /// `--label`, added to every sample as a `label` label.
+    label: Option<String>,
+}
+
+impl Exporter {
+    /// `None` if neither a file nor an address was asked for.
+    pub fn start(
+        file: Option<PathBuf>,
+        addr: Option<&str>,
+        label: Option<String>,
+    ) -> io::Result<Option<Self>> {
+        if file.is_none() && addr.is_none() {
+            return Ok(None);
+        }
+        let exporter = Self {
+            started: Instant::now(),
+            counters: Arc::default(),
+            file,
+            last_written: None,
+            label,
+        };
+
+        if let Some(addr) = addr {
+            let listener = TcpListener::bind(addr)?;
+            let (started, counters) = (exporter.started, exporter.counters.clone());
+            let label = exporter.label.clone();
+            std::thread::spawn(move || serve(listener, started, &counters, label.as_deref()));
+        }
+        Ok(Some(exporter))
+    }
+
+    /// Takes in the latest from `child`, and rewrites the textfile if it's due.
+    pub fn update(&mut self, child: &Child, status: Option<ExitStatus>) {
+        {
+            let mut counters = self.counters.lock().unwrap();
+            let (stdout, stderr) = child.bytes_read();
+            counters.stdout_bytes = counters.earlier_bytes.0 + stdout;
+            counters.stderr_bytes = counters.earlier_bytes.1 + stderr;
+            counters.running = status.is_none();
+            if let Some(status) = status {
+                counters.last_exit_code = Some(status.code().unwrap_or(-1));
+            }
+        }
+
+        let due = self
+            .last_written
+            .is_none_or(|written| written.elapsed() >= WRITE_INTERVAL);
+        if let Some(file) = &self.file
+            && (due || status.is_some())
+        {
+            self.last_written = Some(Instant::now());
+            let text = render(
+                self.started,
+                &self.counters.lock().unwrap(),
+                self.label.as_deref(),
+            );
+            // NOTE: written next to the real file and renamed over it, so a scrape never sees half of it.
+            let mut temporary = file.clone().into_os_string();
+            temporary.push(".tmp");
+            let written = fs::write(&temporary, text).and_then(|()| fs::rename(&temporary, file));
+            if let Err(e) = written {
+                eprintln!("pipe2: couldn't write metrics to {}: {e}", file.display());
+            }
+        }
+    }
+
+    /// Counts a restart; the next [`Exporter::update`] is for the new run.
+    pub fn restarted(&mut self) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.earlier_bytes = (counters.stdout_bytes, counters.stderr_bytes);
+        counters.restarts += 1;
+    }
+}
+
+fn serve(listener: TcpListener, started: Instant, counters: &Mutex<Counters>, label: Option<&str>) {
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        // NOTE: whatever was asked for, the answer is the metrics; the request only has to be read out of the way.
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+        let _ = stream.read(&mut [0; 1024]);
+        let body = render(started, &counters.lock().unwrap(), label);
+        let response = format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let _ = stream.write_all(response.as_bytes());
+    }
+}
+
+fn render(started: Instant, counters: &Counters, label: Option<&str>) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, f64)]| {
+        let _ = writeln!(text, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let labels: Vec<String> = label
+                .map(|label| format!("label=\"{}\"", escape(label)))
+                .into_iter()
+                .chain((!labels.is_empty()).then(|| labels.to_string()))
+                .collect();
+            if labels.is_empty() {
+                let _ = writeln!(text, "{name} {value}");
+            } else {
+                let _ = writeln!(text, "{name}{{{}}} {value}", labels.join(","));
+            }
+        }
+    };
+
+    metric(
+        "pipe2_read_bytes_total",
+        "counter",
+        "Bytes read from the program's output.",
+        &[
+            ("stream=\"stdout\"", counters.stdout_bytes as f64),
+            ("stream=\"stderr\"", counters.stderr_bytes as f64),
+        ],
+    );
+    metric(
+        "pipe2_restarts_total",
+        "counter",
+        "Times the program was started again, with --restart.",
+        &[("", counters.restarts as f64)],
+    );
+    metric(
+        "pipe2_uptime_seconds",
+        "gauge",
+        "Time since pipe2 started supervising.",
+        &[("", started.elapsed().as_secs_f64())],
+    );
+    metric(
+        "pipe2_running",
+        "gauge",
+        "Whether the program is still running.",
+        &[("", f64::from(u8::from(counters.running)))],
+    );
+    if let Some(code) = counters.last_exit_code {
+        metric(
+            "pipe2_last_exit_code",
+            "gauge",
+            "The program's exit code, or -1 if it was killed by a signal.",
+            &[("", f64::from(code))],
+        );
+    }
+    text
+}
+
+/// A label value, with the backslashes, quotes and line breaks Prometheus' text format wants escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
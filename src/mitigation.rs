@@ -0,0 +1,56 @@
+//! Process mitigation policies for the child on Windows, set through its `STARTUPINFOEX` attribute list, see
+//! [`Pipe2::mitigation_policy`](crate::Pipe2::mitigation_policy).
+//!
+//! They're applied by the system as the child is created, before any of its code runs, and it can't lift them
+//! afterwards: what an untrusted plug-in executable needs to be kept from doing.
+
+/// `PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY`, a `DWORD64` of `PROCESS_CREATION_MITIGATION_POLICY_*` flags.
+pub(crate) const PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY: usize = 0x0002_0007;
+
+/// `PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY`, a `DWORD` of `PROCESS_CREATION_CHILD_PROCESS_*` flags.
+pub(crate) const PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY: usize = 0x0002_000e;
+
+/// `PROCESS_CREATION_CHILD_PROCESS_RESTRICTED`.
+pub(crate) const CHILD_PROCESS_RESTRICTED: u32 = 0x01;
+
+/// What the child is kept from doing, see [`Pipe2::mitigation_policy`](crate::Pipe2::mitigation_policy). None of these
+/// need privileges; some need a recent enough Windows 10, and creating the child fails on older ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MitigationPolicy {
+    /// It can't create processes of its own.
+    NoChildProcesses,
+    /// Its images are relocated even if they weren't built for ASLR, with bottom-up randomization.
+    ForceAslr,
+    /// Bottom-up randomization uses all of the 64-bit address space.
+    HighEntropyAslr,
+    /// Using an invalid handle raises an exception rather than returning an error.
+    StrictHandleChecks,
+    /// It can't generate or modify code at runtime.
+    NoDynamicCode,
+    /// It can't make `win32k.sys` system calls, so has no GUI.
+    NoWin32kCalls,
+    /// Legacy extension points, like AppInit DLLs, don't load into it.
+    NoExtensionPoints,
+    /// Only images signed by Microsoft load into it.
+    MicrosoftSignedOnly,
+    /// Images on remote devices, like network shares, don't load into it.
+    NoRemoteImages,
+}
+
+impl MitigationPolicy {
+    /// The policy's `PROCESS_CREATION_MITIGATION_POLICY_*_ALWAYS_ON` flags; none for
+    /// [`MitigationPolicy::NoChildProcesses`], which is an attribute of its own.
+    pub(crate) fn flags(self) -> u64 {
+        match self {
+            Self::NoChildProcesses => 0,
+            Self::ForceAslr => (1 << 8) | (1 << 16),
+            Self::HighEntropyAslr => 1 << 20,
+            Self::StrictHandleChecks => 1 << 24,
+            Self::NoWin32kCalls => 1 << 28,
+            Self::NoExtensionPoints => 1 << 32,
+            Self::NoDynamicCode => 1 << 36,
+            Self::MicrosoftSignedOnly => 1 << 44,
+            Self::NoRemoteImages => 1 << 52,
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Linux namespaces for the child, a lightweight sandbox: a test that must not touch the network gets a network
+//! namespace of its own, with nothing but a loopback interface in it.
+//!
+//! Like the rest of [`crate::pre_exec`], everything here runs between `fork` and `exec`, so what it needs is prepared
+//! by [`Unshare::new`] beforehand.
+
+use std::ffi::CStr;
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// A namespace the child can get a fresh copy of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    /// No network but its own loopback interface.
+    Network,
+    /// Its own mount table; mounts made in it don't propagate back to ours.
+    Mount,
+    /// Its own process IDs, with the child as PID 1.
+    Pid,
+    Ipc,
+    /// Its own hostname.
+    Uts,
+    /// Its own user IDs, with ours mapped to themselves. Added on its own when we're not root, since the others need
+    /// it to be created without privileges.
+    User,
+}
+
+impl Namespace {
+    fn clone_flag(self) -> libc::c_int {
+        match self {
+            Namespace::Network => libc::CLONE_NEWNET,
+            Namespace::Mount => libc::CLONE_NEWNS,
+            Namespace::Pid => libc::CLONE_NEWPID,
+            Namespace::Ipc => libc::CLONE_NEWIPC,
+            Namespace::Uts => libc::CLONE_NEWUTS,
+            Namespace::User => libc::CLONE_NEWUSER,
+        }
+    }
+}
+
+/// What to unshare, along with the ID maps to write if that includes a user namespace.
+#[derive(Clone)]
+pub(crate) struct Unshare {
+    flags: libc::c_int,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+/// The real child, for the process left in between to forward signals to once there's a PID namespace.
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+impl Unshare {
+    /// Returns `None` if there's nothing to unshare.
+    pub(crate) fn new(namespaces: &[Namespace]) -> Option<Self> {
+        let mut flags = namespaces
+            .iter()
+            .fold(0, |flags, namespace| flags | namespace.clone_flag());
+        if flags == 0 {
+            return None;
+        }
+        if unsafe { libc::geteuid() } != 0 {
+            flags |= libc::CLONE_NEWUSER;
+        }
+
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        Some(Self {
+            flags,
+            uid_map: format!("{uid} {uid} 1\n").into_bytes(),
+            gid_map: format!("{gid} {gid} 1\n").into_bytes(),
+        })
+    }
+
+    /// Whether [`Unshare::enter`] forks once more, into a PID namespace.
+    pub(crate) fn forks(&self) -> bool {
+        self.flags & libc::CLONE_NEWPID != 0
+    }
+
+    /// Moves the calling (forked) process into the new namespaces.
+    ///
+    /// NOTE: a new PID namespace only applies to children of whoever unshared it, so this forks once more: the
+    /// process std spawned stays behind, forwarding signals and mirroring the exit status, while its child goes on
+    /// to `exec` as PID 1 of the namespace. `SIGSTOP` can't be forwarded, so [`crate::Child::pause`] doesn't reach it.
+    pub(crate) fn enter(&self) -> io::Result<()> {
+        if unsafe { libc::unshare(self.flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if self.flags & libc::CLONE_NEWUSER != 0 {
+            // NOTE: an unprivileged process may only write `gid_map` once `setgroups` is denied.
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+        }
+        if self.flags & libc::CLONE_NEWNS != 0 {
+            let root = c"/";
+            let flags = libc::MS_REC | libc::MS_PRIVATE;
+            let ok = unsafe {
+                libc::mount(
+                    std::ptr::null(),
+                    root.as_ptr(),
+                    std::ptr::null(),
+                    flags,
+                    std::ptr::null(),
+                )
+            };
+            if ok != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if self.flags & libc::CLONE_NEWNET != 0 {
+            loopback_up()?;
+        }
+        if self.flags & libc::CLONE_NEWPID != 0 {
+            fork_into_pid_namespace()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_file(path: &CStr, contents: &[u8]) -> io::Result<()> {
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = unsafe { libc::write(fd, contents.as_ptr().cast(), contents.len()) };
+    let result = if written < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    };
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// A fresh network namespace starts out with its loopback interface down.
+fn loopback_up() -> io::Result<()> {
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, &src) in request.ifr_name.iter_mut().zip(b"lo") {
+        *dst = src as libc::c_char;
+    }
+    let mut result = Ok(());
+    unsafe {
+        if libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request) != 0 {
+            result = Err(io::Error::last_os_error());
+        } else {
+            request.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            if libc::ioctl(socket, libc::SIOCSIFFLAGS, &request) != 0 {
+                result = Err(io::Error::last_os_error());
+            }
+        }
+        libc::close(socket);
+    }
+    result
+}
+
+/// Returns in the new child only; the process in between never does.
+fn fork_into_pid_namespace() -> io::Result<()> {
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        // NOTE: without anyone left to forward signals, the child shouldn't outlive the process in between.
+        unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL as libc::c_ulong) };
+        return Ok(());
+    }
+
+    FORWARD_TO.store(pid, Ordering::Relaxed);
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = forward as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        for signal in [
+            libc::SIGTERM,
+            libc::SIGINT,
+            libc::SIGHUP,
+            libc::SIGQUIT,
+            libc::SIGUSR1,
+            libc::SIGUSR2,
+            libc::SIGCONT,
+        ] {
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+
+        // NOTE: this includes std's pipe for reporting `exec` errors, which would otherwise stay open until the child
+        // exits, and our copies of its `stdout`/`stderr`, which would hide their EOF.
+        if libc::syscall(libc::SYS_close_range, 0, libc::c_uint::MAX, 0) != 0 {
+            for fd in 0..libc::sysconf(libc::_SC_OPEN_MAX) as libc::c_int {
+                libc::close(fd);
+            }
+        }
+
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) < 0 {
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                libc::_exit(127);
+            }
+        }
+        if libc::WIFSIGNALED(status) {
+            die_of(libc::WTERMSIG(status));
+        }
+        libc::_exit(libc::WEXITSTATUS(status));
+    }
+}
+
+/// Dies of `signal` just like the child did, so that whoever waits for us sees the same status: the signal rather than
+/// an exit code, which is what tells a crash, or a seccomp filter's `SIGSYS`, apart from a failure.
+unsafe fn die_of(signal: libc::c_int) -> ! {
+    unsafe {
+        // NOTE: the child has dumped its core already, if it was going to; ours would only be in the way.
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        libc::signal(signal, libc::SIG_DFL);
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+        // NOTE: only reached if the signal doesn't kill by default, which one the child died of does.
+        libc::_exit(128 + signal);
+    }
+}
+
+extern "C" fn forward(signal: libc::c_int) {
+    let pid = FORWARD_TO.load(Ordering::Relaxed);
+    if pid > 0 {
+        unsafe { libc::kill(pid, signal) };
+    }
+}
@@ -0,0 +1,162 @@
+//! Noticing the child's network connections, see [`Pipe2::monitor_network`](crate::Pipe2::monitor_network).
+//!
+//! Nothing is intercepted: every so often, the sockets the child and its descendants have open (from
+//! `/proc/<pid>/fd`) are looked up in the kernel's socket tables (`/proc/<pid>/net/tcp` and the like), and whatever
+//! TCP or UDP socket shows up is kept. A connection opened and closed between two samples goes unnoticed, so this tells
+//! a tool that phones home on the side, not every packet it sent.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::process::descendants;
+
+/// How often the sockets are looked at.
+pub(crate) const SAMPLE_EVERY: Duration = Duration::from_millis(100);
+
+/// What a [`Connection`] speaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    /// `tcp` or `udp`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+/// A TCP or UDP socket the child (or something it started) had open, see [`Output::connections`].
+///
+/// [`Output::connections`]: crate::Output::connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Connection {
+    pub protocol: Protocol,
+    pub local: SocketAddr,
+    /// Where it's connected to; `None` for a socket that's only bound, or listening.
+    pub remote: Option<SocketAddr>,
+}
+
+/// `tcp 10.0.0.2:51234 -> 93.184.216.34:443`, or `tcp 0.0.0.0:8080` for one that isn't connected.
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.protocol.as_str(), self.local)?;
+        if let Some(remote) = self.remote {
+            write!(f, " -> {remote}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything seen so far, by sampling the sockets of the process `pid` and its descendants.
+pub(crate) struct NetworkMonitor {
+    pid: u32,
+    seen: BTreeSet<Connection>,
+}
+
+impl NetworkMonitor {
+    pub(crate) fn new(pid: u32) -> Self {
+        Self {
+            pid,
+            seen: BTreeSet::new(),
+        }
+    }
+
+    /// Looks at the sockets open right now, and returns the connections that weren't seen before.
+    pub(crate) fn sample(&mut self) -> Vec<Connection> {
+        let mut inodes = BTreeSet::new();
+        for pid in descendants(self.pid) {
+            let Ok(fds) = fs::read_dir(format!("/proc/{pid}/fd")) else {
+                continue;
+            };
+            for fd in fds.flatten() {
+                let Ok(target) = fs::read_link(fd.path()) else {
+                    continue;
+                };
+                let inode = target
+                    .to_str()
+                    .and_then(|target| target.strip_prefix("socket:["))
+                    .and_then(|target| target.strip_suffix(']'))
+                    .and_then(|inode| inode.parse::<u64>().ok());
+                inodes.extend(inode);
+            }
+        }
+        if inodes.is_empty() {
+            return Vec::new();
+        }
+
+        let mut new = Vec::new();
+        for (table, protocol) in [
+            ("tcp", Protocol::Tcp),
+            ("tcp6", Protocol::Tcp),
+            ("udp", Protocol::Udp),
+            ("udp6", Protocol::Udp),
+        ] {
+            // NOTE: the child's own tables, which are those of its network namespace rather than ours.
+            let Ok(contents) = fs::read_to_string(format!("/proc/{}/net/{table}", self.pid)) else {
+                continue;
+            };
+            for line in contents.lines().skip(1) {
+                if let Some((inode, connection)) = parse_socket(line, protocol)
+                    && inodes.contains(&inode)
+                    && self.seen.insert(connection)
+                {
+                    new.push(connection);
+                }
+            }
+        }
+        new
+    }
+
+    /// Everything seen, in order.
+    pub(crate) fn connections(&self) -> Vec<Connection> {
+        self.seen.iter().copied().collect()
+    }
+}
+
+/// A line of `/proc/net/tcp` and the like: `sl local_address rem_address st tx:rx tr:when retrnsmt uid timeout inode`.
+fn parse_socket(line: &str, protocol: Protocol) -> Option<(u64, Connection)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let local = parse_address(fields.get(1)?)?;
+    let remote = parse_address(fields.get(2)?)?;
+    let inode = fields.get(9)?.parse().ok()?;
+    let remote = (!remote.ip().is_unspecified() || remote.port() != 0).then_some(remote);
+    Some((
+        inode,
+        Connection {
+            protocol,
+            local,
+            remote,
+        },
+    ))
+}
+
+/// `0100007F:1F90` is `127.0.0.1:8080`: the address in hex, in 32-bit words of the host's byte order, and the port.
+fn parse_address(field: &str) -> Option<SocketAddr> {
+    let (ip, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let word = |i: usize| {
+        ip.get(i * 8..i * 8 + 8)
+            .and_then(|word| u32::from_str_radix(word, 16).ok())
+            .map(|word| word.to_ne_bytes())
+    };
+    let ip = match ip.len() {
+        8 => IpAddr::V4(Ipv4Addr::from(word(0)?)),
+        32 => {
+            let mut octets = [0; 16];
+            for i in 0..4 {
+                octets[i * 4..i * 4 + 4].copy_from_slice(&word(i)?);
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
@@ -0,0 +1,67 @@
+//! The systemd readiness protocol, for services that say when they're up rather than being guessed at.
+//!
+//! The child finds a datagram socket at the path in [`NOTIFY_ENV`], like systemd's `NOTIFY_SOCKET`, and sends it
+//! `READY=1` once it's ready to serve, which is what `sd_notify(0, "READY=1")` and `systemd-notify --ready` do. Other
+//! `KEY=VALUE` lines are ignored. Messages are picked up by [`Child::poll`](crate::Child::poll), without blocking it.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Name of the environment variable telling the child where the socket is.
+pub const NOTIFY_ENV: &str = "NOTIFY_SOCKET";
+
+/// Messages are cut off past this many bytes; systemd's own are nowhere near it.
+const MAX_MESSAGE: usize = 4096;
+
+/// Tells apart the sockets of runs going at the same time in one process.
+static SOCKETS: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    /// Binds a socket of its own in the temporary directory, removed again once it's dropped.
+    pub(crate) fn bind() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "pipe2-notify-{}-{}.sock",
+            std::process::id(),
+            SOCKETS.fetch_add(1, Ordering::Relaxed)
+        ));
+        // NOTE: a socket left behind by a process that had the same PID can only be stale.
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Takes in whatever the child sent, and returns whether `READY=1` was in it.
+    pub(crate) fn ready(&mut self) -> io::Result<bool> {
+        let mut ready = false;
+        let mut scratchpad = [0u8; MAX_MESSAGE];
+        loop {
+            match self.socket.recv(&mut scratchpad) {
+                Ok(n) => {
+                    ready |= scratchpad[..n]
+                        .split(|&byte| byte == b'\n')
+                        .any(|line| line == b"READY=1");
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(ready),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,102 @@
+//! Rewriting, dropping or stopping on single lines of output, for embedding applications that only need a closure
+//! rather than a whole [`ChunkTransform`].
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::stream::Stream;
+use crate::transform::ChunkTransform;
+
+/// What to do with a line, as returned by a [`Pipe2::on_line`](crate::Pipe2::on_line) closure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LineAction {
+    /// Leave the line as it is.
+    Keep,
+    /// Put this in place of the line; the line ending is added back.
+    Emit(Vec<u8>),
+    /// Leave the line out, of the echo and the capture alike.
+    Drop,
+    /// Keep the line, and stop the child the way [`Child::kill`](crate::Child::kill) does; the run ends with
+    /// [`ExitReason::Aborted`](crate::ExitReason::Aborted).
+    Abort,
+}
+
+/// The closure, shared by every stream of every run.
+pub(crate) type Hook = Arc<dyn Fn(Stream, &[u8]) -> LineAction + Send + Sync>;
+
+/// Set once a closure returned [`LineAction::Abort`]; each run has one of its own, so that the next one starts over.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Abort(Arc<AtomicBool>);
+
+impl Abort {
+    pub(crate) fn abort(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Hands each line of one stream to the closure.
+pub(crate) struct OnLine {
+    hook: Hook,
+    stream: Stream,
+    abort: Abort,
+    /// The start of a line whose end hasn't come yet.
+    partial: Vec<u8>,
+}
+
+impl OnLine {
+    pub(crate) fn new(hook: Hook, stream: Stream, abort: Abort) -> Self {
+        Self {
+            hook,
+            stream,
+            abort,
+            partial: Vec::new(),
+        }
+    }
+
+    fn line(&mut self, line: &[u8], ending: &[u8], out: &mut Vec<u8>) {
+        match (self.hook)(self.stream, line) {
+            LineAction::Keep => {
+                out.extend_from_slice(line);
+                out.extend_from_slice(ending);
+            }
+            LineAction::Emit(replacement) => {
+                out.extend_from_slice(&replacement);
+                out.extend_from_slice(ending);
+            }
+            LineAction::Drop => {}
+            LineAction::Abort => {
+                out.extend_from_slice(line);
+                out.extend_from_slice(ending);
+                self.abort.abort();
+            }
+        }
+    }
+}
+
+impl ChunkTransform for OnLine {
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        for piece in chunk.split_inclusive(|&byte| byte == b'\n') {
+            if !piece.ends_with(b"\n") {
+                self.partial.extend_from_slice(piece);
+                break;
+            }
+            let mut line = std::mem::take(&mut self.partial);
+            line.extend_from_slice(piece);
+            // NOTE: the closure gets the line without its ending, `\r\n` included.
+            let body = line.len() - if line.ends_with(b"\r\n") { 2 } else { 1 };
+            let (body, ending) = line.split_at(body);
+            self.line(body, ending, out);
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.line(&line, b"", out);
+        }
+    }
+}
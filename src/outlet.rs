@@ -0,0 +1,346 @@
+//! Where the echo ends up: our own `stdout` and `stderr`, which don't always keep up with the child.
+//!
+//! The writes happen on a thread of their own, fed through a bounded queue, so that a slow terminal holds up that
+//! thread rather than the capture loop; otherwise the child could end up stuck on a full pipe, which is the very thing
+//! this crate is about avoiding. [`Backpressure`] says what happens once the queue is full.
+//!
+//! Every child's writer thread goes through the same lock to get to our `stdout` and `stderr`, and while more than one
+//! is around, they only write whole lines, so that children running side by side never land in the middle of each
+//! other's lines.
+//!
+//! The chunks are copied into buffers from a pool, which the writer thread hands back once they're written, so a child
+//! writing megabytes a second doesn't mean an allocation for every read.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::child::Settings;
+
+/// What happens to the echo when our `stdout` or `stderr` takes it slower than the child writes, like a slow terminal
+/// or a pipe into something busy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for the destination to catch up once it's more than 1 MiB behind, holding up the capture loop meanwhile;
+    /// a child that writes a lot can end up stuck on a full pipe.
+    #[default]
+    Block,
+    /// Drop the chunks that come while the destination is still busy with earlier ones. The capture is complete
+    /// regardless.
+    Drop,
+    /// Hold on to up to this many bytes while the destination is busy, and drop the chunks that don't fit.
+    Buffer(usize),
+}
+
+/// When the echo is flushed to our `stdout` and `stderr`, trading latency for fewer writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Flush {
+    /// After every chunk read from the child, so it shows up as soon as possible.
+    #[default]
+    Chunk,
+    /// Once a line is complete; the start of a line, like a prompt, waits for its end.
+    Line,
+    /// At most this often.
+    Every(Duration),
+    /// Only once the child is done.
+    Exit,
+}
+
+/// Whatever the [`Flush`] policy, the echo is written out once this much of it piles up.
+const FLUSH_SIZE: usize = 64 * 1024;
+
+/// Held for every write to our `stdout` or `stderr`, whichever child it's for.
+static TERMINAL: Mutex<()> = Mutex::new(());
+
+/// How many writer threads are running, across every child.
+static WRITERS: AtomicUsize = AtomicUsize::new(0);
+
+/// How far behind the echo can fall under [`Backpressure::Block`] before the capture loop waits for it.
+const BLOCK_QUEUE_SIZE: usize = 1024 * 1024;
+
+/// How many buffers [`Pipe2::chunk_pool`](crate::Pipe2::chunk_pool) keeps around for reuse, unless told otherwise.
+pub(crate) const DEFAULT_CHUNK_POOL: usize = 16;
+
+/// The writer thread's end: what's been taken off the queue but not written yet.
+struct Writer {
+    flush: Flush,
+    buffers: [Vec<u8>; 2],
+    last_flush: [Instant; 2],
+    /// Set once the child is done, when whatever is left goes out, whole lines or not.
+    closing: bool,
+}
+
+/// Our `stdout` and `stderr`, as the echo's destination.
+pub(crate) struct Outlet {
+    backpressure: Backpressure,
+    queue: Arc<Queue>,
+    writer: Option<JoinHandle<()>>,
+    /// How many bytes of each stream were dropped so far.
+    dropped: [u64; 2],
+}
+
+/// What's been sent but not written yet, shared with the writer thread.
+#[derive(Default)]
+struct Queue {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// Chunks along with their stream (0 for `stdout`, 1 for `stderr`), in the order they were sent.
+    chunks: VecDeque<(usize, Vec<u8>)>,
+    /// Buffers whose chunks were written, for the next ones to be copied into.
+    pool: Vec<Vec<u8>>,
+    /// How many buffers `pool` keeps at most.
+    pool_size: usize,
+    /// How much is queued, counting the chunk being written.
+    bytes: usize,
+    closing: bool,
+    /// Whether writing each stream failed; nothing more is written to it after that.
+    failed: [bool; 2],
+    /// What went wrong, until it's been reported.
+    errors: [Option<io::Error>; 2],
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes out the chunks as they come, as often as `flush` says, until the outlet is closed and the queue is
+    /// empty.
+    fn write(&self, flush: Flush) {
+        let mut writer = Writer {
+            flush,
+            buffers: Default::default(),
+            last_flush: [Instant::now(); 2],
+            closing: false,
+        };
+        loop {
+            let mut closing = false;
+            let next = {
+                let mut state = self.lock();
+                loop {
+                    if let Some(next) = state.chunks.pop_front() {
+                        break Some(next);
+                    }
+                    if state.closing {
+                        closing = true;
+                        break None;
+                    }
+                    match writer.deadline() {
+                        Some(deadline) => {
+                            let timeout = deadline.saturating_duration_since(Instant::now());
+                            let (woken, waited) = self
+                                .changed
+                                .wait_timeout(state, timeout)
+                                .unwrap_or_else(|e| e.into_inner());
+                            state = woken;
+                            if waited.timed_out() {
+                                break None;
+                            }
+                        }
+                        None => state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner()),
+                    }
+                }
+            };
+            let Some((index, chunk)) = next else {
+                // NOTE: either closing, or an `Every` deadline is up; both mean writing out everything.
+                writer.closing = closing;
+                let failed = self.lock().failed;
+                for index in (0..2).filter(|&index| !failed[index]) {
+                    let flushed = writer.flush(index, None);
+                    self.fail(index, flushed);
+                }
+                if closing {
+                    WRITERS.fetch_sub(1, Ordering::Relaxed);
+                    return;
+                }
+                continue;
+            };
+            let failed = self.lock().failed;
+            if !failed[index] {
+                let written = writer.write(index, &chunk);
+                self.fail(index, written);
+            }
+            let mut state = self.lock();
+            state.bytes -= chunk.len();
+            state.recycle(chunk);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Stops writing stream `index` if `result` is an error, keeping it for [`Outlet::check`].
+    fn fail(&self, index: usize, result: io::Result<()>) {
+        if let Err(e) = result {
+            let mut state = self.lock();
+            state.failed[index] = true;
+            state.errors[index] = Some(e);
+        }
+    }
+}
+
+impl State {
+    /// Puts `buffer` back in the pool, unless that's full already.
+    fn recycle(&mut self, mut buffer: Vec<u8>) {
+        if self.pool.len() < self.pool_size {
+            buffer.clear();
+            self.pool.push(buffer);
+        }
+    }
+}
+
+impl Writer {
+    /// When a buffer has to be written out at the latest, if there's anything in them and the policy is
+    /// [`Flush::Every`].
+    fn deadline(&self) -> Option<Instant> {
+        let Flush::Every(interval) = self.flush else {
+            return None;
+        };
+        (0..2)
+            .filter(|&index| !self.buffers[index].is_empty())
+            .map(|index| self.last_flush[index] + interval)
+            .min()
+    }
+
+    /// Adds a chunk for stream `index`, and writes out what the policy says is due.
+    fn write(&mut self, index: usize, chunk: &[u8]) -> io::Result<()> {
+        self.buffers[index].extend_from_slice(chunk);
+        if self.buffers[index].len() >= FLUSH_SIZE {
+            return self.flush(index, None);
+        }
+        match self.flush {
+            Flush::Chunk => self.flush(index, None),
+            Flush::Line => match self.buffers[index].iter().rposition(|&byte| byte == b'\n') {
+                Some(end) => self.flush(index, Some(end + 1)),
+                None => Ok(()),
+            },
+            Flush::Every(interval) if self.last_flush[index].elapsed() >= interval => {
+                self.flush(index, None)
+            }
+            Flush::Every(_) | Flush::Exit => Ok(()),
+        }
+    }
+
+    /// Writes out the first `end` bytes buffered for stream `index`, or all of them; only up to the last line break
+    /// while other children are echoing too.
+    fn flush(&mut self, index: usize, end: Option<usize>) -> io::Result<()> {
+        self.last_flush[index] = Instant::now();
+        let buffer = &self.buffers[index];
+        let mut end = end.unwrap_or(buffer.len());
+        // NOTE: a line that runs past `FLUSH_SIZE` goes out in pieces regardless, rather than piling up for good.
+        if !self.closing && buffer.len() < FLUSH_SIZE && WRITERS.load(Ordering::Relaxed) > 1 {
+            end = buffer[..end]
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |i| i + 1);
+        }
+        if end == 0 {
+            return Ok(());
+        }
+        let written = write_all(index, &self.buffers[index][..end]);
+        self.buffers[index].drain(..end);
+        written
+    }
+}
+
+impl Outlet {
+    /// Starts the writer thread, unless there's nothing to echo.
+    pub(crate) fn new(settings: &Settings) -> Self {
+        let queue = Arc::new(Queue::default());
+        queue.lock().pool_size = settings.chunk_pool;
+        let writer = settings.echo.then(|| {
+            let (queue, flush) = (queue.clone(), settings.flush);
+            WRITERS.fetch_add(1, Ordering::Relaxed);
+            thread::spawn(move || queue.write(flush))
+        });
+        Self {
+            backpressure: settings.backpressure,
+            queue,
+            writer,
+            dropped: [0; 2],
+        }
+    }
+
+    /// Queues `bytes` for stream `index`, or drops them if the queue is full, depending on the [`Backpressure`].
+    /// Fails with whatever went wrong writing the stream before.
+    pub(crate) fn send(&mut self, index: usize, bytes: &[u8]) -> io::Result<()> {
+        let cap = match self.backpressure {
+            Backpressure::Block => BLOCK_QUEUE_SIZE,
+            Backpressure::Drop => 0,
+            Backpressure::Buffer(cap) => cap,
+        };
+        // NOTE: chunks are kept or dropped whole, and a chunk always goes through when the queue is empty, however big
+        // it is, so the echo doesn't stop for good on a child that writes large chunks.
+        let fits = |state: &State| state.bytes == 0 || state.bytes + bytes.len() <= cap;
+        let mut state = self.queue.lock();
+        if self.backpressure == Backpressure::Block {
+            while !fits(&state) && !state.failed[index] {
+                state = self
+                    .queue
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        if let Some(e) = state.errors[index].take() {
+            return Err(e);
+        }
+        if !fits(&state) {
+            self.dropped[index] += bytes.len() as u64;
+            return Ok(());
+        }
+        let mut chunk = state.pool.pop().unwrap_or_default();
+        chunk.extend_from_slice(bytes);
+        state.chunks.push_back((index, chunk));
+        state.bytes += bytes.len();
+        self.queue.changed.notify_all();
+        Ok(())
+    }
+
+    /// Whatever went wrong writing stream `index` since it was last asked.
+    pub(crate) fn check(&mut self, index: usize) -> io::Result<()> {
+        match self.queue.lock().errors[index].take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// How many bytes of stream `index` were dropped.
+    pub(crate) fn dropped(&self, index: usize) -> u64 {
+        self.dropped[index]
+    }
+
+    /// Waits for everything queued to be written, once the child is done; see [`Outlet::check`] for how it went.
+    pub(crate) fn close(&mut self) {
+        self.queue.lock().closing = true;
+        self.queue.changed.notify_all();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl Drop for Outlet {
+    /// Lets the writer thread finish up on its own, for a child that was never waited on.
+    fn drop(&mut self) {
+        self.queue.lock().closing = true;
+        self.queue.changed.notify_all();
+    }
+}
+
+fn write_all(index: usize, bytes: &[u8]) -> io::Result<()> {
+    let _terminal = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+    match index {
+        0 => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(bytes)?;
+            stdout.flush()
+        }
+        _ => io::stderr().write_all(bytes),
+    }
+}
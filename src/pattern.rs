@@ -0,0 +1,92 @@
+//! Line patterns with named fields, like `{file}:{line}: {message}`, for picking things out of the program's output
+//! without a regex engine.
+//!
+//! Each `{name}` matches at least one character, as few as it can while still letting the rest of the pattern match;
+//! `{line}` and `{column}` only match digits, and `{_}` matches anything without keeping it. Everything else has to
+//! match as is, and the pattern has to cover the whole line.
+
+enum Part {
+    Literal(String),
+    Field(&'static str),
+}
+
+pub struct Pattern {
+    parts: Vec<Part>,
+}
+
+impl Pattern {
+    /// `fields` are the names allowed between braces, besides `_`; `what` names the pattern in errors.
+    pub fn parse(pattern: &str, fields: &[&'static str], what: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            let Some(start) = rest.find('{') else {
+                parts.push(Part::Literal(rest.to_owned()));
+                break;
+            };
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` in {what} {pattern:?}"))?;
+            let name = &rest[start + 1..start + end];
+            let field = std::iter::once(&"_")
+                .chain(fields)
+                .find(|field| **field == name)
+                .ok_or_else(|| format!("unknown field {{{name}}} in {what} {pattern:?}"))?;
+            // NOTE: two fields back to back can't be told apart.
+            if matches!(parts.last(), Some(Part::Field(_))) {
+                return Err(format!(
+                    "fields need something between them in {what} {pattern:?}"
+                ));
+            }
+            parts.push(Part::Field(field));
+            rest = &rest[start + end + 1..];
+        }
+        Ok(Self { parts })
+    }
+
+    /// The value of each field, `{_}` aside, if `line` matches.
+    pub fn captures<'a>(&self, line: &'a str) -> Option<Vec<(&'static str, &'a str)>> {
+        let mut captures = Vec::new();
+        if !match_parts(&self.parts, line, &mut captures) {
+            return None;
+        }
+        captures.retain(|(field, _)| *field != "_");
+        Some(captures)
+    }
+}
+
+/// Matches `parts` against all of `text`, with each field taking as little as it can and giving more if what follows
+/// doesn't match.
+fn match_parts<'a>(
+    parts: &[Part],
+    text: &'a str,
+    captures: &mut Vec<(&'static str, &'a str)>,
+) -> bool {
+    let Some((part, rest)) = parts.split_first() else {
+        return text.is_empty();
+    };
+    match part {
+        Part::Literal(literal) => text
+            .strip_prefix(literal.as_str())
+            .is_some_and(|text| match_parts(rest, text, captures)),
+        Part::Field(field) => {
+            let numeric = matches!(*field, "line" | "column");
+            let ends = text.char_indices().map(|(end, _)| end).skip(1);
+            for end in ends.chain([text.len()]).filter(|&end| end > 0) {
+                let value = &text[..end];
+                if numeric && !value.ends_with(|c: char| c.is_ascii_digit()) {
+                    break;
+                }
+                captures.push((field, value));
+                if match_parts(rest, &text[end..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! `pipe2 pipe`: runs PROGRAM into another command, its stdout going through stages of ours on the way, like
+//! `PROGRAM | filter | CMD` in a shell with both ends supervised. `pipe2 tee`: runs PROGRAM into several commands at
+//! once, like `PROGRAM | tee >(CMD) >(CMD)`.
+//!
+//! The last command's stdout is relayed as PROGRAM's would be, every stderr is relayed and captured, and what they all
+//! captured is summed up at the end. `pipe2` exits with the first failure's exit code, like `set -o pipefail`.
+
+use std::io;
+
+use pipe2::{FanOut, LineAction, Pipe2, Pipeline, Redact, StripAnsi};
+
+use crate::cli::Cli;
+use crate::report;
+
+/// What `pipe` was told about the command on the other end.
+pub struct Pipe {
+    /// The program and its arguments.
+    pub command: Vec<String>,
+    pub stages: Vec<Stage>,
+}
+
+/// What PROGRAM's stdout goes through on its way to CMD, in order.
+pub enum Stage {
+    StripAnsi,
+    /// Replaces the text with `***`.
+    Redact(String),
+    /// Keeps only the lines containing the text.
+    Grep(String),
+    /// Drops the lines containing the text.
+    GrepV(String),
+}
+
+impl Stage {
+    /// `strip-ansi`, `redact=TEXT`, `grep=TEXT` or `grep-v=TEXT`, for `--through`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid --through {value:?}");
+        match value.split_once('=') {
+            None if value == "strip-ansi" => Ok(Self::StripAnsi),
+            Some((_, "")) => Err(invalid()),
+            Some(("redact", text)) => Ok(Self::Redact(text.to_owned())),
+            Some(("grep", text)) => Ok(Self::Grep(text.to_owned())),
+            Some(("grep-v", text)) => Ok(Self::GrepV(text.to_owned())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Runs PROGRAM into CMD, and returns the exit code `pipe2` has.
+pub fn run(cli: &Cli, pipe: &Pipe) -> io::Result<i32> {
+    let (program, args) = pipe
+        .command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--into has no command"))?;
+    let mut producer = Pipe2::new(&cli.program);
+    producer.args(&cli.args);
+    cli.configure(&mut producer);
+    let mut consumer = Pipe2::new(program);
+    consumer.args(args);
+
+    let mut pipeline = Pipeline::new(producer, consumer);
+    for stage in &pipe.stages {
+        match stage {
+            Stage::StripAnsi => {
+                pipeline.transform(StripAnsi::new);
+            }
+            Stage::Redact(text) => {
+                let secrets = vec![text.clone()];
+                pipeline.transform(move || Redact::new(&secrets));
+            }
+            Stage::Grep(text) | Stage::GrepV(text) => {
+                let text = text.clone().into_bytes();
+                let keep = matches!(stage, Stage::Grep(_));
+                pipeline.on_line(move |line| {
+                    let found = line.windows(text.len()).any(|window| window == text);
+                    if found == keep {
+                        LineAction::Keep
+                    } else {
+                        LineAction::Drop
+                    }
+                });
+            }
+        }
+    }
+    let output = pipeline.run()?;
+
+    eprintln!();
+    summarize(&cli.program.to_string_lossy(), &output.producer);
+    summarize(program, &output.consumer);
+    eprintln!("pipe2: {} bytes passed through", output.passed);
+    if output.producer_stopped {
+        eprintln!(
+            "pipe2: {program} exited first, so {} was stopped",
+            cli.program.to_string_lossy()
+        );
+    }
+
+    let consumer = crate::exit_code(&output.consumer);
+    let producer = crate::exit_code(&output.producer);
+    Ok(if consumer == 0 && !output.producer_stopped {
+        producer
+    } else {
+        consumer
+    })
+}
+
+/// Runs PROGRAM into every one of `commands`, and returns the exit code `pipe2` has: PROGRAM's if it failed, or the
+/// first command's that did.
+pub fn tee(cli: &Cli, commands: &[Vec<String>]) -> io::Result<i32> {
+    let mut source = Pipe2::new(&cli.program);
+    source.args(&cli.args);
+    cli.configure(&mut source);
+    let mut fan_out = FanOut::new(source);
+    for command in commands {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--to has no command"))?;
+        let mut sink = Pipe2::new(program);
+        // NOTE: their echoes are mixed in with PROGRAM's.
+        sink.args(args).label(program);
+        fan_out.sink(sink);
+    }
+    let output = fan_out.run()?;
+
+    eprintln!();
+    summarize(&cli.program.to_string_lossy(), &output.source);
+    for (command, sink) in commands.iter().zip(&output.sinks) {
+        summarize(&command[0], sink);
+    }
+    Ok(std::iter::once(&output.source)
+        .chain(&output.sinks)
+        .map(crate::exit_code)
+        .find(|&code| code != 0)
+        .unwrap_or(0))
+}
+
+fn summarize(name: &str, output: &pipe2::Output) {
+    eprintln!(
+        "pipe2: {name}: {}, {} bytes of stderr",
+        report::describe(output.status),
+        output.stderr.len()
+    );
+}
@@ -0,0 +1,162 @@
+//! `a | transform | b` as one supervised run: what one child writes is rewritten by us on its way to the other's
+//! `stdin`, with both children's `stderr` captured and echoed as usual. See [`Pipeline`].
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::child::Output;
+use crate::command::Pipe2;
+use crate::on_line::{self, LineAction, OnLine};
+use crate::stdin::StdinQueue;
+use crate::stream::Stream;
+use crate::transform::{self, ChunkTransform, Factory};
+
+/// How much of the producer's output may be waiting for the consumer before the producer isn't read from anymore, so
+/// that it's held up on a full pipe until the consumer catches up, like it would be in a shell pipeline.
+pub(crate) const MAX_QUEUED: usize = 1024 * 1024;
+
+/// Two children, the `stdout` of the first (the producer) going through the stages added with
+/// [`Pipeline::transform`] and [`Pipeline::on_line`] and into the `stdin` of the second (the consumer): what
+/// `producer | filter | consumer` would be in a shell, with the filter being ours, and both children supervised the
+/// way [`Pipe2`] does.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use pipe2::{LineAction, Pipe2, Pipeline, StripAnsi};
+///
+/// let mut producer = Pipe2::new("cargo");
+/// producer.args(["build", "--color=always"]);
+/// let mut pipeline = Pipeline::new(producer, Pipe2::new("sort"));
+/// pipeline.transform(StripAnsi::new).on_line(|line| {
+///     if line.starts_with(b"warning") {
+///         LineAction::Keep
+///     } else {
+///         LineAction::Drop
+///     }
+/// });
+/// let output = pipeline.run()?;
+/// println!("{} bytes went to sort", output.passed);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pipeline {
+    producer: Pipe2,
+    consumer: Pipe2,
+    transforms: Vec<Factory>,
+}
+
+/// How both children of a [`Pipeline`] went.
+#[derive(Debug, Clone)]
+pub struct PipelineOutput {
+    /// The producer's [`Output`]; its `stdout` is empty, as all of it went to the consumer.
+    pub producer: Output,
+    pub consumer: Output,
+    /// How many bytes the consumer was handed, once through the stages.
+    pub passed: u64,
+    /// Whether the producer was stopped for still running once the consumer exited, the way `SIGPIPE` would stop it
+    /// in a shell.
+    pub producer_stopped: bool,
+}
+
+impl Pipeline {
+    /// `producer`'s `stdout` is always captured, and never echoed; `consumer`'s `stdin` is whatever the stages let
+    /// through, closed once the producer is done.
+    pub fn new(producer: Pipe2, consumer: Pipe2) -> Self {
+        Self {
+            producer,
+            consumer,
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Adds a stage that rewrites what goes from the producer to the consumer, like [`Pipe2::transform`] does for the
+    /// output of one child: a [`Redact`](crate::Redact), or a decompressor of the caller's. Stages run in the order
+    /// they're added, [`Pipeline::on_line`] included.
+    pub fn transform<F, T>(&mut self, make: F) -> &mut Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: ChunkTransform + 'static,
+    {
+        self.transforms.push(Arc::new(move |_, _| {
+            Box::new(make()) as Box<dyn ChunkTransform>
+        }));
+        self
+    }
+
+    /// Hands every line going from the producer to the consumer to `f`, like [`Pipe2::on_line`], to keep, rewrite or
+    /// drop it. [`LineAction::Abort`] stops the producer, and the consumer gets what came before.
+    pub fn on_line<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&[u8]) -> LineAction + Send + Sync + 'static,
+    {
+        let hook: on_line::Hook = Arc::new(move |_, line| f(line));
+        self.transforms.push(Arc::new(move |stream, abort| {
+            Box::new(OnLine::new(hook.clone(), stream, abort.clone())) as Box<dyn ChunkTransform>
+        }));
+        self
+    }
+
+    /// Spawns both children, and passes what the producer writes on to the consumer until both have exited.
+    pub fn run(&mut self) -> io::Result<PipelineOutput> {
+        let queue = StdinQueue::default();
+        self.producer.pipe_stdout();
+        self.consumer.stdin_queue(queue.clone());
+        let mut producer = self.producer.spawn()?;
+        let mut consumer = match self.consumer.spawn() {
+            Ok(consumer) => consumer,
+            Err(e) => {
+                producer.kill()?;
+                producer.wait()?;
+                return Err(e);
+            }
+        };
+
+        // NOTE: the stages abort the producer's run, as if they were its own.
+        let mut stages =
+            transform::Pipeline::new(&self.transforms, Stream::Stdout, None, producer.abort());
+        let mut between = Vec::new();
+        let mut passed = 0;
+        let producer_stopped = loop {
+            // NOTE: the producer isn't polled while the consumer has enough to chew on, its timeouts included; its
+            // pipe fills up, and it waits.
+            if queue.len() < MAX_QUEUED {
+                let exited = producer.poll()?.is_some();
+                let chunk = producer.take_stdout();
+                stages.transform(&chunk, &mut between);
+                producer.recycle(chunk);
+                passed += between.len() as u64;
+                queue.push(&between);
+                between.clear();
+                if exited {
+                    break false;
+                }
+            }
+            if consumer.poll()?.is_some() {
+                let running = producer.poll()?.is_none();
+                if running {
+                    producer.kill()?;
+                }
+                break running;
+            }
+            producer.clock().sleep(Duration::from_millis(10));
+        };
+
+        let mut producer = producer.wait()?;
+        if !producer_stopped {
+            // NOTE: what was read as the producer exited, and what the stages held back for a line to end.
+            stages.transform(&producer.stdout, &mut between);
+            stages.finish(&mut between);
+            passed += between.len() as u64;
+            queue.push(&between);
+        }
+        producer.stdout.clear();
+        queue.close();
+        Ok(PipelineOutput {
+            producer,
+            consumer: consumer.wait()?,
+            passed,
+            producer_stopped,
+        })
+    }
+}
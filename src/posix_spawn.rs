@@ -0,0 +1,107 @@
+//! Spawning through `posix_spawn` rather than std's `Command`, for runs that need nothing it can't do; see
+//! [`Pipe2::posix_spawn`](crate::Pipe2::posix_spawn).
+
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, PipeReader};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+
+use nix::spawn::{PosixSpawnAttr, PosixSpawnFileActions, PosixSpawnFlags, posix_spawnp};
+use nix::sys::signal::{SigSet, Signal};
+
+use crate::process::UnixProcess;
+use crate::stream::Disposition;
+
+/// What the child is spawned with.
+pub(crate) struct Spawn<'a> {
+    pub(crate) program: &'a OsStr,
+    pub(crate) args: &'a [OsString],
+    pub(crate) env: Vec<(OsString, OsString)>,
+    /// An empty `stdin` rather than ours.
+    pub(crate) stdin_null: bool,
+    pub(crate) stdout: Disposition,
+    pub(crate) stderr: Disposition,
+    pub(crate) merge_output: bool,
+}
+
+/// The spawned child, with our ends of its captured streams.
+pub(crate) struct Spawned {
+    pub(crate) process: UnixProcess,
+    pub(crate) stdout: Option<PipeReader>,
+    pub(crate) stderr: Option<PipeReader>,
+}
+
+/// Spawns the child with `posix_spawnp`, setting up its stdio with file actions.
+pub(crate) fn spawn(spawn: Spawn) -> io::Result<Spawned> {
+    let mut actions = PosixSpawnFileActions::init()?;
+    // NOTE: everything opened here is `CLOEXEC`, so it only ends up in the child where it's `dup2`ed, and the child's
+    // copies have to stay open until the spawn is done.
+    let mut theirs: Vec<OwnedFd> = Vec::new();
+    if spawn.stdin_null {
+        let null = File::open("/dev/null")?;
+        actions.add_dup2(null.as_raw_fd(), 0)?;
+        theirs.push(null.into());
+    }
+
+    let mut capture = |disposition: Disposition,
+                       fds: &[i32],
+                       theirs: &mut Vec<OwnedFd>|
+     -> io::Result<Option<PipeReader>> {
+        match disposition {
+            Disposition::Capture => {
+                let (reader, writer) = io::pipe()?;
+                for &fd in fds {
+                    actions.add_dup2(writer.as_raw_fd(), fd)?;
+                }
+                theirs.push(writer.into());
+                Ok(Some(reader))
+            }
+            Disposition::Inherit => Ok(None),
+            Disposition::Null => {
+                let null = OpenOptions::new().write(true).open("/dev/null")?;
+                for &fd in fds {
+                    actions.add_dup2(null.as_raw_fd(), fd)?;
+                }
+                theirs.push(null.into());
+                Ok(None)
+            }
+        }
+    };
+    let (stdout, stderr) = if spawn.merge_output {
+        (capture(spawn.stdout, &[1, 2], &mut theirs)?, None)
+    } else {
+        (
+            capture(spawn.stdout, &[1], &mut theirs)?,
+            capture(spawn.stderr, &[2], &mut theirs)?,
+        )
+    };
+
+    // NOTE: like std does, `SIGPIPE` goes back to its default (Rust ignores it in its programs), and nothing is
+    // blocked.
+    let mut attr = PosixSpawnAttr::init()?;
+    attr.set_flags(
+        PosixSpawnFlags::POSIX_SPAWN_SETSIGDEF | PosixSpawnFlags::POSIX_SPAWN_SETSIGMASK,
+    )?;
+    attr.set_sigdefault(&SigSet::from(Signal::SIGPIPE))?;
+    attr.set_sigmask(&SigSet::empty())?;
+
+    let program = CString::new(spawn.program.as_bytes())?;
+    let args = std::iter::once(spawn.program)
+        .chain(spawn.args.iter().map(OsString::as_os_str))
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let env = spawn
+        .env
+        .iter()
+        .map(|(key, val)| CString::new([key.as_bytes(), b"=", val.as_bytes()].concat()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let pid = posix_spawnp(&program, &actions, &attr, &args, &env)?;
+    drop(theirs);
+    Ok(Spawned {
+        process: UnixProcess::new(pid.as_raw() as u32),
+        stdout,
+        stderr,
+    })
+}
@@ -0,0 +1,294 @@
+//! Process setup done in the child between `fork` and `exec`, on Unix.
+//!
+//! This runs in the forked child, where only async-signal-safe calls are allowed: nothing here allocates, everything
+//! the steps need is prepared beforehand by the builder.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::sys::signal::Signal;
+
+use crate::inherit::Placement;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::namespace::{Namespace, Unshare};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::priority::IoPriority;
+#[cfg(all(feature = "seccomp", target_os = "linux"))]
+use crate::seccomp::{Filter, SyscallPolicy};
+
+/// A step of the caller's own, see [`Pipe2::pre_exec`](crate::Pipe2::pre_exec).
+pub(crate) type Hook = Arc<dyn Fn() -> io::Result<()> + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct PreExec {
+    /// `cgroup.procs` of the cgroup to join, opened by the parent.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) cgroup_procs: Option<std::os::fd::RawFd>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) parent_death_signal: Option<Signal>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) namespaces: Vec<Namespace>,
+    pub(crate) umask: Option<libc::mode_t>,
+    pub(crate) root: Option<PathBuf>,
+    /// [`PreExec::root`] and the working directory inside it, as filled in by [`PreExec::install`].
+    change_root: Option<(CString, CString)>,
+    pub(crate) new_session: bool,
+    /// Makes `stdin`, a pseudo-terminal, the controlling terminal of the new session.
+    pub(crate) controlling_terminal: bool,
+    pub(crate) nice: Option<i32>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) io_priority: Option<IoPriority>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) cpu_set: Option<libc::cpu_set_t>,
+    pub(crate) uid: Option<libc::uid_t>,
+    pub(crate) gid: Option<libc::gid_t>,
+    pub(crate) groups: Option<Vec<libc::gid_t>>,
+    pub(crate) hooks: Vec<Hook>,
+    #[cfg(all(feature = "seccomp", target_os = "linux"))]
+    pub(crate) syscall_policies: Vec<SyscallPolicy>,
+}
+
+impl PreExec {
+    /// Whether there's anything to do at all; std can take a faster path than `fork` when there isn't.
+    pub(crate) fn is_empty(&self) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.cgroup_procs.is_some()
+            || self.parent_death_signal.is_some()
+            || !self.namespaces.is_empty()
+            || self.io_priority.is_some()
+            || self.cpu_set.is_some()
+        {
+            return false;
+        }
+        #[cfg(all(feature = "seccomp", target_os = "linux"))]
+        if !self.syscall_policies.is_empty() {
+            return false;
+        }
+        self.umask.is_none()
+            && self.root.is_none()
+            && !self.new_session
+            && !self.controlling_terminal
+            && self.nice.is_none()
+            && self.uid.is_none()
+            && self.gid.is_none()
+            && self.groups.is_none()
+            && self.hooks.is_empty()
+    }
+
+    /// Installs the steps, in the order they have to happen in.
+    ///
+    /// `current_dir` only matters here with a [`PreExec::root`]: std changes directory before running any of this, so
+    /// with a new root, the change has to be made again once inside it. `fds` puts the inherited descriptors in place.
+    pub(crate) fn install(
+        &self,
+        command: &mut Command,
+        current_dir: Option<&Path>,
+        fds: Option<Placement>,
+    ) -> io::Result<()> {
+        if self.is_empty() && fds.is_none() {
+            return Ok(());
+        }
+
+        let mut steps = self.clone();
+        if let Some(root) = &self.root {
+            let dir = current_dir.unwrap_or(Path::new("/"));
+            steps.change_root = Some((
+                CString::new(root.as_os_str().as_bytes())?,
+                CString::new(dir.as_os_str().as_bytes())?,
+            ));
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let parent = unsafe { libc::getpid() };
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let unshare = Unshare::new(&self.namespaces);
+        #[cfg(all(feature = "seccomp", target_os = "linux"))]
+        let filter = (!self.syscall_policies.is_empty())
+            .then(|| {
+                Filter::new(
+                    &self.syscall_policies,
+                    command.get_program().as_bytes().as_ptr(),
+                )
+            })
+            .transpose()?;
+
+        let mut fds = fds;
+
+        unsafe {
+            command.pre_exec(move || {
+                // NOTE: first, so that everything else is already accounted for in the cgroup.
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                steps.join_cgroup()?;
+                // NOTE: after the cgroup, whose descriptor may sit on one of the targets, and ahead of the hooks, which
+                // get to see the descriptors where the child will.
+                if let Some(fds) = &mut fds {
+                    fds.place()?;
+                }
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                if let Some(unshare) = &unshare {
+                    // NOTE: the process a PID namespace leaves in between never gets to the end of this; it only
+                    // relays signals, so it's simply killed, and its child gets the signal as it goes.
+                    if unshare.forks() && steps.parent_death_signal.is_some() {
+                        arm_parent_death_signal(libc::SIGKILL, Some(parent))?;
+                    }
+                    unshare.enter()?;
+                }
+                steps.session()?;
+                steps.change_root()?;
+                steps.scheduling()?;
+                for hook in &steps.hooks {
+                    hook()?;
+                }
+                // NOTE: after the rest, since anything else may need the privileges this gives up.
+                steps.credentials()?;
+                // NOTE: after the credentials, since changing them clears it, but still before the filter.
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                steps.parent_death_signal(parent, unshare.as_ref().is_some_and(Unshare::forks))?;
+                // NOTE: the very last, so that only the `exec` is left for it to let through; std `exec`s the child
+                // with the very string `Filter::new` was pointed at.
+                #[cfg(all(feature = "seccomp", target_os = "linux"))]
+                if let Some(filter) = &filter {
+                    filter.install()?;
+                }
+
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// Writing `0` to `cgroup.procs` moves the writer itself.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn join_cgroup(&self) -> io::Result<()> {
+        if let Some(procs) = self.cgroup_procs
+            && unsafe { libc::write(procs, b"0".as_ptr().cast(), 1) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// `in_pid_namespace` is for the child of the process a PID namespace leaves in between, which that process has
+    /// armed with `SIGKILL` for itself, and which has to be armed again once its credentials change all the same. As
+    /// PID 1 of the namespace, it only gets a signal it has a handler for, or `SIGKILL`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn parent_death_signal(&self, parent: libc::pid_t, in_pid_namespace: bool) -> io::Result<()> {
+        let signal = match self.parent_death_signal {
+            Some(signal) => signal as libc::c_int,
+            None if in_pid_namespace => libc::SIGKILL,
+            None => return Ok(()),
+        };
+        // NOTE: the process in between is outside the PID namespace, where `getppid` can't see it; it waits for its
+        // child anyway.
+        arm_parent_death_signal(signal, (!in_pid_namespace).then_some(parent))
+    }
+
+    fn session(&self) -> io::Result<()> {
+        if self.new_session && unsafe { libc::setsid() } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // NOTE: std has put the pseudo-terminal in place as `stdin` by now.
+        if self.controlling_terminal && unsafe { libc::ioctl(0, libc::TIOCSCTTY, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if let Some(mask) = self.umask {
+            unsafe { libc::umask(mask) };
+        }
+        Ok(())
+    }
+
+    /// Needs privileges, so it has to come before [`PreExec::credentials`] gives them up.
+    fn change_root(&self) -> io::Result<()> {
+        let Some((root, dir)) = &self.change_root else {
+            return Ok(());
+        };
+        if unsafe { libc::chroot(root.as_ptr()) } != 0 || unsafe { libc::chdir(dir.as_ptr()) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn scheduling(&self) -> io::Result<()> {
+        if let Some(nice) = self.nice
+            && unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            if let Some(priority) = self.io_priority
+                && unsafe {
+                    libc::syscall(
+                        libc::SYS_ioprio_set,
+                        IOPRIO_WHO_PROCESS,
+                        0,
+                        priority.to_raw(),
+                    )
+                } != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            if let Some(cpu_set) = &self.cpu_set
+                && unsafe {
+                    libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), cpu_set)
+                } != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Supplementary groups first and the user last: once the uid is gone, so is the right to change the others.
+    fn credentials(&self) -> io::Result<()> {
+        if let Some(groups) = &self.groups {
+            if unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        } else if (self.uid.is_some() || self.gid.is_some()) && unsafe { libc::getuid() } == 0 {
+            // NOTE: otherwise root's supplementary groups would stick around after dropping to another user.
+            if unsafe { libc::setgroups(0, std::ptr::null()) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        if let Some(gid) = self.gid
+            && unsafe { libc::setgid(gid) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        if let Some(uid) = self.uid
+            && unsafe { libc::setuid(uid) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// Has the kernel send `signal` once our parent dies, and sends it right away if `parent` already has.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn arm_parent_death_signal(signal: libc::c_int, parent: Option<libc::pid_t>) -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // NOTE: if it died between `fork` and the `prctl` above, nobody is left to send the signal.
+    if let Some(parent) = parent
+        && unsafe { libc::getppid() } != parent
+    {
+        unsafe { libc::raise(signal) };
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Pretty-printing JSON log lines for the echo.
+//!
+//! The line is reformatted as written rather than parsed into a `serde_json::Value` and printed back, so its keys
+//! stay in their order and its numbers exactly as they were.
+
+use serde::de::IgnoredAny;
+
+const KEY: &str = "\x1b[36m";
+const STRING: &str = "\x1b[32m";
+const LITERAL: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// `line` indented over several lines, with colors if `color` is set, if it's a JSON object.
+pub(crate) fn pretty(line: &str, color: bool) -> Option<String> {
+    let line = line.trim();
+    if !line.starts_with('{') || serde_json::from_str::<IgnoredAny>(line).is_err() {
+        return None;
+    }
+
+    let paint = |out: &mut String, code: &str, text: &str| {
+        if color {
+            out.push_str(code);
+            out.push_str(text);
+            out.push_str(RESET);
+        } else {
+            out.push_str(text);
+        }
+    };
+    let newline = |out: &mut String, depth: usize| {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    };
+
+    let mut out = String::new();
+    let mut depth = 0;
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '{' | '[' => {
+                let close = if c == '{' { '}' } else { ']' };
+                let inner = rest[1..].trim_start();
+                if inner.starts_with(close) {
+                    out.push(c);
+                    out.push(close);
+                    rest = &inner[1..];
+                    continue;
+                }
+                out.push(c);
+                depth += 1;
+                newline(&mut out, depth);
+            }
+            '}' | ']' => {
+                depth -= 1;
+                newline(&mut out, depth);
+                out.push(c);
+            }
+            ',' => {
+                out.push(',');
+                newline(&mut out, depth);
+            }
+            ':' => out.push_str(": "),
+            '"' => {
+                let end = string_end(rest);
+                let is_key = rest[end..].trim_start().starts_with(':');
+                paint(&mut out, if is_key { KEY } else { STRING }, &rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                let end = rest
+                    .find(|c: char| matches!(c, ',' | '}' | ']') || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                paint(&mut out, LITERAL, &rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    Some(out)
+}
+
+/// Where the string `text` starts with ends, closing quote included.
+fn string_end(text: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return i + 1,
+            _ => escaped = false,
+        }
+    }
+    text.len()
+}
@@ -0,0 +1,174 @@
+//! Finding out from outside that a service is up, for children that don't say so themselves.
+//!
+//! The probing is done on a thread of its own, so that a slow connection attempt never holds up
+//! [`Child::poll`](crate::Child::poll); the child just checks whether it has succeeded yet.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait between attempts.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long one connection attempt may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What counts as the child being ready.
+#[derive(Clone, Debug)]
+pub(crate) enum Probe {
+    /// Something accepts TCP connections at this `host:port`.
+    Port(String),
+    /// A `GET` of this `http://` URL is answered with this status.
+    Http(String, u16),
+}
+
+impl Probe {
+    /// What the `ready` event says it came from.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Port(_) => "port",
+            Self::Http(..) => "http",
+        }
+    }
+
+    /// Makes sure the probe can work at all, before there's a child waiting on it.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        match self {
+            Self::Port(_) => Ok(()),
+            Self::Http(url, _) => HttpUrl::parse(url).map(drop),
+        }
+    }
+
+    fn succeeds(&self) -> bool {
+        match self {
+            Self::Port(address) => address.to_socket_addrs().is_ok_and(|mut addrs| {
+                addrs.any(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).is_ok())
+            }),
+            Self::Http(url, expected) => HttpUrl::parse(url)
+                .and_then(|url| url.status())
+                .is_ok_and(|status| status == *expected),
+        }
+    }
+}
+
+/// The parts of an `http://` URL a request needs.
+pub(crate) struct HttpUrl {
+    /// As it goes in the `Host` header.
+    pub(crate) host: String,
+    /// `host:port`, with the default port filled in.
+    pub(crate) address: String,
+    pub(crate) path: String,
+}
+
+impl HttpUrl {
+    pub(crate) fn parse(url: &str) -> io::Result<Self> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid URL {url:?}"));
+        if url.starts_with("https://") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only http:// URLs are supported",
+            ));
+        }
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err(invalid());
+        }
+        let has_port = host
+            .rsplit_once(':')
+            .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+        let address = if has_port {
+            host.to_owned()
+        } else {
+            format!("{host}:80")
+        };
+        Ok(Self {
+            host: host.to_owned(),
+            address,
+            path: path.to_owned(),
+        })
+    }
+
+    /// Sends a `GET`, and returns the status it was answered with.
+    fn status(&self) -> io::Result<u16> {
+        let mut addrs = self.address.to_socket_addrs()?;
+        let mut stream = loop {
+            let Some(addr) = addrs.next() else {
+                return Err(io::ErrorKind::ConnectionRefused.into());
+            };
+            if let Ok(stream) = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                break stream;
+            }
+        };
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: pipe2\r\nConnection: close\r\n\r\n",
+            self.path, self.host
+        )?;
+
+        // NOTE: only the status line matters, like `HTTP/1.1 200 OK`.
+        let mut head = Vec::new();
+        let mut scratchpad = [0u8; 256];
+        while !head.contains(&b'\n') && head.len() < 1024 {
+            match stream.read(&mut scratchpad)? {
+                0 => break,
+                n => head.extend_from_slice(&scratchpad[..n]),
+            }
+        }
+        let head = String::from_utf8_lossy(&head);
+        head.split_whitespace()
+            .nth(1)
+            .filter(|_| head.starts_with("HTTP/"))
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not an HTTP response"))
+    }
+}
+
+/// A probe being tried over and over, until it succeeds or the child is gone.
+pub(crate) struct Prober {
+    probe: Probe,
+    ready: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Prober {
+    pub(crate) fn start(probe: Probe) -> Self {
+        let ready = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_probe, thread_ready, thread_stop) =
+            (probe.clone(), ready.clone(), stop.clone());
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                if thread_probe.succeeds() {
+                    thread_ready.store(true, Ordering::Relaxed);
+                    return;
+                }
+                thread::sleep(INTERVAL);
+            }
+        });
+        Self { probe, ready, stop }
+    }
+
+    pub(crate) fn probe(&self) -> &Probe {
+        &self.probe
+    }
+
+    pub(crate) fn ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Prober {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,71 @@
+//! `--problem-matcher`: picking compiler-style diagnostics out of the program's output, for the report and for CI
+//! annotations.
+//!
+//! A matcher is a [`Pattern`] with `{file}`, `{line}`, `{column}`, `{severity}` and `{message}` standing for the
+//! parts of a diagnostic, like `{file}:{line}:{column}: {severity}: {message}` for GCC.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pattern::Pattern;
+
+/// One diagnostic found in the output.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Problem {
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    pub column: Option<u64>,
+    pub severity: Option<String>,
+    pub message: String,
+}
+
+/// `file:line:column: severity: message`, leaving out whatever wasn't matched.
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = [
+            self.file.clone(),
+            self.line.map(|line| line.to_string()),
+            self.column.map(|column| column.to_string()),
+        ];
+        let location = location.into_iter().flatten().collect::<Vec<_>>().join(":");
+        let parts = [
+            Some(location),
+            self.severity.clone(),
+            Some(self.message.clone()),
+        ];
+        let parts = parts.into_iter().flatten().filter(|part| !part.is_empty());
+        write!(f, "{}", parts.collect::<Vec<_>>().join(": "))
+    }
+}
+
+pub struct Matcher(Pattern);
+
+impl Matcher {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        const FIELDS: &[&str] = &["file", "line", "column", "severity", "message"];
+        Pattern::parse(pattern, FIELDS, "problem matcher").map(Self)
+    }
+
+    pub fn matches(&self, line: &str) -> Option<Problem> {
+        let mut problem = Problem::default();
+        for (field, value) in self.0.captures(line)? {
+            match field {
+                "file" => problem.file = Some(value.to_owned()),
+                "line" => problem.line = value.parse().ok(),
+                "column" => problem.column = value.parse().ok(),
+                "severity" => problem.severity = Some(value.to_owned()),
+                _ => problem.message = value.to_owned(),
+            }
+        }
+        Some(problem)
+    }
+}
+
+/// Every line of `output` that one of `matchers` recognizes, in order.
+pub fn extract(output: &[u8], matchers: &[Matcher]) -> Vec<Problem> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .filter_map(|line| matchers.iter().find_map(|matcher| matcher.matches(line)))
+        .collect()
+}
@@ -0,0 +1,234 @@
+//! The child process itself, however it came to be.
+
+use std::io;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(windows)]
+use std::os::windows::io::{AsRawHandle, RawHandle};
+
+#[cfg(feature = "wasi")]
+use crate::wasi::WasiProcess;
+#[cfg(windows)]
+use crate::windows_process_utils::RawProcess;
+
+/// What the child used of the machine, as the kernel counted it once the child was reaped: its own usage, and that of
+/// the descendants it waited for itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// The most memory it had resident at once, in KiB.
+    pub max_rss: u64,
+}
+
+pub(crate) enum Process {
+    /// Spawned through [`std::process::Command`], which is the usual case.
+    #[cfg(windows)]
+    Std(std::process::Child),
+    /// Created by us directly, for what std's `Command` can't express (other credentials, for one).
+    #[cfg(windows)]
+    Raw(RawProcess),
+    /// Spawned through [`std::process::Command`] or `posix_spawn` (see [`Pipe2::posix_spawn`](crate::Pipe2::posix_spawn)),
+    /// and reaped by us.
+    #[cfg(unix)]
+    Unix(UnixProcess),
+    /// A WASI module running inside pipe2, see [`Pipe2::wasi`](crate::Pipe2::wasi).
+    #[cfg(feature = "wasi")]
+    Wasi(WasiProcess),
+}
+
+impl Process {
+    pub(crate) fn id(&self) -> u32 {
+        match self {
+            #[cfg(windows)]
+            Process::Std(child) => child.id(),
+            #[cfg(windows)]
+            Process::Raw(process) => process.id(),
+            #[cfg(unix)]
+            Process::Unix(process) => process.id(),
+            // NOTE: it has no ID of its own, and isn't to be taken for ours.
+            #[cfg(feature = "wasi")]
+            Process::Wasi(_) => 0,
+        }
+    }
+
+    /// Whether it's a process of its own, that can be signalled, suspended and looked up; a WASI module isn't.
+    pub(crate) fn is_os_process(&self) -> bool {
+        #[cfg(feature = "wasi")]
+        if let Process::Wasi(_) = self {
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            #[cfg(windows)]
+            Process::Std(child) => child.try_wait(),
+            #[cfg(windows)]
+            Process::Raw(process) => process.try_wait(),
+            #[cfg(unix)]
+            Process::Unix(process) => process.try_wait(),
+            #[cfg(feature = "wasi")]
+            Process::Wasi(process) => process.try_wait(),
+        }
+    }
+
+    /// The CPU time the process has used so far, user and system together; `None` where that can't be told (Unix
+    /// other than Linux), or once it's gone.
+    pub(crate) fn cpu_time(&self) -> Option<Duration> {
+        if !self.is_os_process() {
+            return None;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            // NOTE: `utime` and `stime` are the 14th and 15th fields; the 2nd, the command name, is in parentheses
+            // and can hold spaces itself, so counting starts after it.
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", self.id())).ok()?;
+            let mut fields = stat.get(stat.rfind(')')? + 2..)?.split(' ').skip(11);
+            let utime: u64 = fields.next()?.parse().ok()?;
+            let stime: u64 = fields.next()?.parse().ok()?;
+            let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+            let ticks = u64::try_from(ticks).ok().filter(|&ticks| ticks > 0)?;
+            Some(Duration::from_secs_f64(
+                (utime + stime) as f64 / ticks as f64,
+            ))
+        }
+        #[cfg(windows)]
+        {
+            crate::windows_process_utils::cpu_time(self).ok()
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+        {
+            None
+        }
+    }
+
+    /// What it used, once it's been reaped; Unix only.
+    pub(crate) fn resource_usage(&self) -> Option<ResourceUsage> {
+        match self {
+            #[cfg(unix)]
+            Process::Unix(process) => process.usage,
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// Forcefully kills the process (`SIGKILL`/`TerminateProcess`), or interrupts the WASI module.
+    pub(crate) fn kill(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(windows)]
+            Process::Std(child) => child.kill(),
+            #[cfg(windows)]
+            Process::Raw(process) => process.kill(),
+            #[cfg(unix)]
+            Process::Unix(process) => process.kill(),
+            #[cfg(feature = "wasi")]
+            Process::Wasi(process) => process.kill(),
+        }
+    }
+}
+
+/// A child on Unix, however it was spawned, reaped with `wait4` for what it used along with its exit status.
+///
+/// NOTE: one spawned through std's `Command` is taken over from its `Child`, which must not be waited on or killed
+/// after that, as it'd go by a PID that may have been reaped and handed out again.
+#[cfg(unix)]
+pub(crate) struct UnixProcess {
+    pid: libc::pid_t,
+    /// Kept once the child has been reaped, since it can only be reaped once.
+    status: Option<ExitStatus>,
+    usage: Option<ResourceUsage>,
+}
+
+#[cfg(unix)]
+impl UnixProcess {
+    pub(crate) fn new(pid: u32) -> Self {
+        Self {
+            pid: pid as libc::pid_t,
+            status: None,
+            usage: None,
+        }
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        self.pid as u32
+    }
+
+    pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.status.is_some() {
+            return Ok(self.status);
+        }
+        let mut status = 0;
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        match unsafe { libc::wait4(self.pid, &mut status, libc::WNOHANG, &mut usage) } {
+            0 => Ok(None),
+            -1 => Err(io::Error::last_os_error()),
+            _ => {
+                let time = |time: libc::timeval| {
+                    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+                };
+                // NOTE: Linux reports `ru_maxrss` in KiB, macOS in bytes.
+                #[cfg(target_os = "macos")]
+                let max_rss = usage.ru_maxrss as u64 / 1024;
+                #[cfg(not(target_os = "macos"))]
+                let max_rss = usage.ru_maxrss as u64;
+                self.usage = Some(ResourceUsage {
+                    user_time: time(usage.ru_utime),
+                    system_time: time(usage.ru_stime),
+                    max_rss,
+                });
+                self.status = Some(ExitStatus::from_raw(status));
+                Ok(self.status)
+            }
+        }
+    }
+
+    pub(crate) fn kill(&mut self) -> io::Result<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+        if unsafe { libc::kill(self.pid, libc::SIGKILL) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// `pid` and every process under it, as far as `/proc/<pid>/task/<tid>/children` goes.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn descendants(pid: u32) -> Vec<u32> {
+    let mut pids = vec![pid];
+    let mut i = 0;
+    while i < pids.len() {
+        if let Ok(tasks) = std::fs::read_dir(format!("/proc/{}/task", pids[i])) {
+            for task in tasks.flatten() {
+                let Ok(children) = std::fs::read_to_string(task.path().join("children")) else {
+                    continue;
+                };
+                pids.extend(
+                    children
+                        .split_whitespace()
+                        .filter_map(|pid| pid.parse::<u32>().ok()),
+                );
+            }
+        }
+        i += 1;
+    }
+    pids
+}
+
+#[cfg(windows)]
+impl AsRawHandle for Process {
+    fn as_raw_handle(&self) -> RawHandle {
+        match self {
+            Process::Std(child) => child.as_raw_handle(),
+            Process::Raw(process) => process.as_raw_handle(),
+            #[cfg(feature = "wasi")]
+            Process::Wasi(_) => std::ptr::null_mut(),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Running the child on a pseudo-terminal rather than pipes, for programs that only act like themselves on a terminal:
+//! full-screen ones like `vim` and `htop`, and the many that buffer or drop their colors when writing to a pipe.
+//!
+//! The child gets the terminal's end as its `stdin`, `stdout` and `stderr`, and as its controlling terminal in a
+//! session of its own. What it writes comes out of our end as one stream, read like `stdout` is.
+//!
+//! With passthrough on, what comes in on our own `stdin` goes the other way, keystroke by keystroke, with our terminal
+//! in raw mode meanwhile: the child's terminal is the one doing the line editing and turning `^C` into `SIGINT` then.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use crate::stream::ChildStream;
+
+/// Used when we're not on a terminal ourselves, the size terminals traditionally have.
+const DEFAULT_SIZE: (u16, u16) = (24, 80);
+
+/// Sent to the child once our `stdin` is at EOF, which its terminal takes as an EOF in turn.
+const EOF: u8 = 0x04;
+
+/// Our end of the pseudo-terminal.
+pub(crate) struct Pty {
+    master: File,
+    /// The rows and columns it was opened with.
+    size: (u16, u16),
+    passthrough: Option<Passthrough>,
+}
+
+impl Pty {
+    /// Opens a pseudo-terminal as big as ours, and returns our end along with the child's.
+    pub(crate) fn open() -> io::Result<(Self, OwnedFd)> {
+        let (rows, cols) = terminal_size().unwrap_or(DEFAULT_SIZE);
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let (mut master, mut slave) = (0, 0);
+        if unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        // NOTE: the child would inherit our end otherwise, and the terminal would never hang up.
+        nix::fcntl::fcntl(
+            &master,
+            nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+        )?;
+        let pty = Self {
+            master,
+            size: (rows, cols),
+            passthrough: None,
+        };
+        Ok((pty, slave))
+    }
+
+    /// Starts passing what comes in on our `stdin` on to the child, putting our terminal in raw mode if it's one.
+    pub(crate) fn start_passthrough(&mut self) -> io::Result<()> {
+        self.passthrough = Some(Passthrough {
+            _raw: RawMode::enter()?,
+            pending: Vec::new(),
+            eof: false,
+        });
+        Ok(())
+    }
+
+    /// Puts our terminal back the way it was, once the child is gone.
+    pub(crate) fn stop_passthrough(&mut self) {
+        self.passthrough = None;
+    }
+
+    /// Reads whatever came in on our `stdin`, if passing it on, and writes as much of it to the child as its terminal
+    /// takes without blocking. Returns what was read.
+    pub(crate) fn pump<'a>(&mut self, scratchpad: &'a mut [u8]) -> io::Result<&'a [u8]> {
+        let Some(passthrough) = &mut self.passthrough else {
+            return Ok(&[]);
+        };
+        let mut n = 0;
+        if !passthrough.eof && stdin_readable()? {
+            // NOTE: around std's buffering, which could keep what `poll` should see next.
+            let read = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    scratchpad.as_mut_ptr().cast(),
+                    scratchpad.len(),
+                )
+            };
+            n = usize::try_from(read).map_err(|_| io::Error::last_os_error())?;
+            if n == 0 {
+                passthrough.eof = true;
+                passthrough.pending.push(EOF);
+            }
+            passthrough.pending.extend_from_slice(&scratchpad[..n]);
+        }
+        while !passthrough.pending.is_empty() {
+            // NOTE: our end is non-blocking, since it shares that with the reader.
+            match (&self.master).write(&passthrough.pending) {
+                Ok(written) => {
+                    passthrough.pending.drain(..written);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // NOTE: the child is gone along with its terminal; the output tells the rest.
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => {
+                    passthrough.pending.clear();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(&scratchpad[..n])
+    }
+
+    pub(crate) fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Whether the child's terminal echoes what's typed, which programs turn off while asking for a password.
+    pub(crate) fn echoing(&self) -> bool {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        // NOTE: on our end, this gets at the child's settings.
+        if unsafe { libc::tcgetattr(self.master.as_raw_fd(), &mut termios) } != 0 {
+            return true;
+        }
+        termios.c_lflag & libc::ECHO != 0
+    }
+
+    /// Another handle to our end, to read the child's output from.
+    pub(crate) fn reader(&self) -> io::Result<PtyReader> {
+        let reader = crate::stream::nonblocking(self.master.try_clone()?)?;
+        Ok(PtyReader(reader))
+    }
+
+    /// Sets the terminal's size, which has the kernel send the child `SIGWINCH`.
+    pub(crate) fn resize(&self, rows: u16, cols: u16) -> io::Result<()> {
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// What's on its way from our `stdin` to the child.
+struct Passthrough {
+    _raw: Option<RawMode>,
+    /// What came in that the child's terminal didn't take yet.
+    pending: Vec<u8>,
+    eof: bool,
+}
+
+/// Our terminal's settings from before it was put in raw mode, put back once dropped.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    /// `None` if our `stdin` isn't a terminal, when there's nothing to change.
+    fn enter() -> io::Result<Option<Self>> {
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+            return Ok(None);
+        }
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(Self(saved)))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.0) };
+    }
+}
+
+/// Whether reading our `stdin` would return right away, without making it non-blocking for everyone sharing it.
+fn stdin_readable() -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut fd, 1, 0) } {
+        n if n < 0 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(fd.revents & (libc::POLLIN | libc::POLLHUP) != 0),
+    }
+}
+
+/// Reads what the child writes to the pseudo-terminal.
+pub(crate) struct PtyReader(File);
+
+impl ChildStream for PtyReader {
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        match self.0.read(buf) {
+            Ok(n) => Ok(Some(n)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            // NOTE: that's how Linux says the child's end is closed, rather than with an EOF.
+            Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(Some(0)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The rows and columns of the terminal we're on, looking at `stdout`, `stdin` and `stderr` in turn; `None` if none
+/// of them is a terminal.
+pub fn terminal_size() -> Option<(u16, u16)> {
+    [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+            let ok = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0;
+            (ok && size.ws_row > 0 && size.ws_col > 0).then_some((size.ws_row, size.ws_col))
+        })
+}
@@ -0,0 +1,164 @@
+//! A Python module over [`Pipe2::run`], for test frameworks that would rather capture without the deadlocks
+//! `subprocess.PIPE` is prone to: `pipe2.run(cmd, timeout=..., on_line=...)` returns a [`PyOutput`].
+//!
+//! The GIL is released while the child runs, and only taken back to call `on_line`.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+use crate::command::Pipe2;
+use crate::on_line::LineAction;
+use crate::stream::Stream;
+
+/// How a run went, like `subprocess.CompletedProcess`, with what pipe2 knows on top.
+#[pyclass(name = "Output", module = "pipe2", frozen, get_all)]
+pub struct PyOutput {
+    args: Vec<String>,
+    /// The exit code, or minus the signal that killed the child, as `subprocess` has it.
+    returncode: i32,
+    stdout: Py<PyBytes>,
+    stderr: Py<PyBytes>,
+    timed_out: bool,
+    cancelled: bool,
+    success: bool,
+    /// The [`ExitReason`](crate::ExitReason), like `"timed_out"`.
+    reason: &'static str,
+    run_id: String,
+}
+
+#[pymethods]
+impl PyOutput {
+    /// Raises `subprocess.CalledProcessError` unless the run counts as a success.
+    fn check_returncode(&self, py: Python<'_>) -> PyResult<()> {
+        if self.success {
+            return Ok(());
+        }
+        let error = py
+            .import("subprocess")?
+            .getattr("CalledProcessError")?
+            .call1((
+                self.returncode,
+                self.args.clone(),
+                &self.stdout,
+                &self.stderr,
+            ))?;
+        Err(PyErr::from_value(error))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Output(args={:?}, returncode={}, reason={:?})",
+            self.args, self.returncode, self.reason
+        )
+    }
+}
+
+/// Runs `cmd`, a program and its arguments, and returns how it went.
+///
+/// `timeout` is in seconds; a child still running after it is stopped, and the run comes back with `timed_out` set
+/// rather than raising. `on_line(stream, line)` gets every line of output, `stream` being `"stdout"` or `"stderr"` and
+/// `line` the text without its line ending: returning `None` or `True` keeps it, `False` drops it, and a `str` or
+/// `bytes` takes its place. If it raises, the child is stopped and the exception comes out of `run`.
+#[pyfunction]
+#[pyo3(signature = (cmd, *, timeout = None, on_line = None, echo = false, cwd = None))]
+fn run(
+    py: Python<'_>,
+    cmd: Vec<String>,
+    timeout: Option<f64>,
+    on_line: Option<Py<PyAny>>,
+    echo: bool,
+    cwd: Option<String>,
+) -> PyResult<PyOutput> {
+    let Some((program, args)) = cmd.split_first() else {
+        return Err(PyValueError::new_err("cmd has no program"));
+    };
+    let mut pipe2 = Pipe2::new(program);
+    pipe2.args(args).echo(echo);
+    if let Some(timeout) = timeout {
+        let timeout = Duration::try_from_secs_f64(timeout)
+            .map_err(|e| PyValueError::new_err(format!("invalid timeout: {e}")))?;
+        pipe2.timeout(timeout);
+    }
+    if let Some(cwd) = cwd {
+        pipe2.current_dir(cwd);
+    }
+    // NOTE: the first exception `on_line` raised; the lines after it are kept as they are.
+    let raised = Arc::new(Mutex::new(None::<PyErr>));
+    if let Some(on_line) = on_line {
+        let raised = raised.clone();
+        pipe2.on_line(move |stream, line| {
+            Python::attach(|py| {
+                if raised.lock().unwrap().is_some() {
+                    return LineAction::Keep;
+                }
+                match call(py, &on_line, stream, line) {
+                    Ok(action) => action,
+                    Err(e) => {
+                        *raised.lock().unwrap() = Some(e);
+                        LineAction::Abort
+                    }
+                }
+            })
+        });
+    }
+
+    let output = py.detach(|| pipe2.run())?;
+    if let Some(e) = raised.lock().unwrap().take() {
+        return Err(e);
+    }
+    #[cfg(unix)]
+    let signal = std::os::unix::process::ExitStatusExt::signal(&output.status);
+    #[cfg(not(unix))]
+    let signal: Option<i32> = None;
+    Ok(PyOutput {
+        args: cmd,
+        returncode: output.status.code().or(signal.map(|s| -s)).unwrap_or(-1),
+        stdout: PyBytes::new(py, &output.stdout).unbind(),
+        stderr: PyBytes::new(py, &output.stderr).unbind(),
+        timed_out: output.timed_out,
+        cancelled: output.cancelled,
+        success: output.success,
+        reason: output.reason.as_str(),
+        run_id: output.run_id.to_string(),
+    })
+}
+
+/// Calls `on_line` with a line, and makes a [`LineAction`] of what it returned.
+fn call(py: Python<'_>, on_line: &Py<PyAny>, stream: Stream, line: &[u8]) -> PyResult<LineAction> {
+    let stream = match stream {
+        Stream::Stdout => "stdout",
+        Stream::Stderr => "stderr",
+    };
+    let returned = on_line.call1(py, (stream, String::from_utf8_lossy(line)))?;
+    let returned = returned.bind(py);
+    if returned.is_none() {
+        return Ok(LineAction::Keep);
+    }
+    if let Ok(keep) = returned.extract::<bool>() {
+        return Ok(if keep {
+            LineAction::Keep
+        } else {
+            LineAction::Drop
+        });
+    }
+    if let Ok(text) = returned.cast::<PyString>() {
+        return Ok(LineAction::Emit(text.to_str()?.as_bytes().to_vec()));
+    }
+    if let Ok(bytes) = returned.cast::<PyBytes>() {
+        return Ok(LineAction::Emit(bytes.as_bytes().to_vec()));
+    }
+    Err(PyTypeError::new_err(
+        "on_line has to return None, a bool, a str or bytes",
+    ))
+}
+
+#[pymodule]
+fn pipe2(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(run, module)?)?;
+    module.add_class::<PyOutput>()?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! How big the reads from the child's pipes were, to see how the child writes and whether the buffer they're read into
+//! is big enough, and growing that buffer for children that keep filling it.
+
+use std::fmt;
+
+/// The buffer reads start out with.
+pub(crate) const INITIAL_READ_BUFFER: usize = 1024;
+
+/// The most [`Pipe2::max_read_buffer`](crate::Pipe2::max_read_buffer) lets the buffer grow to, unless told otherwise.
+pub(crate) const DEFAULT_MAX_READ_BUFFER: usize = 1024 * 1024;
+
+/// How many reads in a row have to fill the buffer before it's doubled.
+const FULL_READS_TO_GROW: u32 = 8;
+
+/// Reads of up to 1 byte, 2 bytes, 4 bytes and so on, up to 1 GiB and above.
+const BUCKETS: usize = 31;
+
+/// A histogram of the sizes of the reads from the child's `stdout` and `stderr`, in powers of two, see
+/// [`Output::read_sizes`](crate::Output::read_sizes).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadSizes {
+    counts: [u64; BUCKETS],
+}
+
+impl Default for ReadSizes {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+        }
+    }
+}
+
+impl ReadSizes {
+    pub(crate) fn record(&mut self, n: usize) {
+        let bucket = n.next_power_of_two().trailing_zeros() as usize;
+        self.counts[bucket.min(BUCKETS - 1)] += 1;
+    }
+
+    /// Each size class that saw any reads, smallest first, as the most bytes a read in it had along with how many reads
+    /// it saw; the last class, 1 GiB, takes in anything bigger too.
+    pub fn buckets(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (1 << bucket, *count))
+    }
+
+    /// How many reads there were in all.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// `≤1K: 3, ≤64K: 1200`, and so on.
+impl fmt::Display for ReadSizes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (size, count)) in self.buckets().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            let size = match size {
+                size if size >= 1 << 30 => format!("{}G", size >> 30),
+                size if size >= 1 << 20 => format!("{}M", size >> 20),
+                size if size >= 1 << 10 => format!("{}K", size >> 10),
+                size => size.to_string(),
+            };
+            write!(f, "≤{size}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decides when the read buffer has to grow: once reads keep filling it, it's doubled, up to a cap.
+pub(crate) struct Tuner {
+    max: usize,
+    full_reads: u32,
+}
+
+impl Tuner {
+    pub(crate) fn new(max: usize) -> Self {
+        Self { max, full_reads: 0 }
+    }
+
+    /// Takes in a read of `n` bytes into `buffer`, and returns the size it's to grow to, if it has to.
+    pub(crate) fn read(&mut self, n: usize, buffer: usize) -> Option<usize> {
+        if n < buffer {
+            self.full_reads = 0;
+            return None;
+        }
+        self.full_reads += 1;
+        if self.full_reads < FULL_READS_TO_GROW || buffer >= self.max {
+            return None;
+        }
+        self.full_reads = 0;
+        Some((buffer * 2).min(self.max))
+    }
+}
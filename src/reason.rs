@@ -0,0 +1,66 @@
+//! Why a run ended, as one value orchestration layers can match on instead of reading messages.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Why a run ended. Serialized (in the [`Pipe2::event_log`](crate::Pipe2::event_log), for one) in `snake_case`, like
+/// `timed_out`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    /// The child exited by itself, with whatever exit code, or was left running after closing its output (see
+    /// [`OutputClosed::Detach`](crate::OutputClosed::Detach)).
+    Exited,
+    /// The child was killed by a signal that pipe2 didn't send for one of the other reasons.
+    Signaled,
+    /// Killed for running past [`Pipe2::timeout`](crate::Pipe2::timeout), or for running too long after closing its
+    /// output (see [`OutputClosed::Timeout`](crate::OutputClosed::Timeout)).
+    TimedOut,
+    /// Killed for writing nothing within [`Pipe2::first_output_within`](crate::Pipe2::first_output_within).
+    IdleTimeout,
+    /// Stopped for writing more than it was allowed to. Nothing in pipe2 limits the output as a whole yet, so this
+    /// isn't produced; it's here so that the set of reasons doesn't change when something does.
+    OutputLimit,
+    /// Stopped through a [`CancellationHandle`](crate::CancellationHandle).
+    Cancelled,
+    /// Stopped by a [`LineAction::Abort`](crate::LineAction::Abort) from a
+    /// [`Pipe2::on_line`](crate::Pipe2::on_line) closure.
+    Aborted,
+    /// The child couldn't be started at all; there's no [`Output`](crate::Output) then, only the error from the
+    /// spawn.
+    SpawnError,
+    /// Killed for going over a resource limit, like [`Pipe2::cpu_limit`](crate::Pipe2::cpu_limit).
+    ResourceLimit,
+    /// Killed for making a system call its `Pipe2::syscall_policy` forbids (Linux, with the `seccomp` feature).
+    PolicyViolation,
+    /// Stopped because what its `stdin` was fed from failed partway, like a
+    /// [`StdinPart::Command`](crate::StdinPart::Command) that exited with an error, so it doesn't go on with half of
+    /// its input; see [`Output::stdin_error`](crate::Output::stdin_error).
+    InputFailed,
+}
+
+impl ExitReason {
+    /// The `snake_case` name, as it's serialized.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Exited => "exited",
+            Self::Signaled => "signaled",
+            Self::TimedOut => "timed_out",
+            Self::IdleTimeout => "idle_timeout",
+            Self::OutputLimit => "output_limit",
+            Self::Cancelled => "cancelled",
+            Self::Aborted => "aborted",
+            Self::SpawnError => "spawn_error",
+            Self::ResourceLimit => "resource_limit",
+            Self::PolicyViolation => "policy_violation",
+            Self::InputFailed => "input_failed",
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
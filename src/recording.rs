@@ -0,0 +1,107 @@
+//! A recording of the session as it went, in asciinema's [asciicast v2] format: what the child wrote, and with
+//! [`Pipe2::pty_passthrough`](crate::Pipe2::pty_passthrough), what was typed for it too, each with the time it came.
+//! `asciinema play` replays it, and it's plain enough (a JSON header, then one JSON array per line) to audit with
+//! anything else.
+//!
+//! [asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+
+use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::events::EventSink;
+
+/// Shown in place of each character typed while the child's terminal doesn't echo, see
+/// [`Pipe2::redact_passwords`](crate::Pipe2::redact_passwords).
+const REDACTED: char = '*';
+
+pub(crate) struct Recording {
+    sink: EventSink,
+    started: Instant,
+    /// The start of a UTF-8 character the last chunk of output, or input, ended in the middle of.
+    held: [Vec<u8>; 2],
+}
+
+impl Recording {
+    /// Starts the recording with its header, for a terminal of `size` (rows and columns), or the traditional 24 rows of
+    /// 80 columns if there's no terminal to go by.
+    ///
+    /// NOTE: like the event log, failing to write the recording doesn't fail the run.
+    pub(crate) fn start(sink: EventSink, size: Option<(u16, u16)>) -> Self {
+        let (rows, cols) = size.unwrap_or((24, 80));
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let recording = Self {
+            sink,
+            started: Instant::now(),
+            held: Default::default(),
+        };
+        recording.write(json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp,
+        }));
+        recording
+    }
+
+    /// Records something the child wrote.
+    pub(crate) fn output(&mut self, data: &[u8]) {
+        if let Some(text) = self.text(0, data) {
+            self.event("o", &text);
+        }
+    }
+
+    /// Records something typed for the child; with `redact`, only how many characters it was.
+    pub(crate) fn input(&mut self, data: &[u8], redact: bool) {
+        let Some(mut text) = self.text(1, data) else {
+            return;
+        };
+        if redact {
+            // NOTE: the Enter that ends a password isn't part of it, and the recording reads better with it.
+            text = text
+                .chars()
+                .map(|c| if c == '\r' || c == '\n' { c } else { REDACTED })
+                .collect();
+        }
+        self.event("i", &text);
+    }
+
+    /// Records the terminal being resized.
+    pub(crate) fn resize(&mut self, rows: u16, cols: u16) {
+        self.event("r", &format!("{cols}x{rows}"));
+    }
+
+    /// `data` as text, along with whatever was held back of direction `index` before, holding back an incomplete
+    /// character at the end in turn. What isn't UTF-8 becomes `U+FFFD`, since the format only has room for text.
+    fn text(&mut self, index: usize, data: &[u8]) -> Option<String> {
+        let held = &mut self.held[index];
+        held.extend_from_slice(data);
+        let complete = match std::str::from_utf8(held) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => held.len(),
+        };
+        if complete == 0 {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&held[..complete]).into_owned();
+        held.drain(..complete);
+        Some(text)
+    }
+
+    fn event(&self, code: &str, data: &str) {
+        self.write(json!([self.started.elapsed().as_secs_f64(), code, data]));
+    }
+
+    fn write(&self, line: serde_json::Value) {
+        let Ok(mut sink) = self.sink.lock() else {
+            return;
+        };
+        let mut bytes = line.to_string().into_bytes();
+        bytes.push(b'\n');
+        let _ = sink.write_all(&bytes).and_then(|()| sink.flush());
+    }
+}
@@ -0,0 +1,572 @@
+//! `--report FILE` and `pipe2 show FILE`: everything about a run in one JSON file, to look at after the fact.
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use pipe2::{ExitReason, civil_from_days};
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{Cli, Stdin};
+use crate::problems::Problem;
+
+/// How much of each stream a report keeps; the end, since that's usually where a failure explains itself.
+const CAPTURE_LIMIT: usize = 64 * 1024;
+
+/// Environment variables whose names contain any of these have their values left out of the report.
+const SECRETS: &[&str] = &[
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "KEY",
+    "CREDENTIAL",
+    "AUTH",
+    "COOKIE",
+    "SESSION",
+    "PRIVATE",
+];
+
+/// Stands in for the value of a secret.
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    /// `--label`, to tell the run apart from others.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The ID pipe2 gave the run.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// `--correlation-id`, to tie the run to whatever set it off.
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    pub command: Vec<String>,
+    pub cwd: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub started: f64,
+    pub duration: f64,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    /// Why the run ended.
+    #[serde(default)]
+    pub reason: Option<ExitReason>,
+    /// Whether the run counted as a success, with `--success-codes`; `exit_code` is still the one the child had.
+    #[serde(default)]
+    pub success: Option<bool>,
+    #[serde(default)]
+    pub cpu_limit_exceeded: bool,
+    #[serde(default)]
+    pub first_output_timed_out: bool,
+    #[serde(default)]
+    pub output_closed_timed_out: bool,
+    /// Why the child was stopped, if it was for its stdin failing partway.
+    #[serde(default)]
+    pub stdin_error: Option<String>,
+    pub peak_memory: Option<u64>,
+    pub rusage: Option<Rusage>,
+    /// What `--problem-matcher` found in the output.
+    #[serde(default)]
+    pub problems: Vec<Problem>,
+    /// Lines found by `--classify`, by severity.
+    #[serde(default)]
+    pub severities: Option<Severities>,
+    pub stdout: Capture,
+    pub stderr: Capture,
+    /// What `--paranoid` kept from the child.
+    #[serde(default)]
+    pub sanitized: Option<Sanitized>,
+    /// The sockets `--monitor-network` saw the child with.
+    #[serde(default)]
+    pub connections: Option<Vec<Connection>>,
+    /// The files `--track-file-writes` saw the child write to.
+    #[serde(default)]
+    pub files_written: Option<Vec<String>>,
+    /// With `--report-env`.
+    #[serde(default)]
+    pub environment: Option<Environment>,
+}
+
+/// What it takes to run the command again somewhere else, recorded with `--report-env`.
+#[derive(Serialize, Deserialize)]
+pub struct Environment {
+    /// Where the program was found, if it was.
+    pub program_path: Option<String>,
+    /// The child's working directory.
+    pub cwd: Option<String>,
+    pub stdin: Option<ReportStdin>,
+    /// All of the child's environment, secrets replaced by [`REDACTED`].
+    pub env: BTreeMap<String, String>,
+    pub pipe2_version: String,
+    /// Like `linux-x86_64`.
+    pub platform: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStdin {
+    File(String),
+    Text(String),
+    /// The program and its arguments.
+    Command(Vec<String>),
+    Null,
+    /// One after the other.
+    Concat(Vec<ReportStdin>),
+}
+
+impl ReportStdin {
+    fn capture(stdin: &Stdin) -> Self {
+        match stdin {
+            Stdin::File(path) => Self::File(
+                fs::canonicalize(path)
+                    .unwrap_or(path.clone())
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            Stdin::Text(text) => Self::Text(text.clone()),
+            Stdin::Command(command) => Self::Command(command.clone()),
+            Stdin::Null => Self::Null,
+            Stdin::Concat(parts) => Self::Concat(parts.iter().map(Self::capture).collect()),
+        }
+    }
+
+    /// `data.csv`, `"some text"`, `$(gen --header)` or `empty`; parts are joined with `+`.
+    fn describe(&self) -> String {
+        match self {
+            Self::File(path) => path.clone(),
+            Self::Text(text) => format!("{text:?}"),
+            Self::Command(command) => format!("$({})", command.join(" ")),
+            Self::Null => "empty".to_owned(),
+            Self::Concat(parts) => parts
+                .iter()
+                .map(Self::describe)
+                .collect::<Vec<_>>()
+                .join(" + "),
+        }
+    }
+}
+
+impl Environment {
+    pub fn capture(cli: &Cli) -> Self {
+        let cwd = std::env::current_dir()
+            .ok()
+            .map(|dir| dir.join(cli.cwd.as_deref().unwrap_or(Path::new(""))));
+        let mut env: BTreeMap<OsString, OsString> = std::env::vars_os().collect();
+        env.extend(cli.env.iter().cloned());
+        let path = env.get(OsStr::new("PATH")).cloned();
+        let program_path = resolve(&cli.program, cwd.as_deref(), path.as_deref());
+        let lossy = |path: &Path| path.to_string_lossy().into_owned();
+
+        Self {
+            program_path: program_path.as_deref().map(lossy),
+            cwd: cwd.as_deref().map(lossy),
+            stdin: cli.stdin.as_ref().map(ReportStdin::capture),
+            env: env
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = key.to_string_lossy().into_owned();
+                    let upper = key.to_uppercase();
+                    let value = if SECRETS.iter().any(|secret| upper.contains(secret)) {
+                        REDACTED.to_owned()
+                    } else {
+                        value.to_string_lossy().into_owned()
+                    };
+                    (key, value)
+                })
+                .collect(),
+            pipe2_version: env!("CARGO_PKG_VERSION").to_owned(),
+            platform: format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH),
+        }
+    }
+}
+
+/// Where `program` would be run from: relative to `cwd` if it's a path, otherwise the first match on `path`.
+fn resolve(program: &OsStr, cwd: Option<&Path>, path: Option<&OsStr>) -> Option<PathBuf> {
+    let program = Path::new(program);
+    if program.components().count() > 1 {
+        let program = cwd.map_or(program.to_owned(), |cwd| cwd.join(program));
+        return fs::canonicalize(program).ok();
+    }
+    let found = std::env::split_paths(path?).find_map(|dir| {
+        let candidate = dir.join(program);
+        #[cfg(windows)]
+        if candidate.extension().is_none() {
+            let exe = candidate.with_extension("exe");
+            if exe.is_file() {
+                return Some(exe);
+            }
+        }
+        candidate.is_file().then_some(candidate)
+    })?;
+    fs::canonicalize(&found).ok().or(Some(found))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Rusage {
+    pub user_time: f64,
+    pub system_time: f64,
+    /// In KiB.
+    pub max_rss: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Severities {
+    pub errors: u64,
+    pub warnings: u64,
+    pub infos: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Sanitized {
+    /// Environment variables removed.
+    pub env: Vec<String>,
+    /// `PATH` entries removed for not being absolute.
+    pub path_entries: Vec<String>,
+    /// Descriptors that would have been inherited otherwise.
+    pub fds: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Connection {
+    /// `tcp` or `udp`.
+    pub protocol: String,
+    pub local: String,
+    /// Where it was connected to, if anywhere.
+    pub remote: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Capture {
+    /// Everything the stream carried, including what was cut from `text`.
+    pub bytes: usize,
+    pub text: String,
+    pub truncated: bool,
+}
+
+impl Capture {
+    fn new(captured: &[u8]) -> Self {
+        let start = captured.len().saturating_sub(CAPTURE_LIMIT);
+        Self {
+            bytes: captured.len(),
+            text: String::from_utf8_lossy(&captured[start..]).into_owned(),
+            truncated: start > 0,
+        }
+    }
+
+    /// The end of a `--capture-file`, without reading the rest of it.
+    fn from_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let bytes = file.metadata()?.len() as usize;
+        let start = bytes.saturating_sub(CAPTURE_LIMIT);
+        file.seek(SeekFrom::Start(start as u64))?;
+        let mut end = Vec::new();
+        file.read_to_end(&mut end)?;
+        Ok(Self {
+            bytes,
+            text: String::from_utf8_lossy(&end).into_owned(),
+            truncated: start > 0,
+        })
+    }
+
+    /// A stream's capture, from the file it was captured into if there's one.
+    fn of(captured: &[u8], file: Option<&Path>) -> Self {
+        file.and_then(|path| Self::from_file(path).ok())
+            .unwrap_or_else(|| Self::new(captured))
+    }
+}
+
+/// The files `--capture-file` and `--capture-stderr-file` left `stdout` and `stderr` in, rather than in `output`.
+pub fn capture_files(output: &pipe2::Output) -> [Option<&Path>; 2] {
+    #[cfg(unix)]
+    return [output.stdout_file.as_deref(), output.stderr_file.as_deref()];
+    #[cfg(not(unix))]
+    [None, None]
+}
+
+/// How many bytes `stdout` and `stderr` each captured, in memory or into a file.
+pub fn captured_bytes(output: &pipe2::Output) -> [u64; 2] {
+    let in_memory = [output.stdout.len(), output.stderr.len()];
+    let files = capture_files(output);
+    std::array::from_fn(|index| match files[index] {
+        Some(path) => fs::metadata(path).map_or(0, |metadata| metadata.len()),
+        None => in_memory[index] as u64,
+    })
+}
+
+impl Report {
+    pub fn new(
+        program: &OsString,
+        args: &[OsString],
+        started: SystemTime,
+        duration: Duration,
+        output: &pipe2::Output,
+        problems: Vec<Problem>,
+        classified: bool,
+    ) -> Self {
+        let command = std::iter::once(program)
+            .chain(args)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+
+        Self {
+            label: None,
+            run_id: Some(output.run_id.to_string()),
+            correlation_id: None,
+            command,
+            cwd: std::env::current_dir()
+                .ok()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+            started: started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            duration: duration.as_secs_f64(),
+            exit_code: output.status.code(),
+            signal: signal(output.status),
+            timed_out: output.timed_out,
+            reason: Some(output.reason),
+            success: Some(output.success),
+            cpu_limit_exceeded: output.cpu_limit_exceeded,
+            first_output_timed_out: output.first_output_timed_out,
+            output_closed_timed_out: output.output_closed_timed_out,
+            stdin_error: output.stdin_error.clone(),
+            peak_memory: output.peak_memory,
+            rusage: output.resource_usage.map(|usage| Rusage {
+                user_time: usage.user_time.as_secs_f64(),
+                system_time: usage.system_time.as_secs_f64(),
+                max_rss: usage.max_rss,
+            }),
+            problems,
+            severities: classified.then_some(Severities {
+                errors: output.severities.errors,
+                warnings: output.severities.warnings,
+                infos: output.severities.infos,
+            }),
+            stdout: Capture::of(&output.stdout, capture_files(output)[0]),
+            stderr: Capture::of(&output.stderr, capture_files(output)[1]),
+            sanitized: output.sanitized.as_ref().map(|sanitized| Sanitized {
+                env: sanitized.env.clone(),
+                path_entries: sanitized.path_entries.clone(),
+                fds: sanitized.fds.clone(),
+            }),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            connections: output.connections.as_ref().map(|connections| {
+                connections
+                    .iter()
+                    .map(|connection| Connection {
+                        protocol: connection.protocol.as_str().to_owned(),
+                        local: connection.local.to_string(),
+                        remote: connection.remote.map(|remote| remote.to_string()),
+                    })
+                    .collect()
+            }),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            connections: None,
+            #[cfg(all(feature = "file-access", target_os = "linux"))]
+            files_written: output.files_written.as_ref().map(|files| {
+                files
+                    .iter()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect()
+            }),
+            #[cfg(not(all(feature = "file-access", target_os = "linux")))]
+            files_written: None,
+            environment: None,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// How the child exited, like `exit code 1`.
+    pub fn exit(&self) -> String {
+        describe_exit(self.exit_code, self.signal)
+    }
+
+    /// Pretty-prints the report, for `pipe2 show`.
+    pub fn print(&self) {
+        if let Some(label) = &self.label {
+            println!("Label:       {label}");
+        }
+        if let Some(run_id) = &self.run_id {
+            println!("Run:         {run_id}");
+        }
+        if let Some(correlation_id) = &self.correlation_id {
+            println!("Correlation: {correlation_id}");
+        }
+        println!("Command:     {}", self.command.join(" "));
+        if let Some(cwd) = &self.cwd {
+            println!("Directory:   {cwd}");
+        }
+        println!("Started:     {}", format_timestamp(self.started));
+        println!("Duration:    {:.3}s", self.duration);
+        let exit = self.exit();
+        let killed = if self.timed_out {
+            " (timed out)"
+        } else if self.first_output_timed_out {
+            " (no output in time)"
+        } else if self.output_closed_timed_out {
+            " (still running after closing its output)"
+        } else if self.cpu_limit_exceeded {
+            " (CPU limit exceeded)"
+        } else if self.reason == Some(ExitReason::PolicyViolation) {
+            " (broke its syscall policy)"
+        } else if self.reason == Some(ExitReason::InputFailed) {
+            " (its stdin failed)"
+        } else if self.success == Some(true) && self.exit_code != Some(0) {
+            " (counted as a success)"
+        } else {
+            ""
+        };
+        println!("Exit:        {exit}{killed}");
+        if let Some(error) = &self.stdin_error {
+            println!("Stdin error: {error}");
+        }
+        if let Some(peak) = self.peak_memory {
+            println!("Peak memory: {peak} bytes");
+        }
+        if let Some(rusage) = &self.rusage {
+            println!(
+                "CPU time:    {:.3}s user, {:.3}s system",
+                rusage.user_time, rusage.system_time
+            );
+            println!("Max RSS:     {} KiB", rusage.max_rss);
+        }
+        if let Some(severities) = &self.severities {
+            println!(
+                "Lines:       {} errors, {} warnings, {} info",
+                severities.errors, severities.warnings, severities.infos
+            );
+        }
+        if let Some(sanitized) = &self.sanitized {
+            let mut removed = Vec::new();
+            if !sanitized.env.is_empty() {
+                removed.push(sanitized.env.join(", "));
+            }
+            if !sanitized.path_entries.is_empty() {
+                removed.push(format!("PATH entries {:?}", sanitized.path_entries));
+            }
+            if !sanitized.fds.is_empty() {
+                let fds: Vec<String> = sanitized.fds.iter().map(i32::to_string).collect();
+                removed.push(format!("fds {}", fds.join(", ")));
+            }
+            if removed.is_empty() {
+                println!("Sanitized:   nothing to remove");
+            } else {
+                println!("Sanitized:   removed {}", removed.join("; "));
+            }
+        }
+        if let Some(connections) = &self.connections {
+            if connections.is_empty() {
+                println!("Network:     no connections seen");
+            }
+            for (i, connection) in connections.iter().enumerate() {
+                let label = if i == 0 { "Network:" } else { "" };
+                let remote = match &connection.remote {
+                    Some(remote) => format!(" -> {remote}"),
+                    None => String::new(),
+                };
+                println!(
+                    "{label:<13}{} {}{remote}",
+                    connection.protocol, connection.local
+                );
+            }
+        }
+        if let Some(files) = &self.files_written {
+            if files.is_empty() {
+                println!("Files:       none written");
+            }
+            for (i, path) in files.iter().enumerate() {
+                let label = if i == 0 { "Files:" } else { "" };
+                println!("{label:<13}{path}");
+            }
+        }
+        if let Some(environment) = &self.environment {
+            println!(
+                "Pipe2:       {} on {}",
+                environment.pipe2_version, environment.platform
+            );
+            if let Some(path) = &environment.program_path {
+                println!("Program:     {path}");
+            }
+            if let Some(cwd) = &environment.cwd {
+                println!("Working dir: {cwd}");
+            }
+            if let Some(stdin) = &environment.stdin {
+                println!("Stdin:       {}", stdin.describe());
+            }
+            println!(
+                "\n--- environment ({} variables) ---",
+                environment.env.len()
+            );
+            for (key, value) in &environment.env {
+                println!("{key}={value}");
+            }
+        }
+        if !self.problems.is_empty() {
+            println!("\n--- problems ({}) ---", self.problems.len());
+            for problem in &self.problems {
+                println!("{problem}");
+            }
+        }
+        for (name, capture) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            let truncated = if capture.truncated {
+                ", only the end kept"
+            } else {
+                ""
+            };
+            println!("\n--- {name} ({} bytes{truncated}) ---", capture.bytes);
+            print!("{}", capture.text);
+            if !capture.text.is_empty() && !capture.text.ends_with('\n') {
+                println!();
+            }
+        }
+    }
+}
+
+/// `status` the way a report puts it, like `exit code 1`.
+pub fn describe(status: ExitStatus) -> String {
+    describe_exit(status.code(), signal(status))
+}
+
+fn describe_exit(code: Option<i32>, signal: Option<i32>) -> String {
+    match (code, signal) {
+        (Some(code), _) => format!("exit code {code}"),
+        (None, Some(signal)) => format!("killed by signal {signal}"),
+        (None, None) => "unknown".to_owned(),
+    }
+}
+
+#[cfg(unix)]
+pub fn signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+pub fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC`, without pulling in a date crate for it.
+fn format_timestamp(secs: f64) -> String {
+    let secs = secs as i64;
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
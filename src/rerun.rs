@@ -0,0 +1,167 @@
+//! `pipe2 rerun FILE`: runs the command from a report again, the way it was run then, to see whether a failure comes
+//! back.
+//!
+//! The command runs with the arguments it had, and with what `--report-env` recorded: in the same working directory,
+//! with the same `stdin`, and with the recorded environment on top of ours, leaving out the redacted variables. With
+//! `--diff`, its output is compared line by line to what the report kept of the original run's.
+
+use std::io;
+use std::path::Path;
+
+use pipe2::{Pipe2, StdinPart};
+
+use crate::report::{self, Capture, REDACTED, Report, ReportStdin};
+
+/// How many lines of each side the diff goes through; past that, it's only said whether they're the same.
+const DIFF_LIMIT: usize = 2_000;
+
+/// Runs the command again, and returns the exit code `pipe2` would have.
+pub fn run(file: &Path, diff: bool) -> io::Result<i32> {
+    let report = Report::load(file)?;
+    let Some((program, args)) = report.command.split_first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the report has no command",
+        ));
+    };
+
+    let mut pipe2 = Pipe2::new(program);
+    pipe2.args(args);
+    if let Some(label) = &report.label {
+        pipe2.label(label);
+    }
+    let cwd = report
+        .environment
+        .as_ref()
+        .and_then(|environment| environment.cwd.as_ref())
+        .or(report.cwd.as_ref());
+    match cwd {
+        Some(cwd) if Path::new(cwd).is_dir() => {
+            pipe2.current_dir(cwd);
+        }
+        Some(cwd) => eprintln!("pipe2: {cwd} doesn't exist here, running in the current directory"),
+        None => {}
+    }
+    if let Some(environment) = &report.environment {
+        for (key, value) in &environment.env {
+            if value != REDACTED {
+                pipe2.env(key, value);
+            }
+        }
+        match &environment.stdin {
+            Some(ReportStdin::File(path)) => {
+                pipe2.stdin_file(path);
+            }
+            Some(ReportStdin::Text(text)) => {
+                pipe2.stdin_bytes(text.as_bytes());
+            }
+            Some(ReportStdin::Null) => {
+                pipe2.stdin_null();
+            }
+            Some(stdin @ (ReportStdin::Command(_) | ReportStdin::Concat(_))) => {
+                pipe2.stdin_sources(parts(stdin));
+            }
+            None => {}
+        }
+    } else {
+        eprintln!(
+            "pipe2: the report wasn't saved with --report-env, so only the command and directory are the same"
+        );
+    }
+
+    let output = pipe2.run()?;
+    eprintln!(
+        "\npipe2: {} this time, {} when recorded",
+        report::describe(output.status),
+        report.exit()
+    );
+    if diff {
+        compare("stdout", &report.stdout, &output.stdout);
+        compare("stderr", &report.stderr, &output.stderr);
+    }
+    Ok(crate::exit_code(&output))
+}
+
+/// What a recorded `stdin` was made of, in order.
+fn parts(stdin: &ReportStdin) -> Vec<StdinPart> {
+    match stdin {
+        ReportStdin::File(path) => vec![StdinPart::File(path.into())],
+        ReportStdin::Text(text) => vec![StdinPart::Bytes(text.clone().into_bytes())],
+        ReportStdin::Command(command) => match command.split_first() {
+            Some((program, args)) => vec![StdinPart::Command(
+                program.into(),
+                args.iter().map(Into::into).collect(),
+            )],
+            None => Vec::new(),
+        },
+        ReportStdin::Null => Vec::new(),
+        ReportStdin::Concat(each) => each.iter().flat_map(parts).collect(),
+    }
+}
+
+/// Prints the lines of `stream` that changed since the recorded run, to `stderr`.
+fn compare(stream: &str, recorded: &Capture, now: &[u8]) {
+    let now = String::from_utf8_lossy(now);
+    // NOTE: the report only keeps the end of a long stream, so only as much of this run's is compared.
+    let now = if recorded.truncated {
+        tail(&now, recorded.text.len())
+    } else {
+        &now
+    };
+    let (old, new): (Vec<&str>, Vec<&str>) =
+        (recorded.text.lines().collect(), now.lines().collect());
+    if old == new {
+        eprintln!("pipe2: {stream} is the same as recorded");
+        return;
+    }
+    if old.len() > DIFF_LIMIT || new.len() > DIFF_LIMIT {
+        eprintln!("pipe2: {stream} differs from what was recorded");
+        return;
+    }
+    eprintln!("--- {stream}, recorded\n+++ {stream}, this run");
+    for line in diff(&old, &new) {
+        eprintln!("{line}");
+    }
+}
+
+/// The last `len` bytes of `text`, or a little less to start on a character.
+fn tail(text: &str, len: usize) -> &str {
+    let mut start = text.len().saturating_sub(len);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+/// The lines only in `old`, starting with `-`, and the lines only in `new`, starting with `+`, in order, along a
+/// longest common subsequence of the two.
+fn diff(old: &[&str], new: &[&str]) -> Vec<String> {
+    let (n, m) = (old.len(), new.len());
+    // NOTE: `common[i][j]` is how long the longest common subsequence of `old[i..]` and `new[j..]` is.
+    let mut common = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
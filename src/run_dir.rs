@@ -0,0 +1,102 @@
+//! `--run-dir DIR`: a fresh directory under DIR for each run's artifacts (the report, the event log and the output),
+//! with `DIR/latest` pointing at the last one, so runs don't overwrite each other's.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pipe2::civil_from_days;
+
+const LATEST: &str = "latest";
+
+pub struct RunDir {
+    base: PathBuf,
+    name: String,
+}
+
+impl RunDir {
+    /// Creates `base/<UTC timestamp>`, with a `-N` after it if another run already took that second.
+    pub fn create(base: &Path) -> io::Result<Self> {
+        fs::create_dir_all(base)?;
+        let stamp = timestamp(SystemTime::now());
+        for n in 0.. {
+            let name = match n {
+                0 => stamp.clone(),
+                n => format!("{stamp}-{n}"),
+            };
+            match fs::create_dir(base.join(&name)) {
+                Ok(()) => {
+                    return Ok(Self {
+                        base: base.to_owned(),
+                        name,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.base.join(&self.name)
+    }
+
+    /// Saves the captured output next to the rest.
+    pub fn save_output(&self, output: &pipe2::Output) -> io::Result<()> {
+        fs::write(self.path().join("stdout.log"), &output.stdout)?;
+        fs::write(self.path().join("stderr.log"), &output.stderr)
+    }
+
+    /// Points `base/latest` at this run: a relative symlink on Unix, a junction on Windows.
+    pub fn mark_latest(&self) -> io::Result<()> {
+        let latest = self.base.join(LATEST);
+
+        #[cfg(unix)]
+        {
+            // NOTE: made under another name and renamed over the old one, so `latest` never goes missing.
+            let temporary = self.base.join(format!(".{LATEST}.{}", std::process::id()));
+            let _ = fs::remove_file(&temporary);
+            std::os::unix::fs::symlink(&self.name, &temporary)?;
+            fs::rename(&temporary, &latest)
+        }
+
+        #[cfg(windows)]
+        {
+            // NOTE: a junction neither needs the symlink privilege nor Developer Mode, but has to be made by `mklink`.
+            if fs::symlink_metadata(&latest).is_ok() {
+                fs::remove_dir(&latest)?;
+            }
+            let status = std::process::Command::new("cmd")
+                .arg("/C")
+                .arg("mklink")
+                .arg("/J")
+                .arg(&latest)
+                .arg(self.path())
+                .stdout(std::process::Stdio::null())
+                .status()?;
+            if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("mklink /J exited with {status}")))
+            }
+        }
+    }
+}
+
+/// `YYYYMMDDTHHMMSSZ`.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
@@ -0,0 +1,54 @@
+//! Telling runs apart: every spawn gets a random ID of its own, in the shape of a version 4 UUID, which the event log,
+//! the trace, the control socket and the [`Output`](crate::Output) all carry, along with the caller's own correlation
+//! ID if there's one.
+
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many IDs were handed out, so that two in the same nanosecond still differ.
+static GENERATED: AtomicU64 = AtomicU64::new(0);
+
+/// A run's ID, shown as a UUID like `0f8e6a3c-5b1d-4e2f-9a7b-3c4d5e6f7a8b`; see [`Child::run_id`](crate::Child::run_id).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RunId(u128);
+
+impl RunId {
+    /// A new random ID.
+    ///
+    /// NOTE: `RandomState` is seeded from the OS's randomness, which saves a dependency for something that only has to
+    /// be unique, not unguessable.
+    pub(crate) fn generate() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let count = GENERATED.fetch_add(1, Ordering::Relaxed);
+        let high = RandomState::new().hash_one((nanos, count));
+        let low = RandomState::new().hash_one((std::process::id(), count));
+        let bits = (u128::from(high) << 64) | u128::from(low);
+        // NOTE: the version (4, random) and variant (RFC 9562) bits, so it passes for the UUID it looks like.
+        let bits = (bits & !(0xf << 76)) | (0x4 << 76);
+        Self((bits & !(0x3 << 62)) | (0x2 << 62))
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = format!("{:032x}", self.0);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+}
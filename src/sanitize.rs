@@ -0,0 +1,110 @@
+//! [`Pipe2::paranoid`](crate::Pipe2::paranoid): keeping what the child inherits down to what it's meant to get, for
+//! running tools that are only half trusted.
+//!
+//! The environment loses the variables that get the dynamic loader to load code of someone else's choosing, and
+//! `PATH` loses its relative entries, which resolve against wherever the child happens to be. Every descriptor or
+//! handle other than the child's stdio and the ones passed to it on purpose is closed. What was taken away is kept,
+//! for the report.
+
+use std::ffi::{OsStr, OsString};
+
+/// Variables that get the dynamic loader to load or audit other code into the child.
+const LOADER_VARS: &[&str] = &["LD_PRELOAD", "LD_AUDIT", "LD_LIBRARY_PATH"];
+
+/// The same for the macOS loader, all of whose variables start with it.
+const DYLD_PREFIX: &str = "DYLD_";
+
+/// What [`Pipe2::paranoid`](crate::Pipe2::paranoid) kept from the child, see
+/// [`Output::sanitized`](crate::Output::sanitized).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sanitized {
+    /// The environment variables removed.
+    pub env: Vec<String>,
+    /// The entries removed from `PATH` for not being absolute; an empty one stands for the working directory.
+    pub path_entries: Vec<String>,
+    /// The descriptors the child would have inherited otherwise (Unix). On Windows, the child only inherits its stdio
+    /// and the handles passed to it, but which others it would have isn't known.
+    pub fds: Vec<i32>,
+}
+
+impl Sanitized {
+    /// Whether nothing had to be taken away.
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty() && self.path_entries.is_empty() && self.fds.is_empty()
+    }
+}
+
+/// Works out what to take away from the environment the child would get, ours (unless `env_clear`) with `changes` made
+/// to it; returns that, and the changes that take it away, to be made after `changes`.
+pub(crate) fn environment(
+    env_clear: bool,
+    changes: &[(OsString, Option<OsString>)],
+) -> (Sanitized, Vec<(OsString, Option<OsString>)>) {
+    let mut env: Vec<(OsString, OsString)> = if env_clear {
+        Vec::new()
+    } else {
+        std::env::vars_os().collect()
+    };
+    for (key, val) in changes {
+        env.retain(|(other, _)| !same_name(other, key));
+        if let Some(val) = val {
+            env.push((key.clone(), val.clone()));
+        }
+    }
+
+    let mut sanitized = Sanitized::default();
+    let mut removals = Vec::new();
+    for (key, val) in env {
+        let name = key.to_string_lossy().into_owned();
+        if is_loader_var(&name) {
+            sanitized.env.push(name);
+            removals.push((key, None));
+        } else if same_name(&key, OsStr::new("PATH")) {
+            let (absolute, relative): (Vec<_>, Vec<_>) =
+                std::env::split_paths(&val).partition(|entry| entry.is_absolute());
+            if relative.is_empty() {
+                continue;
+            }
+            sanitized.path_entries.extend(
+                relative
+                    .iter()
+                    .map(|entry| entry.to_string_lossy().into_owned()),
+            );
+            // NOTE: an empty `PATH` would stand for the working directory all over again, so it goes altogether
+            // then, and the child gets the system's default search path.
+            let path = match std::env::join_paths(&absolute) {
+                Ok(path) if !absolute.is_empty() => Some(path),
+                _ => None,
+            };
+            removals.push((key, path));
+        }
+    }
+    (sanitized, removals)
+}
+
+fn is_loader_var(name: &str) -> bool {
+    LOADER_VARS.contains(&name) || name.starts_with(DYLD_PREFIX)
+}
+
+// NOTE: variable names are case-insensitive on Windows.
+#[cfg(windows)]
+fn same_name(a: &OsStr, b: &OsStr) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+#[cfg(not(windows))]
+fn same_name(a: &OsStr, b: &OsStr) -> bool {
+    a == b
+}
+
+/// The descriptors open here that a child would inherit: everything but stdio not marked `FD_CLOEXEC`.
+#[cfg(unix)]
+pub(crate) fn inherited_fds() -> std::io::Result<Vec<i32>> {
+    let mut fds = crate::inherit::open_fds()?;
+    fds.retain(|&fd| {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        flags >= 0 && flags & libc::FD_CLOEXEC == 0
+    });
+    fds.sort_unstable();
+    Ok(fds)
+}
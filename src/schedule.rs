@@ -0,0 +1,330 @@
+//! `pipe2 schedule`: runs the command on an interval or a cron schedule, instead of cron running it through a shell
+//! and `logger`.
+//!
+//! Cron expressions are the usual five fields (minute, hour, day of the month, month, day of the week), each `*`, a
+//! number, a range `a-b`, a step `*/n` or `a-b/n`, or a comma-separated list of those, and are taken in UTC. As with
+//! cron, when both days are restricted (neither starts with `*`), a day matching either one will do.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pipe2::{Child, Pipe2, civil_from_days};
+
+use crate::cli::Cli;
+use crate::report::Report;
+
+/// `schedule`'s own options.
+pub struct Schedule {
+    pub when: When,
+    pub overlap: Overlap,
+    /// Where each run's report goes, as `run-<milliseconds since the epoch>.json`.
+    pub report_dir: Option<PathBuf>,
+    /// How many reports to keep around.
+    pub keep: usize,
+}
+
+pub enum When {
+    Every(Duration),
+    Cron(Cron),
+}
+
+/// What to do when a run is due while the last one is still going.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    /// Let the last run carry on, and drop this one.
+    Skip,
+    /// Start this one as soon as the last one is done.
+    Queue,
+    /// Stop the last run the way `--timeout` would, then start this one.
+    KillPrevious,
+}
+
+impl Overlap {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "skip" => Ok(Self::Skip),
+            "queue" => Ok(Self::Queue),
+            "kill-previous" => Ok(Self::KillPrevious),
+            _ => Err(format!("invalid overlap policy {value:?}")),
+        }
+    }
+}
+
+/// A parsed cron expression: which values each field allows.
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// Whether the day-of-month and day-of-week fields didn't start with `*`, for cron's either-day rule; like cron,
+    /// `*/2` counts as unrestricted.
+    days_restricted: (bool, bool),
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "cron expression {expression:?} should have 5 fields"
+            ));
+        };
+        let mut weekdays = field(weekday, 0, 7)?;
+        // NOTE: 7 is Sunday too.
+        weekdays[0] |= weekdays[7];
+        let cron = Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            days_restricted: (!day.starts_with('*'), !weekday.starts_with('*')),
+        };
+        match cron.next(now()) {
+            Some(_) => Ok(cron),
+            None => Err(format!("cron expression {expression:?} never matches")),
+        }
+    }
+
+    /// The first minute after `after` (seconds since the epoch) that matches, as seconds since the epoch.
+    fn next(&self, after: u64) -> Option<u64> {
+        let first = after / 60 + 1;
+        // NOTE: every valid expression matches at least once in 4 years, leap days included; anything else (like
+        // the 31st of February) never will.
+        (first..first + 4 * 366 * 24 * 60)
+            .find(|&minute| self.matches(minute))
+            .map(|minute| minute * 60)
+    }
+
+    fn matches(&self, minute: u64) -> bool {
+        let days = (minute / (24 * 60)) as i64;
+        let (_, month, day) = civil_from_days(days);
+        // NOTE: 1970-01-01 was a Thursday.
+        let weekday = (days + 4).rem_euclid(7) as usize;
+        let day_matches = match self.days_restricted {
+            (true, true) => self.days[day as usize] || self.weekdays[weekday],
+            _ => self.days[day as usize] && self.weekdays[weekday],
+        };
+        self.minutes[(minute % 60) as usize]
+            && self.hours[(minute / 60 % 24) as usize]
+            && self.months[month as usize]
+            && day_matches
+    }
+}
+
+/// Which of `min..=max` one cron field allows, indexed by value.
+fn field(spec: &str, min: usize, max: usize) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max + 1];
+    let number = |value: &str| -> Result<usize, String> {
+        value
+            .parse()
+            .ok()
+            .filter(|value| (min..=max).contains(value))
+            .ok_or_else(|| format!("invalid value {value:?} in cron field {spec:?}"))
+    };
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| format!("invalid step in cron field {spec:?}"))?;
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => (number(range)?, number(range)?),
+        };
+        if start > end {
+            return Err(format!(
+                "the range {range} in cron field {spec:?} is reversed"
+            ));
+        }
+        for value in (start..=end).step_by(step) {
+            allowed[value] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A run in progress.
+struct Run {
+    number: u32,
+    child: Child,
+    started: SystemTime,
+    clock: Instant,
+}
+
+/// Runs the command whenever it's due, until we're killed; only returns if that fails.
+pub fn run(
+    pipe2: &mut Pipe2,
+    cli: &Cli,
+    schedule: &Schedule,
+) -> io::Result<std::convert::Infallible> {
+    if let Some(dir) = &schedule.report_dir {
+        fs::create_dir_all(dir)?;
+    }
+    let due_after = |last: Instant, at: u64| -> Instant {
+        match &schedule.when {
+            When::Every(interval) => last + *interval,
+            When::Cron(cron) => {
+                let next = cron.next(at).expect("checked when parsing");
+                Instant::now() + Duration::from_secs(next.saturating_sub(now()))
+            }
+        }
+    };
+    // NOTE: an interval schedule starts right away, a cron one waits for its first time.
+    let mut due = match schedule.when {
+        When::Every(_) => Instant::now(),
+        When::Cron(_) => due_after(Instant::now(), now()),
+    };
+    let mut current: Option<Run> = None;
+    let mut queued = 0u32;
+    let mut runs = 0u32;
+
+    loop {
+        if Instant::now() >= due {
+            due = due_after(due, now());
+            match (&mut current, schedule.overlap) {
+                (None, _) => queued += 1,
+                (Some(run), Overlap::Skip) => {
+                    eprintln!("pipe2: [run {}] still going, skipping this one", run.number);
+                }
+                (Some(_), Overlap::Queue) => queued += 1,
+                (Some(run), Overlap::KillPrevious) => {
+                    eprintln!("pipe2: [run {}] still going, stopping it", run.number);
+                    run.child.kill()?;
+                    queued += 1;
+                }
+            }
+        }
+
+        if let Some(run) = &mut current
+            && run.child.poll()?.is_some()
+        {
+            let run = current.take().expect("just polled it");
+            finish(run, cli, schedule)?;
+        }
+
+        if current.is_none() && queued > 0 {
+            queued -= 1;
+            runs += 1;
+            eprintln!("pipe2: [run {runs}] starting {}", cli.command_line());
+            current = Some(Run {
+                number: runs,
+                child: pipe2.spawn()?,
+                started: SystemTime::now(),
+                clock: Instant::now(),
+            });
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Reports on a run that just exited, and drops the oldest reports past the limit.
+fn finish(run: Run, cli: &Cli, schedule: &Schedule) -> io::Result<()> {
+    let output = run.child.wait()?;
+    let duration = run.clock.elapsed();
+    eprintln!(
+        "pipe2: [run {}] exited with {} after {:.3}s",
+        run.number,
+        output.status,
+        duration.as_secs_f64()
+    );
+    let Some(dir) = &schedule.report_dir else {
+        return Ok(());
+    };
+
+    let report = Report::new(
+        &cli.program,
+        &cli.args,
+        run.started,
+        duration,
+        &output,
+        Vec::new(),
+        !cli.classifiers.is_empty(),
+    );
+    let started = run
+        .started
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    report.save(&dir.join(format!("run-{started}.json")))?;
+
+    let mut reports: Vec<PathBuf> = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("run-") && name.ends_with(".json"))
+        })
+        .collect();
+    // NOTE: the epoch milliseconds have the same number of digits until 2286, so the names sort by time.
+    reports.sort();
+    let excess = reports.len().saturating_sub(schedule.keep);
+    for old in &reports[..excess] {
+        let _ = fs::remove_file(old);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01 00:00 UTC, a Monday.
+    const NEW_YEAR: u64 = 1_704_067_200;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn next(expression: &str) -> u64 {
+        Cron::parse(expression).unwrap().next(NEW_YEAR).unwrap()
+    }
+
+    #[test]
+    fn parses() {
+        assert!(Cron::parse("0,30 9-17/2 1-15 */3 1-5").is_ok());
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("* * * * * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("* 24 * * *").is_err());
+        assert!(Cron::parse("* * 0 * *").is_err());
+        assert!(Cron::parse("* * * 13 *").is_err());
+        assert!(Cron::parse("* * * * 8").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert!(Cron::parse("0,30-10 * * * *").is_err());
+        assert!(Cron::parse("0 0 31 2 *").is_err());
+    }
+
+    #[test]
+    fn next_times() {
+        assert_eq!(next("* * * * *"), NEW_YEAR + 60);
+        assert_eq!(next("*/15 * * * *"), NEW_YEAR + 15 * 60);
+        assert_eq!(next("0 12 * * *"), NEW_YEAR + 12 * 60 * 60);
+        assert_eq!(next("0 0 1 * *"), NEW_YEAR + 31 * DAY);
+        assert_eq!(next("0 0 29 2 *"), NEW_YEAR + (31 + 28) * DAY);
+        assert_eq!(next("10-20/5 3 * * *"), NEW_YEAR + 3 * 60 * 60 + 10 * 60);
+        // NOTE: 7 is Sunday as well as 0.
+        assert_eq!(next("0 0 * * 7"), NEW_YEAR + 6 * DAY);
+        assert_eq!(next("0 0 * * 0"), NEW_YEAR + 6 * DAY);
+    }
+
+    #[test]
+    fn either_day() {
+        // NOTE: both days restricted: the 13th, or any Friday, whichever comes first.
+        assert_eq!(next("0 0 13 * 5"), NEW_YEAR + 4 * DAY);
+        // NOTE: a step from `*` isn't a restriction, so this is the 13th, as long as it's an even weekday; the 13th of
+        // January 2024 is a Saturday.
+        assert_eq!(next("0 0 13 * */2"), NEW_YEAR + 12 * DAY);
+        // NOTE: odd days that are Tuesdays; the 2nd is a Tuesday, the 9th the first odd one.
+        assert_eq!(next("0 0 */2 * 2"), NEW_YEAR + 8 * DAY);
+    }
+}
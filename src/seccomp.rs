@@ -0,0 +1,192 @@
+//! Pre-built seccomp policies for the child on Linux, see [`Pipe2::syscall_policy`](crate::Pipe2::syscall_policy).
+//!
+//! Each policy compiles down to a few BPF instructions that kill the child outright when it makes a forbidden call,
+//! which then shows up as [`ExitReason::PolicyViolation`](crate::ExitReason::PolicyViolation) rather than as an
+//! error the child could paper over. It's a pragmatic fence for tools that shouldn't need the network or other
+//! programs, not a full sandbox: only the calls named below are looked at.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// What the child is kept from doing, see [`Pipe2::syscall_policy`](crate::Pipe2::syscall_policy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SyscallPolicy {
+    /// No sockets but Unix and netlink ones, so no network, and no `io_uring`, which could open them regardless.
+    NoNetwork,
+    /// No running other programs: the child's own `execve` goes through, any after it doesn't.
+    NoExec,
+}
+
+/// `AUDIT_ARCH_*` of the architectures the policies know the system call numbers of.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00f3);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+)))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Set in the numbers of the x32 system calls, which are the same calls under other numbers.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const LOAD: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+const JEQ: u16 = (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16;
+#[cfg(target_arch = "x86_64")]
+const JGE: u16 = (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16;
+const RET: u16 = (libc::BPF_RET | libc::BPF_K) as u16;
+
+/// Offsets into `struct seccomp_data`: the call's number, the architecture, and the two halves of the first argument.
+const NR: u32 = 0;
+const ARCH: u32 = 4;
+const ARG0_LOW: u32 = 16;
+const ARG0_HIGH: u32 = 20;
+
+/// A seccomp filter made up in the parent, to be installed in the child between `fork` and `exec`.
+#[derive(Clone)]
+pub(crate) struct Filter(Vec<libc::sock_filter>);
+
+impl Filter {
+    /// The filter for `policies`; `program` is where the path the child is `exec`ed with lives, the only `execve` that
+    /// [`SyscallPolicy::NoExec`] lets through.
+    pub(crate) fn new(policies: &[SyscallPolicy], program: *const u8) -> io::Result<Self> {
+        let Some(arch) = AUDIT_ARCH else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "syscall policies aren't supported on this architecture",
+            ));
+        };
+
+        let mut filter = vec![
+            stmt(LOAD, ARCH),
+            jump(JEQ, arch, 1, 0),
+            stmt(RET, libc::SECCOMP_RET_KILL_PROCESS),
+        ];
+        // NOTE: otherwise, every call could be made again as its x32 twin, which the rules below don't look at.
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            stmt(LOAD, NR),
+            jump(JGE, X32_SYSCALL_BIT, 0, 1),
+            stmt(RET, libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+        for policy in policies {
+            match policy {
+                SyscallPolicy::NoNetwork => {
+                    filter.extend(unless_arg0(
+                        libc::SYS_socket,
+                        &[libc::AF_UNIX as u32, libc::AF_NETLINK as u32],
+                    ));
+                    filter.extend(deny(libc::SYS_io_uring_setup));
+                }
+                SyscallPolicy::NoExec => {
+                    filter.extend(unless_pointer(libc::SYS_execve, program as u64));
+                    filter.extend(deny(libc::SYS_execveat));
+                }
+            }
+        }
+        filter.push(stmt(RET, libc::SECCOMP_RET_ALLOW));
+        Ok(Self(filter))
+    }
+
+    /// Installs the filter on the calling process, for good; runs between `fork` and `exec`, so it doesn't allocate.
+    pub(crate) fn install(&self) -> io::Result<()> {
+        // NOTE: without privileges, a filter can only be installed once nothing can gain any through `exec` anymore.
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let program = libc::sock_fprog {
+            len: self.0.len() as libc::c_ushort,
+            filter: self.0.as_ptr().cast_mut(),
+        };
+        if unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                0,
+                &program as *const libc::sock_fprog,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// Kills the child when it calls `nr`.
+fn deny(nr: libc::c_long) -> Vec<libc::sock_filter> {
+    vec![
+        stmt(LOAD, NR),
+        jump(JEQ, nr as u32, 0, 1),
+        stmt(RET, libc::SECCOMP_RET_KILL_PROCESS),
+    ]
+}
+
+/// Kills the child when it calls `nr` with a first argument (an `int`) that isn't one of `allowed`.
+fn unless_arg0(nr: libc::c_long, allowed: &[u32]) -> Vec<libc::sock_filter> {
+    let n = allowed.len() as u8;
+    let mut rule = vec![
+        stmt(LOAD, NR),
+        jump(JEQ, nr as u32, 0, n + 3),
+        stmt(LOAD, ARG0_LOW),
+    ];
+    for (i, &value) in allowed.iter().enumerate() {
+        rule.push(jump(JEQ, value, n - i as u8, 0));
+    }
+    rule.push(stmt(RET, libc::SECCOMP_RET_KILL_PROCESS));
+    rule.push(stmt(RET, libc::SECCOMP_RET_ALLOW));
+    rule
+}
+
+/// Kills the child when it calls `nr` with a first argument (a pointer) other than `pointer`.
+fn unless_pointer(nr: libc::c_long, pointer: u64) -> Vec<libc::sock_filter> {
+    vec![
+        stmt(LOAD, NR),
+        jump(JEQ, nr as u32, 0, 6),
+        stmt(LOAD, ARG0_LOW),
+        jump(JEQ, pointer as u32, 0, 2),
+        stmt(LOAD, ARG0_HIGH),
+        jump(JEQ, (pointer >> 32) as u32, 1, 0),
+        stmt(RET, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(RET, libc::SECCOMP_RET_ALLOW),
+    ]
+}
+
+/// Where `program` is found on `path`, for [`SyscallPolicy::NoExec`]: the child has to be `exec`ed with a path of ours
+/// for the filter to know it, rather than one `execvp` puts together while searching.
+pub(crate) fn resolve(program: &OsStr, path: Option<&OsStr>) -> io::Result<PathBuf> {
+    if program.as_bytes().contains(&b'/') {
+        return Ok(PathBuf::from(program));
+    }
+    let path = path.map_or_else(|| OsString::from("/bin:/usr/bin"), OsStr::to_owned);
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} wasn't found on PATH", program.to_string_lossy()),
+            )
+        })
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
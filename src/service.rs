@@ -0,0 +1,174 @@
+//! `pipe2 with-service`: starts a service, waits for it to be ready, runs the command against it, and stops the
+//! service again, the usual shape of an integration test run.
+//!
+//! The service's output is relayed along with the command's, its lines starting with `[service]`, and what both
+//! captured is summed up at the end. The command's exit code is `pipe2`'s, unless the service never got ready.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use pipe2::{Child, Output, Pipe2};
+
+use crate::cli::Cli;
+use crate::report;
+
+/// What `with-service` was told about the service.
+pub struct Service {
+    /// The program and its arguments.
+    pub command: Vec<String>,
+    pub ready: Ready,
+    pub timeout: Duration,
+}
+
+/// How to tell the service is ready.
+pub enum Ready {
+    /// Something accepts TCP connections at this `host:port`.
+    Port(String),
+    /// A `GET` of this URL is answered with this status.
+    Http(String, u16),
+    /// The service sends `READY=1` to its `NOTIFY_SOCKET`.
+    #[cfg(unix)]
+    Notify,
+}
+
+/// What the service's lines start with.
+const LABEL: &str = "service";
+
+/// Runs the service and the command, and returns the exit code `pipe2` has: the command's, or 1 if the service never
+/// got ready.
+pub fn run(cli: &Cli, service: &Service) -> io::Result<i32> {
+    let (program, args) = service
+        .command
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the service has no command"))?;
+    let mut pipe2 = Pipe2::new(program);
+    pipe2.args(args).label(LABEL);
+    match &service.ready {
+        Ready::Port(address) => {
+            pipe2.ready_on_port(address);
+        }
+        Ready::Http(url, status) => {
+            pipe2.ready_on_http(url, *status);
+        }
+        #[cfg(unix)]
+        Ready::Notify => {
+            pipe2.notify_ready(true);
+        }
+    }
+
+    let clock = Instant::now();
+    let mut server = pipe2.spawn()?;
+    let ready = match server.wait_ready(service.timeout) {
+        Ok(true) => true,
+        Ok(false) => {
+            eprintln!(
+                "pipe2: the service isn't ready after {:.3}s, stopping it",
+                service.timeout.as_secs_f64()
+            );
+            false
+        }
+        Err(e) => {
+            eprintln!("pipe2: {LABEL}: {e}");
+            false
+        }
+    };
+    if !ready {
+        let output = stop(server)?;
+        summarize(LABEL, &output);
+        return Ok(1);
+    }
+    eprintln!(
+        "pipe2: the service is ready after {:.3}s",
+        clock.elapsed().as_secs_f64()
+    );
+
+    let mut command = Pipe2::new(&cli.program);
+    command.args(&cli.args);
+    cli.configure(&mut command);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            stop(server)?;
+            return Err(e);
+        }
+    };
+    // NOTE: the service keeps writing while the command runs, so both are drained.
+    loop {
+        server.poll()?;
+        if child.poll()?.is_some() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait()?;
+    let crashed = server.poll()?.is_some();
+    let service_output = stop(server)?;
+
+    eprintln!();
+    summarize("command", &output);
+    summarize(LABEL, &service_output);
+    if crashed {
+        eprintln!("pipe2: the service exited while the command was running");
+    }
+    Ok(crate::exit_code(&output))
+}
+
+/// Stops the service, if it's still running, and drains what's left of its output.
+fn stop(mut server: Child) -> io::Result<Output> {
+    server.kill()?;
+    server.wait()
+}
+
+fn summarize(name: &str, output: &Output) {
+    eprintln!(
+        "pipe2: {name}: {}, {} bytes of stdout, {} bytes of stderr",
+        report::describe(output.status),
+        output.stdout.len(),
+        output.stderr.len()
+    );
+}
+
+/// `[HOST:]PORT`, for `--ready-port`; the host is `127.0.0.1` if left out.
+pub fn parse_ready_port(value: &str) -> Result<String, String> {
+    let invalid = || format!("invalid --ready-port {value:?}");
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+            Ok(value.to_owned())
+        }
+        Some(_) => Err(invalid()),
+        None => {
+            value.parse::<u16>().map_err(|_| invalid())?;
+            Ok(format!("127.0.0.1:{value}"))
+        }
+    }
+}
+
+/// Splits a command line into words at whitespace, the way a shell would without expanding anything: quotes keep
+/// words together, and a backslash outside single quotes takes the next character as it is.
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                let next = chars.next().ok_or("the command ends with a backslash")?;
+                word.get_or_insert_default().push(next);
+            }
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("the command has an unclosed quote".to_owned());
+    }
+    words.extend(word);
+    Ok(words)
+}
@@ -0,0 +1,84 @@
+//! Sorting output lines into errors, warnings and info by what they contain, to color them in the echo and count
+//! them for the [`Output`](crate::Output).
+
+/// How serious a line is, least to most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub(crate) fn color(self) -> &'static str {
+        match self {
+            Self::Info => "\x1b[34m",
+            Self::Warning => "\x1b[33m",
+            Self::Error => "\x1b[31m",
+        }
+    }
+}
+
+/// How many lines of each [`Severity`] the child wrote, on `stdout` and `stderr` together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Severities {
+    pub errors: u64,
+    pub warnings: u64,
+    pub infos: u64,
+}
+
+impl Severities {
+    fn add(&mut self, severity: Severity) {
+        match severity {
+            Severity::Info => self.infos += 1,
+            Severity::Warning => self.warnings += 1,
+            Severity::Error => self.errors += 1,
+        }
+    }
+}
+
+/// Text that marks a line as having a [`Severity`], wherever it is in the line.
+pub(crate) type Classifier = (Severity, String);
+
+/// The most serious severity any of `classifiers` finds in `line`.
+pub(crate) fn classify(classifiers: &[Classifier], line: &[u8]) -> Option<Severity> {
+    classifiers
+        .iter()
+        .filter(|(_, text)| {
+            let text = text.as_bytes();
+            !text.is_empty() && line.windows(text.len()).any(|window| window == text)
+        })
+        .map(|(severity, _)| *severity)
+        .max()
+}
+
+/// Counts one stream's lines by severity as its chunks come in.
+#[derive(Default)]
+pub(crate) struct Counter {
+    /// The start of a line that hasn't ended yet.
+    line: Vec<u8>,
+}
+
+impl Counter {
+    pub(crate) fn feed(
+        &mut self,
+        classifiers: &[Classifier],
+        chunk: &[u8],
+        counts: &mut Severities,
+    ) {
+        for piece in chunk.split_inclusive(|&byte| byte == b'\n') {
+            self.line.extend_from_slice(piece);
+            if piece.ends_with(b"\n") {
+                self.finish(classifiers, counts);
+            }
+        }
+    }
+
+    /// Counts the line in progress, for a stream that's done.
+    pub(crate) fn finish(&mut self, classifiers: &[Classifier], counts: &mut Severities) {
+        if let Some(severity) = classify(classifiers, &self.line) {
+            counts.add(severity);
+        }
+        self.line.clear();
+    }
+}
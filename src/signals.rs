@@ -0,0 +1,121 @@
+//! `--signal`: what pipe2 does with the signals it receives while the child runs, instead of dying of them and
+//! leaving the child behind.
+//!
+//! The handlers only note that a signal came in; acting on it (passing it on, stopping the child) happens in the loop
+//! that polls the child. Signals that aren't listed keep their default behavior.
+//!
+//! With `--pty`, `SIGWINCH` is caught too, and the child's pseudo-terminal is made as big as ours again.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use pipe2::Child;
+
+/// What to do with a signal pipe2 receives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handling {
+    /// Send the child the same signal, and carry on.
+    Forward,
+    /// Stop the child the way `--kill-signal` and `--grace-period` say, and don't restart it.
+    Stop,
+    /// Carry on as if nothing happened.
+    Ignore,
+}
+
+/// One flag per signal number, set by the handler.
+static RECEIVED: [AtomicBool; 65] = [const { AtomicBool::new(false) }; 65];
+
+extern "C" fn handler(signal: libc::c_int) {
+    if let Some(received) = RECEIVED.get(signal as usize) {
+        received.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether `signal` came in since the last look.
+fn take(signal: Signal) -> bool {
+    RECEIVED[signal as usize].swap(false, Ordering::Relaxed)
+}
+
+/// The `--signal` settings, with their handlers installed.
+pub struct Signals {
+    handlings: Vec<(Signal, Handling)>,
+    /// Whether to follow the size of our terminal, for `--pty`.
+    resize: bool,
+    stopping: bool,
+}
+
+impl Signals {
+    /// Installs a handler for each signal in `handlings`, where a later entry for the same signal wins, and for
+    /// `SIGWINCH` if `resize`.
+    pub fn install(handlings: &[(Signal, Handling)], resize: bool) -> io::Result<Self> {
+        // NOTE: even ignored signals get a handler rather than `SIG_IGN`, which the child would inherit.
+        let action = SigAction::new(
+            SigHandler::Handler(handler),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        for &(signal, _) in handlings {
+            unsafe { sigaction(signal, &action) }?;
+        }
+        if resize {
+            unsafe { sigaction(Signal::SIGWINCH, &action) }?;
+        }
+        Ok(Self {
+            handlings: handlings.to_vec(),
+            resize,
+            stopping: false,
+        })
+    }
+
+    /// Acts on whatever came in since the last call.
+    pub fn handle(&mut self, child: &mut Child) -> io::Result<()> {
+        // NOTE: taken out first, so that a `--signal WINCH=...` gets to act on it as well.
+        let mut resized = self.resize && take(Signal::SIGWINCH);
+        if resized && let Some((rows, cols)) = pipe2::terminal_size() {
+            child.resize_pty(rows, cols)?;
+        }
+        for &(signal, handling) in self.handlings.iter().rev() {
+            let received = if signal == Signal::SIGWINCH && self.resize {
+                std::mem::take(&mut resized)
+            } else {
+                take(signal)
+            };
+            if !received {
+                continue;
+            }
+            match handling {
+                Handling::Forward => child.send_signal(signal)?,
+                Handling::Stop => {
+                    eprintln!("pipe2: got {signal}, stopping the child");
+                    self.stopping = true;
+                    child.kill()?;
+                }
+                Handling::Ignore => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a signal set to `stop` came in, after which the child isn't restarted.
+    pub fn stopping(&self) -> bool {
+        self.stopping
+    }
+}
+
+/// `SIG=forward|stop|ignore`, for `--signal`.
+pub fn parse(value: &str) -> Result<(Signal, Handling), String> {
+    let invalid = || format!("invalid --signal {value:?}, expected SIG=forward|stop|ignore");
+    let (signal, handling) = value.split_once('=').ok_or_else(invalid)?;
+    let signal = crate::cli::parse_signal(signal)?;
+    if matches!(signal, Signal::SIGKILL | Signal::SIGSTOP) {
+        return Err(format!("{signal} can't be caught"));
+    }
+    let handling = match handling {
+        "forward" => Handling::Forward,
+        "stop" => Handling::Stop,
+        "ignore" => Handling::Ignore,
+        _ => return Err(invalid()),
+    };
+    Ok((signal, handling))
+}
@@ -0,0 +1,553 @@
+//! Feeding the child's `stdin` from a file or from bytes we have, with the same non-blocking writes as the channel:
+//! the child takes what it has room for on every poll, and never stalls us while it's busy.
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+use std::os::windows::io::OwnedHandle;
+#[cfg(unix)]
+use std::process::ChildStdin;
+
+use crate::clock::Clock;
+
+/// Files at least this large get progress lines while they're fed, when [`Pipe2::stdin_progress`] is on.
+///
+/// [`Pipe2::stdin_progress`]: crate::Pipe2::stdin_progress
+const PROGRESS_THRESHOLD: u64 = 16 * 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the child's `stdin` gets closed, so it sees EOF; many children only exit once they do.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StdinClose {
+    /// Right after the spawn, before anything is written, like `< /dev/null`.
+    Immediately,
+    /// Once all of the input has been written.
+    #[default]
+    AfterInput,
+    /// Once the pattern shows up in the child's `stdout` or `stderr`; whatever input is left by then is dropped.
+    OnOutput(Vec<u8>),
+    /// Only when the child exits.
+    Never,
+}
+
+/// How a [`StdinSource`] gets fed.
+#[derive(Clone, Default)]
+pub(crate) struct Feeding {
+    pub(crate) progress: bool,
+    /// At most this many bytes a second.
+    pub(crate) rate: Option<u64>,
+    /// How long to wait after each line before starting on the next one.
+    pub(crate) line_delay: Option<Duration>,
+    pub(crate) close: StdinClose,
+}
+
+/// What the child reads on `stdin`; ours is inherited unless one of these is set.
+#[derive(Clone)]
+pub(crate) enum StdinSource {
+    /// Nothing: the child sees EOF right away.
+    Null,
+    Bytes(Arc<[u8]>),
+    File(PathBuf),
+    /// What another child wrote, as it comes, see [`Pipeline`](crate::Pipeline).
+    Queue(StdinQueue),
+    /// One after the other, see [`Pipe2::stdin_sources`](crate::Pipe2::stdin_sources).
+    Parts(Arc<[StdinPart]>),
+}
+
+/// One of the sources [`Pipe2::stdin_sources`](crate::Pipe2::stdin_sources) feeds the child's `stdin` from, in turn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StdinPart {
+    /// The contents of a file, opened before the child is spawned so that a missing one doesn't leave it running.
+    File(PathBuf),
+    Bytes(Vec<u8>),
+    /// What a program writes on `stdout`, run with these arguments once the parts before this one are through; its
+    /// `stderr` is ours. It fails if it can't be started, or doesn't exit with 0.
+    Command(OsString, Vec<OsString>),
+}
+
+/// `file data.csv`, `12 bytes` or `command gen --header`, for saying which part failed.
+impl fmt::Display for StdinPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file {}", path.display()),
+            Self::Bytes(bytes) => write!(f, "{} bytes", bytes.len()),
+            Self::Command(program, args) => {
+                write!(f, "command {}", program.to_string_lossy())?;
+                for arg in args {
+                    write!(f, " {}", arg.to_string_lossy())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl StdinSource {
+    /// Opens whatever gets fed to the child, before it's spawned so that a missing file doesn't leave it running.
+    /// `None` for [`StdinSource::Null`].
+    pub(crate) fn open(&self, feeding: &Feeding) -> io::Result<Option<Input>> {
+        let (source, size): (Box<dyn Read + Send>, u64) = match self {
+            Self::Null => return Ok(None),
+            Self::Bytes(bytes) => (Box::new(io::Cursor::new(bytes.clone())), bytes.len() as u64),
+            Self::File(path) => {
+                let file = open(path)?;
+                let size = file.metadata()?.len();
+                (Box::new(file), size)
+            }
+            Self::Queue(queue) => (Box::new(queue.clone()), 0),
+            Self::Parts(parts) => {
+                let mut size = 0;
+                let mut opened = VecDeque::new();
+                for part in parts.iter() {
+                    let source = match part {
+                        StdinPart::File(path) => {
+                            let file = open(path)?;
+                            size += file.metadata()?.len();
+                            Part::Reader(Box::new(file))
+                        }
+                        StdinPart::Bytes(bytes) => {
+                            size += bytes.len() as u64;
+                            Part::Reader(Box::new(io::Cursor::new(bytes.clone())))
+                        }
+                        StdinPart::Command(program, args) => {
+                            Part::Command(program.clone(), args.clone())
+                        }
+                    };
+                    opened.push_back((part.to_string(), source));
+                }
+                (
+                    Box::new(Concat {
+                        parts: opened,
+                        done: 0,
+                    }),
+                    size,
+                )
+            }
+        };
+        Ok(Some(Input {
+            source,
+            size,
+            feeding: feeding.clone(),
+        }))
+    }
+
+    /// The handle to give the child as its `stdin`, and the feeder for our end of it.
+    ///
+    /// NOTE: the anonymous pipes std creates block on writes once they're full, so the child gets a named pipe
+    /// instead, with our end in `PIPE_NOWAIT` mode like the channel's.
+    #[cfg(windows)]
+    pub(crate) fn windows_stdin(
+        &self,
+        feeding: &Feeding,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<(OwnedHandle, Option<Feeder>)> {
+        match self.open(feeding)? {
+            None => Ok((File::open("NUL")?.into(), None)),
+            Some(input) => {
+                let (ours, theirs) = crate::windows_pipe_utils::outbound_pipe()?;
+                Ok((theirs, Some(input.feed(File::from(ours), clock))))
+            }
+        }
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("couldn't open {}: {e}", path.display())))
+}
+
+/// The [`StdinPart`]s, read through one after the other.
+struct Concat {
+    /// What's left, each with what it's called in errors.
+    parts: VecDeque<(String, Part)>,
+    /// How many parts are through, to number the one that fails.
+    done: usize,
+}
+
+enum Part {
+    Reader(Box<dyn Read + Send>),
+    /// Not started yet.
+    Command(OsString, Vec<OsString>),
+    Running(CommandOutput),
+}
+
+impl Read for Concat {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some((name, part)) = self.parts.front_mut() {
+            let read = match part {
+                Part::Reader(reader) => reader.read(buf),
+                Part::Command(program, args) => match CommandOutput::start(program, args) {
+                    Ok(output) => {
+                        *part = Part::Running(output);
+                        continue;
+                    }
+                    Err(e) => Err(e),
+                },
+                Part::Running(output) => output.read(buf),
+            };
+            match read {
+                Ok(0) => {
+                    self.parts.pop_front();
+                    self.done += 1;
+                }
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(e),
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("stdin source {} ({name}) failed: {e}", self.done + 1),
+                    ));
+                }
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// A [`StdinPart::Command`]'s `stdout`, read on a thread of its own so that waiting for it never holds up the poll.
+/// An empty chunk is the end of it, once the command exited with 0.
+struct CommandOutput {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    offset: usize,
+}
+
+impl CommandOutput {
+    fn start(program: &OsString, args: &[OsString]) -> io::Result<Self> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        // NOTE: bounded, so a command that's faster than the child waits for it like it would on a pipe.
+        let (sender, chunks) = mpsc::sync_channel(16);
+        thread::spawn(move || {
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let chunk = match stdout.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => Ok(buffer[..n].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                // NOTE: if the child is gone, or this failed, there's no point in the command going on.
+                let failed = chunk.is_err();
+                if sender.send(chunk).is_err() || failed {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return;
+                }
+            }
+            let end = match child.wait() {
+                Ok(status) if status.success() => Ok(Vec::new()),
+                Ok(status) => Err(io::Error::other(format!("it failed ({status})"))),
+                Err(e) => Err(e),
+            };
+            let _ = sender.send(end);
+        });
+        Ok(Self {
+            chunks,
+            chunk: Vec::new(),
+            offset: 0,
+        })
+    }
+}
+
+impl Read for CommandOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.chunk.len() {
+            self.chunk = match self.chunks.try_recv() {
+                Ok(Ok(chunk)) if chunk.is_empty() => return Ok(0),
+                Ok(chunk) => chunk?,
+                Err(TryRecvError::Empty) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(io::Error::other("its output stopped short"));
+                }
+            };
+            self.offset = 0;
+        }
+        let n = buf.len().min(self.chunk.len() - self.offset);
+        buf[..n].copy_from_slice(&self.chunk[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+/// Bytes on their way to the child's `stdin`, pushed as they come rather than known up front. Reading it when it's
+/// empty but not closed yet fails with [`io::ErrorKind::WouldBlock`], so the feeder tries again on the next poll.
+#[derive(Clone, Default)]
+pub(crate) struct StdinQueue(Arc<Mutex<Queued>>);
+
+#[derive(Default)]
+struct Queued {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl StdinQueue {
+    fn lock(&self) -> MutexGuard<'_, Queued> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn push(&self, bytes: &[u8]) {
+        self.lock().bytes.extend(bytes);
+    }
+
+    /// No more is coming: once what's queued has been read, the child's `stdin` is at EOF.
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+    }
+
+    /// How much is waiting for the child to read it.
+    pub(crate) fn len(&self) -> usize {
+        self.lock().bytes.len()
+    }
+}
+
+impl Read for StdinQueue {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut queued = self.lock();
+        if queued.bytes.is_empty() && !queued.closed {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        queued.bytes.read(buf)
+    }
+}
+
+/// An opened [`StdinSource`], waiting for the child's pipe.
+pub(crate) struct Input {
+    source: Box<dyn Read + Send>,
+    size: u64,
+    feeding: Feeding,
+}
+
+impl Input {
+    /// Starts feeding our end of the child's `stdin`, which has to be in non-blocking mode already on Unix, pacing it
+    /// by `clock`.
+    pub(crate) fn feed(
+        self,
+        #[cfg(unix)] pipe: ChildStdin,
+        #[cfg(windows)] pipe: File,
+        clock: Arc<dyn Clock>,
+    ) -> Feeder {
+        let now = clock.now();
+        Feeder {
+            source: self.source,
+            pipe,
+            buffer: vec![0u8; 64 * 1024],
+            offset: 0,
+            len: 0,
+            size: self.size,
+            written: 0,
+            progress: (self.feeding.progress && self.size >= PROGRESS_THRESHOLD).then_some(now),
+            rate: self.feeding.rate.map(|rate| Bucket::new(rate, now)),
+            line_delay: self.feeding.line_delay,
+            next_line: now,
+            exhausted: false,
+            tails: Default::default(),
+            close: self.feeding.close,
+            failure: None,
+            clock,
+        }
+    }
+}
+
+/// Pushes a [`StdinSource`] into the child's `stdin`, a buffer at a time.
+pub(crate) struct Feeder {
+    source: Box<dyn Read + Send>,
+    #[cfg(unix)]
+    pipe: ChildStdin,
+    #[cfg(windows)]
+    pipe: File,
+    buffer: Vec<u8>,
+    /// How much of `buffer` the child has taken already.
+    offset: usize,
+    len: usize,
+    size: u64,
+    written: u64,
+    /// When the last progress line went out, if there are any to print.
+    progress: Option<Instant>,
+    rate: Option<Bucket>,
+    line_delay: Option<Duration>,
+    /// Not before then for the next line, with a `line_delay`.
+    next_line: Instant,
+    /// Whether everything has been read from the source.
+    exhausted: bool,
+    /// The end of what came on `stdout` and `stderr` so far, for an [`StdinClose::OnOutput`] pattern that's split
+    /// across reads.
+    tails: [Vec<u8>; 2],
+    close: StdinClose,
+    /// Why the source couldn't be read to the end, if it couldn't.
+    failure: Option<io::Error>,
+    /// What the [`Feeding::rate`] and the [`Feeding::line_delay`] go by, see [`Pipe2::clock`](crate::Pipe2::clock).
+    clock: Arc<dyn Clock>,
+}
+
+/// Bytes the [`Feeding::rate`] allows writing right now, refilled as time goes by.
+struct Bucket {
+    rate: u64,
+    available: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            available: 0.0,
+            refilled: now,
+        }
+    }
+
+    fn available(&mut self, now: Instant) -> usize {
+        // NOTE: capped at a tenth of a second's worth, so a child that didn't read for a while doesn't then get a
+        // burst of everything it missed.
+        let cap = (self.rate as f64 / 10.0).max(1.0);
+        let refill = now.duration_since(self.refilled).as_secs_f64() * self.rate as f64;
+        self.available = (self.available + refill).min(cap);
+        self.refilled = now;
+        self.available as usize
+    }
+
+    fn take(&mut self, n: usize) {
+        self.available -= n as f64;
+    }
+}
+
+impl Feeder {
+    /// How many bytes the child has taken so far.
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Why the source failed partway, once [`Feeder::pump`] said to close the child's `stdin` because of it.
+    pub(crate) fn take_failure(&mut self) -> Option<io::Error> {
+        self.failure.take()
+    }
+
+    /// Looks for the [`StdinClose::OnOutput`] pattern in a chunk the child wrote on stream `index` (0 for `stdout`,
+    /// 1 for `stderr`).
+    pub(crate) fn observe(&mut self, index: usize, chunk: &[u8]) {
+        let StdinClose::OnOutput(pattern) = &self.close else {
+            return;
+        };
+        if chunk.is_empty() || pattern.is_empty() {
+            return;
+        }
+        let tail = &mut self.tails[index];
+        tail.extend_from_slice(chunk);
+        if tail.windows(pattern.len()).any(|window| window == pattern) {
+            self.close = StdinClose::Immediately;
+            return;
+        }
+        let keep = tail.len().min(pattern.len() - 1);
+        tail.drain(..tail.len() - keep);
+    }
+
+    /// Writes as much as the child has room for right now. Returns `true` once it's time to close the child's
+    /// `stdin`, see [`StdinClose`], the child closed it already, or the source failed (see [`Feeder::take_failure`]);
+    /// dropping the feeder then closes the pipe, so the child sees EOF.
+    pub(crate) fn pump(&mut self) -> io::Result<bool> {
+        loop {
+            // NOTE: `Immediately` never gets this far from the builder; it's what `OnOutput` turns into once the
+            // pattern has been seen.
+            if self.close == StdinClose::Immediately {
+                return Ok(true);
+            }
+            if self.offset == self.len {
+                if self.exhausted {
+                    return Ok(false);
+                }
+                self.len = match self.source.read(&mut self.buffer) {
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                    Err(e) => {
+                        self.failure = Some(e);
+                        return Ok(true);
+                    }
+                };
+                self.offset = 0;
+                if self.len == 0 {
+                    self.exhausted = true;
+                    self.report_progress(true)?;
+                    return Ok(self.close == StdinClose::AfterInput);
+                }
+            }
+
+            let now = self.clock.now();
+            if now < self.next_line {
+                return Ok(false);
+            }
+            let mut chunk = &self.buffer[self.offset..self.len];
+            if let Some(rate) = &mut self.rate {
+                chunk = &chunk[..chunk.len().min(rate.available(now))];
+                if chunk.is_empty() {
+                    return Ok(false);
+                }
+            }
+            if self.line_delay.is_some()
+                && let Some(end) = chunk.iter().position(|&b| b == b'\n')
+            {
+                chunk = &chunk[..=end];
+            }
+            let ends_line = self.line_delay.is_some() && chunk.last() == Some(&b'\n');
+            #[cfg(unix)]
+            let written = self.pipe.write(chunk);
+            #[cfg(windows)]
+            let written = crate::windows_pipe_utils::write_pipe(&mut self.pipe, chunk);
+            let written = match written {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+                // NOTE: a child that stops reading before the end is fine; whatever it didn't want is dropped.
+                Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(true),
+                Err(e) => return Err(e),
+            };
+            if written == 0 {
+                return Ok(false);
+            }
+            if let Some(rate) = &mut self.rate {
+                rate.take(written);
+            }
+            if let Some(delay) = self.line_delay
+                && ends_line
+                && written == chunk.len()
+            {
+                self.next_line = self.clock.now() + delay;
+            }
+            self.offset += written;
+            self.written += written as u64;
+            self.report_progress(false)?;
+        }
+    }
+
+    /// Prints `pipe2: stdin 42% (11.2 of 26.6 MiB)` to `stderr` every so often, and once more at the end.
+    fn report_progress(&mut self, done: bool) -> io::Result<()> {
+        let Some(last) = self.progress else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        if !done && now.saturating_duration_since(last) < PROGRESS_INTERVAL {
+            return Ok(());
+        }
+        self.progress = (!done).then_some(now);
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        writeln!(
+            io::stderr(),
+            "pipe2: stdin {}% ({:.1} of {:.1} MiB)",
+            self.written * 100 / self.size.max(1),
+            mib(self.written),
+            mib(self.size)
+        )
+    }
+}
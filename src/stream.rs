@@ -0,0 +1,144 @@
+//! Transports the child writes into and we drain, without ever blocking on them.
+
+use std::io;
+use std::process::{ChildStderr, ChildStdout, Stdio};
+use std::time::Duration;
+
+#[cfg(unix)]
+use nix::fcntl::{FcntlArg, OFlag, fcntl};
+#[cfg(unix)]
+use std::io::Read;
+#[cfg(unix)]
+use std::os::fd::AsFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[cfg(windows)]
+use std::os::windows::io::AsRawHandle;
+
+/// Where the child's `stdout` or `stderr` goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Disposition {
+    /// Into a pipe we drain, capturing it and echoing it if that's on.
+    #[default]
+    Capture,
+    /// Straight to our own, untouched, like a TUI needs to own the terminal; it isn't captured then.
+    Inherit,
+    /// Nowhere, like `> /dev/null`.
+    Null,
+}
+
+impl Disposition {
+    pub(crate) fn stdio(self) -> Stdio {
+        match self {
+            Self::Capture => Stdio::piped(),
+            Self::Inherit => Stdio::inherit(),
+            Self::Null => Stdio::null(),
+        }
+    }
+}
+
+/// One end of a transport the capture loop reads from: anonymous pipes, socketpair halves, PTY masters and named pipes
+/// all go through this, so the loop doesn't care which one it's looking at.
+pub(crate) trait ChildStream {
+    /// Reads whatever is available right now. `Ok(None)` if there's nothing yet, `Ok(Some(0))` once the writing side
+    /// is gone.
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>>;
+}
+
+/// NOTE: on Unix, the descriptor has to be in non-blocking mode already, see [`nonblocking`].
+#[cfg(unix)]
+fn read_available<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<Option<usize>> {
+    match reader.read(buf) {
+        Ok(n) => Ok(Some(n)),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// NOTE: on Windows, going into `ReadFile` without data being there blocks regardless of the handle's mode, so it's
+/// only done once `PeekNamedPipe` says there's something to read.
+#[cfg(windows)]
+fn read_available<R: AsRawHandle>(reader: &mut R, buf: &mut [u8]) -> io::Result<Option<usize>> {
+    use crate::windows_pipe_utils::*;
+    match peek(reader)? {
+        None => Ok(Some(0)),
+        Some(0) => Ok(None),
+        Some(_) => read_pipe(reader, buf).map(Some),
+    }
+}
+
+macro_rules! impl_child_stream {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl ChildStream for $ty {
+                fn read_available(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+                    read_available(self, buf)
+                }
+            }
+        )*
+    };
+}
+
+impl_child_stream!(ChildStdout, ChildStderr, io::PipeReader, std::fs::File);
+#[cfg(unix)]
+impl_child_stream!(UnixStream);
+
+#[cfg(windows)]
+impl ChildStream for crate::windows_pipe_utils::NamedPipe {
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        match crate::windows_pipe_utils::peek(self)? {
+            None => Ok(Some(0)),
+            Some(0) => Ok(None),
+            Some(_) => self.read_overlapped(buf).map(Some),
+        }
+    }
+}
+
+/// One of the child's output streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stream {
+    /// `stdout`, or both streams with [`Pipe2::merge_output`](crate::Pipe2::merge_output).
+    Stdout,
+    Stderr,
+}
+
+/// What happens when the child closes its `stdout` and `stderr` but keeps running, like a daemon that redirects its
+/// output elsewhere once it's up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputClosed {
+    /// Keep waiting for the child to exit.
+    #[default]
+    Wait,
+    /// Stop waiting, and leave the child running; the run counts as a success.
+    Detach,
+    /// Kill the child (see [`Child::kill`](crate::Child::kill)) if it's still running this long after.
+    Timeout(Duration),
+}
+
+/// Where the capture loop is at with one of the child's streams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum StreamState {
+    /// Still being read.
+    Open,
+    /// The writing side is gone, and everything it wrote was read.
+    Eof,
+    /// Reading it failed; the error was returned, and it isn't read anymore.
+    Error,
+}
+
+/// Stands in for a stream the child doesn't have, like `stderr` when it shares `stdout`'s pipe.
+pub(crate) struct Closed;
+
+impl ChildStream for Closed {
+    fn read_available(&mut self, _buf: &mut [u8]) -> io::Result<Option<usize>> {
+        Ok(Some(0))
+    }
+}
+
+/// Puts the descriptor in non-blocking mode, as [`ChildStream`] expects on Unix.
+#[cfg(unix)]
+pub(crate) fn nonblocking<F: AsFd>(fd: F) -> io::Result<F> {
+    fcntl(&fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    Ok(fd)
+}
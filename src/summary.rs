@@ -0,0 +1,99 @@
+//! One line per run, for when a single program runs several times or several programs run, and how they went
+//! has to be seen at a glance.
+
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use crate::child::Output;
+
+/// How one run went, as a line of [`RunSummary::table`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunSummary {
+    /// What the run is known by, like its command line or the item it ran for.
+    pub name: String,
+    pub status: ExitStatus,
+    /// Whether the child was killed for running too long, by any of the timeouts.
+    pub timed_out: bool,
+    pub duration: Duration,
+    pub stdout_bytes: usize,
+    pub stderr_bytes: usize,
+    /// How many times the program was started again after exiting, on top of the first run.
+    pub restarts: u32,
+}
+
+impl RunSummary {
+    /// Sums up the run that produced `output`, which took `duration`, with no restarts.
+    pub fn new<S: Into<String>>(name: S, output: &Output, duration: Duration) -> Self {
+        Self {
+            name: name.into(),
+            status: output.status,
+            timed_out: output.timed_out
+                || output.first_output_timed_out
+                || output.output_closed_timed_out,
+            duration,
+            stdout_bytes: output.stdout.len(),
+            stderr_bytes: output.stderr.len(),
+            restarts: 0,
+        }
+    }
+
+    /// The exit code, `signal N` for a child killed by a signal, and `timeout` for one killed by a timeout.
+    pub fn exit(&self) -> String {
+        if self.timed_out {
+            return "timeout".to_owned();
+        }
+        if let Some(code) = self.status.code() {
+            return code.to_string();
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = self.status.signal() {
+                return format!("signal {signal}");
+            }
+        }
+        self.status.to_string()
+    }
+
+    /// `summaries` as a table with a header line and one line per run, its columns aligned, and numbers aligned to
+    /// the right.
+    pub fn table(summaries: &[RunSummary]) -> String {
+        const HEADER: [&str; 6] = ["NAME", "EXIT", "DURATION", "STDOUT", "STDERR", "RESTARTS"];
+
+        let rows: Vec<[String; 6]> = summaries
+            .iter()
+            .map(|summary| {
+                [
+                    summary.name.clone(),
+                    summary.exit(),
+                    format!("{:.3}s", summary.duration.as_secs_f64()),
+                    summary.stdout_bytes.to_string(),
+                    summary.stderr_bytes.to_string(),
+                    summary.restarts.to_string(),
+                ]
+            })
+            .collect();
+        let mut widths = HEADER.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut table = String::new();
+        let header = HEADER.map(str::to_owned);
+        for row in std::iter::once(&header).chain(&rows) {
+            let mut line = String::new();
+            for (column, (cell, width)) in row.iter().zip(widths).enumerate() {
+                let pad = " ".repeat(width - cell.chars().count());
+                match column {
+                    0 | 1 => line.push_str(&format!("{cell}{pad}  ")),
+                    _ => line.push_str(&format!("{pad}{cell}  ")),
+                }
+            }
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
@@ -0,0 +1,111 @@
+//! `--tap`: runs one or more commands, separated by `:::`, as a TAP (Test Anything Protocol) test suite on `stdout`,
+//! for `prove` and other TAP harnesses.
+//!
+//! Each command is a test that passes if it exits with 0, and gets its exit, duration and captured output as YAML
+//! diagnostics. With `--summary`, a table of every run follows on `stderr`.
+
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use pipe2::{Pipe2, RunSummary};
+
+use crate::cli::Cli;
+
+/// Separates the commands, like in GNU parallel.
+pub const SEPARATOR: &str = ":::";
+
+/// Runs every command in turn, and returns 0 if they all passed.
+pub fn run(cli: &Cli) -> i32 {
+    let words = std::iter::once(&cli.program).chain(&cli.args);
+    let mut commands: Vec<Vec<&OsString>> = vec![Vec::new()];
+    for word in words {
+        match commands.last_mut() {
+            Some(command) if word != SEPARATOR => command.push(word),
+            _ => commands.push(Vec::new()),
+        }
+    }
+    commands.retain(|command| !command.is_empty());
+
+    println!("TAP version 13\n1..{}", commands.len());
+    let mut failed = false;
+    let mut summaries = Vec::new();
+    for (number, command) in commands.iter().enumerate() {
+        let name = command
+            .iter()
+            .map(|word| word.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut pipe2 = Pipe2::new(command[0]);
+        pipe2.args(&command[1..]);
+        cli.configure(&mut pipe2);
+        // NOTE: the output only goes into the diagnostics; relayed as is, it could pass for TAP lines of its own.
+        pipe2.echo(false);
+
+        let clock = Instant::now();
+        let (ok, mut diagnostics) = match pipe2.run() {
+            Ok(output) => {
+                summaries.push(RunSummary::new(&name, &output, clock.elapsed()));
+                let mut diagnostics = vec![
+                    format!("duration_ms: {}", clock.elapsed().as_millis()),
+                    match output.status.code() {
+                        Some(code) => format!("exit_code: {code}"),
+                        None => format!("exit: {}", quote(&output.status.to_string())),
+                    },
+                ];
+                if output.timed_out {
+                    diagnostics.push("timed_out: true".to_owned());
+                }
+                if output.first_output_timed_out {
+                    diagnostics.push("first_output_timed_out: true".to_owned());
+                }
+                if output.output_closed_timed_out {
+                    diagnostics.push("output_closed_timed_out: true".to_owned());
+                }
+                if output.cpu_limit_exceeded {
+                    diagnostics.push("cpu_limit_exceeded: true".to_owned());
+                }
+                for (key, captured) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+                    if !captured.is_empty() {
+                        diagnostics.push(block(key, &String::from_utf8_lossy(captured)));
+                    }
+                }
+                (output.success, diagnostics)
+            }
+            Err(e) => (false, vec![format!("error: {}", quote(&e.to_string()))]),
+        };
+        failed |= !ok;
+
+        let status = if ok { "ok" } else { "not ok" };
+        println!("{status} {} - {}", number + 1, name.replace('#', "\\#"));
+        diagnostics.insert(0, "---".to_owned());
+        diagnostics.push("...".to_owned());
+        for line in diagnostics {
+            println!("  {line}");
+        }
+        let _ = io::stdout().flush();
+    }
+    if cli.summary {
+        eprint!("{}", RunSummary::table(&summaries));
+    }
+    i32::from(failed)
+}
+
+/// A double-quoted YAML string.
+fn quote(text: &str) -> String {
+    serde_json::to_string(text).unwrap_or_default()
+}
+
+/// `key: |` with `text` indented under it, minus the control characters YAML doesn't allow there.
+fn block(key: &str, text: &str) -> String {
+    let text: String = text
+        .chars()
+        .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+        .collect();
+    let mut block = format!("{key}: |");
+    for line in text.lines() {
+        block.push_str("\n    ");
+        block.push_str(line);
+    }
+    block
+}
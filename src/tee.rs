@@ -0,0 +1,51 @@
+//! `--tee FILE`: a copy of everything the child writes to `stdout` and `stderr`, in the order it came, written to a file
+//! on a thread of its own, so that a slow disk doesn't hold up the child or the echo.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use pipe2::{Child, Event};
+
+/// How far a tee can fall behind before it starts missing output.
+const CAPACITY: usize = 16 * 1024 * 1024;
+
+/// A file that's opened once, and that every run of the child (see `--restart`) is appended to.
+pub struct Tee {
+    path: PathBuf,
+    file: File,
+}
+
+impl Tee {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            file: File::create(path)?,
+        })
+    }
+
+    /// Starts copying what `child` writes from now on; the thread is done once the child is.
+    pub fn start(&self, child: &mut Child) -> io::Result<JoinHandle<()>> {
+        let (mut file, path) = (self.file.try_clone()?, self.path.clone());
+        let subscriber = child.subscribe(CAPACITY);
+        Ok(thread::spawn(move || {
+            while let Some(event) = subscriber.recv() {
+                let (Event::Stdout(chunk) | Event::Stderr(chunk)) = event else {
+                    continue;
+                };
+                if let Err(e) = file.write_all(&chunk) {
+                    eprintln!("pipe2: couldn't write to {}: {e}", path.display());
+                    return;
+                }
+            }
+            let missed = subscriber.missed();
+            if missed > 0 {
+                eprintln!(
+                    "pipe2: {} is missing {missed} bytes of output, writing it couldn't keep up",
+                    path.display()
+                );
+            }
+        }))
+    }
+}
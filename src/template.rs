@@ -0,0 +1,190 @@
+//! `--set`, `--values` and `--template`: `{name}` placeholders in the program and its arguments (or in a task's
+//! settings), filled in before anything runs.
+//!
+//! Values come from `--set name=value` first, then `--values` files, then the environment; a placeholder with none
+//! is an error rather than an empty string. `{{` and `}}` stand for literal braces, and `{}` is left as it is, for
+//! `each` to fill in with its item, or for `find`. Placeholders are only filled in once one of the options is given.
+
+use std::ffi::OsString;
+use std::fs;
+
+#[derive(Default)]
+pub struct Variables {
+    /// In the order given, so a later `--set` or file wins.
+    values: Vec<(String, String)>,
+    /// Whether placeholders are filled in at all.
+    pub enabled: bool,
+}
+
+impl Variables {
+    /// A `name=value` from `--set`.
+    pub fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("--set expects NAME=VALUE, not {assignment:?}"))?;
+        self.values.push((name.to_owned(), value.to_owned()));
+        self.enabled = true;
+        Ok(())
+    }
+
+    /// A `--values` file: one `name=value` per line, with `#` starting a comment line.
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+        let mut values = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{path}:{}: expected NAME=VALUE", number + 1))?;
+            values.push((name.trim().to_owned(), value.trim().to_owned()));
+        }
+        // NOTE: `--set` wins over files whichever came first, so files go in front of everything set so far.
+        let set = std::mem::replace(&mut self.values, values);
+        self.values.extend(set);
+        self.enabled = true;
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        let set = self.values.iter().rev().find(|(key, _)| key == name);
+        set.map(|(_, value)| value.clone())
+            .or_else(|| std::env::var(name).ok())
+    }
+
+    /// `text` with its placeholders filled in, or as is if templating isn't on.
+    pub fn fill(&self, text: &str) -> Result<String, String> {
+        if !self.enabled {
+            return Ok(text.to_owned());
+        }
+        let mut filled = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(['{', '}']) {
+            filled.push_str(&rest[..start]);
+            let brace = &rest[start..start + 1];
+            rest = &rest[start + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                filled.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err(format!(
+                    "unmatched `}}` in {text:?}; use `}}}}` for a literal one"
+                ));
+            }
+            let end = rest.find('}').ok_or_else(|| {
+                format!("unclosed `{{` in {text:?}; use `{{{{` for a literal one")
+            })?;
+            let name = &rest[..end];
+            if name.is_empty() {
+                filled.push_str("{}");
+                rest = &rest[end + 1..];
+                continue;
+            }
+            let value = self
+                .get(name)
+                .ok_or_else(|| format!("no value for {{{name}}} in {text:?}"))?;
+            filled.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        filled.push_str(rest);
+        Ok(filled)
+    }
+
+    /// Like [`Variables::fill`], for arguments; ones that aren't UTF-8 are left alone.
+    pub fn fill_os(&self, arg: OsString) -> Result<OsString, String> {
+        match arg.to_str() {
+            Some(text) => self.fill(text).map(OsString::from),
+            None => Ok(arg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(assignments: &[&str]) -> Variables {
+        let mut variables = Variables::default();
+        for assignment in assignments {
+            variables.set(assignment).unwrap();
+        }
+        variables
+    }
+
+    fn values_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("pipe2-values-{name}-{}", std::process::id()));
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn fills_in() {
+        let variables = set(&["x=1", "name=world"]);
+        assert_eq!(
+            variables.fill("hello {name}, {x}{x}").unwrap(),
+            "hello world, 11"
+        );
+        assert_eq!(
+            variables.fill("no placeholders").unwrap(),
+            "no placeholders"
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let variables = Variables::default();
+        assert_eq!(variables.fill("{x} {} }{").unwrap(), "{x} {} }{");
+    }
+
+    #[test]
+    fn escapes() {
+        let variables = set(&["x=1"]);
+        assert_eq!(variables.fill("{{x}} {{{x}}}").unwrap(), "{x} {1}");
+        assert_eq!(variables.fill("}}{{").unwrap(), "}{");
+    }
+
+    #[test]
+    fn leaves_empty_braces() {
+        let variables = set(&["x=1"]);
+        assert_eq!(variables.fill("echo {x} {}").unwrap(), "echo 1 {}");
+        assert_eq!(variables.fill("{}{}").unwrap(), "{}{}");
+    }
+
+    #[test]
+    fn unmatched_braces() {
+        let variables = set(&["x=1"]);
+        assert!(variables.fill("a } b").is_err());
+        assert!(variables.fill("a { b").is_err());
+        assert!(variables.fill("{x").is_err());
+        assert!(variables.fill("{missing}").is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        // NOTE: `PATH` is set wherever the tests run, and the tests don't change the environment.
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(set(&["x=1"]).fill("{PATH}").unwrap(), path);
+
+        let file = values_file("precedence", "# a comment\nPATH = from the file\nx=file\n");
+        let mut variables = Variables::default();
+        variables.load(&file).unwrap();
+        assert_eq!(variables.fill("{PATH}").unwrap(), "from the file");
+
+        // NOTE: `--set` wins over a file, whether it comes before or after it.
+        let mut before = set(&["x=set"]);
+        before.load(&file).unwrap();
+        let mut after = Variables::default();
+        after.load(&file).unwrap();
+        after.set("x=set").unwrap();
+        assert_eq!(before.fill("{x}").unwrap(), "set");
+        assert_eq!(after.fill("{x}").unwrap(), "set");
+
+        // NOTE: among `--set`s, the last one wins.
+        assert_eq!(set(&["x=1", "x=2"]).fill("{x}").unwrap(), "2");
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+//! A low-level trace of the capture loop, for when output seems stuck: every poll, every read and how big it was,
+//! every read that would have blocked, and every stream and run state change, one line each. Unlike the event log,
+//! it's for reading along with pipe2's source, and its lines can change from one version to the next.
+//!
+//! The times go by [`Pipe2::clock`](crate::Pipe2::clock), so with a [`MockClock`](crate::MockClock) the trace of a run
+//! comes out the same every time.
+
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::clock::Clock;
+use crate::events::EventSink;
+use crate::run_id::RunId;
+
+pub(crate) struct Trace {
+    sink: EventSink,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    pid: u32,
+    /// How many times the child was polled, to tell the wakeups apart.
+    polls: u64,
+}
+
+impl Trace {
+    pub(crate) fn new(sink: EventSink, clock: Arc<dyn Clock>, pid: u32, run_id: RunId) -> Self {
+        let started = clock.now();
+        let trace = Self {
+            sink,
+            clock,
+            started,
+            pid,
+            polls: 0,
+        };
+        trace.record(format_args!("spawned, run {run_id}"));
+        trace
+    }
+
+    pub(crate) fn poll(&mut self) {
+        self.polls += 1;
+        self.record(format_args!("poll {}", self.polls));
+    }
+
+    /// Writes `{elapsed} [{pid}] {message}`.
+    ///
+    /// NOTE: like the event log, failing to write the trace doesn't fail the run.
+    pub(crate) fn record(&self, message: fmt::Arguments) {
+        let elapsed = self
+            .clock
+            .now()
+            .saturating_duration_since(self.started)
+            .as_secs_f64();
+        let Ok(mut sink) = self.sink.lock() else {
+            return;
+        };
+        let line = format!("{elapsed:.6} [{}] {message}\n", self.pid);
+        let _ = sink.write_all(line.as_bytes()).and_then(|()| sink.flush());
+    }
+}
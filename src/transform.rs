@@ -0,0 +1,256 @@
+//! Rewriting the output as it's read, before it's captured or echoed: stripping color codes, hiding secrets,
+//! converting from another encoding, or whatever a library user plugs in.
+
+use std::sync::Arc;
+
+use crate::line_limit::LineLimit;
+use crate::on_line::Abort;
+use crate::stream::Stream;
+
+/// One stage of rewriting what the child writes, see [`Pipe2::transform`](crate::Pipe2::transform). Each stream gets
+/// its own, so the state it keeps (like half of a color code) is only ever about one stream.
+pub trait ChunkTransform: Send {
+    /// Appends what `chunk` becomes to `out`. What comes at the end of a chunk may be held back, until the next one
+    /// says what it was part of.
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>);
+
+    /// Appends whatever was held back to `out`, once the stream is over.
+    fn finish(&mut self, _out: &mut Vec<u8>) {}
+}
+
+/// Makes a new stage for each stream of each run, given what aborts that run.
+pub(crate) type Factory = Arc<dyn Fn(Stream, &Abort) -> Box<dyn ChunkTransform> + Send + Sync>;
+
+/// The stages a stream goes through, in order.
+#[derive(Default)]
+pub(crate) struct Pipeline(Vec<Box<dyn ChunkTransform>>);
+
+impl Pipeline {
+    /// The stages made by `factories` for `stream` of the run `abort` stops, followed by cutting long lines short at
+    /// `max_line`.
+    pub(crate) fn new(
+        factories: &[Factory],
+        stream: Stream,
+        max_line: Option<usize>,
+        abort: &Abort,
+    ) -> Self {
+        let mut stages: Vec<Box<dyn ChunkTransform>> =
+            factories.iter().map(|make| make(stream, abort)).collect();
+        if let Some(max) = max_line {
+            stages.push(Box::new(LineLimit::new(max)));
+        }
+        Self(stages)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Appends what `chunk` becomes, once it's gone through every stage, to `out`.
+    pub(crate) fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        transform(&mut self.0, chunk, out);
+    }
+
+    /// Appends what every stage held back to `out`, putting what one stage lets go of through the stages after it.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) {
+        finish(&mut self.0, out);
+    }
+}
+
+fn transform(stages: &mut [Box<dyn ChunkTransform>], chunk: &[u8], out: &mut Vec<u8>) {
+    let Some((first, rest)) = stages.split_first_mut() else {
+        out.extend_from_slice(chunk);
+        return;
+    };
+    if rest.is_empty() {
+        first.transform(chunk, out);
+        return;
+    }
+    let mut between = Vec::new();
+    first.transform(chunk, &mut between);
+    transform(rest, &between, out);
+}
+
+fn finish(stages: &mut [Box<dyn ChunkTransform>], out: &mut Vec<u8>) {
+    let Some((first, rest)) = stages.split_first_mut() else {
+        return;
+    };
+    let mut held = Vec::new();
+    first.finish(&mut held);
+    transform(rest, &held, out);
+    finish(rest, out);
+}
+
+/// Drops ANSI escape sequences (colors, cursor movement, window titles), for output that's read by something other
+/// than a terminal.
+#[derive(Default)]
+pub struct StripAnsi {
+    state: Sequence,
+}
+
+/// How far into an escape sequence [`StripAnsi`] is.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Sequence {
+    #[default]
+    None,
+    /// Right after `ESC`.
+    Started,
+    /// In a `ESC [` sequence, up to its final byte.
+    Csi,
+    /// In a `ESC ]` sequence, up to `BEL` or `ESC \`.
+    Osc,
+    /// Right after an `ESC` in a `ESC ]` sequence.
+    OscEscape,
+}
+
+impl StripAnsi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChunkTransform for StripAnsi {
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        for &byte in chunk {
+            self.state = match (self.state, byte) {
+                (Sequence::None, 0x1b) => Sequence::Started,
+                (Sequence::None, _) => {
+                    out.push(byte);
+                    Sequence::None
+                }
+                (Sequence::Started, b'[') => Sequence::Csi,
+                (Sequence::Started, b']') => Sequence::Osc,
+                (Sequence::Started, _) => Sequence::None,
+                (Sequence::Csi, 0x40..=0x7e) => Sequence::None,
+                (Sequence::Csi, _) => Sequence::Csi,
+                (Sequence::Osc, 0x07) => Sequence::None,
+                (Sequence::Osc, 0x1b) => Sequence::OscEscape,
+                (Sequence::Osc, _) => Sequence::Osc,
+                (Sequence::OscEscape, b'\\') => Sequence::None,
+                (Sequence::OscEscape, _) => Sequence::Osc,
+            };
+        }
+    }
+}
+
+/// Replaces every occurrence of the secrets with `***`, like CI logs mask them, even when one is split across chunks.
+pub struct Redact {
+    secrets: Vec<Vec<u8>>,
+    /// The end of the last chunk, when it could be the start of a secret.
+    held: Vec<u8>,
+}
+
+impl Redact {
+    /// Redacts each of `secrets`; empty ones are left out.
+    pub fn new<I, S>(secrets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        Self {
+            secrets: secrets
+                .into_iter()
+                .map(|secret| secret.as_ref().to_vec())
+                .filter(|secret| !secret.is_empty())
+                .collect(),
+            held: Vec::new(),
+        }
+    }
+}
+
+impl ChunkTransform for Redact {
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        let mut text = std::mem::take(&mut self.held);
+        text.extend_from_slice(chunk);
+        let mut i = 0;
+        'text: while i < text.len() {
+            let rest = &text[i..];
+            for secret in &self.secrets {
+                if rest.starts_with(secret) {
+                    out.extend_from_slice(b"***");
+                    i += secret.len();
+                    continue 'text;
+                }
+            }
+            // NOTE: the rest may be the start of a secret that the next chunk finishes.
+            if self
+                .secrets
+                .iter()
+                .any(|secret| secret.len() > rest.len() && secret.starts_with(rest))
+            {
+                self.held = rest.to_vec();
+                return;
+            }
+            out.push(text[i]);
+            i += 1;
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.held);
+    }
+}
+
+/// Converts Latin-1 (ISO 8859-1) to UTF-8, for older programs that don't write UTF-8.
+#[derive(Default)]
+pub struct Latin1;
+
+impl ChunkTransform for Latin1 {
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        for &byte in chunk {
+            let mut buffer = [0; 2];
+            out.extend_from_slice(char::from(byte).encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+}
+
+/// Converts little-endian UTF-16 to UTF-8, for Windows programs that write it to a pipe, dropping the byte order mark.
+/// What isn't valid UTF-16 becomes `U+FFFD`.
+#[derive(Default)]
+pub struct Utf16Le {
+    /// An odd byte, or a high surrogate, left over from the last chunk.
+    held: Vec<u8>,
+    started: bool,
+}
+
+impl Utf16Le {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChunkTransform for Utf16Le {
+    fn transform(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        self.held.extend_from_slice(chunk);
+        let even = self.held.len() & !1;
+        let mut units: Vec<u16> = self.held[..even]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        self.held.drain(..even);
+        if let Some(&last) = units.last()
+            && (0xd800..0xdc00).contains(&last)
+        {
+            units.pop();
+            self.held.splice(0..0, last.to_le_bytes());
+        }
+        if !self.started && !units.is_empty() {
+            self.started = true;
+            if units[0] == 0xfeff {
+                units.remove(0);
+            }
+        }
+        for c in char::decode_utf16(units) {
+            let mut buffer = [0; 4];
+            let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+            out.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.held.is_empty() {
+            self.held.clear();
+            out.extend_from_slice("\u{fffd}".as_bytes());
+        }
+    }
+}
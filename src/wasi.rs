@@ -0,0 +1,213 @@
+//! Running a WASI module as the child, with wasmtime inside pipe2 rather than as a process of its own; see
+//! [`Pipe2::wasi`](crate::Pipe2::wasi).
+//!
+//! The module runs on a thread of its own, with OS pipes for its stdio like a process would have, so that everything
+//! downstream of the pipes (the echo, the capture, transforms, events) can't tell the difference.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, PipeReader, Write};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::thread::{self, JoinHandle};
+
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+#[cfg(windows)]
+use std::os::windows::io::OwnedHandle;
+#[cfg(windows)]
+use std::os::windows::process::ExitStatusExt;
+
+use wasmtime::{Config, Engine, Linker, Module, Store, Trap};
+use wasmtime_wasi::cli::{InputFile, OutputFile};
+use wasmtime_wasi::p1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
+
+use crate::stream::Disposition;
+
+/// What the module is run with.
+pub(crate) struct Spawn<'a> {
+    /// The `.wasm` file, or its `.wat` text.
+    pub(crate) module: &'a OsStr,
+    pub(crate) args: &'a [OsString],
+    pub(crate) env: Vec<(OsString, OsString)>,
+    /// The directory the module sees as `.`; it sees none without one.
+    pub(crate) dir: Option<&'a Path>,
+    /// What the module reads on `stdin`; ours if `None`.
+    pub(crate) stdin: Option<File>,
+    pub(crate) stdout: Disposition,
+    pub(crate) stderr: Disposition,
+    pub(crate) merge_output: bool,
+}
+
+/// The running module, with our ends of its captured streams.
+pub(crate) struct Spawned {
+    pub(crate) process: WasiProcess,
+    pub(crate) stdout: Option<PipeReader>,
+    pub(crate) stderr: Option<PipeReader>,
+}
+
+/// A module running through [`spawn`], waited on like a process is.
+pub(crate) struct WasiProcess {
+    /// Moving its epoch on interrupts the module.
+    engine: Engine,
+    /// `None` once it's been joined.
+    thread: Option<JoinHandle<ExitStatus>>,
+    status: Option<ExitStatus>,
+}
+
+impl WasiProcess {
+    pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        if let Some(thread) = self.thread.take_if(|thread| thread.is_finished()) {
+            let status = thread
+                .join()
+                .map_err(|_| io::Error::other("the WASI module's thread panicked"))?;
+            self.status = Some(status);
+        }
+        Ok(self.status)
+    }
+
+    /// Traps the module as soon as it's running its own code again; a call into WASI that blocks, like a read from
+    /// `stdin`, has to return first.
+    pub(crate) fn kill(&mut self) -> io::Result<()> {
+        self.engine.increment_epoch();
+        Ok(())
+    }
+}
+
+/// Compiles and instantiates the module, and starts its `_start` on a thread of its own.
+pub(crate) fn spawn(spawn: Spawn) -> io::Result<Spawned> {
+    let invalid =
+        |e: wasmtime::Error| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:#}"));
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    // NOTE: an engine of its own, since moving its epoch on interrupts everything running on it.
+    let engine = Engine::new(&config).map_err(io::Error::other)?;
+    // NOTE: read here rather than by wasmtime, so that a missing module fails like a missing program does.
+    let module = Module::new(&engine, std::fs::read(spawn.module)?).map_err(invalid)?;
+
+    let mut wasi = WasiCtxBuilder::new();
+    let string = |s: &OsStr| s.to_string_lossy().into_owned();
+    wasi.arg(string(spawn.module));
+    for arg in spawn.args {
+        wasi.arg(string(arg));
+    }
+    for (key, val) in &spawn.env {
+        wasi.env(string(key), string(val));
+    }
+    if let Some(dir) = spawn.dir {
+        wasi.preopened_dir(dir, ".", DirPerms::all(), FilePerms::all())
+            .map_err(|e| e.downcast::<io::Error>().unwrap_or_else(io::Error::other))?;
+    }
+    match spawn.stdin {
+        Some(stdin) => wasi.stdin(InputFile::new(stdin)),
+        None => wasi.inherit_stdin(),
+    };
+
+    // NOTE: where a trap is reported, as wasmtime's own CLI does.
+    let mut complaints: Option<Box<dyn Write + Send>> = None;
+    let stdout = match spawn.stdout {
+        Disposition::Capture => {
+            let (reader, writer) = io::pipe()?;
+            let writer = into_file(writer);
+            if spawn.merge_output {
+                complaints = Some(Box::new(writer.try_clone()?));
+                wasi.stderr(OutputFile::new(writer.try_clone()?));
+            }
+            wasi.stdout(OutputFile::new(writer));
+            Some(reader)
+        }
+        Disposition::Inherit => {
+            wasi.inherit_stdout();
+            None
+        }
+        Disposition::Null => None,
+    };
+    let stderr = match spawn.stderr {
+        _ if spawn.merge_output => None,
+        Disposition::Capture => {
+            let (reader, writer) = io::pipe()?;
+            let writer = into_file(writer);
+            complaints = Some(Box::new(writer.try_clone()?));
+            wasi.stderr(OutputFile::new(writer));
+            Some(reader)
+        }
+        Disposition::Inherit => {
+            wasi.inherit_stderr();
+            complaints = Some(Box::new(io::stderr()));
+            None
+        }
+        Disposition::Null => None,
+    };
+
+    let mut linker: Linker<WasiP1Ctx> = Linker::new(&engine);
+    p1::add_to_linker_sync(&mut linker, |wasi| wasi).map_err(io::Error::other)?;
+    let mut store = Store::new(&engine, wasi.build_p1());
+    store.set_epoch_deadline(1);
+    let instance = linker.instantiate(&mut store, &module).map_err(invalid)?;
+    let start = instance
+        .get_typed_func::<(), ()>(&mut store, "_start")
+        .map_err(invalid)?;
+
+    // NOTE: the thread owns the store, and with it the module's ends of the pipes, which close as it returns.
+    let thread = thread::Builder::new()
+        .name("pipe2-wasi".to_owned())
+        .spawn(move || match start.call(&mut store, ()) {
+            Ok(()) => exited(0),
+            Err(e) => {
+                if let Some(exit) = e.downcast_ref::<I32Exit>() {
+                    return exited(exit.0);
+                }
+                if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+                    return killed();
+                }
+                if let Some(complaints) = &mut complaints {
+                    let _ = writeln!(complaints, "Error: {e:#}");
+                }
+                trapped()
+            }
+        })?;
+    Ok(Spawned {
+        process: WasiProcess {
+            engine,
+            thread: Some(thread),
+            status: None,
+        },
+        stdout,
+        stderr,
+    })
+}
+
+/// Either end of a pipe, as a file.
+pub(crate) fn into_file(
+    #[cfg(unix)] end: impl Into<OwnedFd>,
+    #[cfg(windows)] end: impl Into<OwnedHandle>,
+) -> File {
+    File::from(end.into())
+}
+
+/// The module returned, or called `proc_exit`, with `code`.
+fn exited(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    return ExitStatus::from_raw((code & 0xff) << 8);
+    #[cfg(windows)]
+    return ExitStatus::from_raw(code as u32);
+}
+
+/// The module was interrupted by [`WasiProcess::kill`]; it looks like a process that was killed would.
+fn killed() -> ExitStatus {
+    #[cfg(unix)]
+    return ExitStatus::from_raw(libc::SIGKILL);
+    #[cfg(windows)]
+    return ExitStatus::from_raw(1);
+}
+
+/// The module trapped, like on `unreachable`; it looks like a process that aborted would.
+fn trapped() -> ExitStatus {
+    #[cfg(unix)]
+    return ExitStatus::from_raw(libc::SIGABRT);
+    #[cfg(windows)]
+    return ExitStatus::from_raw(3);
+}
@@ -0,0 +1,120 @@
+//! `--watch PATH`: runs the program again whenever something under the watched paths changes, stopping the current
+//! run first, like a minimal `cargo watch`.
+//!
+//! Changes come from the platform's file notifications (inotify, FSEvents, `ReadDirectoryChangesW`) through the
+//! `notify` crate; those in `.git` and `target` directories are ignored.
+
+use std::convert::Infallible;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::{Event, RecursiveMode, Watcher};
+use pipe2::{Child, Pipe2};
+
+/// How long the child goes between polls while nothing changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long the paths have to stay the same after a change before the program is restarted, so that saving a batch
+/// of files (or a build writing them) causes one restart rather than many.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+type Events = Receiver<notify::Result<Event>>;
+
+/// Runs `pipe2` until we're killed, starting it over on every change; only returns if that fails.
+pub fn run(pipe2: &mut Pipe2, paths: &[PathBuf], command: &str) -> io::Result<Infallible> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    for path in paths {
+        watcher
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(|e| io_error(e, path))?;
+    }
+
+    let mut runs = 0;
+    loop {
+        runs += 1;
+        eprintln!("pipe2: [run {runs}] starting {command}");
+        let mut child = Some(pipe2.spawn()?);
+
+        loop {
+            poll(&mut child, runs)?;
+            if changed(&events, POLL_INTERVAL)? {
+                settle(&events, &mut child, runs)?;
+                break;
+            }
+        }
+
+        if let Some(child) = child {
+            eprintln!("pipe2: [run {runs}] stopping for changes");
+            stop(child)?;
+        }
+    }
+}
+
+/// Polls the child, if it's still running, saying so once it exits.
+fn poll(child: &mut Option<Child>, runs: u32) -> io::Result<()> {
+    if let Some(running) = child
+        && let Some(status) = running.poll()?
+    {
+        *child = None;
+        eprintln!("pipe2: [run {runs}] exited with {status}, waiting for changes");
+    }
+    Ok(())
+}
+
+/// Waits for the changes to stop, polling the child all the while so that its output keeps flowing.
+fn settle(events: &Events, child: &mut Option<Child>, runs: u32) -> io::Result<()> {
+    let mut quiet_since = Instant::now();
+    while quiet_since.elapsed() < DEBOUNCE {
+        poll(child, runs)?;
+        if changed(events, POLL_INTERVAL)? {
+            quiet_since = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+/// Whether anything that counts changed within `timeout`; the events that don't count are drained along the way.
+fn changed(events: &Events, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let event = match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Ok(false),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("the file watcher stopped"));
+            }
+        };
+        // NOTE: an error, like the kernel's queue overflowing, may have lost a change; it's safer to count it as one.
+        let Ok(event) = event else {
+            return Ok(true);
+        };
+        if event.need_rescan()
+            || !event.kind.is_access() && event.paths.iter().any(|path| !ignored(path))
+        {
+            return Ok(true);
+        }
+    }
+}
+
+fn ignored(path: &Path) -> bool {
+    path.components()
+        .any(|component| component.as_os_str() == ".git" || component.as_os_str() == "target")
+}
+
+/// Stops the child the way `--timeout` would, still relaying whatever it writes on the way out.
+fn stop(mut child: Child) -> io::Result<()> {
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+fn io_error(e: notify::Error, path: &Path) -> io::Error {
+    let kind = match &e.kind {
+        notify::ErrorKind::Io(e) => e.kind(),
+        notify::ErrorKind::PathNotFound => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, format!("couldn't watch {}: {e}", path.display()))
+}
@@ -0,0 +1,306 @@
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use winapi::shared::minwindef::TRUE;
+use winapi::shared::winerror::{
+    ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_NO_DATA, ERROR_NO_SYSTEM_RESOURCES,
+    ERROR_NOT_ENOUGH_MEMORY, ERROR_NOT_ENOUGH_QUOTA, ERROR_PIPE_CONNECTED, ERROR_PIPE_LISTENING,
+    ERROR_PIPE_NOT_CONNECTED, ERROR_WORKING_SET_QUOTA,
+};
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::fileapi::{CreateFileW, OPEN_EXISTING, ReadFile, WriteFile};
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::ioapiset::GetOverlappedResult;
+use winapi::um::minwinbase::OVERLAPPED;
+use winapi::um::namedpipeapi::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PeekNamedPipe,
+};
+use winapi::um::synchapi::CreateEventW;
+use winapi::um::winbase::{
+    FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX, PIPE_ACCESS_INBOUND,
+    PIPE_ACCESS_OUTBOUND, PIPE_NOWAIT, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+    PIPE_TYPE_BYTE, PIPE_WAIT,
+};
+use winapi::um::winnt::{GENERIC_READ, GENERIC_WRITE};
+
+pub(crate) const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+
+/// How many times a `PeekNamedPipe` that failed for lack of resources is tried again before the error is given up on.
+const PEEK_RETRIES: u32 = 3;
+
+/// How many bytes are waiting in the pipe, or `None` once the writing side is gone and everything it wrote was read.
+///
+/// Running out of memory or quota is usually over quickly, so those are retried a few times before they're returned;
+/// any other failure is returned right away.
+pub fn peek<R: AsRawHandle>(pipe: &R) -> io::Result<Option<u32>> {
+    let mut retries = 0;
+    loop {
+        let mut bytes_avail = 0u32;
+        let ok = unsafe {
+            PeekNamedPipe(
+                pipe.as_raw_handle() as _,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                &mut bytes_avail,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok != 0 {
+            return Ok(Some(bytes_avail));
+        }
+        match unsafe { GetLastError() } {
+            ERROR_BROKEN_PIPE | ERROR_PIPE_NOT_CONNECTED => return Ok(None),
+            // NOTE: nobody connected to the other end of the named pipe yet, which isn't the end of it.
+            ERROR_PIPE_LISTENING => return Ok(Some(0)),
+            ERROR_NO_SYSTEM_RESOURCES
+            | ERROR_NOT_ENOUGH_MEMORY
+            | ERROR_NOT_ENOUGH_QUOTA
+            | ERROR_WORKING_SET_QUOTA
+                if retries < PEEK_RETRIES =>
+            {
+                retries += 1;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            err => return Err(io::Error::from_raw_os_error(err as i32)),
+        }
+    }
+}
+
+/// Whether there's anything to read, so that reading won't block.
+pub fn can_read<R: AsRawHandle>(pipe: &R) -> io::Result<bool> {
+    Ok(peek(pipe)?.is_some_and(|bytes_avail| bytes_avail > 0))
+}
+
+pub fn read_pipe<R: AsRawHandle>(pipe: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let handle = pipe.as_raw_handle();
+    let mut read = 0u32;
+    let ok = unsafe {
+        ReadFile(
+            handle as _,
+            buf.as_mut_ptr() as *mut _,
+            buf.len() as u32,
+            &mut read,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return match unsafe { GetLastError() } {
+            ERROR_BROKEN_PIPE => Ok(0),
+            err => Err(io::Error::from_raw_os_error(err as i32)),
+        };
+    }
+    Ok(read as usize)
+}
+
+pub fn write_pipe<W: AsRawHandle>(pipe: &mut W, buf: &[u8]) -> io::Result<usize> {
+    let handle = pipe.as_raw_handle();
+    let mut written = 0u32;
+    let ok = unsafe {
+        WriteFile(
+            handle as _,
+            buf.as_ptr() as *const _,
+            buf.len() as u32,
+            &mut written,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(written as usize)
+}
+
+/// Generates a name no other pipe of ours uses, in `\\.\pipe\`.
+fn unique_pipe_name() -> Vec<u16> {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    let name = format!(
+        r"\\.\pipe\pipe2-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    OsStr::new(&name).encode_wide().chain(Some(0)).collect()
+}
+
+fn create_named_pipe(
+    name: &[u16],
+    open_mode: u32,
+    pipe_mode: u32,
+    buffer_size: u32,
+) -> io::Result<OwnedHandle> {
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            pipe_mode | PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            buffer_size,
+            buffer_size,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle as _) })
+}
+
+/// Connects to the pipe we just created, from our own process; the resulting handle is what gets handed to the child.
+fn open_client(name: &[u16], access: u32) -> io::Result<OwnedHandle> {
+    let handle = unsafe {
+        CreateFileW(
+            name.as_ptr(),
+            access,
+            0,
+            std::ptr::null_mut(),
+            OPEN_EXISTING,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle as _) })
+}
+
+/// Creates a connected, duplex named pipe, returning `(server, client)`.
+///
+/// The server end is put in `PIPE_NOWAIT` mode, so writes to it complete with however many bytes fit in the pipe
+/// buffer instead of blocking until the other side reads.
+pub fn duplex_pipe() -> io::Result<(OwnedHandle, OwnedHandle)> {
+    let name = unique_pipe_name();
+    let server = create_named_pipe(&name, PIPE_ACCESS_DUPLEX, PIPE_NOWAIT, PIPE_BUFFER_SIZE)?;
+    let client = open_client(&name, GENERIC_READ | GENERIC_WRITE)?;
+    Ok((server, client))
+}
+
+/// Creates a connected, outbound named pipe for the child's `stdin`, returning `(server, client)`.
+///
+/// Like [`duplex_pipe`], the server end is in `PIPE_NOWAIT` mode.
+pub fn outbound_pipe() -> io::Result<(OwnedHandle, OwnedHandle)> {
+    let name = unique_pipe_name();
+    let server = create_named_pipe(&name, PIPE_ACCESS_OUTBOUND, PIPE_NOWAIT, PIPE_BUFFER_SIZE)?;
+    let client = open_client(&name, GENERIC_READ)?;
+    Ok((server, client))
+}
+
+/// Creates the server end of a pipe called `name` (`\\.\pipe\...`) for clients to connect to one at a time, in
+/// `PIPE_NOWAIT` mode so that [`accept`] doesn't wait for them.
+pub fn control_pipe(name: &OsStr) -> io::Result<OwnedHandle> {
+    let name: Vec<u16> = name.encode_wide().chain(Some(0)).collect();
+    create_named_pipe(&name, PIPE_ACCESS_DUPLEX, PIPE_NOWAIT, 4096)
+}
+
+/// Whether a client is connected to a [`control_pipe`], without waiting for one.
+pub fn accept(pipe: &OwnedHandle) -> io::Result<bool> {
+    if unsafe { ConnectNamedPipe(pipe.as_raw_handle() as _, std::ptr::null_mut()) } != 0 {
+        return Ok(true);
+    }
+    match unsafe { GetLastError() } {
+        ERROR_PIPE_CONNECTED => Ok(true),
+        ERROR_PIPE_LISTENING => Ok(false),
+        // NOTE: a client came and went already; the pipe has to be disconnected before the next one can connect.
+        ERROR_NO_DATA => disconnect(pipe).map(|()| false),
+        err => Err(io::Error::from_raw_os_error(err as i32)),
+    }
+}
+
+/// Whether the client on the other end of the pipe is gone.
+pub fn peer_closed<R: AsRawHandle>(pipe: &R) -> bool {
+    let ok = unsafe {
+        PeekNamedPipe(
+            pipe.as_raw_handle() as _,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    ok == 0 && unsafe { GetLastError() } == ERROR_BROKEN_PIPE
+}
+
+/// Lets go of the client of a [`control_pipe`], so the next one can connect.
+pub fn disconnect(pipe: &OwnedHandle) -> io::Result<()> {
+    if unsafe { DisconnectNamedPipe(pipe.as_raw_handle() as _) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Our reading end of a named pipe that replaces one of the child's anonymous output pipes.
+///
+/// Opened for overlapped I/O; reads are still only issued once `PeekNamedPipe` reports data, so waiting on them
+/// completes right away.
+pub struct NamedPipe {
+    handle: OwnedHandle,
+    event: OwnedHandle,
+}
+
+impl NamedPipe {
+    /// Creates the pipe with `buffer_size` bytes of kernel buffer, returning our end and the write end for the child.
+    pub fn inbound(buffer_size: u32) -> io::Result<(Self, OwnedHandle)> {
+        let name = unique_pipe_name();
+        let handle = create_named_pipe(
+            &name,
+            PIPE_ACCESS_INBOUND | FILE_FLAG_OVERLAPPED,
+            PIPE_WAIT,
+            buffer_size,
+        )?;
+        let client = open_client(&name, GENERIC_WRITE)?;
+
+        let event = unsafe { CreateEventW(std::ptr::null_mut(), TRUE, 0, std::ptr::null()) };
+        if event.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let event = unsafe { OwnedHandle::from_raw_handle(event as _) };
+
+        Ok((Self { handle, event }, client))
+    }
+
+    pub fn read_overlapped(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let handle = self.handle.as_raw_handle();
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        overlapped.hEvent = self.event.as_raw_handle() as _;
+
+        let mut read = 0u32;
+        let ok = unsafe {
+            ReadFile(
+                handle as _,
+                buf.as_mut_ptr() as *mut _,
+                buf.len() as u32,
+                std::ptr::null_mut(),
+                &mut overlapped,
+            )
+        };
+        if ok == 0 {
+            match unsafe { GetLastError() } {
+                ERROR_IO_PENDING => {}
+                ERROR_BROKEN_PIPE => return Ok(0),
+                err => return Err(io::Error::from_raw_os_error(err as i32)),
+            }
+        }
+
+        let ok = unsafe { GetOverlappedResult(handle as _, &mut overlapped, &mut read, TRUE) };
+        if ok == 0 {
+            return match unsafe { GetLastError() } {
+                ERROR_BROKEN_PIPE => Ok(0),
+                err => Err(io::Error::from_raw_os_error(err as i32)),
+            };
+        }
+        Ok(read as usize)
+    }
+}
+
+impl AsRawHandle for NamedPipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handle.as_raw_handle()
+    }
+}
@@ -0,0 +1,198 @@
+use std::io;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::os::windows::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use winapi::shared::minwindef::BOOL;
+use winapi::shared::ntdef::{HANDLE, NTSTATUS};
+
+// NOTE: not part of the documented Win32 surface, but exported by ntdll since forever; it's what Process Explorer's
+// "Suspend" uses. Unlike suspending threads one by one, there's no window in which new threads slip through.
+#[link(name = "ntdll")]
+unsafe extern "system" {
+    fn NtSuspendProcess(process: HANDLE) -> NTSTATUS;
+    fn NtResumeProcess(process: HANDLE) -> NTSTATUS;
+}
+
+fn check(status: NTSTATUS) -> io::Result<()> {
+    if status < 0 {
+        return Err(io::Error::other(format!(
+            "NTSTATUS {:#010x}",
+            status as u32
+        )));
+    }
+    Ok(())
+}
+
+pub fn suspend_process<P: AsRawHandle>(process: &P) -> io::Result<()> {
+    check(unsafe { NtSuspendProcess(process.as_raw_handle() as _) })
+}
+
+pub fn resume_process<P: AsRawHandle>(process: &P) -> io::Result<()> {
+    check(unsafe { NtResumeProcess(process.as_raw_handle() as _) })
+}
+
+/// Sends `CTRL_BREAK_EVENT` to the process group `pid` leads, as if Ctrl+Break was hit in its console.
+///
+/// NOTE: only reaches children that share our console and were created with `CREATE_NEW_PROCESS_GROUP`; without the
+/// latter, the event would go to every process on the console, us included.
+pub fn send_ctrl_break(pid: u32) -> io::Result<()> {
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, GenerateConsoleCtrlEvent};
+
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn SetConsoleCtrlHandler(
+        handler: Option<unsafe extern "system" fn(u32) -> BOOL>,
+        add: BOOL,
+    ) -> BOOL;
+}
+
+/// Takes every console control event (`CTRL_C_EVENT`, `CTRL_BREAK_EVENT`, ...) as handled, so none of them stops us.
+pub fn ignore_console_signals() {
+    unsafe extern "system" fn handled(_event: u32) -> BOOL {
+        1
+    }
+    unsafe { SetConsoleCtrlHandler(Some(handled), 1) };
+}
+
+/// Puts `process` in a job of ours that kills everything in it once it's closed, which happens as we exit, however we
+/// exit; whatever the process starts goes in the job too. The one job is shared by every child, and never closed
+/// otherwise.
+pub fn kill_on_our_exit<P: AsRawHandle>(process: &P) -> io::Result<()> {
+    use std::sync::OnceLock;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::jobapi2::{AssignProcessToJobObject, SetInformationJobObject};
+    use winapi::um::winbase::CreateJobObjectW;
+    use winapi::um::winnt::{
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectExtendedLimitInformation,
+    };
+
+    // NOTE: a `HANDLE` isn't `Send`, hence the address; and an `io::Error` isn't `Clone`, hence the error code.
+    static JOB: OnceLock<Result<usize, i32>> = OnceLock::new();
+    let job = JOB.get_or_init(|| {
+        let job = unsafe { CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+        if job.is_null() {
+            return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+        }
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        let set = unsafe {
+            SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &mut limits as *mut _ as _,
+                std::mem::size_of_val(&limits) as u32,
+            )
+        };
+        if set == 0 {
+            let e = io::Error::last_os_error();
+            unsafe { CloseHandle(job) };
+            return Err(e.raw_os_error().unwrap_or(0));
+        }
+        Ok(job as usize)
+    });
+    let job = (*job).map_err(io::Error::from_raw_os_error)?;
+    if unsafe { AssignProcessToJobObject(job as HANDLE, process.as_raw_handle() as _) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// NOTE: winapi declares the mask as a `DWORD`, where it's really a `DWORD_PTR`; that would cut it to 32 CPUs.
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn SetProcessAffinityMask(process: HANDLE, mask: usize) -> BOOL;
+}
+
+pub fn set_affinity<P: AsRawHandle>(process: &P, mask: usize) -> io::Result<()> {
+    if unsafe { SetProcessAffinityMask(process.as_raw_handle() as _, mask) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The CPU time the process has used so far, in user and kernel mode together.
+pub fn cpu_time<P: AsRawHandle>(process: &P) -> io::Result<Duration> {
+    use winapi::shared::minwindef::FILETIME;
+    use winapi::um::processthreadsapi::GetProcessTimes;
+
+    let mut times: [FILETIME; 4] = unsafe { std::mem::zeroed() };
+    let [creation, exit, kernel, user] = &mut times;
+    if unsafe { GetProcessTimes(process.as_raw_handle() as _, creation, exit, kernel, user) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // NOTE: in units of 100ns.
+    let ticks = |time: &FILETIME| (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64;
+    Ok(Duration::from_nanos((ticks(kernel) + ticks(user)) * 100))
+}
+
+/// A process we created ourselves rather than through std.
+pub struct RawProcess {
+    handle: OwnedHandle,
+    pid: u32,
+}
+
+impl RawProcess {
+    /// # Safety
+    ///
+    /// `handle` must be an open process handle, with at least `SYNCHRONIZE`, `PROCESS_QUERY_LIMITED_INFORMATION` and
+    /// `PROCESS_TERMINATE` access, that nothing else owns.
+    pub unsafe fn from_raw(handle: HANDLE, pid: u32) -> Self {
+        Self {
+            handle: unsafe { OwnedHandle::from_raw_handle(handle as _) },
+            pid,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        use winapi::shared::winerror::WAIT_TIMEOUT;
+        use winapi::um::processthreadsapi::GetExitCodeProcess;
+        use winapi::um::synchapi::WaitForSingleObject;
+        use winapi::um::winbase::WAIT_OBJECT_0;
+
+        let handle = self.handle.as_raw_handle();
+        match unsafe { WaitForSingleObject(handle as _, 0) } {
+            WAIT_OBJECT_0 => {}
+            WAIT_TIMEOUT => return Ok(None),
+            _ => return Err(io::Error::last_os_error()),
+        }
+
+        let mut code = 0u32;
+        if unsafe { GetExitCodeProcess(handle as _, &mut code) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(ExitStatus::from_raw(code)))
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        use winapi::um::processthreadsapi::TerminateProcess;
+
+        if unsafe { TerminateProcess(self.handle.as_raw_handle() as _, 1) } == 0 {
+            let e = io::Error::last_os_error();
+            // NOTE: terminating a process that already exited fails with access denied, std treats that as success.
+            if self.try_wait()?.is_some() {
+                return Ok(());
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl AsRawHandle for RawProcess {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handle.as_raw_handle()
+    }
+}
@@ -0,0 +1,511 @@
+//! Spawning the child with `CreateProcess*` ourselves on Windows, for what std's `Command` has no way of doing:
+//! other credentials, the window it starts with, and the rest of its `STARTUPINFOEX`.
+//!
+//! The child's `stdout`/`stderr` are named pipes we create (see [`crate::windows_pipe_utils::NamedPipe`]), so our
+//! ends stay ours no matter who the child runs as: it only ever sees the write ends, handed over through
+//! `STARTUPINFO`.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, BorrowedHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::path::Path;
+
+use winapi::shared::minwindef::TRUE;
+use winapi::um::handleapi::{DuplicateHandle, SetHandleInformation};
+use winapi::um::processenv::GetStdHandle;
+use winapi::um::processthreadsapi::{
+    CreateProcessAsUserW, CreateProcessW, DeleteProcThreadAttributeList, GetCurrentProcess,
+    InitializeProcThreadAttributeList, LPPROC_THREAD_ATTRIBUTE_LIST, OpenProcessToken,
+    PROCESS_INFORMATION, STARTUPINFOW, UpdateProcThreadAttribute,
+};
+use winapi::um::securitybaseapi::CreateRestrictedToken;
+use winapi::um::winbase::{
+    CREATE_UNICODE_ENVIRONMENT, CreateProcessWithLogonW, EXTENDED_STARTUPINFO_PRESENT,
+    HANDLE_FLAG_INHERIT, LOGON_WITH_PROFILE, STARTF_USESHOWWINDOW, STARTF_USESTDHANDLES,
+    STARTUPINFOEXW, STD_INPUT_HANDLE,
+};
+use winapi::um::winnt::{
+    DISABLE_MAX_PRIVILEGE, DUPLICATE_SAME_ACCESS, HANDLE, LUA_TOKEN, TOKEN_ASSIGN_PRIMARY,
+    TOKEN_DUPLICATE, TOKEN_QUERY,
+};
+
+use crate::mitigation::{
+    CHILD_PROCESS_RESTRICTED, MitigationPolicy, PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY,
+    PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY,
+};
+use crate::windows_process_utils::RawProcess;
+
+/// `PROC_THREAD_ATTRIBUTE_HANDLE_LIST`, which `winapi` doesn't define.
+const PROC_THREAD_ATTRIBUTE_HANDLE_LIST: usize = 0x0002_0002;
+
+/// Whose credentials the child runs with.
+#[derive(Clone)]
+pub(crate) enum RunAs {
+    /// Logs on as another user, through `CreateProcessWithLogonW`.
+    Logon {
+        user: OsString,
+        domain: Option<OsString>,
+        password: OsString,
+    },
+    /// Our own user, with a restricted token: administrator groups disabled and every privilege but
+    /// `SeChangeNotifyPrivilege` removed.
+    Restricted,
+}
+
+impl RunAs {
+    /// Whether handles other than the child's stdio can be inherited; `CreateProcessWithLogonW` only hands over those.
+    pub(crate) fn inherits_handles(&self) -> bool {
+        matches!(self, RunAs::Restricted)
+    }
+}
+
+/// The parts of the child's `STARTUPINFOEX` beyond its stdio, for [`Pipe2::startup_info`] hooks to adjust.
+///
+/// [`Pipe2::startup_info`]: crate::Pipe2::startup_info
+#[derive(Default)]
+pub struct StartupInfo {
+    desktop: Option<Vec<u16>>,
+    handles: Option<Vec<RawHandle>>,
+    attributes: Vec<(usize, Vec<u8>)>,
+    /// The `PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY` flags, if any.
+    mitigations: u64,
+    /// The `PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY` flags, if any.
+    child_processes: u32,
+}
+
+impl StartupInfo {
+    /// Starts the child on `desktop`, like `winsta0\default`, rather than on ours.
+    pub fn desktop<S: AsRef<OsStr>>(&mut self, desktop: S) -> &mut Self {
+        self.desktop = Some(wide(desktop.as_ref()));
+        self
+    }
+
+    /// Has the child inherit `handle`, and only the handles added this way, rather than every inheritable handle we
+    /// have open (through `PROC_THREAD_ATTRIBUTE_HANDLE_LIST`). The handle has to be inheritable already. The
+    /// child's stdio, [`Pipe2::pass_handle`] handles and the [`Pipe2::channel`] are added to the list by pipe2.
+    ///
+    /// [`Pipe2::pass_handle`]: crate::Pipe2::pass_handle
+    /// [`Pipe2::channel`]: crate::Pipe2::channel
+    pub fn inherit_handle(&mut self, handle: BorrowedHandle) -> &mut Self {
+        self.handles
+            .get_or_insert_with(Vec::new)
+            .push(handle.as_raw_handle());
+        self
+    }
+
+    /// Applies the mitigation `policy` to the child, on top of any others; see
+    /// [`Pipe2::mitigation_policy`](crate::Pipe2::mitigation_policy).
+    pub fn mitigation_policy(&mut self, policy: MitigationPolicy) -> &mut Self {
+        match policy {
+            MitigationPolicy::NoChildProcesses => self.child_processes |= CHILD_PROCESS_RESTRICTED,
+            policy => self.mitigations |= policy.flags(),
+        }
+        self
+    }
+
+    /// Adds `attribute` to the child's attribute list, as `UpdateProcThreadAttribute` does, like
+    /// `PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY` with the policy flags as `value`.
+    ///
+    /// # Safety
+    ///
+    /// `value` has to be what `attribute` expects, and any handle in it has to stay open until the child is
+    /// created.
+    pub unsafe fn attribute(&mut self, attribute: usize, value: &[u8]) -> &mut Self {
+        self.attributes.push((attribute, value.to_vec()));
+        self
+    }
+
+    /// Has the child inherit only the handles on the list, even if no hook added any; see [`Pipe2::paranoid`].
+    ///
+    /// [`Pipe2::paranoid`]: crate::Pipe2::paranoid
+    pub(crate) fn inherit_listed_only(&mut self) {
+        self.handles.get_or_insert_with(Vec::new);
+    }
+
+    /// Whether the child needs an attribute list, which only `CreateProcessW` and `CreateProcessAsUserW` take.
+    fn has_attributes(&self) -> bool {
+        self.handles.is_some()
+            || !self.attributes.is_empty()
+            || self.mitigations != 0
+            || self.child_processes != 0
+    }
+}
+
+/// See [`Pipe2::startup_info`](crate::Pipe2::startup_info).
+pub(crate) type StartupHook = Box<dyn Fn(&mut StartupInfo) -> io::Result<()> + Send + Sync>;
+
+/// An initialized `PROC_THREAD_ATTRIBUTE_LIST`, along with the handle list it points into.
+struct AttributeList {
+    buffer: Vec<usize>,
+    _handles: Vec<RawHandle>,
+}
+
+impl AttributeList {
+    /// NOTE: the list only points at the values, so `info` has to outlive it.
+    fn new(info: &StartupInfo, mut handles: Vec<RawHandle>) -> io::Result<Self> {
+        let count = info.attributes.len()
+            + usize::from(info.handles.is_some())
+            + usize::from(info.mitigations != 0)
+            + usize::from(info.child_processes != 0);
+        let mut size = 0;
+        unsafe {
+            InitializeProcThreadAttributeList(std::ptr::null_mut(), count as u32, 0, &mut size)
+        };
+        let mut buffer = vec![0usize; size.div_ceil(std::mem::size_of::<usize>())];
+        if unsafe {
+            InitializeProcThreadAttributeList(buffer.as_mut_ptr() as _, count as u32, 0, &mut size)
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let mut list = Self {
+            buffer,
+            _handles: Vec::new(),
+        };
+
+        if let Some(extra) = &info.handles {
+            handles.extend(extra);
+            // NOTE: a handle listed twice fails the whole creation.
+            handles.sort();
+            handles.dedup();
+            list.update(
+                PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+                handles.as_ptr() as _,
+                handles.len() * std::mem::size_of::<RawHandle>(),
+            )?;
+            list._handles = handles;
+        }
+        if info.mitigations != 0 {
+            list.update(
+                PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY,
+                &info.mitigations as *const u64 as _,
+                std::mem::size_of::<u64>(),
+            )?;
+        }
+        if info.child_processes != 0 {
+            list.update(
+                PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY,
+                &info.child_processes as *const u32 as _,
+                std::mem::size_of::<u32>(),
+            )?;
+        }
+        for (attribute, value) in &info.attributes {
+            list.update(*attribute, value.as_ptr() as _, value.len())?;
+        }
+        Ok(list)
+    }
+
+    fn update(
+        &mut self,
+        attribute: usize,
+        value: *mut std::ffi::c_void,
+        size: usize,
+    ) -> io::Result<()> {
+        let ok = unsafe {
+            UpdateProcThreadAttribute(
+                self.as_ptr(),
+                0,
+                attribute,
+                value as _,
+                size,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn as_ptr(&mut self) -> LPPROC_THREAD_ATTRIBUTE_LIST {
+        self.buffer.as_mut_ptr() as _
+    }
+}
+
+impl Drop for AttributeList {
+    fn drop(&mut self) {
+        unsafe { DeleteProcThreadAttributeList(self.as_ptr()) };
+    }
+}
+
+/// What to create the child from.
+pub(crate) struct Spawn<'a> {
+    pub(crate) program: &'a OsStr,
+    pub(crate) args: &'a [OsString],
+    /// The complete environment, or `None` to inherit ours.
+    pub(crate) env: Option<Vec<(OsString, OsString)>>,
+    pub(crate) current_dir: Option<&'a Path>,
+    pub(crate) creation_flags: u32,
+    /// `SW_*` value for the child's first window.
+    pub(crate) show_window: Option<u16>,
+    /// Ours is passed on when there's none.
+    pub(crate) stdin: Option<&'a OwnedHandle>,
+    pub(crate) stdout: &'a OwnedHandle,
+    pub(crate) stderr: &'a OwnedHandle,
+    /// As the [`StartupInfo`] hooks left it, along with the other handles the child is handed.
+    pub(crate) startup_info: Option<(StartupInfo, Vec<RawHandle>)>,
+}
+
+/// Creates the child as `run_as`, or as us if that's `None`.
+pub(crate) fn spawn(run_as: Option<&RunAs>, spawn: Spawn) -> io::Result<RawProcess> {
+    if matches!(run_as, Some(RunAs::Logon { .. }))
+        && spawn
+            .startup_info
+            .as_ref()
+            .is_some_and(|(info, _)| info.has_attributes())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "handle lists and attributes can't be combined with running as another user",
+        ));
+    }
+
+    let mut command_line = command_line(spawn.program, spawn.args);
+    let mut env = spawn.env.as_deref().map(environment_block);
+    let env_ptr = env
+        .as_mut()
+        .map_or(std::ptr::null_mut(), |env| env.as_mut_ptr() as *mut _);
+    let cwd = spawn.current_dir.map(|dir| wide(dir.as_os_str()));
+    let cwd_ptr = cwd.as_ref().map_or(std::ptr::null(), |cwd| cwd.as_ptr());
+    let mut flags = spawn.creation_flags | CREATE_UNICODE_ENVIRONMENT;
+
+    let inherited = match spawn.stdin {
+        Some(_) => None,
+        None => inheritable_stdin()?,
+    };
+    let stdin = spawn.stdin.or(inherited.as_ref());
+    for handle in spawn.stdin.into_iter().chain([spawn.stdout, spawn.stderr]) {
+        if unsafe {
+            SetHandleInformation(
+                handle.as_raw_handle() as _,
+                HANDLE_FLAG_INHERIT,
+                HANDLE_FLAG_INHERIT,
+            )
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let mut extended: STARTUPINFOEXW = unsafe { std::mem::zeroed() };
+    let startup_info: &mut STARTUPINFOW = &mut extended.StartupInfo;
+    startup_info.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
+    startup_info.dwFlags = STARTF_USESTDHANDLES;
+    startup_info.hStdInput = stdin.map_or(std::ptr::null_mut(), |stdin| stdin.as_raw_handle() as _);
+    startup_info.hStdOutput = spawn.stdout.as_raw_handle() as _;
+    startup_info.hStdError = spawn.stderr.as_raw_handle() as _;
+    if let Some(show) = spawn.show_window {
+        startup_info.dwFlags |= STARTF_USESHOWWINDOW;
+        startup_info.wShowWindow = show;
+    }
+
+    let mut attributes = None;
+    if let Some((info, passed)) = &spawn.startup_info {
+        if let Some(desktop) = &info.desktop {
+            startup_info.lpDesktop = desktop.as_ptr() as *mut _;
+        }
+        if info.has_attributes() {
+            let mut handles: Vec<RawHandle> = stdin
+                .into_iter()
+                .chain([spawn.stdout, spawn.stderr])
+                .map(|handle| handle.as_raw_handle())
+                .collect();
+            handles.extend(passed);
+            let list = attributes.insert(AttributeList::new(info, handles)?);
+            extended.StartupInfo.cb = std::mem::size_of::<STARTUPINFOEXW>() as u32;
+            extended.lpAttributeList = list.as_ptr();
+            flags |= EXTENDED_STARTUPINFO_PRESENT;
+        }
+    }
+    // NOTE: `CreateProcess*` reads the attribute list past the `STARTUPINFOW` when the flag says it's there.
+    let startup_info = (&mut extended as *mut STARTUPINFOEXW).cast::<STARTUPINFOW>();
+
+    let mut info: PROCESS_INFORMATION = unsafe { std::mem::zeroed() };
+    let ok = match run_as {
+        None => unsafe {
+            CreateProcessW(
+                std::ptr::null(),
+                command_line.as_mut_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                TRUE,
+                flags,
+                env_ptr,
+                cwd_ptr,
+                startup_info,
+                &mut info,
+            )
+        },
+        Some(RunAs::Logon {
+            user,
+            domain,
+            password,
+        }) => {
+            let user = wide(user);
+            let domain = domain.as_deref().map(wide);
+            let password = wide(password);
+            unsafe {
+                CreateProcessWithLogonW(
+                    user.as_ptr(),
+                    domain
+                        .as_ref()
+                        .map_or(std::ptr::null(), |domain| domain.as_ptr()),
+                    password.as_ptr(),
+                    LOGON_WITH_PROFILE,
+                    std::ptr::null(),
+                    command_line.as_mut_ptr(),
+                    flags,
+                    env_ptr,
+                    cwd_ptr,
+                    startup_info,
+                    &mut info,
+                )
+            }
+        }
+        Some(RunAs::Restricted) => {
+            let token = restricted_token()?;
+            unsafe {
+                CreateProcessAsUserW(
+                    token.as_raw_handle() as _,
+                    std::ptr::null(),
+                    command_line.as_mut_ptr(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    TRUE,
+                    flags,
+                    env_ptr,
+                    cwd_ptr,
+                    startup_info,
+                    &mut info,
+                )
+            }
+        }
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    drop(unsafe { OwnedHandle::from_raw_handle(info.hThread as _) });
+    Ok(unsafe { RawProcess::from_raw(info.hProcess, info.dwProcessId) })
+}
+
+fn restricted_token() -> io::Result<OwnedHandle> {
+    let mut token: HANDLE = std::ptr::null_mut();
+    let access = TOKEN_DUPLICATE | TOKEN_ASSIGN_PRIMARY | TOKEN_QUERY;
+    if unsafe { OpenProcessToken(GetCurrentProcess(), access, &mut token) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let token = unsafe { OwnedHandle::from_raw_handle(token as _) };
+
+    let mut restricted: HANDLE = std::ptr::null_mut();
+    let ok = unsafe {
+        CreateRestrictedToken(
+            token.as_raw_handle() as _,
+            DISABLE_MAX_PRIVILEGE | LUA_TOKEN,
+            0,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            &mut restricted,
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(restricted as _) })
+}
+
+/// An inheritable duplicate of our own `stdin`, which is what the child would get from std too.
+fn inheritable_stdin() -> io::Result<Option<OwnedHandle>> {
+    let stdin = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    if stdin.is_null() || stdin == winapi::um::handleapi::INVALID_HANDLE_VALUE {
+        return Ok(None);
+    }
+
+    let mut duplicate: HANDLE = std::ptr::null_mut();
+    let ok = unsafe {
+        DuplicateHandle(
+            GetCurrentProcess(),
+            stdin,
+            GetCurrentProcess(),
+            &mut duplicate,
+            0,
+            TRUE,
+            DUPLICATE_SAME_ACCESS,
+        )
+    };
+    if ok == 0 {
+        return Ok(None);
+    }
+    Ok(Some(unsafe {
+        OwnedHandle::from_raw_handle(duplicate as _)
+    }))
+}
+
+fn wide(s: &OsStr) -> Vec<u16> {
+    s.encode_wide().chain(Some(0)).collect()
+}
+
+/// Builds the command line the way the MSVC runtime (and so std) splits it back apart.
+fn command_line(program: &OsStr, args: &[OsString]) -> Vec<u16> {
+    let mut line = Vec::new();
+    append_arg(&mut line, program);
+    for arg in args {
+        line.push(u16::from(b' '));
+        append_arg(&mut line, arg);
+    }
+    line.push(0);
+    line
+}
+
+fn append_arg(line: &mut Vec<u16>, arg: &OsStr) {
+    let quote = arg.is_empty()
+        || arg
+            .encode_wide()
+            .any(|c| c == u16::from(b' ') || c == u16::from(b'\t'));
+    if quote {
+        line.push(u16::from(b'"'));
+    }
+
+    // NOTE: backslashes are only special right before a quote, where each of them has to be doubled.
+    let mut backslashes = 0;
+    for c in arg.encode_wide() {
+        if c == u16::from(b'\\') {
+            backslashes += 1;
+        } else {
+            if c == u16::from(b'"') {
+                line.extend(std::iter::repeat_n(u16::from(b'\\'), backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        line.push(c);
+    }
+
+    if quote {
+        line.extend(std::iter::repeat_n(u16::from(b'\\'), backslashes));
+        line.push(u16::from(b'"'));
+    }
+}
+
+/// `KEY=VALUE\0` for every variable, sorted the way Windows expects, ending with an extra `\0`.
+fn environment_block(env: &[(OsString, OsString)]) -> Vec<u16> {
+    let mut env: Vec<_> = env.iter().collect();
+    env.sort_by_cached_key(|(key, _)| key.to_string_lossy().to_uppercase());
+
+    let mut block = Vec::new();
+    for (key, value) in env {
+        block.extend(key.encode_wide());
+        block.push(u16::from(b'='));
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
@@ -0,0 +1,158 @@
+//! Timing driven by a [`MockClock`]: each of these would take an hour or more on the system's clock.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use pipe2::{ExitReason, FakeChild, MockClock, Pipe2};
+use serde_json::Value;
+
+const HELPER: &str = env!("CARGO_BIN_EXE_pipe2-fake-child");
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// How long any of these may take for real; the child only ever sleeps for a fraction of it.
+const REAL_TIME: Duration = Duration::from_secs(20);
+
+fn hanging(clock: &MockClock) -> Pipe2 {
+    let mut script = FakeChild::new();
+    script.stdout("started\n").sleep(Duration::from_secs(60));
+    let mut pipe2 = script.command(HELPER);
+    pipe2.echo(false).clock(clock.clone());
+    pipe2
+}
+
+#[test]
+fn timeout() {
+    let clock = MockClock::new();
+    let started = Instant::now();
+    let output = hanging(&clock).timeout(HOUR).run().unwrap();
+    assert!(started.elapsed() < REAL_TIME);
+    assert!(clock.elapsed() >= HOUR);
+    assert!(output.timed_out);
+    assert_eq!(output.reason, ExitReason::TimedOut);
+}
+
+#[test]
+fn first_output_within() {
+    let mut script = FakeChild::new();
+    script.sleep(Duration::from_secs(60));
+    let clock = MockClock::new();
+    let started = Instant::now();
+    let output = script
+        .command(HELPER)
+        .echo(false)
+        .clock(clock.clone())
+        .first_output_within(HOUR)
+        .run()
+        .unwrap();
+    assert!(started.elapsed() < REAL_TIME);
+    assert!(clock.elapsed() >= HOUR);
+    assert_eq!(output.reason, ExitReason::IdleTimeout);
+}
+
+#[test]
+fn grace_period() {
+    let mut script = FakeChild::new();
+    script
+        .ignore_signals(true)
+        .stdout("started\n")
+        .sleep(Duration::from_secs(60));
+    let clock = MockClock::new();
+    let mut child = script
+        .command(HELPER)
+        .echo(false)
+        .clock(clock.clone())
+        .grace_period(HOUR)
+        .spawn()
+        .unwrap();
+    while child.take_stdout().is_empty() {
+        child.poll().unwrap();
+    }
+    child.kill().unwrap();
+    let before = clock.elapsed();
+    let output = child.wait().unwrap();
+    assert!(clock.elapsed() - before >= HOUR);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(output.status.signal(), Some(libc::SIGKILL));
+    }
+    assert_eq!(output.reason, ExitReason::Signaled);
+}
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn event_elapsed() {
+    let clock = MockClock::new();
+    let log = Shared::default();
+    hanging(&clock)
+        .timeout(HOUR)
+        .event_log(log.clone())
+        .run()
+        .unwrap();
+    let log = log.0.lock().unwrap();
+    let events = log
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice::<Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let elapsed = |name: &str| {
+        events
+            .iter()
+            .find(|event| event["event"] == name)
+            .and_then(|event| event["elapsed"].as_f64())
+            .unwrap_or_else(|| panic!("no {name} event"))
+    };
+    assert_eq!(elapsed("spawned"), 0.0);
+    assert!(elapsed("timeout") >= HOUR.as_secs_f64());
+    assert!(elapsed("exited") >= elapsed("timeout"));
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_line_delay() {
+    let clock = MockClock::new();
+    let started = Instant::now();
+    let output = Pipe2::new("cat")
+        .echo(false)
+        .clock(clock.clone())
+        .stdin_bytes("one\ntwo\nthree\n")
+        .stdin_line_delay(HOUR)
+        .run()
+        .unwrap();
+    assert!(started.elapsed() < REAL_TIME);
+    assert_eq!(output.stdout, b"one\ntwo\nthree\n");
+    // NOTE: the delay follows every line, the last one too, before the end of the input is seen.
+    assert!(clock.elapsed() >= 2 * HOUR);
+}
+
+#[cfg(unix)]
+#[test]
+fn stdin_rate() {
+    let clock = MockClock::new();
+    let started = Instant::now();
+    let output = Pipe2::new("cat")
+        .echo(false)
+        .clock(clock.clone())
+        .stdin_bytes(vec![b'x'; 1000])
+        .stdin_rate(1)
+        .run()
+        .unwrap();
+    assert!(started.elapsed() < REAL_TIME);
+    assert_eq!(output.stdout.len(), 1000);
+    assert!(clock.elapsed() >= Duration::from_secs(999));
+}
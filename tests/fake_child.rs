@@ -0,0 +1,259 @@
+//! Capture behavior, driven by the scripted `pipe2-fake-child` helper.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use pipe2::{ExitReason, FakeChild, LineAction, Pipe2, Stream};
+
+const HELPER: &str = env!("CARGO_BIN_EXE_pipe2-fake-child");
+
+/// Long enough that a test only gets past it by stopping the child.
+const FOREVER: Duration = Duration::from_secs(30);
+
+fn command(script: &FakeChild) -> Pipe2 {
+    let mut pipe2 = script.command(HELPER);
+    pipe2.echo(false);
+    pipe2
+}
+
+#[test]
+fn captures_interleaved_streams() {
+    let mut script = FakeChild::new();
+    for i in 1..=50 {
+        script
+            .stdout(format!("out {i}\n"))
+            .stderr(format!("err {i}\n"));
+    }
+    let output = command(&script).run().unwrap();
+    script.assert_output(&output);
+    assert_eq!(output.reason, ExitReason::Exited);
+    assert!(output.success);
+}
+
+#[test]
+fn merged_output_keeps_the_order_written() {
+    let mut script = FakeChild::new();
+    script
+        .stdout("one\n")
+        .stderr("two\n")
+        .stdout("three\n")
+        .stderr("four\n");
+    let output = command(&script).merge_output(true).run().unwrap();
+    assert_eq!(output.stdout, b"one\ntwo\nthree\nfour\n");
+    assert!(output.stderr.is_empty());
+    assert_eq!(output.reason, ExitReason::Exited);
+}
+
+#[test]
+fn keeps_everything_written_before_exiting() {
+    let mut script = FakeChild::new();
+    script
+        .lines(Stream::Stdout, 10_000)
+        .stderr("no line ending at EOF")
+        .exit_code(3);
+    let output = command(&script).run().unwrap();
+    script.assert_output(&output);
+    assert_eq!(output.reason, ExitReason::Exited);
+    assert!(!output.success);
+}
+
+#[test]
+fn keeps_a_partial_line_across_a_pause() {
+    let mut script = FakeChild::new();
+    script
+        .stdout("half a ")
+        .sleep(Duration::from_millis(100))
+        .stdout("line\n");
+    let output = command(&script).run().unwrap();
+    script.assert_output(&output);
+}
+
+#[test]
+fn timeout() {
+    let mut script = FakeChild::new();
+    script.stdout("before\n").sleep(FOREVER).stdout("after\n");
+    let started = Instant::now();
+    let output = command(&script)
+        .timeout(Duration::from_millis(200))
+        .run()
+        .unwrap();
+    assert!(started.elapsed() < FOREVER);
+    assert_eq!(output.stdout, b"before\n");
+    assert!(output.timed_out);
+    assert_eq!(output.reason, ExitReason::TimedOut);
+    assert!(!output.success);
+}
+
+#[test]
+fn first_output_within() {
+    let mut script = FakeChild::new();
+    script.sleep(FOREVER).stdout("too late\n");
+    let output = command(&script)
+        .first_output_within(Duration::from_millis(200))
+        .run()
+        .unwrap();
+    assert!(output.stdout.is_empty());
+    assert!(output.first_output_timed_out);
+    assert_eq!(output.reason, ExitReason::IdleTimeout);
+}
+
+#[test]
+fn first_output_in_time() {
+    let mut script = FakeChild::new();
+    script.stdout("early\n").sleep(Duration::from_millis(300));
+    let output = command(&script)
+        .first_output_within(Duration::from_millis(200))
+        .run()
+        .unwrap();
+    script.assert_output(&output);
+    assert_eq!(output.reason, ExitReason::Exited);
+}
+
+#[test]
+fn timeout_wins_over_the_grace_period() {
+    let mut script = FakeChild::new();
+    script
+        .ignore_signals(true)
+        .stdout("stubborn\n")
+        .sleep(FOREVER);
+    let output = command(&script)
+        .timeout(Duration::from_millis(200))
+        .grace_period(Duration::from_millis(200))
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout, b"stubborn\n");
+    assert_eq!(output.reason, ExitReason::TimedOut);
+}
+
+#[test]
+fn on_line_aborts_only_its_own_run() {
+    let mut script = FakeChild::new();
+    script
+        .stdout("fatal\n")
+        .sleep(Duration::from_millis(300))
+        .stdout("done\n");
+    let lines = AtomicUsize::new(0);
+    let mut pipe2 = command(&script);
+    pipe2.on_line(move |_, _| {
+        // NOTE: only the first line of the first run aborts.
+        if lines.fetch_add(1, Ordering::Relaxed) == 0 {
+            LineAction::Abort
+        } else {
+            LineAction::Keep
+        }
+    });
+
+    let output = pipe2.run().unwrap();
+    assert_eq!(output.stdout, b"fatal\n");
+    assert!(output.aborted);
+    assert!(!output.cancelled);
+    assert_eq!(output.reason, ExitReason::Aborted);
+
+    let output = pipe2.run().unwrap();
+    script.assert_output(&output);
+    assert!(!output.aborted);
+    assert_eq!(output.reason, ExitReason::Exited);
+}
+
+#[test]
+fn max_line_length_keeps_characters_whole() {
+    let mut script = FakeChild::new();
+    script
+        .stdout("aaé\n")
+        .stdout(b"aa\xc3")
+        .sleep(Duration::from_millis(100))
+        .stdout(b"\xa9\n")
+        .stdout(b"aa\xff\xff\n");
+    let output = command(&script).max_line_length(3).run().unwrap();
+    // NOTE: what isn't UTF-8 is still cut at the byte.
+    let mut expected = "aa…[+2 bytes]\naa…[+2 bytes]\n".as_bytes().to_vec();
+    expected.extend_from_slice(b"aa\xff");
+    expected.extend_from_slice("…[+1 bytes]\n".as_bytes());
+    assert_eq!(output.stdout, expected);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", windows))]
+#[test]
+fn cpu_affinity_out_of_range() {
+    let script = FakeChild::new();
+    for cpus in [vec![], vec![0, pipe2::CPU_LIMIT]] {
+        let e = command(&script).cpu_affinity(cpus).run().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn pre_exec_sees_passed_fds() {
+    use std::io;
+
+    let script = FakeChild::new();
+    let mut pipe2 = command(&script);
+    pipe2.pass_fd(std::fs::File::open(HELPER).unwrap(), 9);
+    unsafe {
+        pipe2.pre_exec(|| {
+            if libc::fcntl(9, libc::F_GETFD) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    };
+    let output = pipe2.run().unwrap();
+    assert_eq!(output.reason, ExitReason::Exited);
+}
+
+#[cfg(unix)]
+mod kill {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    /// Spawns `script`, waits for its first line so that it's gone through its setup, and kills it.
+    fn kill(script: &FakeChild, grace: Duration) -> pipe2::Output {
+        let mut child = command(script).grace_period(grace).spawn().unwrap();
+        while child.poll().unwrap().is_none() && child.take_stdout().is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        child.kill().unwrap();
+        child.wait().unwrap()
+    }
+
+    #[test]
+    fn stops_on_the_kill_signal() {
+        let mut script = FakeChild::new();
+        script.stdout("ready\n").sleep(FOREVER);
+        let started = Instant::now();
+        let output = kill(&script, FOREVER);
+        assert!(started.elapsed() < FOREVER);
+        assert_eq!(output.status.signal(), Some(libc::SIGTERM));
+        assert_eq!(output.reason, ExitReason::Signaled);
+        assert!(!output.success);
+    }
+
+    #[test]
+    fn escalates_once_the_grace_period_is_over() {
+        let mut script = FakeChild::new();
+        script.ignore_signals(true).stdout("ready\n").sleep(FOREVER);
+        let started = Instant::now();
+        let output = kill(&script, Duration::from_millis(200));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < FOREVER);
+        assert_eq!(output.status.signal(), Some(libc::SIGKILL));
+        assert_eq!(output.reason, ExitReason::Signaled);
+    }
+
+    #[test]
+    fn drains_what_is_written_on_the_way_out() {
+        let mut script = FakeChild::new();
+        script
+            .ignore_signals(true)
+            .stdout("ready\n")
+            .sleep(Duration::from_millis(100))
+            .stdout("cleaning up\n")
+            .exit_code(4);
+        let output = kill(&script, FOREVER);
+        assert_eq!(output.stdout, b"cleaning up\n");
+        assert_eq!(output.status.code(), Some(4));
+        assert_eq!(output.reason, ExitReason::Exited);
+    }
+}
@@ -0,0 +1,75 @@
+//! What's already at the path given to a socket of pipe2's.
+#![cfg(unix)]
+
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PIPE2: &str = env!("CARGO_BIN_EXE_pipe2");
+
+fn scratch(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("pipe2-socket-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn control(path: &Path) -> bool {
+    Command::new(PIPE2)
+        .arg("--control")
+        .arg(path)
+        .args(["--", "true"])
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn control_keeps_a_file_in_its_way() {
+    let dir = scratch("control-file");
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "important\n").unwrap();
+    assert!(!control(&path));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "important\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn control_replaces_a_stale_socket() {
+    let dir = scratch("control-stale");
+    let path = dir.join("ctl");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    assert!(control(&path));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn agent_keeps_a_file_in_its_way() {
+    let dir = scratch("agent-file");
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "important\n").unwrap();
+    let mut agent = Command::new(PIPE2)
+        .arg("agent")
+        .arg("--socket")
+        .arg(&path)
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = agent.try_wait().unwrap() {
+            break Some(status);
+        }
+        if Instant::now() > deadline {
+            agent.kill().unwrap();
+            agent.wait().unwrap();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(status.is_some_and(|status| !status.success()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "important\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}